@@ -0,0 +1,24 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use boot_format::{Slot, HEADER_SIZE, SLOT_SIZE};
+
+fn main() {
+    // The image runs in place from its firmware slot: link it for the slot named by APP_SLOT
+    // (`a` by default), leaving room for the header added by `tools/mkimage`.
+    let name = env::var("APP_SLOT").unwrap_or_else(|_| "a".to_string());
+    let slot = Slot::from_name(&name).unwrap_or_else(|| panic!("APP_SLOT must be `a` or `b`, not `{}`", name));
+
+    let memory = fs::read_to_string("memory.x.in")
+        .unwrap()
+        .replace("{FLASH_ORIGIN}", &format!("{:#010x}", slot.image_address()))
+        .replace("{FLASH_LENGTH}", &(SLOT_SIZE - HEADER_SIZE).to_string());
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(out.join("memory.x"), memory).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-env-changed=APP_SLOT");
+    println!("cargo:rerun-if-changed=memory.x.in");
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -1,28 +1,57 @@
 
-
 #![allow(dead_code)]
-use drivers::gpio::*;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use kernel::config;
+use kernel::input::{self, ButtonConfig, ButtonEvent, InputEvent};
 use crate:: led::*;
 
-pub const GPIO_MODE_INPUT: u32 = 0;
-
 pub const PORTA: u32 = 0;
 pub const BUTTON_PIN :u32 = 0;
 pub const BUTTON_PORT : u32 = PORTA;
 
-pub fn init_user_button(){
-    gpio_configure_mode (BUTTON_PORT, BUTTON_PIN, GPIO_MODE_INPUT);
+/// The Discovery user button pulls PA0 high when pressed (external pull-down).
+pub const BUTTON_ACTIVE_LOW: bool = false;
 
-}
+/// LED driven by the user button.
+pub const BUTTON_LED: u32 = 2;
 
-// pub fn led_control_with_button() {
-//     let button_status = gpio_read(BUTTON_PORT, BUTTON_PIN);
+/// Configuration key keeping the button LED brightness across resets.
+const BRIGHTNESS_KEY: &str = "led2.brightness";
 
-//     if button_status {
-//         led_on();
-//     } else {
-//         led_off();
-//     }
+static USER_BUTTON: AtomicUsize = AtomicUsize::new(usize::MAX);
 
-   
-// }
+/// Needs the configuration store (`kernel::config::init`) for the saved LED brightness.
+pub fn init_user_button(){
+    let id = input::add_button(BUTTON_PORT, BUTTON_PIN, BUTTON_ACTIVE_LOW, ButtonConfig::default());
+    USER_BUTTON.store(id, Ordering::Relaxed);
+
+    if let Ok(Some(percent)) = config::get_u32(BRIGHTNESS_KEY) {
+        led_set_brightness(BUTTON_LED, percent.min(100));
+    }
+}
+
+/// Click toggles the button LED, double-click cycles its brightness (saved in the configuration),
+/// long press switches it off.
+pub fn handle_button_event(event: InputEvent) {
+    if event.button != USER_BUTTON.load(Ordering::Relaxed) {
+        return;
+    }
+
+    match event.event {
+        ButtonEvent::Click => led_toggle(BUTTON_LED),
+        ButtonEvent::DoubleClick => {
+            let next = match led_brightness(BUTTON_LED) {
+                b if b > 50 => 50,
+                b if b > 10 => 10,
+                _ => 100,
+            };
+            led_set_brightness(BUTTON_LED, next);
+            led_write(BUTTON_LED, LED_ON);
+            if let Err(err) = config::set_u32(BRIGHTNESS_KEY, next) {
+                log::warn!("brightness not saved: {:?}", err);
+            }
+        }
+        ButtonEvent::LongPress => led_write(BUTTON_LED, LED_OFF),
+        ButtonEvent::Press | ButtonEvent::Release => {}
+    }
+}
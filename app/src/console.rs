@@ -0,0 +1,31 @@
+
+#![allow(dead_code)]
+use drivers::gpio::*;
+use drivers::usart::{UsartConfig, USART2};
+use kernel::console;
+use kernel::serial::Serial;
+use log::LevelFilter;
+
+pub const PORTA : u32 = 0;
+
+pub const GPIO_AF_USART2: u32 = 7;
+
+pub const CONSOLE_PORT : u32 = PORTA;
+pub const CONSOLE_TX_PIN : u32 = 2;
+pub const CONSOLE_RX_PIN : u32 = 3;
+pub const CONSOLE_BAUD_RATE : u32 = 115_200;
+
+
+/// Route USART2 to PA2 (TX) / PA3 (RX), open it at 115200 8N1 and make it the kernel console
+/// and `log` backend.
+pub fn init_console(){
+    gpio_configure_mode (CONSOLE_PORT, CONSOLE_TX_PIN, GPIO_MODE_ALTERNATE);
+    gpio_configure_mode (CONSOLE_PORT, CONSOLE_RX_PIN, GPIO_MODE_ALTERNATE);
+    gpio_set_alternate_function (CONSOLE_PORT, CONSOLE_TX_PIN, GPIO_AF_USART2);
+    gpio_set_alternate_function (CONSOLE_PORT, CONSOLE_RX_PIN, GPIO_AF_USART2);
+    gpio_pulup_puldown_configure (CONSOLE_PORT, CONSOLE_RX_PIN, GPIO_PULL_UP);
+
+    let serial = Serial::open(USART2, &UsartConfig::new(CONSOLE_BAUD_RATE));
+    console::init(serial);
+    console::init_logger(LevelFilter::Info);
+}
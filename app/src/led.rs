@@ -1,6 +1,9 @@
 
 #![allow(dead_code)]
+use core::sync::atomic::{AtomicU32, Ordering};
 use drivers::gpio::*;
+use drivers::timer::*;
+use kernel::power::{PowerLock, PowerMode};
 
 pub const PORTA : u32 = 0;
 pub const PORTB : u32 = 1;
@@ -23,54 +26,99 @@ pub const LED_2_PIN : u32 = 13;
 pub const LED_3_PIN : u32 = 14;
 pub const LED_4_PIN : u32 = 15;
 
+/// PD12..PD15 are TIM4 CH1..CH4: LED n is driven by channel n.
+pub const LED_TIMER : u32 = TIM4;
+pub const LED_PWM_FREQUENCY_HZ : u32 = 1_000;
 
+/// Brightness (percent) used when each LED is switched on.
+static BRIGHTNESS: [AtomicU32; 4] = [const { AtomicU32::new(100) }; 4];
+
+/// The PWM stops in Stop, leaving a dimmed LED fully on or off.
+static DIMMED_LOCK: PowerLock = PowerLock::busy_while("led pwm", PowerMode::Stop, any_dimmed);
+
+/// Whether an LED is on at less than full brightness.
+fn any_dimmed() -> bool {
+    (1..=4).any(|led| led_is_on(led) && led_brightness(led) < 100)
+}
 
 
 pub fn init_led(){
-    gpio_configure_mode (LED_PORT, LED_1_PIN, GPIO_MODE_GP_OUTPUT);
-    gpio_configure_mode (LED_PORT, LED_2_PIN, GPIO_MODE_GP_OUTPUT);
-    gpio_configure_mode (LED_PORT, LED_3_PIN, GPIO_MODE_GP_OUTPUT);
-    gpio_configure_mode (LED_PORT, LED_4_PIN, GPIO_MODE_GP_OUTPUT);
-
-    gpio_output_type_configure (LED_PORT, LED_1_PIN, GPIO_OUTPUT_PUSH_PULL );
-    gpio_output_type_configure (LED_PORT, LED_2_PIN, GPIO_OUTPUT_PUSH_PULL );
-    gpio_output_type_configure (LED_PORT, LED_3_PIN, GPIO_OUTPUT_PUSH_PULL );
-    gpio_output_type_configure (LED_PORT, LED_4_PIN, GPIO_OUTPUT_PUSH_PULL );
+    let af = timer_alternate_function(LED_TIMER);
+    for pin in [LED_1_PIN, LED_2_PIN, LED_3_PIN, LED_4_PIN] {
+        gpio_output_type_configure (LED_PORT, pin, GPIO_OUTPUT_PUSH_PULL );
+        gpio_set_alternate_function (LED_PORT, pin, af);
+        gpio_configure_mode (LED_PORT, pin, GPIO_MODE_ALTERNATE);
+    }
+
+    timer_init(LED_TIMER);
+    timer_set_frequency(LED_TIMER, LED_PWM_FREQUENCY_HZ);
+    for channel in 1..=4 {
+        timer_pwm_init(LED_TIMER, channel, false);
+    }
+    timer_start(LED_TIMER);
+    DIMMED_LOCK.register();
+}
+
+/// Set the brightness of LED `led` (1-4) in percent; applied now if the LED is on, otherwise the
+/// next time it is switched on.
+pub fn led_set_brightness(led: u32, percent: u32){
+    let percent = percent.min(100);
+    BRIGHTNESS[(led - 1) as usize].store(percent, Ordering::Relaxed);
+    if led_is_on(led) {
+        timer_pwm_set_duty_percent(LED_TIMER, led, percent);
+    }
+}
+
+pub fn led_brightness(led: u32) -> u32 {
+    BRIGHTNESS[(led - 1) as usize].load(Ordering::Relaxed)
+}
+
+pub fn led_is_on(led: u32) -> bool {
+    timer_pwm_get_duty(LED_TIMER, led) != 0
+}
+
+pub fn led_write(led: u32, state: bool){
+    let percent = if state == LED_ON { led_brightness(led) } else { 0 };
+    timer_pwm_set_duty_percent(LED_TIMER, led, percent);
+}
+
+pub fn led_toggle(led: u32){
+    led_write(led, !led_is_on(led));
 }
 
 
 pub fn led1_on(){
-    gpio_write (LED_PORT, LED_1_PIN,LED_ON);   
+    led_write (1, LED_ON);
 
 }
 
 pub fn led2_on(){
-    gpio_write (LED_PORT, LED_2_PIN,LED_ON);   
+    led_write (2, LED_ON);
 
 }
 pub fn led3_on(){
-    gpio_write (LED_PORT, LED_3_PIN,LED_ON);   
+    led_write (3, LED_ON);
 
 }
 pub fn led4_on(){
-    gpio_write (LED_PORT, LED_4_PIN,LED_ON);   
+    led_write (4, LED_ON);
 
 }
 
 pub fn led1_toggle(){
-    toggle_gpio(LED_PORT, LED_1_PIN);  
+    led_toggle(1);
 
 }
 
 pub fn led2_toggle(){
-   toggle_gpio(LED_PORT, LED_2_PIN);  
+   led_toggle(2);
 
 }
 pub fn led3_toggle(){
-    toggle_gpio(LED_PORT, LED_3_PIN);    
+    led_toggle(3);
 
 }
 pub fn led4_toggle(){
-    toggle_gpio(LED_PORT, LED_4_PIN);    
+    led_toggle(4);
 
-}
\ No newline at end of file
+}
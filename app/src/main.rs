@@ -6,25 +6,26 @@
 
 mod led;
 mod button;
+mod console;
+mod shell;
 use cortex_m_rt:: {entry};
 
 
 //use drivers::exti::*;
-//use button::*;
+use button::*;
 //use drivers::systick::{SysTick};
 use kernel::os::*;
 use kernel::os_config::*;
 use crate:: led::*;
 //use drivers::gpio::*; 
 use core::panic::PanicInfo;
+use drivers::rcc::{clear_reset_flags, reset_cause, ResetCause, RccConfig};
+use drivers::pwr::{pwr_clear_wakeup_flags, pwr_woke_from_standby};
+use drivers::rtc::{rtc_datetime, rtc_init, rtc_is_set, RtcClock};
 
 
-// #[allow(non_snake_case)]
-// #[unsafe(no_mangle)] 
-// fn EXTI0_Handler(){
-//     clear_exti_pending(0);
-//     toggle_led();
-// }
+// configure_gpio_interrupt(PORTA, 0, Trigger::Rising);
+// on_line(0, |_| toggle_led());
 
 
 // #[exception]
@@ -32,7 +33,14 @@ use core::panic::PanicInfo;
 //     toggle_led();
 // }
 
-//const CORE_CLOCK_MHZ: u32 = 8;
+/// 8 MHz crystal on the STM32F4-Discovery board.
+const HSE_HZ: u32 = 8_000_000;
+const SYSCLK_HZ: u32 = 168_000_000;
+
+/// Hardware watchdog timeout: above the longest flash sector erase (~2 s) even with a fast LSI.
+const WATCHDOG_TIMEOUT_MS: u32 = 4000;
+/// task2 checks in every 500 ms.
+const TASK2_DEADLINE_MS: u32 = 2000;
 
 
 #[entry]
@@ -41,8 +49,50 @@ fn main() -> ! {
     // let mut systick = SysTick::take().expect("Failed to take SysTick instance! It's likely already in use.");
     //systick.init(7999, ClockSource::Core);   
 
+    // HCLK 168 MHz, APB1 42 MHz, APB2 84 MHz. Must come before any peripheral is initialised.
+    RccConfig::new().hse(HSE_HZ).sysclk(SYSCLK_HZ).freeze();
+
     init_led();
-    
+    console::init_console();
+    if let Err(err) = kernel::config::init() {
+        log::error!("configuration store unavailable: {:?}", err);
+    }
+    init_user_button();
+
+    // A task fault stops only that task; the report survives the reset for any other fault
+    if let Some(report) = kernel::fault::last_fault() {
+        log::warn!("previous run ended with a fault:\n{}", report);
+        kernel::fault::clear_last_fault();
+    }
+    kernel::fault::set_fault_policy(kernel::fault::FaultPolicy::KillTask);
+    if pwr_woke_from_standby() {
+        log::info!("woke up from standby");
+        pwr_clear_wakeup_flags();
+    }
+    match (reset_cause(), kernel::watchdog::last_stall()) {
+        (ResetCause::IndependentWatchdog, Some(stall)) => log::warn!("watchdog reset: {}", stall),
+        (ResetCause::IndependentWatchdog, None) => log::warn!("watchdog reset: no task stalled, the kernel stopped"),
+        (cause, _) => log::info!("reset cause: {:?}", cause),
+    }
+    kernel::watchdog::clear_last_stall();
+    clear_reset_flags();
+    kernel::crashlog::init();
+    match kernel::boot::running_slot() {
+        Some(slot) => log::info!("running from slot {}{}", slot, if kernel::boot::is_test_boot() { " (test boot)" } else { "" }),
+        None => log::info!("not started by the bootloader"),
+    }
+    // Not every Discovery board has the 32.768 kHz crystal (X3) fitted
+    let rtc = rtc_init(RtcClock::Lse).or_else(|_| {
+        log::warn!("no LSE crystal, RTC clocked from the LSI (inaccurate)");
+        rtc_init(RtcClock::Lsi)
+    });
+    match rtc {
+        Ok(()) if rtc_is_set() => log::info!("time: {}", rtc_datetime()),
+        Ok(()) => log::info!("time not set (shell: date YYYY-MM-DD HH:MM:SS)"),
+        Err(err) => log::error!("RTC unavailable: {:?}", err),
+    }
+
+    kernel::watchdog::start(WATCHDOG_TIMEOUT_MS);
     scheduler_init();
     
 
@@ -54,36 +104,59 @@ fn main() -> ! {
 }
 
 #[panic_handler]
-fn panic_handler(_info: &PanicInfo) -> ! {
+fn panic_handler(info: &PanicInfo) -> ! {
+    kernel::console::panic_print(info);
     loop {}
 }
 
 
+/// Runs when every task is blocked: sleep until one has work (see `kernel::power`).
 #[unsafe(no_mangle)]
 pub extern "C" fn Idle_task_handler() {
     loop {
-        led1_toggle();
+        kernel::power::idle();
     }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn task1_handler() {
     loop {
-       led2_toggle();
+        if let Some(event) = kernel::input::next_event(kernel::sync::WAIT_FOREVER) {
+            handle_button_event(event);
+        }
     }
 }
 
 
+/// A test-booted image confirms itself after running this long.
+const CONFIRM_AFTER_MS: u32 = 5000;
+
 #[unsafe(no_mangle)]
 pub extern "C" fn task2_handler() {
+    let mut elapsed = 0;
+    kernel::watchdog::register(TASK2_DEADLINE_MS);
     loop {
+        kernel::watchdog::check_in();
         led3_toggle();
+        task_delay(500);
+        elapsed += 500;
+        if elapsed == CONFIRM_AFTER_MS && kernel::boot::is_test_boot() {
+            match kernel::boot::confirm() {
+                Ok(()) => log::info!("firmware confirmed"),
+                Err(err) => log::error!("firmware not confirmed: {:?}", err),
+            }
+        }
     }
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn task3_handler() {
-    loop {
-        led4_toggle();
+    shell::register_commands();
+    match kernel::console::serial() {
+        Some(serial) => kernel::shell::run(serial),
+        None => loop {
+            led4_toggle();
+            task_delay(1000);
+        },
     }
 }
@@ -0,0 +1,66 @@
+
+#![allow(dead_code)]
+use core::fmt::Write;
+use kernel::shell::{register_command, Command, CommandResult};
+use crate:: led::*;
+use drivers::rtc::{rtc_datetime, rtc_is_set, rtc_set_datetime, DateTime};
+
+
+/// `led <1-4> [0-100]`: toggle one of the user LEDs on PD12..PD15, or set its brightness.
+fn cmd_led(args: &[&str], out: &mut dyn Write) -> CommandResult {
+    const USAGE: &str = "usage: led <1-4> [0-100]";
+    let led = match args.get(1).and_then(|arg| arg.parse::<u32>().ok()) {
+        Some(led @ 1..=4) => led,
+        _ => return Err(USAGE),
+    };
+    match args.get(2) {
+        None => led_toggle(led),
+        Some(arg) => match arg.parse::<u32>() {
+            Ok(percent @ 0..=100) => {
+                led_set_brightness(led, percent);
+                led_write(led, LED_ON);
+            }
+            _ => return Err(USAGE),
+        },
+    }
+    let _ = writeln!(out, "ok");
+    Ok(())
+}
+
+/// Parse `YYYY-MM-DD` and `HH:MM:SS`.
+fn parse_datetime(date: &str, time: &str) -> Option<DateTime> {
+    let mut date = date.split('-').map(|field| field.parse::<u16>().ok());
+    let mut time = time.split(':').map(|field| field.parse::<u8>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if date.next().is_some() || time.next().is_some() {
+        return None;
+    }
+    DateTime::new(year, u8::try_from(month).ok()?, u8::try_from(day).ok()?, hour, minute, second)
+}
+
+/// `date [YYYY-MM-DD HH:MM:SS]`: show or set the RTC calendar.
+fn cmd_date(args: &[&str], out: &mut dyn Write) -> CommandResult {
+    const USAGE: &str = "usage: date [YYYY-MM-DD HH:MM:SS] (years 2000-2099)";
+    match args {
+        [_] if rtc_is_set() => {
+            let _ = writeln!(out, "{}", rtc_datetime());
+        }
+        [_] => {
+            let _ = writeln!(out, "{} (not set)", rtc_datetime());
+        }
+        [_, date, time] => {
+            let datetime = parse_datetime(date, time).ok_or(USAGE)?;
+            rtc_set_datetime(&datetime);
+            let _ = writeln!(out, "{}", rtc_datetime());
+        }
+        _ => return Err(USAGE),
+    }
+    Ok(())
+}
+
+/// Commands the application adds on top of the kernel shell built-ins.
+pub fn register_commands(){
+    register_command(Command { name: "led", help: "led <1-4> [0-100]: toggle a user LED or set its brightness", handler: cmd_led });
+    register_command(Command { name: "date", help: "date [YYYY-MM-DD HH:MM:SS]: show or set the real-time clock", handler: cmd_date });
+}
@@ -0,0 +1,965 @@
+//! Flash layout, image header and boot state shared by the bootloader, the firmware and the host
+//! tools.
+//!
+//! Flash map (STM32F407, 1 MB):
+//!
+//! | Sectors | Address      | Size  | Content                                    |
+//! |---------|--------------|-------|--------------------------------------------|
+//! | 0–3     | `0x08000000` | 64K   | bootloader                                 |
+//! | 4       | `0x08010000` | 64K   | boot state log                             |
+//! | 5–6     | `0x08020000` | 256K  | slot A                                     |
+//! | 7–8     | `0x08060000` | 256K  | slot B                                     |
+//! | 9–10    | `0x080A0000` | 256K  | kernel configuration store                 |
+//! | 11      | `0x080E0000` | 128K  | kernel crash log                           |
+//!
+//! Images run in place from their slot, so an image is linked for one slot (the application
+//! build script selects it with `APP_SLOT`). A slot starts with a `HEADER_SIZE`-byte header
+//! (magic, version, load address, length, CRC-32 and SHA-256 of the image) followed by the image,
+//! whose vector table must be at `Slot::image_address`.
+//!
+//! Boot state log: 16-byte `BootRecord`s appended to the boot state sector, the last valid one
+//! being the current state. A record is `STATE_MAGIC`, the packed state, a reserved word and the
+//! CRC-32 of the first three words; a record cut short by a power failure fails its CRC and the
+//! previous state stays current. Test boot sequence:
+//!
+//! 1. the firmware stores an image in the other slot and appends `Pending { slot: new }`;
+//! 2. the bootloader finds `Pending`, appends `Testing` and starts the new image;
+//! 3. the new image appends `Confirmed` once it is satisfied it works;
+//! 4. if the bootloader still finds `Testing` at the next reset, the image did not confirm: it
+//!    appends `Confirmed { slot: previous }` with `FLAG_ROLLED_BACK` and starts the previous one.
+//!
+//! `choose_slot` is that decision, over the state found by `scan_state`.
+
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt;
+use sha2::{Digest, Sha256};
+
+/// Value of erased flash.
+pub const ERASED: u32 = 0xFFFF_FFFF;
+
+pub const BOOTLOADER_ADDRESS: u32 = 0x0800_0000;
+pub const BOOTLOADER_SIZE: u32 = 64 * 1024;
+
+/// Sector and address of the boot state log.
+pub const BOOT_STATE_SECTOR: u32 = 4;
+pub const BOOT_STATE_ADDRESS: u32 = 0x0801_0000;
+pub const BOOT_STATE_SIZE: u32 = 64 * 1024;
+
+/// Size of a slot, header included.
+pub const SLOT_SIZE: u32 = 256 * 1024;
+/// Flash sectors per slot.
+pub const SLOT_SECTORS: u32 = 2;
+
+/// Space reserved for the header at the start of a slot. The vector table that follows must be
+/// 512-byte aligned (98 vectors).
+pub const HEADER_SIZE: u32 = 0x200;
+/// Bytes of the header actually used; the rest is left erased.
+pub const HEADER_LEN: usize = 60;
+
+/// First word of an image header.
+pub const IMAGE_MAGIC: u32 = 0xB007_1A6E;
+/// Version of the header layout.
+pub const HEADER_FORMAT: u32 = 1;
+
+/// First word of a boot state record.
+pub const STATE_MAGIC: u32 = 0xB007_57A7;
+/// Size of a boot state record in words.
+pub const STATE_RECORD_WORDS: usize = 4;
+
+/// The `Confirmed` record was written by a rollback.
+pub const FLAG_ROLLED_BACK: u8 = 1 << 0;
+
+/// Firmware slot.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    A = 0,
+    B = 1,
+}
+
+impl Slot {
+    pub const ALL: [Slot; 2] = [Slot::A, Slot::B];
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Slot::A),
+            1 => Some(Slot::B),
+            _ => None,
+        }
+    }
+
+    /// The slot named `a` or `b` (any case).
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "a" | "A" => Some(Slot::A),
+            "b" | "B" => Some(Slot::B),
+            _ => None,
+        }
+    }
+
+    pub fn other(self) -> Self {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+
+    /// Address of the slot (its header).
+    pub fn address(self) -> u32 {
+        match self {
+            Slot::A => 0x0802_0000,
+            Slot::B => 0x0806_0000,
+        }
+    }
+
+    /// Address of the image (its vector table).
+    pub fn image_address(self) -> u32 {
+        self.address() + HEADER_SIZE
+    }
+
+    /// First flash sector of the slot.
+    pub fn first_sector(self) -> u32 {
+        match self {
+            Slot::A => 5,
+            Slot::B => 7,
+        }
+    }
+
+    /// Slot containing `address`, e.g. a reset vector or the vector table of the running image.
+    pub fn containing(address: u32) -> Option<Self> {
+        Slot::ALL.into_iter().find(|slot| (slot.address()..slot.address() + SLOT_SIZE).contains(&address))
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Slot::A => "A",
+            Slot::B => "B",
+        })
+    }
+}
+
+/// Image version, ordered major first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u16,
+}
+
+impl Version {
+    pub const fn new(major: u8, minor: u8, patch: u16) -> Self {
+        Version { major, minor, patch }
+    }
+
+    pub fn to_u32(self) -> u32 {
+        (self.major as u32) << 24 | (self.minor as u32) << 16 | self.patch as u32
+    }
+
+    pub fn from_u32(value: u32) -> Self {
+        Version { major: (value >> 24) as u8, minor: (value >> 16) as u8, patch: value as u16 }
+    }
+
+    /// Parse `major.minor.patch`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut parts = text.split('.');
+        let version = Version {
+            major: parts.next()?.parse().ok()?,
+            minor: parts.next()?.parse().ok()?,
+            patch: parts.next()?.parse().ok()?,
+        };
+        parts.next().is_none().then_some(version)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Why an image is rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    /// No header (erased or foreign data).
+    BadMagic,
+    BadHeaderCrc,
+    /// Header written by a newer tool.
+    UnsupportedFormat,
+    /// The image is linked for another slot.
+    WrongSlot,
+    /// The image does not fit in its slot.
+    TooLarge,
+    BadCrc,
+    BadHash,
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ImageError::BadMagic => "no image",
+            ImageError::BadHeaderCrc => "corrupted header",
+            ImageError::UnsupportedFormat => "unsupported header format",
+            ImageError::WrongSlot => "image linked for the other slot",
+            ImageError::TooLarge => "image larger than its slot",
+            ImageError::BadCrc => "image CRC mismatch",
+            ImageError::BadHash => "image SHA-256 mismatch",
+        })
+    }
+}
+
+/// Header placed at the start of a slot.
+///
+/// Byte layout (little-endian): magic, header format, version, load address, image length,
+/// image CRC-32, image SHA-256 (32 bytes), CRC-32 of the preceding 56 bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub version: Version,
+    /// Address of the vector table the image is linked for.
+    pub load_address: u32,
+    /// Image size in bytes, header excluded.
+    pub length: u32,
+    pub crc32: u32,
+    pub sha256: [u8; 32],
+}
+
+impl ImageHeader {
+    /// Header describing `image`, linked to run at `load_address`.
+    pub fn new(version: Version, load_address: u32, image: &[u8]) -> Self {
+        ImageHeader {
+            version,
+            load_address,
+            length: image.len() as u32,
+            crc32: crc32(image),
+            sha256: Sha256::digest(image).into(),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        let words = [IMAGE_MAGIC, HEADER_FORMAT, self.version.to_u32(), self.load_address, self.length, self.crc32];
+        for (chunk, word) in bytes.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes[24..56].copy_from_slice(&self.sha256);
+        let crc = crc32(&bytes[..56]);
+        bytes[56..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Decode and check a header (not the image it describes).
+    pub fn parse(bytes: &[u8]) -> Result<Self, ImageError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(ImageError::BadMagic);
+        }
+        let word = |i: usize| u32::from_le_bytes([bytes[4 * i], bytes[4 * i + 1], bytes[4 * i + 2], bytes[4 * i + 3]]);
+        if word(0) != IMAGE_MAGIC {
+            return Err(ImageError::BadMagic);
+        }
+        if word(14) != crc32(&bytes[..56]) {
+            return Err(ImageError::BadHeaderCrc);
+        }
+        if word(1) != HEADER_FORMAT {
+            return Err(ImageError::UnsupportedFormat);
+        }
+        let mut sha256 = [0u8; 32];
+        sha256.copy_from_slice(&bytes[24..56]);
+        Ok(ImageHeader {
+            version: Version::from_u32(word(2)),
+            load_address: word(3),
+            length: word(4),
+            crc32: word(5),
+            sha256,
+        })
+    }
+
+    /// Slot the image is linked for.
+    pub fn slot(&self) -> Option<Slot> {
+        Slot::ALL.into_iter().find(|slot| slot.image_address() == self.load_address)
+    }
+
+    /// Check `image` against the length, CRC-32 and SHA-256 of the header.
+    pub fn verify(&self, image: &[u8]) -> Result<(), ImageError> {
+        if image.len() != self.length as usize {
+            return Err(ImageError::TooLarge);
+        }
+        if crc32(image) != self.crc32 {
+            return Err(ImageError::BadCrc);
+        }
+        let hash: [u8; 32] = Sha256::digest(image).into();
+        if hash != self.sha256 {
+            return Err(ImageError::BadHash);
+        }
+        Ok(())
+    }
+}
+
+/// Validate the content of `slot` (`contents` = the whole slot, e.g. read in place from flash).
+pub fn check_slot(slot: Slot, contents: &[u8]) -> Result<ImageHeader, ImageError> {
+    let header = ImageHeader::parse(contents)?;
+    if header.slot() != Some(slot) {
+        return Err(ImageError::WrongSlot);
+    }
+    let end = HEADER_SIZE as usize + header.length as usize;
+    if header.length > SLOT_SIZE - HEADER_SIZE || end > contents.len() {
+        return Err(ImageError::TooLarge);
+    }
+    header.verify(&contents[HEADER_SIZE as usize..end])?;
+    Ok(header)
+}
+
+/// Boot state kinds, see the crate documentation.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootState {
+    /// Boot `slot` normally.
+    Confirmed = 1,
+    /// Test-boot `slot` at the next reset.
+    Pending = 2,
+    /// `slot` is being test-booted and has not confirmed yet.
+    Testing = 3,
+}
+
+impl BootState {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(BootState::Confirmed),
+            2 => Some(BootState::Pending),
+            3 => Some(BootState::Testing),
+            _ => None,
+        }
+    }
+}
+
+/// One entry of the boot state log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootRecord {
+    pub state: BootState,
+    pub slot: Slot,
+    /// Slot to roll back to (the running one when a test boot is requested).
+    pub previous: Slot,
+    pub flags: u8,
+}
+
+impl BootRecord {
+    pub fn encode(&self) -> [u32; STATE_RECORD_WORDS] {
+        let packed = self.state as u32 | (self.slot as u32) << 8 | (self.previous as u32) << 16 | (self.flags as u32) << 24;
+        let mut words = [STATE_MAGIC, packed, 0, 0];
+        words[3] = crc32_words(&words[..3]);
+        words
+    }
+
+    /// Decode a record, `None` if it is not a complete valid one.
+    pub fn parse(words: &[u32]) -> Option<Self> {
+        if words.len() < STATE_RECORD_WORDS || words[0] != STATE_MAGIC || words[3] != crc32_words(&words[..3]) {
+            return None;
+        }
+        Some(BootRecord {
+            state: BootState::from_u8(words[1] as u8)?,
+            slot: Slot::from_u8((words[1] >> 8) as u8)?,
+            previous: Slot::from_u8((words[1] >> 16) as u8)?,
+            flags: (words[1] >> 24) as u8,
+        })
+    }
+}
+
+impl fmt::Display for BootRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.state {
+            BootState::Confirmed => write!(f, "slot {} confirmed", self.slot)?,
+            BootState::Pending => write!(f, "slot {} pending test boot", self.slot)?,
+            BootState::Testing => write!(f, "slot {} on test boot", self.slot)?,
+        }
+        if self.flags & FLAG_ROLLED_BACK != 0 {
+            write!(f, " (rolled back from slot {})", self.previous)?;
+        }
+        Ok(())
+    }
+}
+
+/// Scan a boot state log (`words` = the whole sector): the current state and the index of the
+/// word where the next record goes, `None` when the log is full and must be erased.
+pub fn scan_state(words: &[u32]) -> (Option<BootRecord>, Option<usize>) {
+    let mut state = None;
+    for (i, record) in words.chunks_exact(STATE_RECORD_WORDS).enumerate() {
+        if record.iter().all(|&word| word == ERASED) {
+            return (state, Some(i * STATE_RECORD_WORDS));
+        }
+        if let Some(record) = BootRecord::parse(record) {
+            state = Some(record);
+        }
+    }
+    (state, None)
+}
+
+/// Slot the bootloader starts at reset, following the boot state `state` (from `scan_state`).
+///
+/// `version(slot)` is the version of the valid image in `slot` (`None` without one), and `record`
+/// appends a record to the boot state log, returning `false` if the flash failed. A pending test
+/// boot is recorded as `Testing` before its image is chosen, and an image found still `Testing`
+/// is rolled back. With no state recorded, the valid image with the highest version is chosen.
+/// Returns `None` when no slot holds a valid image.
+pub fn choose_slot(
+    state: Option<BootRecord>,
+    version: impl Fn(Slot) -> Option<Version>,
+    mut record: impl FnMut(BootRecord) -> bool,
+) -> Option<Slot> {
+    // Nothing better to do if recording a rollback fails: the state is re-evaluated at the next reset
+    let rolled_back = |failed: Slot, previous: Slot| BootRecord {
+        state: BootState::Confirmed,
+        slot: previous,
+        previous: failed,
+        flags: FLAG_ROLLED_BACK,
+    };
+    let wanted = match state {
+        Some(BootRecord { state: BootState::Pending, slot, previous, .. }) => {
+            let testing = BootRecord { state: BootState::Testing, slot, previous, flags: 0 };
+            // The image only gets its chance if a rollback is possible
+            if version(slot).is_some() && record(testing) {
+                return Some(slot);
+            }
+            record(rolled_back(slot, previous));
+            previous
+        }
+        Some(BootRecord { state: BootState::Testing, slot, previous, .. }) => {
+            record(rolled_back(slot, previous));
+            previous
+        }
+        Some(BootRecord { state: BootState::Confirmed, slot, .. }) => slot,
+        None => {
+            return Slot::ALL
+                .into_iter()
+                .filter_map(|slot| version(slot).map(|version| (version, slot)))
+                .max_by_key(|&(version, _)| version)
+                .map(|(_, slot)| slot);
+        }
+    };
+    // A damaged image is not worth stopping for while the other slot holds a valid one
+    [wanted, wanted.other()].into_iter().find(|&slot| version(slot).is_some())
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE 802.3, the zlib/Ethernet CRC).
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+/// Streaming `crc32`, for data that is not in a single slice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc32 {
+    /// Reflected running value, not inverted.
+    state: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Crc32 { state: 0xFFFF_FFFF }
+    }
+
+    /// Feed the next bytes.
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state = (self.state >> 8) ^ CRC_TABLE[((self.state ^ byte as u32) & 0xFF) as usize];
+        }
+    }
+
+    /// CRC-32 of all the bytes fed so far.
+    pub const fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+/// A CRC-32/MPEG-2 unit over 32-bit words, such as the STM32 CRC calculation unit: polynomial
+/// 0x04C11DB7 fed MSB first from 0xFFFFFFFF, without reflection or final XOR.
+pub trait Crc32Unit {
+    /// Feed the next word.
+    fn feed(&mut self, word: u32);
+
+    /// Value after the words fed so far.
+    fn value(&self) -> u32;
+}
+
+/// `Crc32` computed on a `Crc32Unit`.
+///
+/// The IEEE CRC of four bytes is the MPEG-2 CRC of the bit-reversed little-endian word, bit-reversed
+/// back. Bytes waiting for a whole word are kept here, and those left over at the end (fewer than
+/// four) are processed in software from the unit value.
+#[derive(Debug)]
+pub struct WordCrc32<U> {
+    unit: U,
+    pending: [u8; 4],
+    pending_len: usize,
+}
+
+impl<U: Crc32Unit> WordCrc32<U> {
+    /// A computation on `unit`, which must start from 0xFFFFFFFF.
+    pub const fn new(unit: U) -> Self {
+        WordCrc32 { unit, pending: [0; 4], pending_len: 0 }
+    }
+
+    /// Feed the next bytes, of any length and alignment.
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        if self.pending_len > 0 {
+            let take = (4 - self.pending_len).min(bytes.len());
+            self.pending[self.pending_len..self.pending_len + take].copy_from_slice(&bytes[..take]);
+            self.pending_len += take;
+            bytes = &bytes[take..];
+            if self.pending_len < 4 {
+                return;
+            }
+            self.unit.feed(u32::from_le_bytes(self.pending).reverse_bits());
+            self.pending_len = 0;
+        }
+        let mut words = bytes.chunks_exact(4);
+        for word in &mut words {
+            self.unit.feed(u32::from_le_bytes([word[0], word[1], word[2], word[3]]).reverse_bits());
+        }
+        let rest = words.remainder();
+        self.pending[..rest.len()].copy_from_slice(rest);
+        self.pending_len = rest.len();
+    }
+
+    /// CRC-32 of all the bytes fed so far.
+    pub fn finish(&self) -> u32 {
+        let mut crc = Crc32 { state: self.unit.value().reverse_bits() };
+        crc.update(&self.pending[..self.pending_len]);
+        crc.finish()
+    }
+}
+
+/// CRC-32 of little-endian words.
+fn crc32_words(words: &[u32]) -> u32 {
+    let mut bytes = [0u8; 4 * (STATE_RECORD_WORDS - 1)];
+    for (chunk, word) in bytes.chunks_exact_mut(4).zip(words) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    crc32(&bytes[..4 * words.len()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECK: &[u8] = b"123456789";
+    const CHECK_CRC: u32 = 0xCBF4_3926;
+
+    /// Bit-by-bit model of the STM32 CRC calculation unit.
+    struct Mpeg2 {
+        value: u32,
+    }
+
+    impl Crc32Unit for Mpeg2 {
+        fn feed(&mut self, word: u32) {
+            self.value ^= word;
+            for _ in 0..32 {
+                self.value = if self.value & 0x8000_0000 != 0 { (self.value << 1) ^ 0x04C1_1DB7 } else { self.value << 1 };
+            }
+        }
+
+        fn value(&self) -> u32 {
+            self.value
+        }
+    }
+
+    fn word_crc32(pieces: &[&[u8]]) -> u32 {
+        let mut crc = WordCrc32::new(Mpeg2 { value: 0xFFFF_FFFF });
+        for piece in pieces {
+            crc.update(piece);
+        }
+        crc.finish()
+    }
+
+    /// Message of `len` bytes.
+    fn message(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 151 + 17) as u8).collect()
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(CHECK), CHECK_CRC);
+        assert_eq!(crc32(&[]), 0);
+        for split in 0..=CHECK.len() {
+            let mut crc = Crc32::new();
+            crc.update(&CHECK[..split]);
+            crc.update(&CHECK[split..]);
+            assert_eq!(crc.finish(), CHECK_CRC, "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn word_unit_check_value() {
+        assert_eq!(word_crc32(&[CHECK]), CHECK_CRC);
+        assert_eq!(word_crc32(&[]), 0);
+    }
+
+    #[test]
+    fn word_unit_matches_software_for_every_leftover() {
+        // Lengths 0 to 3 modulo 4, so that 0 to 3 bytes are finished in software
+        for len in 0..=24 {
+            let bytes = message(len);
+            assert_eq!(word_crc32(&[&bytes]), crc32(&bytes), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn word_unit_matches_software_for_every_split() {
+        for len in [9, 14, 16, 23] {
+            let bytes = message(len);
+            let expected = crc32(&bytes);
+            for first in 0..=len {
+                for second in first..=len {
+                    let pieces = [&bytes[..first], &bytes[first..second], &bytes[second..]];
+                    assert_eq!(word_crc32(&pieces), expected, "{} bytes split at {} and {}", len, first, second);
+                }
+            }
+        }
+        // One byte at a time
+        let bytes = message(19);
+        let pieces: Vec<&[u8]> = bytes.chunks(1).collect();
+        assert_eq!(word_crc32(&pieces), crc32(&bytes));
+    }
+
+    #[test]
+    fn word_unit_finish_keeps_going() {
+        let bytes = message(11);
+        let mut crc = WordCrc32::new(Mpeg2 { value: 0xFFFF_FFFF });
+        for end in 1..=bytes.len() {
+            crc.update(&bytes[end - 1..end]);
+            assert_eq!(crc.finish(), crc32(&bytes[..end]), "{} bytes", end);
+        }
+    }
+
+    fn image(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + 3) as u8).collect()
+    }
+
+    /// Slot contents holding `image` linked for `slot`, the rest erased.
+    fn slot_contents(slot: Slot, version: Version, image: &[u8]) -> Vec<u8> {
+        let mut contents = vec![0xFF; HEADER_SIZE as usize + image.len() + 64];
+        contents[..HEADER_LEN].copy_from_slice(&ImageHeader::new(version, slot.image_address(), image).encode());
+        contents[HEADER_SIZE as usize..][..image.len()].copy_from_slice(image);
+        contents
+    }
+
+    #[test]
+    fn image_header_round_trip() {
+        let image = image(1000);
+        let header = ImageHeader::new(Version::new(2, 1, 300), Slot::B.image_address(), &image);
+        assert_eq!(ImageHeader::parse(&header.encode()), Ok(header));
+        assert_eq!(header.length, 1000);
+        assert_eq!(header.crc32, crc32(&image));
+        assert_eq!(header.slot(), Some(Slot::B));
+        assert_eq!(header.verify(&image), Ok(()));
+        assert_eq!(ImageHeader { load_address: 0x0800_0000, ..header }.slot(), None);
+    }
+
+    #[test]
+    fn image_header_rejects_damage() {
+        let header = ImageHeader::new(Version::new(1, 0, 0), Slot::A.image_address(), &image(100)).encode();
+        assert_eq!(ImageHeader::parse(&[0xFF; HEADER_LEN]), Err(ImageError::BadMagic));
+        assert_eq!(ImageHeader::parse(&header[..HEADER_LEN - 1]), Err(ImageError::BadMagic));
+        for i in 4..HEADER_LEN {
+            let mut damaged = header;
+            damaged[i] ^= 0x10;
+            assert_eq!(ImageHeader::parse(&damaged), Err(ImageError::BadHeaderCrc), "byte {}", i);
+        }
+        let mut newer = header;
+        newer[4] = 2;
+        let crc = crc32(&newer[..56]);
+        newer[56..].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(ImageHeader::parse(&newer), Err(ImageError::UnsupportedFormat));
+    }
+
+    #[test]
+    fn image_header_verifies_the_image() {
+        let mut image = image(100);
+        let header = ImageHeader::new(Version::new(1, 0, 0), Slot::A.image_address(), &image);
+        assert_eq!(header.verify(&image[..99]), Err(ImageError::TooLarge));
+        assert_eq!(ImageHeader { sha256: [0; 32], ..header }.verify(&image), Err(ImageError::BadHash));
+        image[50] ^= 1;
+        assert_eq!(header.verify(&image), Err(ImageError::BadCrc));
+    }
+
+    #[test]
+    fn check_slot_accepts_a_valid_image() {
+        let contents = slot_contents(Slot::A, Version::new(1, 2, 3), &image(500));
+        let header = check_slot(Slot::A, &contents).unwrap();
+        assert_eq!(header.version, Version::new(1, 2, 3));
+        assert_eq!(header.length, 500);
+    }
+
+    #[test]
+    fn check_slot_rejects() {
+        let image = image(500);
+        let contents = slot_contents(Slot::A, Version::new(1, 0, 0), &image);
+        assert_eq!(check_slot(Slot::A, &[0xFF; 1024]), Err(ImageError::BadMagic));
+        assert_eq!(check_slot(Slot::B, &contents), Err(ImageError::WrongSlot));
+        // Cut short, as if the slot were smaller than the header says
+        assert_eq!(check_slot(Slot::A, &contents[..HEADER_SIZE as usize + 499]), Err(ImageError::TooLarge));
+        let mut huge = ImageHeader::new(Version::new(1, 0, 0), Slot::A.image_address(), &image);
+        huge.length = SLOT_SIZE;
+        let mut too_large = contents.clone();
+        too_large[..HEADER_LEN].copy_from_slice(&huge.encode());
+        assert_eq!(check_slot(Slot::A, &too_large), Err(ImageError::TooLarge));
+        let mut damaged = contents;
+        damaged[HEADER_SIZE as usize + 10] ^= 0x80;
+        assert_eq!(check_slot(Slot::A, &damaged), Err(ImageError::BadCrc));
+    }
+
+    #[test]
+    fn boot_record_round_trip() {
+        for state in [BootState::Confirmed, BootState::Pending, BootState::Testing] {
+            for (slot, previous) in [(Slot::A, Slot::B), (Slot::B, Slot::A)] {
+                for flags in [0, FLAG_ROLLED_BACK] {
+                    let record = BootRecord { state, slot, previous, flags };
+                    assert_eq!(BootRecord::parse(&record.encode()), Some(record));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn boot_record_rejects_damage() {
+        let words = BootRecord { state: BootState::Pending, slot: Slot::B, previous: Slot::A, flags: 0 }.encode();
+        assert_eq!(BootRecord::parse(&words[..STATE_RECORD_WORDS - 1]), None);
+        assert_eq!(BootRecord::parse(&[ERASED; STATE_RECORD_WORDS]), None);
+        for i in 0..STATE_RECORD_WORDS {
+            for bit in [0, 9, 31] {
+                let mut damaged = words;
+                damaged[i] ^= 1 << bit;
+                assert_eq!(BootRecord::parse(&damaged), None, "word {} bit {}", i, bit);
+            }
+        }
+        // Well-formed, but an unknown state or slot
+        for packed in [0x0000_0100, 0x0000_0204, 0x0000_0201, 0x0002_0001] {
+            let mut unknown = [STATE_MAGIC, packed, 0, 0];
+            unknown[3] = crc32_words(&unknown[..3]);
+            assert_eq!(BootRecord::parse(&unknown), None, "{:#010x}", packed);
+        }
+    }
+
+    #[test]
+    fn scan_state_finds_the_last_record_and_the_free_space() {
+        let first = BootRecord { state: BootState::Confirmed, slot: Slot::A, previous: Slot::B, flags: 0 };
+        let second = BootRecord { state: BootState::Pending, slot: Slot::B, previous: Slot::A, flags: 0 };
+        let mut log = vec![ERASED; 3 * STATE_RECORD_WORDS];
+        assert_eq!(scan_state(&log), (None, Some(0)));
+        log[..4].copy_from_slice(&first.encode());
+        assert_eq!(scan_state(&log), (Some(first), Some(4)));
+        log[4..8].copy_from_slice(&second.encode());
+        assert_eq!(scan_state(&log), (Some(second), Some(8)));
+        log[8..].copy_from_slice(&first.encode());
+        assert_eq!(scan_state(&log), (Some(first), None));
+    }
+
+    /// Flash of a simulated device: two slots and a small boot state log.
+    struct Device {
+        slots: [Vec<u8>; 2],
+        log: Vec<u32>,
+        /// Programming the log fails.
+        flash_fails: bool,
+    }
+
+    impl Device {
+        fn new() -> Self {
+            Device {
+                slots: [vec![0xFF; 1024], vec![0xFF; 1024]],
+                log: vec![ERASED; 4 * STATE_RECORD_WORDS],
+                flash_fails: false,
+            }
+        }
+
+        fn install(&mut self, slot: Slot, version: Version) {
+            self.slots[slot as usize] = slot_contents(slot, version, &image(600 + slot as usize));
+        }
+
+        /// Append a record the way the bootloader and the firmware do, erasing the full log.
+        fn append(&mut self, record: BootRecord) -> bool {
+            append(&mut self.log, self.flash_fails, record)
+        }
+
+        fn state(&self) -> Option<BootRecord> {
+            scan_state(&self.log).0
+        }
+
+        /// Reset: the slot the bootloader starts.
+        fn boot(&mut self) -> Option<Slot> {
+            let Device { slots, log, flash_fails } = self;
+            let version = |slot: Slot| check_slot(slot, &slots[slot as usize]).ok().map(|header| header.version);
+            choose_slot(scan_state(log).0, version, |record| append(log, *flash_fails, record))
+        }
+    }
+
+    fn append(log: &mut [u32], flash_fails: bool, record: BootRecord) -> bool {
+        if flash_fails {
+            return false;
+        }
+        let index = scan_state(log).1.unwrap_or_else(|| {
+            log.fill(ERASED);
+            0
+        });
+        log[index..index + STATE_RECORD_WORDS].copy_from_slice(&record.encode());
+        true
+    }
+
+    fn confirmed(slot: Slot) -> BootRecord {
+        BootRecord { state: BootState::Confirmed, slot, previous: slot.other(), flags: 0 }
+    }
+
+    fn pending(slot: Slot) -> BootRecord {
+        BootRecord { state: BootState::Pending, slot, previous: slot.other(), flags: 0 }
+    }
+
+    fn rolled_back(failed: Slot) -> BootRecord {
+        BootRecord { state: BootState::Confirmed, slot: failed.other(), previous: failed, flags: FLAG_ROLLED_BACK }
+    }
+
+    #[test]
+    fn normal_boot() {
+        let mut device = Device::new();
+        device.install(Slot::A, Version::new(1, 0, 0));
+        device.install(Slot::B, Version::new(2, 0, 0));
+        device.append(confirmed(Slot::A));
+        for _ in 0..3 {
+            assert_eq!(device.boot(), Some(Slot::A));
+        }
+        assert_eq!(scan_state(&device.log), (Some(confirmed(Slot::A)), Some(STATE_RECORD_WORDS)));
+    }
+
+    #[test]
+    fn first_boot_picks_the_highest_version() {
+        let mut device = Device::new();
+        assert_eq!(device.boot(), None);
+        device.install(Slot::B, Version::new(1, 0, 0));
+        assert_eq!(device.boot(), Some(Slot::B));
+        device.install(Slot::A, Version::new(1, 0, 1));
+        assert_eq!(device.boot(), Some(Slot::A));
+        assert_eq!(device.state(), None);
+    }
+
+    #[test]
+    fn test_boot_then_confirm() {
+        let mut device = Device::new();
+        device.install(Slot::A, Version::new(1, 0, 0));
+        device.append(confirmed(Slot::A));
+        device.install(Slot::B, Version::new(1, 1, 0));
+        device.append(pending(Slot::B));
+        assert_eq!(device.boot(), Some(Slot::B));
+        assert_eq!(device.state(), Some(BootRecord { state: BootState::Testing, ..pending(Slot::B) }));
+        // The new firmware runs its self-test and confirms
+        device.append(confirmed(Slot::B));
+        assert_eq!(device.boot(), Some(Slot::B));
+        assert_eq!(device.state(), Some(confirmed(Slot::B)));
+    }
+
+    #[test]
+    fn missing_confirm_rolls_back() {
+        let mut device = Device::new();
+        device.install(Slot::A, Version::new(1, 0, 0));
+        device.install(Slot::B, Version::new(1, 1, 0));
+        device.append(confirmed(Slot::A));
+        device.append(pending(Slot::B));
+        assert_eq!(device.boot(), Some(Slot::B));
+        // Reset before the new firmware confirmed
+        assert_eq!(device.boot(), Some(Slot::A));
+        assert_eq!(device.state(), Some(rolled_back(Slot::B)));
+        assert_eq!(device.boot(), Some(Slot::A));
+        assert_eq!(device.state(), Some(rolled_back(Slot::B)));
+    }
+
+    #[test]
+    fn test_boot_and_rollback_across_a_full_log() {
+        let mut device = Device::new();
+        device.install(Slot::A, Version::new(1, 0, 0));
+        device.install(Slot::B, Version::new(1, 1, 0));
+        device.append(confirmed(Slot::A));
+        device.append(confirmed(Slot::A));
+        device.append(confirmed(Slot::A));
+        device.append(pending(Slot::B));
+        assert_eq!(scan_state(&device.log).1, None);
+        // Testing goes at the start of the erased log
+        assert_eq!(device.boot(), Some(Slot::B));
+        let testing = BootRecord { state: BootState::Testing, ..pending(Slot::B) };
+        assert_eq!(scan_state(&device.log), (Some(testing), Some(STATE_RECORD_WORDS)));
+        assert_eq!(device.boot(), Some(Slot::A));
+        assert_eq!(device.state(), Some(rolled_back(Slot::B)));
+    }
+
+    #[test]
+    fn corrupt_header_in_the_other_slot() {
+        let mut device = Device::new();
+        device.install(Slot::A, Version::new(1, 0, 0));
+        device.install(Slot::B, Version::new(1, 1, 0));
+        device.slots[Slot::B as usize][20] ^= 0x01;
+        device.append(confirmed(Slot::A));
+        assert_eq!(device.boot(), Some(Slot::A));
+        assert_eq!(device.state(), Some(confirmed(Slot::A)));
+
+        // A pending test boot of the damaged image is rolled back at once
+        device.append(pending(Slot::B));
+        assert_eq!(device.boot(), Some(Slot::A));
+        assert_eq!(device.state(), Some(rolled_back(Slot::B)));
+
+        // The confirmed image itself damaged: the other slot runs, the log is left alone
+        device.install(Slot::B, Version::new(1, 1, 0));
+        device.append(confirmed(Slot::B));
+        device.slots[Slot::B as usize][HEADER_SIZE as usize] ^= 0x01;
+        assert_eq!(device.boot(), Some(Slot::A));
+        assert_eq!(device.state(), Some(confirmed(Slot::B)));
+    }
+
+    #[test]
+    fn torn_record_is_ignored() {
+        let mut device = Device::new();
+        device.install(Slot::A, Version::new(1, 0, 0));
+        device.install(Slot::B, Version::new(1, 1, 0));
+        device.append(confirmed(Slot::A));
+        // Power lost while the firmware was writing a pending test boot: CRC word still erased
+        let mut torn = pending(Slot::B).encode();
+        torn[3] = ERASED;
+        device.log[4..8].copy_from_slice(&torn);
+        assert_eq!(scan_state(&device.log), (Some(confirmed(Slot::A)), Some(8)));
+        assert_eq!(device.boot(), Some(Slot::A));
+        // The next record goes after the torn one
+        device.append(pending(Slot::B));
+        assert_eq!(device.log[8..12], pending(Slot::B).encode());
+        assert_eq!(device.boot(), Some(Slot::B));
+    }
+
+    #[test]
+    fn no_test_boot_without_a_testing_record() {
+        let mut device = Device::new();
+        device.install(Slot::A, Version::new(1, 0, 0));
+        device.install(Slot::B, Version::new(1, 1, 0));
+        device.append(confirmed(Slot::A));
+        device.append(pending(Slot::B));
+        device.flash_fails = true;
+        assert_eq!(device.boot(), Some(Slot::A));
+        assert_eq!(device.state(), Some(pending(Slot::B)));
+    }
+}
@@ -0,0 +1,8 @@
+MEMORY
+{
+/* Sectors 0-3; the boot state log and the firmware slots follow (see boot-format) */
+FLASH (rx)      : ORIGIN = 0x8000000, LENGTH = 64K
+/* Top of SRAM only: the application keeps data across resets (.noinit) lower in RAM, and its
+   own stacks, which hold nothing worth keeping, are up here */
+RAM (xrw)      : ORIGIN = 0x2001C000, LENGTH = 16K
+}
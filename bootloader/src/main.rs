@@ -0,0 +1,72 @@
+//! Bootloader: picks the firmware slot to run and starts it.
+//!
+//! Runs from flash sectors 0–3 with the reset clock (16 MHz HSI) and no interrupts. It reads the
+//! boot state log, validates the image of the chosen slot (header, CRC-32 and SHA-256, see
+//! `boot-format`) and jumps to it. A pending test boot is recorded as `Testing` before the new
+//! image is started; finding `Testing` again at the next reset means the image never confirmed,
+//! and the bootloader rolls back to the previous slot. With no state recorded (first boot, or the
+//! log was being erased), the valid image with the highest version runs. The decision itself is
+//! `boot_format::choose_slot`, tested on the host.
+//!
+//! When no slot holds a valid image the bootloader stops; the firmware has to be programmed with
+//! a debug probe.
+
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use cortex_m_rt::entry;
+use boot_format::{
+    check_slot, choose_slot, scan_state, BootRecord, Slot, Version, BOOT_STATE_ADDRESS, BOOT_STATE_SECTOR,
+    BOOT_STATE_SIZE, SLOT_SIZE,
+};
+use drivers::cortex_m4::start_image;
+use drivers::flash::{flash_erase_sector, flash_lock, flash_program_words, flash_unlock, FlashError};
+
+fn slot_contents(slot: Slot) -> &'static [u8] {
+    unsafe { core::slice::from_raw_parts(slot.address() as *const u8, SLOT_SIZE as usize) }
+}
+
+fn state_log() -> &'static [u32] {
+    unsafe { core::slice::from_raw_parts(BOOT_STATE_ADDRESS as *const u32, BOOT_STATE_SIZE as usize / 4) }
+}
+
+/// Version of the valid image in `slot`.
+fn valid_image(slot: Slot) -> Option<Version> {
+    check_slot(slot, slot_contents(slot)).ok().map(|header| header.version)
+}
+
+/// Append `record` to the boot state log, erasing the log first when it is full.
+fn record_state(record: BootRecord) -> Result<(), FlashError> {
+    let (_, next) = scan_state(state_log());
+    flash_unlock();
+    let result = match next {
+        Some(index) => Ok(index),
+        None => flash_erase_sector(BOOT_STATE_SECTOR).map(|_| 0),
+    }
+    .and_then(|index| flash_program_words(BOOT_STATE_ADDRESS + 4 * index as u32, &record.encode()));
+    flash_lock();
+    result
+}
+
+#[entry]
+fn main() -> ! {
+    let (state, _) = scan_state(state_log());
+    match choose_slot(state, valid_image, |record| record_state(record).is_ok()) {
+        Some(slot) => unsafe { start_image(slot.image_address()) },
+        None => loop {
+            wait_for_interrupt();
+        },
+    }
+}
+
+fn wait_for_interrupt() {
+    unsafe { core::arch::asm!("wfi", options(nomem, nostack, preserves_flags)) };
+}
+
+#[panic_handler]
+fn panic_handler(_info: &PanicInfo) -> ! {
+    loop {
+        wait_for_interrupt();
+    }
+}
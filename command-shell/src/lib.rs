@@ -0,0 +1,509 @@
+//! Line editor, history and command dispatcher of the interactive shell.
+//!
+//! `Shell` is driven byte by byte: every received byte is passed to `Shell::feed` together with an
+//! output sink (any `core::fmt::Write`). The crate has no hardware or kernel dependency, so the
+//! kernel runs it on a serial port (kernel `shell` module) and a host program can feed it a byte
+//! stream and collect the output in a `String`.
+//!
+//! `feed` returns each completed line instead of running it: the caller runs it (`Shell::execute`,
+//! or `Shell::command` then `run_command`) and prints the next prompt. A shell shared between tasks
+//! thus only needs to be locked to look the command up, not while it runs.
+//!
+//! Line editing: printable characters are echoed, Backspace/DEL erase, Ctrl-U clears the line,
+//! Ctrl-C aborts it, and the Up/Down arrow keys walk through the last `HISTORY_DEPTH` lines.
+//! Lines end with CR, LF or CRLF.
+//!
+//! Commands: the table given to `Shell::new` (the kernel built-ins) and up to `MAX_COMMANDS` added
+//! with `Shell::register`. `help` lists them all.
+
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt::Write;
+
+/// Longest accepted command line.
+pub const MAX_LINE: usize = 80;
+/// Number of lines kept in the history.
+pub const HISTORY_DEPTH: usize = 8;
+/// Maximum number of whitespace-separated arguments, including the command name.
+pub const MAX_ARGS: usize = 8;
+/// Maximum number of commands added with `Shell::register`.
+pub const MAX_COMMANDS: usize = 16;
+
+pub const PROMPT: &str = "> ";
+
+/// Result of a command: `Err` carries a message printed as `error: <msg>`.
+pub type CommandResult = Result<(), &'static str>;
+
+/// Command entry point. `args[0]` is the command name.
+pub type CommandHandler = fn(args: &[&str], out: &mut dyn Write) -> CommandResult;
+
+/// A named shell command.
+#[derive(Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub handler: CommandHandler,
+}
+
+#[derive(Clone, Copy)]
+struct Line {
+    buf: [u8; MAX_LINE],
+    len: usize,
+}
+
+impl Line {
+    const fn new() -> Self {
+        Line { buf: [0; MAX_LINE], len: 0 }
+    }
+
+    fn as_str(&self) -> &str {
+        // Only printable ASCII is ever stored.
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    Esc,
+    Csi,
+}
+
+/// A non-empty command line completed by `Shell::feed`.
+#[derive(Clone, Copy)]
+pub struct CommandLine(Line);
+
+impl CommandLine {
+    /// The line without leading and trailing whitespace.
+    pub fn as_str(&self) -> &str {
+        self.0.as_str().trim()
+    }
+}
+
+/// Line editor, history and command table.
+pub struct Shell {
+    builtins: &'static [Command],
+    commands: [Option<Command>; MAX_COMMANDS],
+    line: Line,
+    history: [Line; HISTORY_DEPTH],
+    history_len: usize,
+    history_next: usize,
+    browse: Option<usize>, // 0 = most recent history entry
+    escape: Escape,
+    last_was_cr: bool,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new(&[])
+    }
+}
+
+impl Shell {
+    /// A shell running `builtins` in addition to the commands registered later.
+    pub const fn new(builtins: &'static [Command]) -> Self {
+        Shell {
+            builtins,
+            commands: [None; MAX_COMMANDS],
+            line: Line::new(),
+            history: [Line::new(); HISTORY_DEPTH],
+            history_len: 0,
+            history_next: 0,
+            browse: None,
+            escape: Escape::None,
+            last_was_cr: false,
+        }
+    }
+
+    /// Add an application command. Returns `false` if the table is full or the name is taken.
+    pub fn register(&mut self, command: Command) -> bool {
+        if self.find(command.name).is_some() {
+            return false;
+        }
+        match self.commands.iter_mut().find(|c| c.is_none()) {
+            Some(slot) => {
+                *slot = Some(command);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<Command> {
+        self.commands
+            .iter()
+            .flatten()
+            .chain(self.builtins.iter())
+            .find(|c| c.name == name)
+            .copied()
+    }
+
+    pub fn prompt(&self, out: &mut dyn Write) {
+        let _ = out.write_str(PROMPT);
+    }
+
+    /// Process one received byte, echoing through `out`. Returns the line it completes, to be run
+    /// before printing the next prompt.
+    pub fn feed(&mut self, byte: u8, out: &mut dyn Write) -> Option<CommandLine> {
+        let after_cr = self.last_was_cr;
+        self.last_was_cr = byte == b'\r';
+
+        match self.escape {
+            Escape::Esc => {
+                self.escape = if byte == b'[' { Escape::Csi } else { Escape::None };
+                return None;
+            }
+            Escape::Csi => {
+                // Parameter bytes (digits, ';') precede the final byte of the sequence.
+                if (0x40..=0x7E).contains(&byte) {
+                    self.escape = Escape::None;
+                    match byte {
+                        b'A' => self.history_up(out),
+                        b'B' => self.history_down(out),
+                        _ => {}
+                    }
+                }
+                return None;
+            }
+            Escape::None => {}
+        }
+
+        match byte {
+            0x1B => self.escape = Escape::Esc,
+            b'\n' if after_cr => {}
+            b'\r' | b'\n' => {
+                let _ = out.write_str("\n");
+                let line = self.line;
+                self.line.len = 0;
+                self.browse = None;
+                if line.as_str().trim().is_empty() {
+                    self.prompt(out);
+                } else {
+                    self.push_history(&line);
+                    return Some(CommandLine(line));
+                }
+            }
+            0x08 | 0x7F if self.line.len > 0 => {
+                self.line.len -= 1;
+                let _ = out.write_str("\x08 \x08");
+            }
+            0x03 => {
+                // Ctrl-C
+                self.line.len = 0;
+                self.browse = None;
+                let _ = out.write_str("^C\n");
+                self.prompt(out);
+            }
+            0x15 => {
+                // Ctrl-U
+                self.line.len = 0;
+                self.redraw(out);
+            }
+            0x20..=0x7E if self.line.len < MAX_LINE => {
+                self.line.buf[self.line.len] = byte;
+                self.line.len += 1;
+                let _ = out.write_char(byte as char);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Parse and run one command line.
+    pub fn execute(&self, line: &str, out: &mut dyn Write) {
+        if let Some(command) = self.command(line, out) {
+            run_command(command, line, out);
+        }
+    }
+
+    /// The command `line` names, to run with `run_command`. Answers `help` itself, and reports a
+    /// line that cannot be parsed or names no command, returning `None` for them.
+    pub fn command(&self, line: &str, out: &mut dyn Write) -> Option<Command> {
+        let mut args = [""; MAX_ARGS];
+        let name = match parse_args(line, &mut args) {
+            Ok(0) => return None,
+            Ok(_) => args[0],
+            Err(msg) => {
+                let _ = writeln!(out, "error: {}", msg);
+                return None;
+            }
+        };
+        if name == "help" {
+            self.print_help(out);
+            return None;
+        }
+        let command = self.find(name);
+        if command.is_none() {
+            let _ = writeln!(out, "unknown command: {} (try `help`)", name);
+        }
+        command
+    }
+
+    fn print_help(&self, out: &mut dyn Write) {
+        let _ = writeln!(out, "{:<8} list commands", "help");
+        for command in self.builtins.iter().chain(self.commands.iter().flatten()) {
+            let _ = writeln!(out, "{:<8} {}", command.name, command.help);
+        }
+    }
+
+    fn push_history(&mut self, line: &Line) {
+        if self.history_len > 0 {
+            let newest = (self.history_next + HISTORY_DEPTH - 1) % HISTORY_DEPTH;
+            if self.history[newest].as_str() == line.as_str() {
+                return;
+            }
+        }
+        self.history[self.history_next] = *line;
+        self.history_next = (self.history_next + 1) % HISTORY_DEPTH;
+        self.history_len = (self.history_len + 1).min(HISTORY_DEPTH);
+    }
+
+    fn history_entry(&self, age: usize) -> Line {
+        let idx = (self.history_next + HISTORY_DEPTH - 1 - age) % HISTORY_DEPTH;
+        self.history[idx]
+    }
+
+    fn history_up(&mut self, out: &mut dyn Write) {
+        if self.history_len == 0 {
+            return;
+        }
+        let age = match self.browse {
+            None => 0,
+            Some(age) => (age + 1).min(self.history_len - 1),
+        };
+        self.browse = Some(age);
+        self.line = self.history_entry(age);
+        self.redraw(out);
+    }
+
+    fn history_down(&mut self, out: &mut dyn Write) {
+        match self.browse {
+            None => return,
+            Some(0) => {
+                self.browse = None;
+                self.line.len = 0;
+            }
+            Some(age) => {
+                self.browse = Some(age - 1);
+                self.line = self.history_entry(age - 1);
+            }
+        }
+        self.redraw(out);
+    }
+
+    /// Erase the terminal line and print the prompt and current line again.
+    fn redraw(&self, out: &mut dyn Write) {
+        let _ = out.write_str("\r\x1b[K");
+        self.prompt(out);
+        let _ = out.write_str(self.line.as_str());
+    }
+}
+
+/// Run `command` with the arguments of `line` (as checked by `Shell::command`), reporting its error.
+pub fn run_command(command: Command, line: &str, out: &mut dyn Write) {
+    let mut args = [""; MAX_ARGS];
+    let Ok(argc @ 1..) = parse_args(line, &mut args) else {
+        return;
+    };
+    if let Err(msg) = (command.handler)(&args[..argc], out) {
+        let _ = writeln!(out, "error: {}", msg);
+    }
+}
+
+/// Split `line` on whitespace into `args`. Returns the number of arguments.
+pub fn parse_args<'a>(line: &'a str, args: &mut [&'a str; MAX_ARGS]) -> Result<usize, &'static str> {
+    let mut argc = 0;
+    for word in line.split_whitespace() {
+        if argc == MAX_ARGS {
+            return Err("too many arguments");
+        }
+        args[argc] = word;
+        argc += 1;
+    }
+    Ok(argc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UP: &[u8] = b"\x1b[A";
+    const DOWN: &[u8] = b"\x1b[B";
+    const REDRAW: &str = "\r\x1b[K> ";
+
+    fn cmd_echo(args: &[&str], out: &mut dyn Write) -> CommandResult {
+        let _ = writeln!(out, "{}", args[1..].join(" "));
+        Ok(())
+    }
+
+    fn cmd_fail(_args: &[&str], _out: &mut dyn Write) -> CommandResult {
+        Err("it failed")
+    }
+
+    const BUILTINS: [Command; 2] = [
+        Command { name: "echo", help: "print the arguments", handler: cmd_echo },
+        Command { name: "fail", help: "always fails", handler: cmd_fail },
+    ];
+
+    /// Feed `input` to `shell`, running the lines it completes, and return what it wrote.
+    fn feed(shell: &mut Shell, input: &[u8]) -> String {
+        let mut out = String::new();
+        for &byte in input {
+            if let Some(line) = shell.feed(byte, &mut out) {
+                shell.execute(line.as_str(), &mut out);
+                shell.prompt(&mut out);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn runs_a_command_on_cr_lf_and_crlf() {
+        let mut shell = Shell::new(&BUILTINS);
+        assert_eq!(feed(&mut shell, b"echo a\r"), "echo a\na\n> ");
+        assert_eq!(feed(&mut shell, b"echo b\n"), "echo b\nb\n> ");
+        // The LF of a CRLF does not end a second, empty line
+        assert_eq!(feed(&mut shell, b"echo c\r\necho d\r\n"), "echo c\nc\n> echo d\nd\n> ");
+        // An empty line only prints a new prompt
+        assert_eq!(feed(&mut shell, b"\r\n  \n"), "\n>   \n> ");
+    }
+
+    #[test]
+    fn feed_returns_the_line_without_running_it() {
+        let mut shell = Shell::new(&BUILTINS);
+        let mut out = String::new();
+        let lines: Vec<_> = b"  echo  hi \r".iter().filter_map(|&byte| shell.feed(byte, &mut out)).collect();
+        assert_eq!(out, "  echo  hi \n");
+        let [line] = &lines[..] else { panic!("one line expected") };
+        assert_eq!(line.as_str(), "echo  hi");
+
+        // Looked up first, run afterwards (in the kernel, without the shell locked)
+        let command = shell.command(line.as_str(), &mut out).unwrap();
+        assert_eq!(command.name, "echo");
+        run_command(command, line.as_str(), &mut out);
+        assert_eq!(out, "  echo  hi \nhi\n");
+
+        // `help`, unknown commands and parse errors are answered by the lookup
+        assert!(shell.command("help", &mut out).is_none());
+        assert!(shell.command("nope", &mut out).is_none());
+        assert!(shell.command("   ", &mut out).is_none());
+    }
+
+    #[test]
+    fn splits_arguments_on_whitespace() {
+        let mut shell = Shell::new(&BUILTINS);
+        assert_eq!(feed(&mut shell, b"  echo   one  two  \r"), "  echo   one  two  \none two\n> ");
+    }
+
+    #[test]
+    fn backspace_and_del_erase_the_last_character() {
+        let mut shell = Shell::new(&BUILTINS);
+        let out = feed(&mut shell, b"echo abx\x08c\x7Fd\r");
+        assert_eq!(out, "echo abx\x08 \x08c\x08 \x08d\nabd\n> ");
+        // Nothing to erase on an empty line
+        assert_eq!(feed(&mut shell, b"\x08\x7F"), "");
+    }
+
+    #[test]
+    fn ctrl_u_clears_the_line() {
+        let mut shell = Shell::new(&BUILTINS);
+        let out = feed(&mut shell, b"garbage\x15echo ok\r");
+        assert_eq!(out, format!("garbage{}echo ok\nok\n> ", REDRAW));
+    }
+
+    #[test]
+    fn ctrl_c_aborts_the_line() {
+        let mut shell = Shell::new(&BUILTINS);
+        assert_eq!(feed(&mut shell, b"echo never\x03"), "echo never^C\n> ");
+        assert_eq!(feed(&mut shell, b"\r"), "\n> ");
+        // The aborted line is not in the history
+        assert_eq!(feed(&mut shell, UP), "");
+    }
+
+    #[test]
+    fn arrows_walk_through_the_history() {
+        let mut shell = Shell::new(&BUILTINS);
+        feed(&mut shell, b"echo 1\recho 2\recho 2\recho 3\r");
+
+        assert_eq!(feed(&mut shell, UP), format!("{}echo 3", REDRAW));
+        // A repeated line is stored once
+        assert_eq!(feed(&mut shell, UP), format!("{}echo 2", REDRAW));
+        assert_eq!(feed(&mut shell, UP), format!("{}echo 1", REDRAW));
+        // Stays on the oldest entry
+        assert_eq!(feed(&mut shell, UP), format!("{}echo 1", REDRAW));
+        assert_eq!(feed(&mut shell, DOWN), format!("{}echo 2", REDRAW));
+        assert_eq!(feed(&mut shell, b"0\r"), "0\n20\n> ");
+
+        // Down past the newest entry gives an empty line
+        feed(&mut shell, UP);
+        assert_eq!(feed(&mut shell, DOWN), REDRAW);
+        assert_eq!(feed(&mut shell, DOWN), "");
+    }
+
+    #[test]
+    fn history_keeps_the_last_lines() {
+        let mut shell = Shell::new(&BUILTINS);
+        for i in 0..HISTORY_DEPTH + 2 {
+            feed(&mut shell, format!("echo {}\r", i).as_bytes());
+        }
+        for _ in 0..HISTORY_DEPTH + 2 {
+            feed(&mut shell, UP);
+        }
+        assert_eq!(feed(&mut shell, b"\r"), "\n2\n> ");
+    }
+
+    #[test]
+    fn ignores_other_escape_sequences_and_control_bytes() {
+        let mut shell = Shell::new(&BUILTINS);
+        assert_eq!(feed(&mut shell, b"echo \x1b[1;5Cx\x1bOy\x01\r"), "echo xy\nxy\n> ");
+    }
+
+    #[test]
+    fn truncates_long_lines() {
+        let mut shell = Shell::new(&BUILTINS);
+        let mut input = b"echo ".to_vec();
+        input.resize(MAX_LINE + 10, b'x');
+        let out = feed(&mut shell, &input);
+        assert_eq!(out.len(), MAX_LINE);
+    }
+
+    #[test]
+    fn rejects_too_many_arguments() {
+        let mut shell = Shell::new(&BUILTINS);
+        assert_eq!(feed(&mut shell, b"echo 1 2 3 4 5 6 7\r"), "echo 1 2 3 4 5 6 7\n1 2 3 4 5 6 7\n> ");
+        assert_eq!(feed(&mut shell, b"echo 1 2 3 4 5 6 7 8\r"), "echo 1 2 3 4 5 6 7 8\nerror: too many arguments\n> ");
+
+        let mut args = [""; MAX_ARGS];
+        assert_eq!(parse_args("a b  c", &mut args), Ok(3));
+        assert_eq!(args[..3], ["a", "b", "c"]);
+        assert_eq!(parse_args(" \t ", &mut args), Ok(0));
+    }
+
+    #[test]
+    fn reports_unknown_commands_and_command_errors() {
+        let mut shell = Shell::new(&BUILTINS);
+        assert_eq!(feed(&mut shell, b"nope 1\r"), "nope 1\nunknown command: nope (try `help`)\n> ");
+        assert_eq!(feed(&mut shell, b"fail\r"), "fail\nerror: it failed\n> ");
+    }
+
+    #[test]
+    fn registers_commands_and_lists_them_in_help() {
+        let mut shell = Shell::new(&BUILTINS);
+        assert!(shell.register(Command { name: "extra", help: "added later", handler: cmd_echo }));
+        // Names are unique, built-ins included
+        assert!(!shell.register(Command { name: "echo", help: "", handler: cmd_fail }));
+        assert!(!shell.register(Command { name: "extra", help: "", handler: cmd_fail }));
+
+        assert_eq!(feed(&mut shell, b"extra x\r"), "extra x\nx\n> ");
+        assert_eq!(
+            feed(&mut shell, b"help\r"),
+            "help\nhelp     list commands\necho     print the arguments\nfail     always fails\nextra    added later\n> "
+        );
+
+        for i in 1..MAX_COMMANDS {
+            let name: &'static str = format!("c{}", i).leak();
+            assert!(shell.register(Command { name, help: "", handler: cmd_echo }));
+        }
+        assert!(!shell.register(Command { name: "full", help: "", handler: cmd_echo }));
+    }
+}
@@ -0,0 +1,660 @@
+//! Crash record format shared by the firmware and the host tools.
+//!
+//! A crash record is a fixed block of `RECORD_WORDS` little-endian 32-bit words, written word by
+//! word into flash by the kernel crash log and read back on the device (shell `crash` command) or
+//! on a host (`tools/crashdump`) from a dump of the flash sector. Records are never decoded into a
+//! large structure: `RecordBuilder` fills a word buffer in place and `Record` is a view over the
+//! stored words, so both fit in the small stacks of the firmware.
+//!
+//! Word layout:
+//!
+//! | Words   | Content                                                        |
+//! |---------|----------------------------------------------------------------|
+//! | 0       | `RECORD_MAGIC`                                                 |
+//! | 1       | sequence number                                                |
+//! | 2–6     | fault kind, task, flags, EXC_RETURN, stack pointer             |
+//! | 7–14    | stacked R0, R1, R2, R3, R12, LR, PC, xPSR                      |
+//! | 15–19   | CFSR, HFSR, MMFAR, BFAR, tick                                  |
+//! | 20–84   | trace event count, `TRACE_EVENTS` × (tick, kind/task/argument) |
+//! | 85–253  | stack count, `STACK_SLOTS` × `StackSnapshot`                   |
+//! | 255     | CRC-32 of words 0–254                                          |
+//!
+//! The magic is written first and the CRC last: a record interrupted by a power failure occupies
+//! its slot but fails the CRC check.
+
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt;
+
+/// Size of a record in words and bytes.
+pub const RECORD_WORDS: usize = 256;
+pub const RECORD_SIZE: usize = RECORD_WORDS * 4;
+
+/// First word of every record.
+pub const RECORD_MAGIC: u32 = 0xC4A5_4106;
+
+/// Value of erased flash.
+pub const ERASED: u32 = 0xFFFF_FFFF;
+
+/// Trace events kept in a record (the most recent ones).
+pub const TRACE_EVENTS: usize = 32;
+
+/// Tasks whose stack is captured.
+pub const STACK_SLOTS: usize = 8;
+
+/// Words captured from the top of each task stack.
+pub const STACK_WORDS: usize = 16;
+
+/// Bytes of task name stored per stack snapshot.
+pub const NAME_LEN: usize = 8;
+
+const FAULT_OFFSET: usize = 2;
+const FAULT_WORDS: usize = 18;
+const TRACE_OFFSET: usize = FAULT_OFFSET + FAULT_WORDS;
+const STACKS_OFFSET: usize = TRACE_OFFSET + 1 + 2 * TRACE_EVENTS;
+const SNAPSHOT_WORDS: usize = 5 + STACK_WORDS;
+const CRC_OFFSET: usize = RECORD_WORDS - 1;
+
+const _: () = assert!(STACKS_OFFSET + 1 + STACK_SLOTS * SNAPSHOT_WORDS <= CRC_OFFSET);
+
+// Flags word
+pub const FLAG_ON_PSP: u32 = 1 << 0;
+pub const FLAG_FRAME_VALID: u32 = 1 << 1;
+pub const FLAG_MMFAR_VALID: u32 = 1 << 2;
+pub const FLAG_BFAR_VALID: u32 = 1 << 3;
+
+/// Kernel event recorded in the trace ring.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceKind {
+    /// Context switch; argument: task switched to.
+    Switch = 1,
+    /// Task blocked; argument: timeout in ticks (saturated).
+    Block = 2,
+    /// Task made ready; argument: task woken.
+    Wake = 3,
+    /// Task killed; argument: task killed.
+    Kill = 4,
+    /// Application event; argument chosen by the application.
+    User = 5,
+}
+
+impl TraceKind {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(TraceKind::Switch),
+            2 => Some(TraceKind::Block),
+            3 => Some(TraceKind::Wake),
+            4 => Some(TraceKind::Kill),
+            5 => Some(TraceKind::User),
+            _ => None,
+        }
+    }
+}
+
+/// One trace event: what happened, in which task, at which tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TraceEvent {
+    pub tick: u32,
+    /// `TraceKind` value.
+    pub kind: u8,
+    /// Task running when the event was recorded.
+    pub task: u8,
+    pub arg: u16,
+}
+
+impl TraceEvent {
+    pub const EMPTY: TraceEvent = TraceEvent { tick: 0, kind: 0, task: 0, arg: 0 };
+
+    fn to_words(self) -> [u32; 2] {
+        [self.tick, self.kind as u32 | (self.task as u32) << 8 | (self.arg as u32) << 16]
+    }
+
+    fn from_words(words: &[u32]) -> Self {
+        TraceEvent {
+            tick: words[0],
+            kind: words[1] as u8,
+            task: (words[1] >> 8) as u8,
+            arg: (words[1] >> 16) as u16,
+        }
+    }
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{:>8}] task {} ", self.tick, self.task)?;
+        match TraceKind::from_u8(self.kind) {
+            Some(TraceKind::Switch) => write!(f, "switch to task {}", self.arg),
+            Some(TraceKind::Block) => write!(f, "block for {} ticks", self.arg),
+            Some(TraceKind::Wake) => write!(f, "wake task {}", self.arg),
+            Some(TraceKind::Kill) => write!(f, "kill task {}", self.arg),
+            Some(TraceKind::User) => write!(f, "user event {:#06x}", self.arg),
+            None => write!(f, "event {} ({:#06x})", self.kind, self.arg),
+        }
+    }
+}
+
+/// What the fault handler found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FaultInfo {
+    /// 0 HardFault, 1 MemManage, 2 BusFault, 3 UsageFault.
+    pub kind: u32,
+    pub task: u32,
+    /// `FLAG_*` bits.
+    pub flags: u32,
+    pub exc_return: u32,
+    pub sp: u32,
+    /// Stacked R0, R1, R2, R3, R12, LR, PC, xPSR.
+    pub frame: [u32; 8],
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+    pub tick: u32,
+}
+
+impl FaultInfo {
+    pub fn pc(&self) -> u32 {
+        self.frame[6]
+    }
+
+    pub fn lr(&self) -> u32 {
+        self.frame[5]
+    }
+
+    /// Name of the fault exception.
+    pub fn kind_name(&self) -> &'static str {
+        match self.kind {
+            0 => "HardFault",
+            1 => "MemManage",
+            2 => "BusFault",
+            3 => "UsageFault",
+            _ => "unknown fault",
+        }
+    }
+
+    fn write_words(&self, out: &mut [u32]) {
+        out[..5].copy_from_slice(&[self.kind, self.task, self.flags, self.exc_return, self.sp]);
+        out[5..13].copy_from_slice(&self.frame);
+        out[13..].copy_from_slice(&[self.cfsr, self.hfsr, self.mmfar, self.bfar, self.tick]);
+    }
+
+    fn from_words(words: &[u32]) -> Self {
+        let mut frame = [0u32; 8];
+        frame.copy_from_slice(&words[5..13]);
+        FaultInfo {
+            kind: words[0],
+            task: words[1],
+            flags: words[2],
+            exc_return: words[3],
+            sp: words[4],
+            frame,
+            cfsr: words[13],
+            hfsr: words[14],
+            mmfar: words[15],
+            bfar: words[16],
+            tick: words[17],
+        }
+    }
+}
+
+/// Top of a task stack at the time of the crash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StackSnapshot {
+    pub task: u32,
+    /// Task name, zero-padded (truncated to `NAME_LEN` bytes).
+    pub name: [u8; NAME_LEN],
+    /// Stack pointer (saved PSP, or the live one for the faulting task).
+    pub sp: u32,
+    /// Number of valid entries in `words`.
+    pub depth: u32,
+    /// Words from `sp` upwards.
+    pub words: [u32; STACK_WORDS],
+}
+
+impl StackSnapshot {
+    /// Snapshot of `task` named `name`, with the words found at `sp`.
+    pub fn new(task: u32, name: &str, sp: u32, stack: &[u32]) -> Self {
+        let mut snapshot = StackSnapshot { task, sp, ..Default::default() };
+        let len = name.len().min(NAME_LEN);
+        snapshot.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        let depth = stack.len().min(STACK_WORDS);
+        snapshot.words[..depth].copy_from_slice(&stack[..depth]);
+        snapshot.depth = depth as u32;
+        snapshot
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+
+    /// The captured words.
+    pub fn stack(&self) -> &[u32] {
+        &self.words[..(self.depth as usize).min(STACK_WORDS)]
+    }
+
+    fn write_words(&self, out: &mut [u32]) {
+        out[0] = self.task;
+        out[1] = u32::from_le_bytes([self.name[0], self.name[1], self.name[2], self.name[3]]);
+        out[2] = u32::from_le_bytes([self.name[4], self.name[5], self.name[6], self.name[7]]);
+        out[3] = self.sp;
+        out[4] = self.depth;
+        out[5..].copy_from_slice(&self.words);
+    }
+
+    fn from_words(words: &[u32]) -> Self {
+        let mut name = [0u8; NAME_LEN];
+        name[..4].copy_from_slice(&words[1].to_le_bytes());
+        name[4..].copy_from_slice(&words[2].to_le_bytes());
+        let mut stack = [0u32; STACK_WORDS];
+        stack.copy_from_slice(&words[5..SNAPSHOT_WORDS]);
+        StackSnapshot { task: words[0], name, sp: words[3], depth: words[4].min(STACK_WORDS as u32), words: stack }
+    }
+}
+
+impl fmt::Display for StackSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stack of task {} ({}) at {:#010x}:", self.task, self.name(), self.sp)?;
+        for (i, word) in self.stack().iter().enumerate() {
+            if i % 4 == 0 {
+                write!(f, "\n   ")?;
+            }
+            write!(f, " {:08x}", word)?;
+        }
+        Ok(())
+    }
+}
+
+// ---------- Writing ----------
+
+/// Fills a word buffer with a record, in place.
+pub struct RecordBuilder<'a> {
+    words: &'a mut [u32; RECORD_WORDS],
+    trace_len: usize,
+    stack_count: usize,
+}
+
+impl<'a> RecordBuilder<'a> {
+    /// Start record number `seq` for `fault` in `words`.
+    pub fn new(words: &'a mut [u32; RECORD_WORDS], seq: u32, fault: &FaultInfo) -> Self {
+        words.fill(0);
+        words[0] = RECORD_MAGIC;
+        words[1] = seq;
+        fault.write_words(&mut words[FAULT_OFFSET..TRACE_OFFSET]);
+        RecordBuilder { words, trace_len: 0, stack_count: 0 }
+    }
+
+    /// Append a trace event (oldest first). Returns `false` once `TRACE_EVENTS` are stored.
+    pub fn push_trace(&mut self, event: TraceEvent) -> bool {
+        if self.trace_len == TRACE_EVENTS {
+            return false;
+        }
+        let at = TRACE_OFFSET + 1 + 2 * self.trace_len;
+        self.words[at..at + 2].copy_from_slice(&event.to_words());
+        self.trace_len += 1;
+        self.words[TRACE_OFFSET] = self.trace_len as u32;
+        true
+    }
+
+    /// Append a stack snapshot. Returns `false` once `STACK_SLOTS` are stored.
+    pub fn push_stack(&mut self, stack: &StackSnapshot) -> bool {
+        if self.stack_count == STACK_SLOTS {
+            return false;
+        }
+        let at = STACKS_OFFSET + 1 + SNAPSHOT_WORDS * self.stack_count;
+        stack.write_words(&mut self.words[at..at + SNAPSHOT_WORDS]);
+        self.stack_count += 1;
+        self.words[STACKS_OFFSET] = self.stack_count as u32;
+        true
+    }
+
+    /// Seal the record with its CRC.
+    pub fn finish(self) {
+        self.words[CRC_OFFSET] = crc32(&self.words[..CRC_OFFSET]);
+    }
+}
+
+// ---------- Reading ----------
+
+/// Why a block of words is not a valid record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Erased flash: no record here.
+    Empty,
+    /// Not a crash record.
+    BadMagic,
+    /// Incomplete or corrupted record.
+    BadCrc,
+    /// Fewer than `RECORD_WORDS` words.
+    Truncated,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DecodeError::Empty => "empty",
+            DecodeError::BadMagic => "not a crash record",
+            DecodeError::BadCrc => "corrupted (bad CRC)",
+            DecodeError::Truncated => "truncated",
+        })
+    }
+}
+
+/// A validated record, read in place from its words.
+#[derive(Clone, Copy)]
+pub struct Record<'a> {
+    words: &'a [u32],
+}
+
+impl<'a> Record<'a> {
+    /// Check the magic and CRC of the record starting at `words[0]`.
+    pub fn parse(words: &'a [u32]) -> Result<Self, DecodeError> {
+        if words.len() < RECORD_WORDS {
+            return Err(DecodeError::Truncated);
+        }
+        if words[0] == ERASED {
+            return Err(DecodeError::Empty);
+        }
+        if words[0] != RECORD_MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        if crc32(&words[..CRC_OFFSET]) != words[CRC_OFFSET] {
+            return Err(DecodeError::BadCrc);
+        }
+        Ok(Record { words: &words[..RECORD_WORDS] })
+    }
+
+    /// The raw words of the record.
+    pub fn words(&self) -> &'a [u32] {
+        self.words
+    }
+
+    /// Increases by one with every record written since the log was erased.
+    pub fn seq(&self) -> u32 {
+        self.words[1]
+    }
+
+    pub fn fault(&self) -> FaultInfo {
+        FaultInfo::from_words(&self.words[FAULT_OFFSET..TRACE_OFFSET])
+    }
+
+    /// Trace events, oldest first.
+    pub fn trace(&self) -> impl Iterator<Item = TraceEvent> + 'a {
+        let len = (self.words[TRACE_OFFSET] as usize).min(TRACE_EVENTS);
+        self.words[TRACE_OFFSET + 1..TRACE_OFFSET + 1 + 2 * len].chunks(2).map(TraceEvent::from_words)
+    }
+
+    pub fn stacks(&self) -> impl Iterator<Item = StackSnapshot> + 'a {
+        let count = (self.words[STACKS_OFFSET] as usize).min(STACK_SLOTS);
+        self.words[STACKS_OFFSET + 1..STACKS_OFFSET + 1 + SNAPSHOT_WORDS * count]
+            .chunks(SNAPSHOT_WORDS)
+            .map(StackSnapshot::from_words)
+    }
+
+    /// Stack snapshot of the faulting task, if it was captured.
+    pub fn task_stack(&self) -> Option<StackSnapshot> {
+        let task = self.fault().task;
+        self.stacks().find(|s| s.task == task)
+    }
+
+    /// One-line summary: sequence number, fault, task and PC.
+    pub fn summary(&self) -> Summary<'a> {
+        Summary(*self)
+    }
+}
+
+/// One-line description of a record (see `Record::summary`).
+pub struct Summary<'a>(Record<'a>);
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fault = self.0.fault();
+        write!(f, "#{} {} in task {}", self.0.seq(), fault.kind_name(), fault.task)?;
+        if let Some(stack) = self.0.task_stack() {
+            write!(f, " ({})", stack.name())?;
+        }
+        write!(f, " at tick {}", fault.tick)?;
+        if fault.flags & FLAG_FRAME_VALID != 0 {
+            write!(f, ", PC {:#010x}", fault.pc())?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fault = self.fault();
+        let stack = if fault.flags & FLAG_ON_PSP != 0 { "PSP" } else { "MSP" };
+        writeln!(f, "{} on {}", self.summary(), stack)?;
+
+        if fault.flags & FLAG_FRAME_VALID != 0 {
+            let r = &fault.frame;
+            writeln!(f, "  PC   {:#010x}  LR  {:#010x}  xPSR {:#010x}", r[6], r[5], r[7])?;
+            writeln!(f, "  R0   {:#010x}  R1  {:#010x}  R2   {:#010x}  R3 {:#010x}", r[0], r[1], r[2], r[3])?;
+            writeln!(f, "  R12  {:#010x}  SP  {:#010x}", r[4], fault.sp)?;
+        } else {
+            writeln!(f, "  stacked frame unavailable (SP {:#010x})", fault.sp)?;
+        }
+        writeln!(f, "  CFSR {:#010x}  HFSR {:#010x}  EXC_RETURN {:#010x}", fault.cfsr, fault.hfsr, fault.exc_return)?;
+        if fault.flags & FLAG_MMFAR_VALID != 0 {
+            writeln!(f, "  MMFAR {:#010x}", fault.mmfar)?;
+        }
+        if fault.flags & FLAG_BFAR_VALID != 0 {
+            writeln!(f, "  BFAR  {:#010x}", fault.bfar)?;
+        }
+        for cause in fault_causes(fault.cfsr, fault.hfsr) {
+            writeln!(f, "  - {}", cause)?;
+        }
+
+        writeln!(f, "  trace:")?;
+        for event in self.trace() {
+            writeln!(f, "    {}", event)?;
+        }
+        for stack in self.stacks() {
+            writeln!(f, "  {}", stack)?;
+        }
+        Ok(())
+    }
+}
+
+// ---------- Fault status decoding ----------
+
+const CFSR_BITS: [(u32, &str); 17] = [
+    (1 << 0, "IACCVIOL: instruction fetch from a no-execute region"),
+    (1 << 1, "DACCVIOL: data access violation"),
+    (1 << 3, "MUNSTKERR: MemManage fault on exception return unstacking"),
+    (1 << 4, "MSTKERR: MemManage fault on exception entry stacking"),
+    (1 << 5, "MLSPERR: MemManage fault during lazy FP state preservation"),
+    (1 << 8, "IBUSERR: bus error on instruction fetch"),
+    (1 << 9, "PRECISERR: precise data bus error"),
+    (1 << 10, "IMPRECISERR: imprecise data bus error"),
+    (1 << 11, "UNSTKERR: bus fault on exception return unstacking"),
+    (1 << 12, "STKERR: bus fault on exception entry stacking"),
+    (1 << 13, "LSPERR: bus fault during lazy FP state preservation"),
+    (1 << 16, "UNDEFINSTR: undefined instruction"),
+    (1 << 17, "INVSTATE: invalid EPSR state (Thumb bit clear)"),
+    (1 << 18, "INVPC: invalid EXC_RETURN or PC load"),
+    (1 << 19, "NOCP: coprocessor access (FPU disabled?)"),
+    (1 << 24, "UNALIGNED: unaligned access"),
+    (1 << 25, "DIVBYZERO: integer division by zero"),
+];
+
+const HFSR_BITS: [(u32, &str); 3] = [
+    (1 << 1, "VECTTBL: bus fault on vector table read"),
+    (1 << 30, "FORCED: escalated from a configurable fault"),
+    (1 << 31, "DEBUGEVT: debug event"),
+];
+
+/// Descriptions of the status bits set in CFSR and HFSR.
+pub fn fault_causes(cfsr: u32, hfsr: u32) -> impl Iterator<Item = &'static str> {
+    CFSR_BITS
+        .iter()
+        .filter(move |(mask, _)| cfsr & mask != 0)
+        .chain(HFSR_BITS.iter().filter(move |(mask, _)| hfsr & mask != 0))
+        .map(|(_, text)| *text)
+}
+
+/// CRC-32 (IEEE 802.3, as used by zip/Ethernet) of the words, taken as little-endian bytes.
+pub fn crc32(words: &[u32]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for word in words {
+        for byte in word.to_le_bytes() {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (crc & 1).wrapping_neg();
+                crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+            }
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fault() -> FaultInfo {
+        FaultInfo {
+            kind: 2,
+            task: 3,
+            flags: FLAG_ON_PSP | FLAG_FRAME_VALID | FLAG_BFAR_VALID,
+            exc_return: 0xFFFF_FFFD,
+            sp: 0x2000_1F80,
+            frame: [1, 2, 3, 4, 12, 0x0802_0451, 0x0802_0A3C, 0x6100_0000],
+            cfsr: 1 << 9 | 1 << 15,
+            hfsr: 0,
+            mmfar: 0,
+            bfar: 0x4002_FC00,
+            tick: 123_456,
+        }
+    }
+
+    fn trace_event(i: usize) -> TraceEvent {
+        TraceEvent { tick: 1000 + i as u32, kind: TraceKind::Switch as u8, task: (i % 4) as u8, arg: i as u16 }
+    }
+
+    /// Record `seq` with `traces` trace events and `stacks` stack snapshots.
+    fn record(seq: u32, traces: usize, stacks: usize) -> [u32; RECORD_WORDS] {
+        let mut words = [0u32; RECORD_WORDS];
+        let mut builder = RecordBuilder::new(&mut words, seq, &fault());
+        for i in 0..traces {
+            builder.push_trace(trace_event(i));
+        }
+        for task in 0..stacks {
+            let name = ["idle", "shell", "button", "sensor"][task % 4];
+            let stack: Vec<u32> = (0..task as u32 * 3).collect();
+            builder.push_stack(&StackSnapshot::new(task as u32, name, 0x2000_0000, &stack));
+        }
+        builder.finish();
+        words
+    }
+
+    #[test]
+    fn round_trip() {
+        let words = record(7, 5, 4);
+        let record = Record::parse(&words).unwrap();
+        assert_eq!(record.seq(), 7);
+        assert_eq!(record.fault(), fault());
+        assert_eq!(record.trace().collect::<Vec<_>>(), (0..5).map(trace_event).collect::<Vec<_>>());
+
+        let stacks: Vec<_> = record.stacks().collect();
+        assert_eq!(stacks.len(), 4);
+        assert_eq!(stacks[1].name(), "shell");
+        assert_eq!(stacks[2].stack(), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(record.task_stack().map(|s| s.name().to_string()), Some("sensor".to_string()));
+        assert_eq!(record.summary().to_string(), "#7 BusFault in task 3 (sensor) at tick 123456, PC 0x08020a3c");
+    }
+
+    #[test]
+    fn keeps_the_first_events_and_stacks_that_fit() {
+        let mut words = [0u32; RECORD_WORDS];
+        let mut builder = RecordBuilder::new(&mut words, 1, &fault());
+        for i in 0..TRACE_EVENTS {
+            assert!(builder.push_trace(trace_event(i)));
+        }
+        assert!(!builder.push_trace(trace_event(99)));
+        let long: Vec<u32> = (0..STACK_WORDS as u32 + 5).collect();
+        for task in 0..STACK_SLOTS as u32 {
+            assert!(builder.push_stack(&StackSnapshot::new(task, "a_very_long_name", 0, &long)));
+        }
+        assert!(!builder.push_stack(&StackSnapshot::default()));
+        builder.finish();
+
+        let record = Record::parse(&words).unwrap();
+        assert_eq!(record.trace().count(), TRACE_EVENTS);
+        assert_eq!(record.trace().last(), Some(trace_event(TRACE_EVENTS - 1)));
+        assert_eq!(record.stacks().count(), STACK_SLOTS);
+        let stack = record.stacks().next().unwrap();
+        assert_eq!(stack.name(), "a_very_l");
+        assert_eq!(stack.stack(), &long[..STACK_WORDS]);
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        let words = record(1, 2, 1);
+        assert_eq!(Record::parse(&words[..RECORD_WORDS - 1]).err(), Some(DecodeError::Truncated));
+        assert_eq!(Record::parse(&[]).err(), Some(DecodeError::Truncated));
+    }
+
+    #[test]
+    fn rejects_corrupted_records() {
+        let words = record(1, 2, 1);
+        for i in 1..RECORD_WORDS {
+            let mut bad = words;
+            bad[i] ^= 1 << (i % 32);
+            assert_eq!(Record::parse(&bad).err(), Some(DecodeError::BadCrc), "word {}", i);
+        }
+        // A record cut short by a power failure: the CRC was not written yet
+        let mut torn = words;
+        torn[CRC_OFFSET] = ERASED;
+        assert_eq!(Record::parse(&torn).err(), Some(DecodeError::BadCrc));
+
+        let mut other = words;
+        other[0] = 0x1234_5678;
+        assert_eq!(Record::parse(&other).err(), Some(DecodeError::BadMagic));
+    }
+
+    #[test]
+    fn erased_flash_is_empty() {
+        assert_eq!(Record::parse(&[ERASED; RECORD_WORDS]).err(), Some(DecodeError::Empty));
+    }
+
+    #[test]
+    fn records_back_to_back() {
+        // A crash log sector: two records, a torn one, then erased flash
+        let mut sector = Vec::new();
+        sector.extend(record(1, 3, 2));
+        sector.extend(record(2, 0, 0));
+        let mut torn = record(3, 1, 1);
+        torn[CRC_OFFSET] = ERASED;
+        sector.extend(torn);
+        sector.extend([ERASED; 2 * RECORD_WORDS]);
+
+        let results: Vec<_> =
+            sector.chunks(RECORD_WORDS).map(|words| Record::parse(words).map(|record| record.seq())).collect();
+        assert_eq!(
+            results,
+            [Ok(1), Ok(2), Err(DecodeError::BadCrc), Err(DecodeError::Empty), Err(DecodeError::Empty)]
+        );
+    }
+
+    #[test]
+    fn crc_check_value() {
+        // "12345678"
+        assert_eq!(crc32(&[u32::from_le_bytes(*b"1234"), u32::from_le_bytes(*b"5678")]), 0x9AE0_DAAF);
+    }
+
+    #[test]
+    fn describes_fault_status_bits() {
+        let causes: Vec<_> = fault_causes(1 << 9 | 1 << 25, 1 << 30).collect();
+        assert_eq!(
+            causes,
+            [
+                "PRECISERR: precise data bus error",
+                "DIVBYZERO: integer division by zero",
+                "FORCED: escalated from a configurable fault"
+            ]
+        );
+    }
+}
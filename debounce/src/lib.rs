@@ -0,0 +1,287 @@
+//! Button debouncing and gesture recognition.
+//!
+//! `ButtonMachine` turns raw pin levels sampled at known times into clean button events. It has no
+//! hardware or kernel dependency, so it can be driven from the kernel input service (`input`
+//! module) or from a host program replaying a synthetic pin-level timeline:
+//!
+//! ```ignore
+//! let mut button = ButtonMachine::new(ButtonConfig::default());
+//! for (now_ms, pressed) in timeline {
+//!     button.update(pressed, now_ms, |event| println!("{now_ms}: {event:?}"));
+//! }
+//! ```
+//!
+//! A level must stay unchanged for `debounce_ms` to be accepted. A press held for `long_press_ms`
+//! reports `LongPress` (and no click when released). A release starts a `double_click_ms` window:
+//! a second click whose press starts inside it reports `DoubleClick`, otherwise `Click` is
+//! reported when the window closes. With `double_click_ms == 0`, `Click` is reported on release.
+//!
+//! Times are in milliseconds and may wrap around.
+
+#![cfg_attr(not(test), no_std)]
+
+/// Gesture thresholds, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonConfig {
+    /// The level must be stable this long to count as a change.
+    pub debounce_ms: u32,
+    /// Holding the button this long reports `LongPress`.
+    pub long_press_ms: u32,
+    /// Longest gap between two clicks of a double-click; 0 disables double-click detection.
+    pub double_click_ms: u32,
+}
+
+impl Default for ButtonConfig {
+    fn default() -> Self {
+        ButtonConfig { debounce_ms: 20, long_press_ms: 800, double_click_ms: 300 }
+    }
+}
+
+/// Event reported by a button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonEvent {
+    /// Debounced press.
+    Press,
+    /// Debounced release.
+    Release,
+    /// Short press and release, not followed by a second one.
+    Click,
+    /// Two clicks in a row.
+    DoubleClick,
+    /// Press held for `long_press_ms`; reported while the button is still held.
+    LongPress,
+}
+
+/// Debouncing and gesture state of one button.
+#[derive(Debug, Clone, Copy)]
+pub struct ButtonMachine {
+    config: ButtonConfig,
+    /// Last raw level seen and when it changed.
+    raw: bool,
+    raw_since: u32,
+    /// Debounced level.
+    pressed: bool,
+    pressed_at: u32,
+    released_at: u32,
+    long_reported: bool,
+    /// Completed clicks waiting for the double-click window to close (0 or 1).
+    pending_clicks: u8,
+}
+
+impl ButtonMachine {
+    /// Button initially released.
+    pub const fn new(config: ButtonConfig) -> Self {
+        ButtonMachine {
+            config,
+            raw: false,
+            raw_since: 0,
+            pressed: false,
+            pressed_at: 0,
+            released_at: 0,
+            long_reported: false,
+            pending_clicks: 0,
+        }
+    }
+
+    pub fn config(&self) -> ButtonConfig {
+        self.config
+    }
+
+    /// Debounced state.
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    /// `true` when nothing can happen until the pin changes: the button is released, stable and no
+    /// click is waiting for its double-click window. The caller may stop sampling until the next edge.
+    pub fn is_idle(&self) -> bool {
+        !self.pressed && !self.raw && self.pending_clicks == 0
+    }
+
+    /// Feed the raw level (`true` = pressed) sampled at `now_ms`, reporting any resulting events
+    /// through `emit` (at most two per call). Call it on every edge and periodically while the
+    /// machine is not idle, at a period well below the thresholds.
+    pub fn update(&mut self, pressed: bool, now_ms: u32, mut emit: impl FnMut(ButtonEvent)) {
+        if pressed != self.raw {
+            self.raw = pressed;
+            self.raw_since = now_ms;
+        } else if pressed != self.pressed && now_ms.wrapping_sub(self.raw_since) >= self.config.debounce_ms {
+            self.pressed = pressed;
+            if pressed {
+                self.pressed_at = now_ms;
+                self.long_reported = false;
+                emit(ButtonEvent::Press);
+            } else {
+                self.released_at = now_ms;
+                emit(ButtonEvent::Release);
+                self.on_release(&mut emit);
+            }
+        }
+
+        if self.pressed && !self.long_reported && now_ms.wrapping_sub(self.pressed_at) >= self.config.long_press_ms {
+            self.long_reported = true;
+            self.pending_clicks = 0;
+            emit(ButtonEvent::LongPress);
+        }
+
+        if !self.pressed && self.pending_clicks > 0 && now_ms.wrapping_sub(self.released_at) >= self.config.double_click_ms {
+            self.pending_clicks = 0;
+            emit(ButtonEvent::Click);
+        }
+    }
+
+    fn on_release(&mut self, emit: &mut impl FnMut(ButtonEvent)) {
+        if self.long_reported {
+            return;
+        }
+        if self.config.double_click_ms == 0 {
+            emit(ButtonEvent::Click);
+        } else if self.pending_clicks > 0 {
+            self.pending_clicks = 0;
+            emit(ButtonEvent::DoubleClick);
+        } else {
+            self.pending_clicks = 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ButtonEvent::*;
+
+    const CONFIG: ButtonConfig = ButtonConfig { debounce_ms: 20, long_press_ms: 800, double_click_ms: 300 };
+
+    /// Sample a pin every millisecond from `start_ms` to `end_ms` (excluded). The pin takes the
+    /// level of the last `(time, pressed)` change at or before each sample, released before the
+    /// first one. Returns the events with the time they were reported.
+    fn replay(config: ButtonConfig, start_ms: u32, end_ms: u32, changes: &[(u32, bool)]) -> Vec<(u32, ButtonEvent)> {
+        let mut button = ButtonMachine::new(config);
+        let mut events = Vec::new();
+        let mut now = start_ms;
+        while now != end_ms {
+            let pressed = changes
+                .iter()
+                .rev()
+                .find(|(at, _)| now.wrapping_sub(start_ms) >= at.wrapping_sub(start_ms))
+                .is_some_and(|&(_, pressed)| pressed);
+            button.update(pressed, now, |event| events.push((now, event)));
+            now = now.wrapping_add(1);
+        }
+        events
+    }
+
+    /// Feed one sample and return the events it reports.
+    fn sample(button: &mut ButtonMachine, pressed: bool, now_ms: u32) -> Vec<ButtonEvent> {
+        let mut events = Vec::new();
+        button.update(pressed, now_ms, |event| events.push(event));
+        events
+    }
+
+    #[test]
+    fn rejects_bounces() {
+        // Glitches shorter than the debounce time never get through
+        let glitches: Vec<(u32, bool)> = (0..20).flat_map(|i| [(100 + 30 * i, true), (100 + 30 * i + 19, false)]).collect();
+        assert_eq!(replay(CONFIG, 0, 2000, &glitches), []);
+
+        // A bouncing press and release count once, 20 ms after the last bounce
+        let bouncy = [(100, true), (102, false), (105, true), (107, false), (110, true), (300, false), (303, true), (305, false)];
+        assert_eq!(replay(CONFIG, 0, 1000, &bouncy), [(130, Press), (325, Release), (625, Click)]);
+    }
+
+    #[test]
+    fn single_click() {
+        let events = replay(CONFIG, 0, 1000, &[(100, true), (200, false)]);
+        assert_eq!(events, [(120, Press), (220, Release), (520, Click)]);
+    }
+
+    #[test]
+    fn double_click() {
+        let events = replay(CONFIG, 0, 1500, &[(100, true), (200, false), (400, true), (500, false)]);
+        assert_eq!(events, [(120, Press), (220, Release), (420, Press), (520, Release), (520, DoubleClick)]);
+
+        // A third click starts a new gesture
+        let events = replay(CONFIG, 0, 1500, &[(100, true), (150, false), (200, true), (250, false), (300, true), (350, false)]);
+        assert_eq!(events.iter().filter(|(_, e)| *e == DoubleClick).count(), 1);
+        assert_eq!(events.last(), Some(&(670, Click)));
+    }
+
+    #[test]
+    fn clicks_too_far_apart_are_single_clicks() {
+        let events = replay(CONFIG, 0, 2000, &[(100, true), (200, false), (600, true), (700, false)]);
+        assert_eq!(
+            events,
+            [(120, Press), (220, Release), (520, Click), (620, Press), (720, Release), (1020, Click)]
+        );
+    }
+
+    #[test]
+    fn long_press() {
+        let events = replay(CONFIG, 0, 2500, &[(100, true), (1500, false)]);
+        assert_eq!(events, [(120, Press), (920, LongPress), (1520, Release)]);
+
+        // A long press also cancels a click waiting for its double-click window
+        let events = replay(CONFIG, 0, 2500, &[(100, true), (200, false), (300, true), (1500, false)]);
+        assert_eq!(events, [(120, Press), (220, Release), (320, Press), (1120, LongPress), (1520, Release)]);
+    }
+
+    #[test]
+    fn click_on_release_without_double_click() {
+        let config = ButtonConfig { double_click_ms: 0, ..CONFIG };
+        let events = replay(config, 0, 1000, &[(100, true), (200, false), (300, true), (400, false)]);
+        assert_eq!(events, [(120, Press), (220, Release), (220, Click), (320, Press), (420, Release), (420, Click)]);
+    }
+
+    #[test]
+    fn threshold_boundaries() {
+        let mut button = ButtonMachine::new(CONFIG);
+        assert!(button.is_idle());
+
+        // Debounce: stable for debounce_ms - 1 is not enough, debounce_ms is
+        assert_eq!(sample(&mut button, true, 1000), []);
+        assert!(!button.is_idle());
+        assert_eq!(sample(&mut button, true, 1019), []);
+        assert_eq!(sample(&mut button, true, 1020), [Press]);
+        assert!(button.is_pressed());
+
+        // Long press: held long_press_ms - 1 is a click, long_press_ms is a long press
+        assert_eq!(sample(&mut button, true, 1819), []);
+        assert_eq!(sample(&mut button, false, 1819), []);
+        assert_eq!(sample(&mut button, false, 1839), [Release]);
+        assert!(!button.is_idle());
+        // Double-click window: still open at double_click_ms - 1, closed at double_click_ms
+        assert_eq!(sample(&mut button, false, 2138), []);
+        assert_eq!(sample(&mut button, false, 2139), [Click]);
+        assert!(button.is_idle());
+
+        assert_eq!(sample(&mut button, true, 3000), []);
+        assert_eq!(sample(&mut button, true, 3020), [Press]);
+        assert_eq!(sample(&mut button, true, 3819), []);
+        assert_eq!(sample(&mut button, true, 3820), [LongPress]);
+        assert_eq!(sample(&mut button, false, 3900), []);
+        assert_eq!(sample(&mut button, false, 3920), [Release]);
+        assert!(button.is_idle());
+
+        // A second press accepted at double_click_ms - 1 after the release is a double-click
+        sample(&mut button, true, 5000);
+        sample(&mut button, true, 5020);
+        sample(&mut button, false, 5050);
+        assert_eq!(sample(&mut button, false, 5070), [Release]);
+        assert_eq!(sample(&mut button, true, 5349), []);
+        assert_eq!(sample(&mut button, true, 5369), [Press]);
+        sample(&mut button, false, 5400);
+        assert_eq!(sample(&mut button, false, 5420), [Release, DoubleClick]);
+        assert!(button.is_idle());
+    }
+
+    #[test]
+    fn times_wrap_around() {
+        let start = u32::MAX - 150;
+        let changes = [(start.wrapping_add(100), true), (start.wrapping_add(200), false)];
+        let events = replay(CONFIG, start, start.wrapping_add(1000), &changes);
+        assert_eq!(
+            events,
+            [(start.wrapping_add(120), Press), (start.wrapping_add(220), Release), (start.wrapping_add(520), Click)]
+        );
+    }
+}
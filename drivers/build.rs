@@ -0,0 +1,13 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    // Put `device.x` (weak aliases for the STM32F407 IRQ handlers) where the linker can find it.
+    // cortex-m-rt includes it from `link.x` when its `device` feature is enabled.
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::copy("device.x", out.join("device.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=device.x");
+    println!("cargo:rerun-if-changed=build.rs");
+}
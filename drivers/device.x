@@ -0,0 +1,82 @@
+PROVIDE(WWDG_Handler = DefaultHandler);
+PROVIDE(PVD_Handler = DefaultHandler);
+PROVIDE(TAMP_STAMP_Handler = DefaultHandler);
+PROVIDE(RTC_WKUP_Handler = DefaultHandler);
+PROVIDE(FLASH_Handler = DefaultHandler);
+PROVIDE(RCC_Handler = DefaultHandler);
+PROVIDE(EXTI0_Handler = DefaultHandler);
+PROVIDE(EXTI1_Handler = DefaultHandler);
+PROVIDE(EXTI2_Handler = DefaultHandler);
+PROVIDE(EXTI3_Handler = DefaultHandler);
+PROVIDE(EXTI4_Handler = DefaultHandler);
+PROVIDE(DMA1_Stream0_Handler = DefaultHandler);
+PROVIDE(DMA1_Stream1_Handler = DefaultHandler);
+PROVIDE(DMA1_Stream2_Handler = DefaultHandler);
+PROVIDE(DMA1_Stream3_Handler = DefaultHandler);
+PROVIDE(DMA1_Stream4_Handler = DefaultHandler);
+PROVIDE(DMA1_Stream5_Handler = DefaultHandler);
+PROVIDE(DMA1_Stream6_Handler = DefaultHandler);
+PROVIDE(ADC_Handler = DefaultHandler);
+PROVIDE(CAN1_TX_Handler = DefaultHandler);
+PROVIDE(CAN1_RX0_Handler = DefaultHandler);
+PROVIDE(CAN1_RX1_Handler = DefaultHandler);
+PROVIDE(CAN1_SCE_Handler = DefaultHandler);
+PROVIDE(EXTI9_5_Handler = DefaultHandler);
+PROVIDE(TIM1_BRK_TIM9_Handler = DefaultHandler);
+PROVIDE(TIM1_UP_TIM10_Handler = DefaultHandler);
+PROVIDE(TIM1_TRG_COM_TIM11_Handler = DefaultHandler);
+PROVIDE(TIM1_CC_Handler = DefaultHandler);
+PROVIDE(TIM2_Handler = DefaultHandler);
+PROVIDE(TIM3_Handler = DefaultHandler);
+PROVIDE(TIM4_Handler = DefaultHandler);
+PROVIDE(I2C1_EV_Handler = DefaultHandler);
+PROVIDE(I2C1_ER_Handler = DefaultHandler);
+PROVIDE(I2C2_EV_Handler = DefaultHandler);
+PROVIDE(I2C2_ER_Handler = DefaultHandler);
+PROVIDE(SPI1_Handler = DefaultHandler);
+PROVIDE(SPI2_Handler = DefaultHandler);
+PROVIDE(USART1_Handler = DefaultHandler);
+PROVIDE(USART2_Handler = DefaultHandler);
+PROVIDE(USART3_Handler = DefaultHandler);
+PROVIDE(EXTI15_10_Handler = DefaultHandler);
+PROVIDE(RTC_Alarm_Handler = DefaultHandler);
+PROVIDE(OTG_FS_WKUP_Handler = DefaultHandler);
+PROVIDE(TIM8_BRK_TIM12_Handler = DefaultHandler);
+PROVIDE(TIM8_UP_TIM13_Handler = DefaultHandler);
+PROVIDE(TIM8_TRG_COM_TIM14_Handler = DefaultHandler);
+PROVIDE(TIM8_CC_Handler = DefaultHandler);
+PROVIDE(DMA1_Stream7_Handler = DefaultHandler);
+PROVIDE(FSMC_Handler = DefaultHandler);
+PROVIDE(SDIO_Handler = DefaultHandler);
+PROVIDE(TIM5_Handler = DefaultHandler);
+PROVIDE(SPI3_Handler = DefaultHandler);
+PROVIDE(UART4_Handler = DefaultHandler);
+PROVIDE(UART5_Handler = DefaultHandler);
+PROVIDE(TIM6_DAC_Handler = DefaultHandler);
+PROVIDE(TIM7_Handler = DefaultHandler);
+PROVIDE(DMA2_Stream0_Handler = DefaultHandler);
+PROVIDE(DMA2_Stream1_Handler = DefaultHandler);
+PROVIDE(DMA2_Stream2_Handler = DefaultHandler);
+PROVIDE(DMA2_Stream3_Handler = DefaultHandler);
+PROVIDE(DMA2_Stream4_Handler = DefaultHandler);
+PROVIDE(ETH_Handler = DefaultHandler);
+PROVIDE(ETH_WKUP_Handler = DefaultHandler);
+PROVIDE(CAN2_TX_Handler = DefaultHandler);
+PROVIDE(CAN2_RX0_Handler = DefaultHandler);
+PROVIDE(CAN2_RX1_Handler = DefaultHandler);
+PROVIDE(CAN2_SCE_Handler = DefaultHandler);
+PROVIDE(OTG_FS_Handler = DefaultHandler);
+PROVIDE(DMA2_Stream5_Handler = DefaultHandler);
+PROVIDE(DMA2_Stream6_Handler = DefaultHandler);
+PROVIDE(DMA2_Stream7_Handler = DefaultHandler);
+PROVIDE(USART6_Handler = DefaultHandler);
+PROVIDE(I2C3_EV_Handler = DefaultHandler);
+PROVIDE(I2C3_ER_Handler = DefaultHandler);
+PROVIDE(OTG_HS_EP1_OUT_Handler = DefaultHandler);
+PROVIDE(OTG_HS_EP1_IN_Handler = DefaultHandler);
+PROVIDE(OTG_HS_WKUP_Handler = DefaultHandler);
+PROVIDE(OTG_HS_Handler = DefaultHandler);
+PROVIDE(DCMI_Handler = DefaultHandler);
+PROVIDE(CRYP_Handler = DefaultHandler);
+PROVIDE(HASH_RNG_Handler = DefaultHandler);
+PROVIDE(FPU_Handler = DefaultHandler);
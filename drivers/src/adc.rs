@@ -0,0 +1,720 @@
+#![allow(dead_code)]
+
+/// # ADC Driver Module
+///
+/// This module drives ADC1, ADC2 and ADC3 of the STM32F407: single and scan conversions of the
+/// regular group (polled, or continuous into a circular DMA buffer), injected channels started by
+/// software or a timer, the internal temperature sensor and VREFINT, and the analog watchdog.
+///
+/// ## Instance Conventions
+///
+/// - `adc: u32` — converter number (1–3), all on APB2.
+/// - `channel: u8` — input channel (0–18). Channels 16 (temperature sensor) and 17 (VREFINT) exist on
+///   ADC1 only; 18 is VBAT.
+///
+/// The ADC clock is PCLK2 divided by the smallest common prescaler (2, 4, 6 or 8) keeping it at or
+/// below 36 MHz, e.g. 21 MHz with PCLK2 = 84 MHz.
+///
+/// ## Pins
+///
+/// Input pins must be in analog mode: `adc_configure_pin` looks the pin up and calls
+/// `gpio_configure_mode(port, pin, GPIO_MODE_ANALOG)`.
+///
+/// ## Sequences
+///
+/// The regular group holds up to 16 conversions, the injected group up to 4. A channel may appear
+/// several times in a sequence.
+///
+/// ## Polled Conversions
+///
+/// `adc_read` and `adc_read_sequence` poll each result. In a sequence, a result not read before the
+/// next conversion ends (e.g. the task was preempted) is an overrun: the converter stops the
+/// sequence and the call returns `AdcError::Overrun`, to be retried. `adc_start_continuous_dma`
+/// has no such limit.
+///
+/// ## Interrupts
+///
+/// `adc_set_callback` registers a function called from the shared ADC interrupt at the end of an
+/// injected sequence, when the analog watchdog fires, or on overrun.
+use crate::stm32f407_registers::*;
+use crate::read_write::{read_register, write_register, reg_write_bit, reg_write_bits};
+use crate::cortex_m4::enable_irq;
+use crate::dma::{dma_claim, DataSize, DmaConfig, Priority, Transfer, WriteBuffer};
+use crate::gpio::{gpio_configure_mode, GPIO_MODE_ANALOG};
+use crate::rcc::clocks;
+
+pub const ADC1: u32 = 1;
+pub const ADC2: u32 = 2;
+pub const ADC3: u32 = 3;
+
+pub const ADC_CHANNEL_TEMPERATURE: u8 = 16;
+pub const ADC_CHANNEL_VREFINT: u8 = 17;
+pub const ADC_CHANNEL_VBAT: u8 = 18;
+
+/// Longest regular / injected sequences.
+pub const ADC_MAX_REGULAR: usize = 16;
+pub const ADC_MAX_INJECTED: usize = 4;
+
+const NUM_ADCS: usize = 3;
+
+/// Iterations to wait for a conversion (at most 492 ADC clock periods, under 70 µs at 8 MHz).
+const EOC_TIMEOUT: u32 = 100_000;
+const ADCCLK_MAX_HZ: u32 = 36_000_000;
+
+// Factory calibration (STM32F407 datasheet, temperature sensor and VREFINT), taken at 3.3 V, 12 bits
+const VREFINT_CAL_ADDR: u32 = 0x1FFF_7A2A;
+const TS_CAL1_ADDR: u32 = 0x1FFF_7A2C;
+const TS_CAL2_ADDR: u32 = 0x1FFF_7A2E;
+const CAL_VDDA_MV: u32 = 3300;
+const TS_CAL1_TEMP: i32 = 30;
+const TS_CAL2_TEMP: i32 = 110;
+
+// Register offsets
+const ADC_SR: u32 = 0x00;
+const ADC_CR1: u32 = 0x04;
+const ADC_CR2: u32 = 0x08;
+const ADC_SMPR1: u32 = 0x0C;
+const ADC_SMPR2: u32 = 0x10;
+const ADC_HTR: u32 = 0x24;
+const ADC_LTR: u32 = 0x28;
+const ADC_SQR1: u32 = 0x2C;
+const ADC_JSQR: u32 = 0x38;
+const ADC_JDR1: u32 = 0x3C;
+const ADC_DR: u32 = 0x4C;
+
+// Common registers
+const ADC_CCR: u32 = ADC_COMMON_BASE + 0x04;
+const CCR_ADCPRE: u32 = 16;
+const CCR_TSVREFE: u32 = 23;
+
+// SR bits
+const SR_AWD: u32 = 1 << 0;
+const SR_EOC: u32 = 1 << 1;
+const SR_JEOC: u32 = 1 << 2;
+const SR_OVR: u32 = 1 << 5;
+
+// CR1 bits
+const CR1_AWDCH: u32 = 0;
+const CR1_AWDIE: u32 = 6;
+const CR1_JEOCIE: u32 = 7;
+const CR1_SCAN: u32 = 8;
+const CR1_AWDSGL: u32 = 9;
+const CR1_JAWDEN: u32 = 22;
+const CR1_AWDEN: u32 = 23;
+const CR1_RES: u32 = 24;
+const CR1_OVRIE: u32 = 26;
+
+// CR2 bits
+const CR2_ADON: u32 = 0;
+const CR2_CONT: u32 = 1;
+const CR2_DMA: u32 = 8;
+const CR2_DDS: u32 = 9;
+const CR2_EOCS: u32 = 10;
+const CR2_JEXTSEL: u32 = 16;
+const CR2_JEXTEN: u32 = 20;
+const CR2_JSWSTART: u32 = 22;
+const CR2_SWSTART: u32 = 30;
+
+/// Conversion resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Bits12 = 0b00,
+    Bits10 = 0b01,
+    Bits8 = 0b10,
+    Bits6 = 0b11,
+}
+
+/// Sampling time in ADC clock cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleTime {
+    Cycles3 = 0b000,
+    Cycles15 = 0b001,
+    Cycles28 = 0b010,
+    Cycles56 = 0b011,
+    Cycles84 = 0b100,
+    Cycles112 = 0b101,
+    Cycles144 = 0b110,
+    Cycles480 = 0b111,
+}
+
+/// Event starting an injected sequence (RM0090 table 72, JEXTSEL).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InjectedTrigger {
+    /// Started by `adc_injected_start` (not a JEXTSEL value).
+    Software = 0x10,
+    Tim1Cc4 = 0b0000,
+    Tim1Trgo = 0b0001,
+    Tim2Cc1 = 0b0010,
+    Tim2Trgo = 0b0011,
+    Tim3Cc2 = 0b0100,
+    Tim3Cc4 = 0b0101,
+    Tim4Cc1 = 0b0110,
+    Tim4Cc2 = 0b0111,
+    Tim4Cc3 = 0b1000,
+    Tim4Trgo = 0b1001,
+    Tim5Cc4 = 0b1010,
+    Tim5Trgo = 0b1011,
+    Tim8Cc2 = 0b1100,
+    Tim8Cc3 = 0b1101,
+    Tim8Cc4 = 0b1110,
+    Exti15 = 0b1111,
+}
+
+/// Errors of polled conversions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdcError {
+    /// A result was overwritten before being read; the sequence stopped.
+    Overrun,
+    /// No conversion ended (converter off or not initialised).
+    Timeout,
+}
+
+/// Events reported to ADC callbacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdcEvent {
+    /// The injected sequence finished; results are in `adc_injected_read`.
+    InjectedComplete,
+    /// A watched channel converted outside the watchdog thresholds.
+    Watchdog,
+    /// A regular conversion was lost (DMA too slow or not serviced).
+    Overrun,
+}
+
+/// Callback invoked from interrupt context with the converter number and event.
+pub type AdcCallback = fn(adc: u32, event: AdcEvent);
+
+static mut CALLBACKS: [Option<AdcCallback>; NUM_ADCS] = [None; NUM_ADCS];
+
+/// Function name: `select_adc_base`
+///
+/// Description:
+/// Returns the base address of the given converter.
+///
+/// Parameters:
+/// - `adc`: Converter number (1–3).
+///
+/// Return:
+/// - Base address (`u32`) of the peripheral.
+fn select_adc_base(adc: u32) -> u32 {
+    match adc {
+        1 => ADC1_BASE,
+        2 => ADC2_BASE,
+        3 => ADC3_BASE,
+        _ => panic!("Invalid ADC: {}. Valid range is 1 – 3.", adc),
+    }
+}
+
+fn reg(adc: u32, offset: u32) -> *mut u32 {
+    (select_adc_base(adc) + offset) as *mut u32
+}
+
+fn check_channel(adc: u32, channel: u8) {
+    assert!(channel <= 18, "Invalid ADC channel: {}", channel);
+    assert!(adc == 1 || channel < 16, "ADC channel {} exists on ADC1 only", channel);
+}
+
+fn adc_is_enabled(adc: u32) -> bool {
+    unsafe { read_register(RCC_APB2ENR as *mut u32) & (1 << (7 + adc)) != 0 }
+}
+
+/// Function name: `adc_channel_pin`
+///
+/// Description:
+/// Returns the GPIO pin wired to an external channel (datasheet pinout, LQFP100/144).
+///
+/// Parameters:
+/// - `adc`: Converter number (1–3).
+/// - `channel`: Channel (0–15).
+///
+/// Return:
+/// - `Some((port, pin))`, or `None` for internal channels.
+pub fn adc_channel_pin(adc: u32, channel: u8) -> Option<(u32, u32)> {
+    const PORTA: u32 = 0;
+    const PORTB: u32 = 1;
+    const PORTC: u32 = 2;
+    const PORTF: u32 = 5;
+
+    match (adc, channel) {
+        (_, 0..=3) => Some((PORTA, channel as u32)),
+        (1 | 2, 4..=7) => Some((PORTA, channel as u32)),
+        (1 | 2, 8 | 9) => Some((PORTB, channel as u32 - 8)),
+        (_, 10..=13) => Some((PORTC, channel as u32 - 10)),
+        (1 | 2, 14 | 15) => Some((PORTC, channel as u32 - 10)),
+        (3, 4..=8) => Some((PORTF, channel as u32 + 2)),
+        (3, 9) => Some((PORTF, 3)),
+        (3, 14 | 15) => Some((PORTF, channel as u32 - 10)),
+        _ => None,
+    }
+}
+
+/// Put the pin of an external channel into analog mode.
+pub fn adc_configure_pin(adc: u32, channel: u8) {
+    let (port, pin) = adc_channel_pin(adc, channel)
+        .unwrap_or_else(|| panic!("ADC{} channel {} has no pin", adc, channel));
+    gpio_configure_mode(port, pin, GPIO_MODE_ANALOG);
+}
+
+/// Function name: `adc_init`
+///
+/// Description:
+/// Enables the converter clock, sets the common prescaler and the resolution, and powers the
+/// converter on. All channels default to 84-cycle sampling.
+///
+/// Parameters:
+/// - `adc`: Converter number (1–3).
+/// - `resolution`: Conversion resolution.
+///
+/// Return:
+/// - None
+pub fn adc_init(adc: u32, resolution: Resolution) {
+    let _ = select_adc_base(adc);
+    unsafe {
+        reg_write_bit(RCC_APB2ENR as *mut u32, 7 + adc, true);
+
+        // ADCPRE: PCLK2 / 2, 4, 6 or 8
+        let pclk2 = clocks().pclk2();
+        let prescaler = (1..=4).find(|n| pclk2 / (2 * n) <= ADCCLK_MAX_HZ).unwrap_or(4);
+        reg_write_bits(ADC_CCR as *mut u32, prescaler - 1, CCR_ADCPRE, 2);
+
+        write_register(reg(adc, ADC_CR1), (resolution as u32) << CR1_RES);
+        write_register(reg(adc, ADC_CR2), 1 << CR2_ADON);
+        write_register(reg(adc, ADC_SR), 0);
+    }
+
+    for channel in 0..=18 {
+        if adc == 1 || channel < 16 {
+            adc_set_sample_time(adc, channel, SampleTime::Cycles84);
+        }
+    }
+}
+
+/// Function name: `adc_set_sample_time`
+///
+/// Description:
+/// Sets the sampling time of a channel. Longer times suit high-impedance sources; the temperature
+/// sensor needs at least 10 us.
+///
+/// Parameters:
+/// - `adc`: Converter number (1–3).
+/// - `channel`: Channel (0–18).
+/// - `time`: Sampling time.
+///
+/// Return:
+/// - None
+pub fn adc_set_sample_time(adc: u32, channel: u8, time: SampleTime) {
+    check_channel(adc, channel);
+    let (offset, index) = if channel < 10 { (ADC_SMPR2, channel) } else { (ADC_SMPR1, channel - 10) };
+    unsafe {
+        reg_write_bits(reg(adc, offset), time as u32, index as u32 * 3, 3);
+    }
+}
+
+/// Program the regular sequence (SQR1..3) and its length.
+fn set_regular_sequence(adc: u32, channels: &[u8]) {
+    assert!(!channels.is_empty() && channels.len() <= ADC_MAX_REGULAR, "Regular sequence must have 1 – 16 channels");
+    let mut sqr = [0u32; 3]; // SQR1, SQR2, SQR3
+    for (rank, &channel) in channels.iter().enumerate() {
+        check_channel(adc, channel);
+        // SQR3 holds ranks 1..6, SQR2 7..12, SQR1 13..16
+        let register = 2 - rank / 6;
+        sqr[register] |= (channel as u32) << ((rank % 6) * 5);
+    }
+    sqr[0] |= (channels.len() as u32 - 1) << 20;
+
+    unsafe {
+        for (i, value) in sqr.iter().enumerate() {
+            write_register(reg(adc, ADC_SQR1 + i as u32 * 4), *value);
+        }
+        reg_write_bit(reg(adc, ADC_CR1), CR1_SCAN, channels.len() > 1);
+    }
+}
+
+fn start_regular(adc: u32) {
+    unsafe {
+        reg_write_bit(reg(adc, ADC_CR2), CR2_SWSTART, true);
+    }
+}
+
+fn wait_eoc(adc: u32) -> Result<u16, AdcError> {
+    for _ in 0..EOC_TIMEOUT {
+        let sr = unsafe { read_register(reg(adc, ADC_SR)) };
+        if sr & SR_OVR != 0 {
+            unsafe {
+                write_register(reg(adc, ADC_SR), !SR_OVR & 0x3F);
+            }
+            return Err(AdcError::Overrun);
+        }
+        if sr & SR_EOC != 0 {
+            // Reading DR clears EOC
+            return Ok(unsafe { read_register(reg(adc, ADC_DR)) } as u16);
+        }
+    }
+    Err(AdcError::Timeout)
+}
+
+/// Function name: `adc_read`
+///
+/// Description:
+/// Performs a single blocking conversion of one channel.
+///
+/// Parameters:
+/// - `adc`: Converter number (1–3).
+/// - `channel`: Channel (0–18).
+///
+/// Return:
+/// - Raw conversion result (right-aligned), or the conversion error.
+pub fn adc_read(adc: u32, channel: u8) -> Result<u16, AdcError> {
+    let mut result = [0];
+    adc_read_sequence(adc, &[channel], &mut result)?;
+    Ok(result[0])
+}
+
+/// Function name: `adc_read_sequence`
+///
+/// Description:
+/// Converts `channels` in scan mode, polling each result (EOC after every conversion).
+///
+/// Parameters:
+/// - `adc`: Converter number (1–3).
+/// - `channels`: Sequence, 1–16 channels.
+/// - `results`: Output, one entry per channel.
+///
+/// Return:
+/// - `results`, filled, or the error that stopped the sequence (see "Polled Conversions").
+pub fn adc_read_sequence<'a>(adc: u32, channels: &[u8], results: &'a mut [u16]) -> Result<&'a mut [u16], AdcError> {
+    assert!(results.len() >= channels.len(), "Result buffer too short");
+    set_regular_sequence(adc, channels);
+    unsafe {
+        let cr2 = read_register(reg(adc, ADC_CR2));
+        let cr2 = (cr2 | (1 << CR2_EOCS)) & !((1 << CR2_CONT) | (1 << CR2_DMA));
+        write_register(reg(adc, ADC_CR2), cr2);
+        write_register(reg(adc, ADC_SR), !(SR_EOC | SR_OVR) & 0x3F);
+    }
+
+    start_regular(adc);
+    for result in results.iter_mut().take(channels.len()) {
+        *result = wait_eoc(adc)?;
+    }
+    Ok(results)
+}
+
+/// (controller, stream, channel) of the DMA request of each converter (RM0090 table 43).
+fn adc_dma_stream(adc: u32) -> (u32, u32, u32) {
+    match adc {
+        1 => (2, 0, 0),
+        2 => (2, 2, 1),
+        3 => (2, 1, 2),
+        _ => panic!("Invalid ADC: {}", adc),
+    }
+}
+
+/// Function name: `adc_start_continuous_dma`
+///
+/// Description:
+/// Converts `channels` over and over, the DMA writing each result into `buffer` in a circle
+/// (sequence results in order, then wrapping). The DMA stream of the converter is claimed for the
+/// duration; `Transfer::stop` followed by `adc_stop` ends the acquisition.
+///
+/// Parameters:
+/// - `adc`: Converter number (1–3).
+/// - `channels`: Sequence, 1–16 channels.
+/// - `buffer`: Destination; its length should be a multiple of `channels.len()`.
+///
+/// Return:
+/// - The running circular DMA transfer.
+pub fn adc_start_continuous_dma<B>(adc: u32, channels: &[u8], buffer: B) -> Transfer<B>
+where
+    B: WriteBuffer<Word = u16>,
+{
+    let (dma, stream, dma_channel) = adc_dma_stream(adc);
+    let stream = dma_claim(dma, stream).unwrap_or_else(|| panic!("ADC{} DMA stream already in use", adc));
+
+    adc_stop(adc);
+    set_regular_sequence(adc, channels);
+
+    let config = DmaConfig::new(dma_channel)
+        .peripheral_size(DataSize::HalfWord)
+        .priority(Priority::High)
+        .circular(true);
+    let peripheral = select_adc_base(adc) + ADC_DR;
+    // Safe: DR is the data register of this converter, which drives the selected request channel.
+    let transfer = unsafe { Transfer::peripheral_to_memory(stream, &config, peripheral, buffer) };
+
+    unsafe {
+        let cr2 = read_register(reg(adc, ADC_CR2)) & !(1 << CR2_EOCS);
+        write_register(reg(adc, ADC_CR2), cr2 | (1 << CR2_CONT) | (1 << CR2_DMA) | (1 << CR2_DDS));
+        write_register(reg(adc, ADC_SR), !SR_OVR & 0x3F);
+    }
+    start_regular(adc);
+    transfer
+}
+
+/// Function name: `adc_stop`
+///
+/// Description:
+/// Stops continuous conversion and detaches the DMA request. A conversion in progress completes.
+///
+/// Parameters:
+/// - `adc`: Converter number (1–3).
+///
+/// Return:
+/// - None
+pub fn adc_stop(adc: u32) {
+    unsafe {
+        let cr2 = read_register(reg(adc, ADC_CR2));
+        write_register(reg(adc, ADC_CR2), cr2 & !((1 << CR2_CONT) | (1 << CR2_DMA) | (1 << CR2_DDS)));
+    }
+}
+
+// ---------- Injected group ----------
+
+/// Function name: `adc_configure_injected`
+///
+/// Description:
+/// Sets the injected sequence and what starts it. A timer trigger starts the sequence on the rising
+/// edge of the selected event (see `timer_set_trigger_output` for TRGO sources).
+///
+/// Parameters:
+/// - `adc`: Converter number (1–3).
+/// - `channels`: Sequence, 1–4 channels.
+/// - `trigger`: `InjectedTrigger::Software` or a timer/EXTI event.
+///
+/// Return:
+/// - None
+pub fn adc_configure_injected(adc: u32, channels: &[u8], trigger: InjectedTrigger) {
+    assert!(!channels.is_empty() && channels.len() <= ADC_MAX_INJECTED, "Injected sequence must have 1 – 4 channels");
+
+    // With JL < 3 the sequence occupies the last JSQ slots (RM0090 13.13.12)
+    let first_slot = ADC_MAX_INJECTED - channels.len();
+    let mut jsqr = (channels.len() as u32 - 1) << 20;
+    for (i, &channel) in channels.iter().enumerate() {
+        check_channel(adc, channel);
+        jsqr |= (channel as u32) << ((first_slot + i) * 5);
+    }
+
+    unsafe {
+        write_register(reg(adc, ADC_JSQR), jsqr);
+        // The injected group only converts past rank 1 in scan mode
+        if channels.len() > 1 {
+            reg_write_bit(reg(adc, ADC_CR1), CR1_SCAN, true);
+        }
+
+        let (jextsel, jexten) = match trigger {
+            InjectedTrigger::Software => (0, 0b00),
+            other => (other as u32, 0b01),
+        };
+        reg_write_bits(reg(adc, ADC_CR2), jextsel, CR2_JEXTSEL, 4);
+        reg_write_bits(reg(adc, ADC_CR2), jexten, CR2_JEXTEN, 2);
+    }
+}
+
+/// Start the injected sequence by software.
+pub fn adc_injected_start(adc: u32) {
+    unsafe {
+        reg_write_bit(reg(adc, ADC_CR2), CR2_JSWSTART, true);
+    }
+}
+
+/// `true` once the injected sequence has finished (clears the flag).
+pub fn adc_injected_complete(adc: u32) -> bool {
+    unsafe {
+        let done = read_register(reg(adc, ADC_SR)) & SR_JEOC != 0;
+        if done {
+            write_register(reg(adc, ADC_SR), !SR_JEOC & 0x3F);
+        }
+        done
+    }
+}
+
+/// Result of injected rank `rank` (1–4, in sequence order).
+pub fn adc_injected_read(adc: u32, rank: u32) -> u16 {
+    assert!((1..=4).contains(&rank), "Invalid injected rank: {}", rank);
+    unsafe { read_register(reg(adc, ADC_JDR1 + (rank - 1) * 4)) as u16 }
+}
+
+// ---------- Analog watchdog ----------
+
+/// Function name: `adc_watchdog_enable`
+///
+/// Description:
+/// Raises `AdcEvent::Watchdog` whenever a conversion falls outside `low..=high`.
+///
+/// Parameters:
+/// - `adc`: Converter number (1–3).
+/// - `channel`: Channel to watch, or `None` for every regular and injected channel.
+/// - `low`, `high`: Thresholds (12-bit scale, whatever the resolution).
+///
+/// Return:
+/// - None
+pub fn adc_watchdog_enable(adc: u32, channel: Option<u8>, low: u16, high: u16) {
+    unsafe {
+        write_register(reg(adc, ADC_LTR), (low & 0xFFF) as u32);
+        write_register(reg(adc, ADC_HTR), (high & 0xFFF) as u32);
+
+        let cr1 = reg(adc, ADC_CR1);
+        match channel {
+            Some(channel) => {
+                check_channel(adc, channel);
+                reg_write_bits(cr1, channel as u32, CR1_AWDCH, 5);
+                reg_write_bit(cr1, CR1_AWDSGL, true);
+            }
+            None => reg_write_bit(cr1, CR1_AWDSGL, false),
+        }
+        reg_write_bit(cr1, CR1_AWDEN, true);
+        reg_write_bit(cr1, CR1_JAWDEN, true);
+        write_register(reg(adc, ADC_SR), !SR_AWD & 0x3F);
+        reg_write_bit(cr1, CR1_AWDIE, true);
+    }
+    enable_irq(ADC_IRQ);
+}
+
+pub fn adc_watchdog_disable(adc: u32) {
+    unsafe {
+        let cr1 = reg(adc, ADC_CR1);
+        reg_write_bit(cr1, CR1_AWDIE, false);
+        reg_write_bit(cr1, CR1_AWDEN, false);
+        reg_write_bit(cr1, CR1_JAWDEN, false);
+    }
+}
+
+// ---------- Internal channels ----------
+
+/// Power the temperature sensor and VREFINT (ADC1 channels 16 and 17).
+pub fn adc_enable_internal_channels(enable: bool) {
+    unsafe {
+        reg_write_bit(ADC_CCR as *mut u32, CCR_TSVREFE, enable);
+    }
+}
+
+fn read_calibration(addr: u32) -> u32 {
+    unsafe { core::ptr::read_volatile(addr as *const u16) as u32 }
+}
+
+/// Function name: `adc_read_vdda_mv`
+///
+/// Description:
+/// Measures VREFINT and derives the actual analog supply from its factory calibration, so that
+/// other readings can be compensated for supply variation. ADC1 must be initialised at 12 bits
+/// with the internal channels enabled.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - VDDA in millivolts, or the conversion error.
+pub fn adc_read_vdda_mv() -> Result<u32, AdcError> {
+    adc_set_sample_time(ADC1, ADC_CHANNEL_VREFINT, SampleTime::Cycles480);
+    let raw = adc_read(ADC1, ADC_CHANNEL_VREFINT)?.max(1) as u32;
+    Ok(CAL_VDDA_MV * read_calibration(VREFINT_CAL_ADDR) / raw)
+}
+
+/// Convert a raw 12-bit reading to millivolts for a given supply.
+pub fn adc_to_millivolts(raw: u16, vdda_mv: u32) -> u32 {
+    raw as u32 * vdda_mv / 4095
+}
+
+/// Function name: `adc_read_temperature_mc`
+///
+/// Description:
+/// Reads the internal temperature sensor, compensated for VDDA through VREFINT and converted with
+/// the two-point factory calibration (30 °C and 110 °C). ADC1 must be initialised at 12 bits with
+/// the internal channels enabled.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - Die temperature in millidegrees Celsius, or the conversion error.
+pub fn adc_read_temperature_mc() -> Result<i32, AdcError> {
+    let vdda = adc_read_vdda_mv()?;
+    adc_set_sample_time(ADC1, ADC_CHANNEL_TEMPERATURE, SampleTime::Cycles480);
+    let raw = adc_read(ADC1, ADC_CHANNEL_TEMPERATURE)? as u32;
+
+    // Scale to what the reading would have been at the calibration supply
+    let raw = (raw * vdda / CAL_VDDA_MV) as i32;
+    let cal1 = read_calibration(TS_CAL1_ADDR) as i32;
+    let cal2 = read_calibration(TS_CAL2_ADDR) as i32;
+    Ok(TS_CAL1_TEMP * 1000 + (raw - cal1) * (TS_CAL2_TEMP - TS_CAL1_TEMP) * 1000 / (cal2 - cal1))
+}
+
+// ---------- Interrupts ----------
+
+/// Function name: `adc_set_callback`
+///
+/// Description:
+/// Registers (or removes) the callback and enables the injected-complete and overrun interrupts
+/// (the watchdog interrupt is enabled by `adc_watchdog_enable`).
+///
+/// Parameters:
+/// - `adc`: Converter number (1–3).
+/// - `callback`: Function called from interrupt context, or `None`.
+///
+/// Return:
+/// - None
+pub fn adc_set_callback(adc: u32, callback: Option<AdcCallback>) {
+    let _ = select_adc_base(adc);
+    unsafe {
+        CALLBACKS[(adc - 1) as usize] = callback;
+        let cr1 = reg(adc, ADC_CR1);
+        reg_write_bit(cr1, CR1_JEOCIE, callback.is_some());
+        reg_write_bit(cr1, CR1_OVRIE, callback.is_some());
+    }
+    if callback.is_some() {
+        enable_irq(ADC_IRQ);
+    }
+}
+
+/// Function name: `adc_irq_handler`
+///
+/// Description:
+/// Services one converter: clears its pending, enabled flags and reports them to the callback.
+///
+/// Parameters:
+/// - `adc`: Converter number (1–3).
+///
+/// Return:
+/// - None
+pub fn adc_irq_handler(adc: u32) {
+    let (events, callback) = unsafe {
+        let sr = read_register(reg(adc, ADC_SR));
+        let cr1 = read_register(reg(adc, ADC_CR1));
+        let mut pending = 0;
+        if sr & SR_JEOC != 0 && cr1 & (1 << CR1_JEOCIE) != 0 {
+            pending |= SR_JEOC;
+        }
+        if sr & SR_AWD != 0 && cr1 & (1 << CR1_AWDIE) != 0 {
+            pending |= SR_AWD;
+        }
+        if sr & SR_OVR != 0 && cr1 & (1 << CR1_OVRIE) != 0 {
+            pending |= SR_OVR;
+        }
+        if pending == 0 {
+            return;
+        }
+        write_register(reg(adc, ADC_SR), !pending & 0x3F);
+        (pending, CALLBACKS[(adc - 1) as usize])
+    };
+
+    if let Some(callback) = callback {
+        if events & SR_JEOC != 0 {
+            callback(adc, AdcEvent::InjectedComplete);
+        }
+        if events & SR_AWD != 0 {
+            callback(adc, AdcEvent::Watchdog);
+        }
+        if events & SR_OVR != 0 {
+            callback(adc, AdcEvent::Overrun);
+        }
+    }
+}
+
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn ADC_Handler() {
+    for adc in [ADC1, ADC2, ADC3] {
+        if adc_is_enabled(adc) {
+            adc_irq_handler(adc);
+        }
+    }
+}
@@ -1,9 +1,54 @@
 #![allow(dead_code)]
 
-
+/// # Cortex-M4 Core Peripherals
+///
+/// NVIC (enable, pending, active and priority of device IRQs) and SCB (priority grouping, system
+/// handler priorities, vector table relocation, PendSV, sleep control and reset) helpers, plus
+/// PRIMASK and BASEPRI control.
+///
+/// ## Priorities
+///
+/// The STM32F407 implements the upper 4 bits of each 8-bit priority field, so every priority in
+/// this module is a value `0..=15` (0 = highest), shifted into place when written. With
+/// `set_interrupt_priority_grouping(g)` the top `min(4, 7 - g)` of those bits are the preemption
+/// priority and the rest the subpriority; `encode_priority` / `decode_priority` convert between the
+/// two forms for the current grouping.
 use crate:: stm32f407_registers::*;
 use crate::read_write::{read_register, write_register};
 
+/// Number of priority bits implemented by the STM32F407.
+pub const NVIC_PRIO_BITS: u32 = 4;
+
+/// Lowest (numerically highest) priority.
+pub const LOWEST_PRIORITY: u8 = (1 << NVIC_PRIO_BITS) - 1;
+
+/// Number of device IRQs (vector table positions 0–81).
+pub const NUM_IRQS: u32 = 82;
+
+/// Core exceptions whose priority is configurable (value = exception number).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemHandler {
+    MemManage = 4,
+    BusFault = 5,
+    UsageFault = 6,
+    SVCall = 11,
+    DebugMonitor = 12,
+    PendSV = 14,
+    SysTick = 15,
+}
+
+fn check_irq(irq_number: u32) {
+    if irq_number >= NUM_IRQS {
+        panic!("Invalid IRQ number: {}", irq_number);
+    }
+}
+
+/// Word address and bit mask of an IRQ in one of the NVIC bit arrays (ISER, ICER, ISPR, ...).
+fn irq_bit(base: u32, irq_number: u32) -> (*mut u32, u32) {
+    check_irq(irq_number);
+    (((base + (irq_number / 32) * 4) as *mut u32), 1 << (irq_number % 32))
+}
+
 
 /// Enables the IRQ for the given IRQ number by setting the appropriate
 /// bit in the NVIC ISER register.
@@ -14,13 +59,10 @@ use crate::read_write::{read_register, write_register};
 /// # Safety
 /// Assumes `irq_number` is valid and within NVIC supported IRQ range.
 pub fn enable_irq(irq_number: u32) {
-    let register_offset = (irq_number / 32) * 4;
-    let bit_position = irq_number % 32;
-    let iser_addr = (NVIC_ISER + register_offset) as *mut u32;
+    // Writing 0 has no effect: no read-modify-write needed
+    let (iser_addr, bit) = irq_bit(NVIC_ISER, irq_number);
     unsafe {
-        let mut value = read_register(iser_addr);
-        value |= 1 << bit_position;
-        write_register(iser_addr, value);
+        write_register(iser_addr, bit);
     }
 }
 
@@ -34,16 +76,68 @@ pub fn enable_irq(irq_number: u32) {
 /// # Safety
 /// Assumes `irq_number` is valid and within NVIC supported IRQ range.
 pub fn disable_irq(irq_number: u32) {
-    let register_offset = (irq_number / 32) * 4;
-    let bit_position = irq_number % 32;
-    let icer_addr = (NVIC_ICER + register_offset) as *mut u32;
+    // ICER reads back the enabled IRQs: writing that value back would disable all of them
+    let (icer_addr, bit) = irq_bit(NVIC_ICER, irq_number);
+    unsafe {
+        write_register(icer_addr, bit);
+        core::arch::asm!("dsb", "isb", options(nostack, preserves_flags));
+    }
+}
+
+/// Returns `true` if the IRQ is enabled in the NVIC.
+pub fn is_irq_enabled(irq_number: u32) -> bool {
+    let (iser_addr, bit) = irq_bit(NVIC_ISER, irq_number);
+    unsafe { read_register(iser_addr) & bit != 0 }
+}
+
+/// Function name: set_pending_irq
+///
+/// Description:
+/// Marks the IRQ pending (NVIC ISPR), as if the peripheral had requested it. The handler runs
+/// as soon as the IRQ is enabled and its priority allows.
+///
+/// # Parameters
+/// - `irq_number`: The IRQ number.
+///
+/// # Return
+/// - None
+pub fn set_pending_irq(irq_number: u32) {
+    let (ispr_addr, bit) = irq_bit(NVIC_ISPR, irq_number);
+    unsafe {
+        write_register(ispr_addr, bit);
+    }
+}
+
+/// Function name: clear_pending_irq
+///
+/// Description:
+/// Removes the pending state of the IRQ (NVIC ICPR). A level request still asserted by the
+/// peripheral sets it pending again.
+///
+/// # Parameters
+/// - `irq_number`: The IRQ number.
+///
+/// # Return
+/// - None
+pub fn clear_pending_irq(irq_number: u32) {
+    let (icpr_addr, bit) = irq_bit(NVIC_ICPR, irq_number);
     unsafe {
-        let mut value = read_register(icer_addr);
-        value |= 1 << bit_position;
-        write_register(icer_addr, value);
+        write_register(icpr_addr, bit);
     }
 }
 
+/// Returns `true` if the IRQ is pending.
+pub fn is_irq_pending(irq_number: u32) -> bool {
+    let (ispr_addr, bit) = irq_bit(NVIC_ISPR, irq_number);
+    unsafe { read_register(ispr_addr) & bit != 0 }
+}
+
+/// Returns `true` if the IRQ handler is running or preempted (NVIC IABR).
+pub fn is_irq_active(irq_number: u32) -> bool {
+    let (iabr_addr, bit) = irq_bit(NVIC_IABR, irq_number);
+    unsafe { read_register(iabr_addr) & bit != 0 }
+}
+
 
 /// Function name: disable_global_interrupt
 ///
@@ -95,36 +189,193 @@ pub fn enable_global_interrupt() {
 }
 
 
+/// Function name: is_global_interrupt_enabled
+///
+/// Description:
+/// Reads PRIMASK, e.g. to restore it after masking interrupts with `disable_global_interrupt`.
+///
+/// # Parameters
+/// - None
+///
+/// # Return
+/// - `true` if maskable interrupts are enabled (PRIMASK clear).
+pub fn is_global_interrupt_enabled() -> bool {
+    let primask: usize;
+    unsafe {
+        core::arch::asm!("mrs {}, PRIMASK", out(reg) primask, options(nomem, nostack, preserves_flags));
+    }
+    primask & 1 == 0
+}
+
+
+/// Function name: get_basepri
+///
+/// Description:
+/// Reads the BASEPRI register: exceptions with a priority value greater than or equal to it are
+/// masked. `0` means no masking.
+///
+/// # Parameters
+/// - None
+///
+/// # Return
+/// - The masking threshold, `0..=15` (0 = masking disabled).
+pub fn get_basepri() -> u8 {
+    let basepri: usize;
+    unsafe {
+        core::arch::asm!("mrs {}, BASEPRI", out(reg) basepri, options(nomem, nostack, preserves_flags));
+    }
+    (basepri >> (8 - NVIC_PRIO_BITS)) as u8
+}
+
+/// Function name: set_basepri
+///
+/// Description:
+/// Writes BASEPRI, masking every exception whose priority value is `priority` or higher
+/// (i.e. of equal or lower urgency). Unlike PRIMASK, more urgent interrupts keep running.
+/// Also acts as a compiler barrier.
+///
+/// # Parameters
+/// - `priority`: Masking threshold `1..=15`, or `0` to unmask everything.
+///
+/// # Return
+/// - None
+pub fn set_basepri(priority: u8) {
+    if priority > LOWEST_PRIORITY {
+        panic!("Invalid priority: {}", priority);
+    }
+    let value = (priority as usize) << (8 - NVIC_PRIO_BITS);
+    unsafe {
+        core::arch::asm!("msr BASEPRI, {}", "isb", in(reg) value, options(nostack, preserves_flags));
+    }
+}
+
+/// Function name: set_basepri_max
+///
+/// Description:
+/// Raises the masking threshold to `priority` only if that masks more than the current BASEPRI
+/// (BASEPRI_MAX semantics), so nested critical sections never lower it. Also acts as a compiler
+/// barrier.
+///
+/// # Parameters
+/// - `priority`: Masking threshold `1..=15`.
+///
+/// # Return
+/// - None
+pub fn set_basepri_max(priority: u8) {
+    if priority == 0 || priority > LOWEST_PRIORITY {
+        panic!("Invalid priority: {}", priority);
+    }
+    let value = (priority as usize) << (8 - NVIC_PRIO_BITS);
+    unsafe {
+        core::arch::asm!("msr BASEPRI_MAX, {}", "isb", in(reg) value, options(nostack, preserves_flags));
+    }
+}
+
+/// Returns the priority (`0..=15`) of the exception currently being serviced, or `None` in
+/// Thread mode or for fixed-priority exceptions (Reset, NMI, HardFault).
+pub fn get_active_priority() -> Option<u8> {
+    match get_active_exception() {
+        0..=3 => None,
+        4 => Some(get_system_handler_priority(SystemHandler::MemManage)),
+        5 => Some(get_system_handler_priority(SystemHandler::BusFault)),
+        6 => Some(get_system_handler_priority(SystemHandler::UsageFault)),
+        11 => Some(get_system_handler_priority(SystemHandler::SVCall)),
+        12 => Some(get_system_handler_priority(SystemHandler::DebugMonitor)),
+        14 => Some(get_system_handler_priority(SystemHandler::PendSV)),
+        15 => Some(get_system_handler_priority(SystemHandler::SysTick)),
+        exception if exception >= 16 => Some(get_interrupt_priority(exception - 16)),
+        _ => None,
+    }
+}
 
 /// Function name: set_interrupt_priority
 ///
 /// Description:
 /// Sets the priority level of a specific IRQ number in the NVIC (Nested Vectored Interrupt Controller).
 /// Lower numerical values correspond to higher priority (0 = highest priority).
-/// STM32F407 supports 4 bits of priority (0..15) by default, but actual implemented bits may vary.
-///
-/// # Safety
-/// - Caller must ensure `irq_number` is valid and corresponds to an IRQ supported by the MCU.
-/// - `priority` must be within the valid priority range supported by the device (usually 0..15).
+/// Each IRQ owns one byte of the IPR registers, of which the STM32F407 implements the upper 4 bits;
+/// the byte is written on its own so the neighbouring IRQs keep their priority.
 ///
 /// # Parameters
 /// - `irq_number`: The IRQ number to set priority for.
-/// - `priority`: The priority value to assign (lower is higher priority).
+/// - `priority`: The priority value to assign, `0..=15` (lower is higher priority). Use
+///   `encode_priority` to build it from a preemption priority and a subpriority.
 ///
 /// # Return
 /// - None
 pub fn set_interrupt_priority(irq_number: u32, priority: u8) {
-    
-    if irq_number >= 240 {
-        panic!("Invalid IRQ number");
+    check_irq(irq_number);
+    if priority > LOWEST_PRIORITY {
+        panic!("Invalid priority: {}", priority);
     }
     let ipr_addr = (NVIC_IPR + irq_number) as *mut u8;
 
     unsafe {
-        // Priority registers are 8-bit wide; STM32F407 uses upper 4 bits for priority
-        let priority_value = priority << 4;
-        write_register(ipr_addr as *mut u32, priority_value as u32);
+        core::ptr::write_volatile(ipr_addr, priority << (8 - NVIC_PRIO_BITS));
+    }
+}
+
+/// Returns the priority (`0..=15`) of an IRQ.
+pub fn get_interrupt_priority(irq_number: u32) -> u8 {
+    check_irq(irq_number);
+    let ipr_addr = (NVIC_IPR + irq_number) as *const u8;
+    unsafe { core::ptr::read_volatile(ipr_addr) >> (8 - NVIC_PRIO_BITS) }
+}
+
+/// Function name: set_interrupt_priority_grouped
+///
+/// Description:
+/// Sets an IRQ priority from a preemption priority and a subpriority, split according to the
+/// current priority grouping.
+///
+/// # Parameters
+/// - `irq_number`: The IRQ number.
+/// - `preempt`: Preemption priority (only a higher one can interrupt a running handler).
+/// - `sub`: Subpriority (orders pending IRQs of equal preemption priority).
+///
+/// # Return
+/// - None
+pub fn set_interrupt_priority_grouped(irq_number: u32, preempt: u8, sub: u8) {
+    set_interrupt_priority(irq_number, encode_priority(get_interrupt_priority_grouping(), preempt, sub));
+}
+
+/// Number of (preemption, sub) priority bits for a grouping, out of the 4 implemented bits.
+fn priority_split(priority_group: u8) -> (u32, u32) {
+    if priority_group > 7 {
+        panic!("Invalid priority group");
+    }
+    let preempt_bits = (7 - priority_group as u32).min(NVIC_PRIO_BITS);
+    (preempt_bits, NVIC_PRIO_BITS - preempt_bits)
+}
+
+/// Function name: encode_priority
+///
+/// Description:
+/// Combines a preemption priority and a subpriority into a priority value for the given grouping
+/// (see `set_interrupt_priority_grouping`).
+///
+/// # Parameters
+/// - `priority_group`: The priority grouping value (0..7).
+/// - `preempt`: Preemption priority, `0..2^preempt_bits`.
+/// - `sub`: Subpriority, `0..2^sub_bits`.
+///
+/// # Panics
+/// Panics if either value does not fit the bits the grouping gives it.
+///
+/// # Return
+/// - Priority value `0..=15`.
+pub fn encode_priority(priority_group: u8, preempt: u8, sub: u8) -> u8 {
+    let (preempt_bits, sub_bits) = priority_split(priority_group);
+    if (preempt as u32) >= (1 << preempt_bits) || (sub as u32) >= (1 << sub_bits) {
+        panic!("Priority {}.{} does not fit group {}", preempt, sub, priority_group);
     }
+    (preempt << sub_bits) | sub
+}
+
+/// Splits a priority value `0..=15` into (preemption priority, subpriority) for the given grouping.
+pub fn decode_priority(priority_group: u8, priority: u8) -> (u8, u8) {
+    let (_, sub_bits) = priority_split(priority_group);
+    (priority >> sub_bits, priority & ((1 << sub_bits) - 1))
 }
 
 /// Function name: set_interrupt_priority_grouping
@@ -160,7 +411,259 @@ pub fn set_interrupt_priority_grouping(priority_group: u8) {
 
     unsafe {
         let current = read_register(scb_aircr);
-        let new_value = (current & !PRIGROUP_MASK) | VECTKEY | ((priority_group as u32) << 8);
+        let new_value = (current & !(PRIGROUP_MASK | VECTKEY_MASK)) | VECTKEY | ((priority_group as u32) << 8);
         write_register(scb_aircr, new_value);
     }
-}
\ No newline at end of file
+}
+
+/// Returns the current priority grouping (AIRCR.PRIGROUP, 0..7).
+pub fn get_interrupt_priority_grouping() -> u8 {
+    unsafe { ((read_register(SCB_AIRCR_BASE as *mut u32) >> 8) & 0x7) as u8 }
+}
+
+/// Function name: set_system_handler_priority
+///
+/// Description:
+/// Sets the priority of a configurable core exception through its byte in SHPR1–SHPR3.
+///
+/// # Parameters
+/// - `handler`: The exception.
+/// - `priority`: The priority value, `0..=15` (lower is higher priority).
+///
+/// # Return
+/// - None
+pub fn set_system_handler_priority(handler: SystemHandler, priority: u8) {
+    if priority > LOWEST_PRIORITY {
+        panic!("Invalid priority: {}", priority);
+    }
+    let shpr_addr = (SCB_SHPR_BASE + handler as u32 - 4) as *mut u8;
+    unsafe {
+        core::ptr::write_volatile(shpr_addr, priority << (8 - NVIC_PRIO_BITS));
+    }
+}
+
+/// Returns the priority (`0..=15`) of a configurable core exception.
+pub fn get_system_handler_priority(handler: SystemHandler) -> u8 {
+    let shpr_addr = (SCB_SHPR_BASE + handler as u32 - 4) as *const u8;
+    unsafe { core::ptr::read_volatile(shpr_addr) >> (8 - NVIC_PRIO_BITS) }
+}
+
+/// Function name: enable_fault_handlers
+///
+/// Description:
+/// Enables the MemManage, BusFault and UsageFault exceptions (SHCSR) so that those faults reach
+/// their own handler instead of escalating to HardFault, and makes integer division by zero
+/// fault (CCR.DIV_0_TRP) instead of returning 0.
+///
+/// # Parameters
+/// - None
+///
+/// # Return
+/// - None
+pub fn enable_fault_handlers() {
+    const MEMFAULTENA: u32 = 1 << 16;
+    const BUSFAULTENA: u32 = 1 << 17;
+    const USGFAULTENA: u32 = 1 << 18;
+    const DIV_0_TRP: u32 = 1 << 4;
+
+    unsafe {
+        let shcsr = read_register(SCB_SHCSR as *mut u32);
+        write_register(SCB_SHCSR as *mut u32, shcsr | MEMFAULTENA | BUSFAULTENA | USGFAULTENA);
+        let ccr = read_register(SCB_CCR as *mut u32);
+        write_register(SCB_CCR as *mut u32, ccr | DIV_0_TRP);
+        core::arch::asm!("dsb", "isb", options(nostack, preserves_flags));
+    }
+}
+
+/// Function name: set_vector_table
+///
+/// Description:
+/// Relocates the vector table by writing VTOR, e.g. when an application is started by a
+/// bootloader or the table is copied to RAM. The table (98 entries) must be 512-byte aligned.
+///
+/// # Safety
+/// `address` must point to a valid vector table that stays in place while in use.
+///
+/// # Parameters
+/// - `address`: Address of the new vector table.
+///
+/// # Return
+/// - None
+pub unsafe fn set_vector_table(address: u32) {
+    if !address.is_multiple_of(512) {
+        panic!("Vector table at {:#010x} is not 512-byte aligned", address);
+    }
+    unsafe {
+        write_register(SCB_VTOR as *mut u32, address);
+        core::arch::asm!("dsb", "isb", options(nostack, preserves_flags));
+    }
+}
+
+/// Returns the current vector table address (VTOR).
+pub fn get_vector_table() -> u32 {
+    unsafe { read_register(SCB_VTOR as *mut u32) }
+}
+
+/// Function name: start_image
+///
+/// Description:
+/// Starts another firmware image the way the core starts after reset: points VTOR at its vector
+/// table, loads MSP from entry 0 and branches to the reset handler in entry 1. Used by the
+/// bootloader; the caller must leave no interrupt or peripheral (SysTick included) running.
+///
+/// # Safety
+/// `vector_table` must hold a valid image vector table (512-byte aligned).
+///
+/// # Parameters
+/// - `vector_table`: Address of the vector table of the image.
+///
+/// # Return
+/// - Never returns.
+pub unsafe fn start_image(vector_table: u32) -> ! {
+    unsafe {
+        let stack_pointer = core::ptr::read_volatile(vector_table as *const u32) as usize;
+        let reset_handler = core::ptr::read_volatile((vector_table + 4) as *const u32) as usize;
+        set_vector_table(vector_table);
+        core::arch::asm!(
+            "msr msp, {sp}",
+            "isb",
+            "bx {reset}",
+            sp = in(reg) stack_pointer,
+            reset = in(reg) reset_handler,
+            options(noreturn, nostack),
+        );
+    }
+}
+
+/// Requests a PendSV exception (ICSR.PENDSVSET), used to defer a context switch.
+pub fn set_pendsv() {
+    const PENDSVSET: u32 = 1 << 28;
+    unsafe {
+        // ICSR bits are write-1-to-act: other fields ignore a 0
+        write_register(SCB_ICSR as *mut u32, PENDSVSET);
+    }
+}
+
+/// Function name: set_sleep_deep
+///
+/// Description:
+/// Selects what `wait_for_interrupt` enters (SCR.SLEEPDEEP): the Sleep mode (core clock stopped)
+/// or the deep sleep, which the PWR controller turns into Stop or Standby (see `pwr`).
+///
+/// # Parameters
+/// - `deep`: `true` for deep sleep.
+///
+/// # Return
+/// - None
+pub fn set_sleep_deep(deep: bool) {
+    const SLEEPDEEP: u32 = 1 << 2;
+    unsafe {
+        let scr = read_register(SCB_SCR as *mut u32);
+        write_register(SCB_SCR as *mut u32, if deep { scr | SLEEPDEEP } else { scr & !SLEEPDEEP });
+    }
+}
+
+/// Function name: set_sleep_on_exit
+///
+/// Description:
+/// Selects whether the core goes back to sleep when it returns from the last active exception to
+/// Thread mode (SCR.SLEEPONEXIT), so that an interrupt-driven program sleeps without running any
+/// Thread mode code between interrupts.
+///
+/// # Parameters
+/// - `enable`: `true` to sleep on exit.
+///
+/// # Return
+/// - None
+pub fn set_sleep_on_exit(enable: bool) {
+    const SLEEPONEXIT: u32 = 1 << 1;
+    unsafe {
+        let scr = read_register(SCB_SCR as *mut u32);
+        write_register(SCB_SCR as *mut u32, if enable { scr | SLEEPONEXIT } else { scr & !SLEEPONEXIT });
+    }
+}
+
+/// Function name: wait_for_interrupt
+///
+/// Description:
+/// Executes WFI after completing outstanding memory accesses: the core sleeps until an interrupt
+/// (or debug event) wakes it. An interrupt that is enabled in the NVIC wakes the core even when
+/// masked by PRIMASK, in which case execution simply continues after the WFI; one masked by
+/// BASEPRI does not.
+///
+/// # Parameters
+/// - None
+///
+/// # Return
+/// - None
+pub fn wait_for_interrupt() {
+    unsafe {
+        core::arch::asm!("dsb", "wfi", "isb", options(nostack, preserves_flags));
+    }
+}
+
+/// Returns the lowest-numbered pending exception (ICSR.VECTPENDING), 0 if none.
+pub fn get_pending_exception() -> u32 {
+    unsafe { (read_register(SCB_ICSR as *mut u32) >> 12) & 0x1FF }
+}
+/// Function name: get_active_exception
+///
+/// Description:
+/// Reads the IPSR register, which holds the number of the exception currently being serviced.
+/// `0` means the processor is in Thread mode (task context); `16 + n` means IRQ `n` is active.
+///
+/// # Parameters
+/// - None
+///
+/// # Return
+/// - The active exception number.
+pub fn get_active_exception() -> u32 {
+    let ipsr: usize;
+    unsafe {
+        core::arch::asm!("mrs {}, IPSR", out(reg) ipsr, options(nomem, nostack, preserves_flags));
+    }
+    (ipsr as u32) & 0x1FF
+}
+
+/// Function name: is_in_interrupt
+///
+/// Description:
+/// Reports whether the caller runs in Handler mode (inside an exception or interrupt handler).
+///
+/// # Parameters
+/// - None
+///
+/// # Return
+/// - `true` if called from an exception/interrupt handler, `false` from task context.
+pub fn is_in_interrupt() -> bool {
+    get_active_exception() != 0
+}
+
+/// Function name: system_reset
+///
+/// Description:
+/// Requests a system reset by setting SYSRESETREQ in the AIRCR register (keeping the current
+/// priority grouping) and waits for the reset to take effect.
+///
+/// # Parameters
+/// - None
+///
+/// # Return
+/// - Never returns.
+#[allow(clippy::empty_loop)]
+pub fn system_reset() -> ! {
+    const VECTKEY: u32 = 0x5FA << 16;
+    const PRIGROUP_MASK: u32 = 0x700;
+    const SYSRESETREQ: u32 = 1 << 2;
+
+    let scb_aircr = SCB_AIRCR_BASE as *mut u32;
+
+    unsafe {
+        core::arch::asm!("dsb", options(nostack, preserves_flags));
+        let current = read_register(scb_aircr);
+        write_register(scb_aircr, VECTKEY | (current & PRIGROUP_MASK) | SYSRESETREQ);
+        core::arch::asm!("dsb", options(nostack, preserves_flags));
+    }
+
+    loop {}
+}
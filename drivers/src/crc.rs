@@ -0,0 +1,185 @@
+#![allow(dead_code)]
+
+/// # CRC Calculation Unit Driver Module
+///
+/// This module computes the CRC-32 used throughout the firmware (IEEE 802.3, the zlib/Ethernet
+/// CRC: reflected polynomial 0xEDB88320, initial value and final XOR 0xFFFFFFFF), the one
+/// `boot_format::crc32` and the crash log use, with the CRC calculation unit of the STM32F407.
+///
+/// ## Hardware
+///
+/// The unit only implements the non-reflected CRC-32/MPEG-2 over whole 32-bit words, fed MSB
+/// first, and has a single running state. `boot_format::WordCrc32` turns it into the IEEE CRC
+/// (bit-reversed words, leftover bytes finished in software); that part is tested on the host.
+///
+/// ## Streaming
+///
+/// `Crc32::new` starts a computation, `update` feeds it any number of bytes in pieces of any size
+/// and `finalize` returns the CRC:
+///
+/// ```ignore
+/// let mut crc = Crc32::new();
+/// crc.update(header);
+/// crc.update(payload);
+/// let value = crc.finalize();
+/// ```
+///
+/// A `Crc32` uses the hardware unit when it is free and falls back to `boot_format::Crc32` in
+/// software otherwise (another computation in progress, possibly in another task or an
+/// ISR), so computations never interfere. Both give identical results; `Crc32::software` forces
+/// the software path.
+use core::sync::atomic::{AtomicBool, Ordering};
+use boot_format::{Crc32Unit, WordCrc32};
+use crate::stm32f407_registers::*;
+use crate::read_write::{read_register, write_register, reg_write_bit};
+
+const RCC_AHB1ENR_CRCEN: u32 = 12;
+
+// Register offsets
+const CRC_DR: u32 = 0x00;
+const CRC_CR: u32 = 0x08;
+
+// CR bits
+const CR_RESET: u32 = 1 << 0;
+
+/// The hardware unit is owned by a `Crc32`.
+static HARDWARE_BUSY: AtomicBool = AtomicBool::new(false);
+
+fn reg(offset: u32) -> *mut u32 {
+    (CRC_BASE + offset) as *mut u32
+}
+
+/// The CRC calculation unit, while a `Crc32` owns it.
+#[derive(Debug)]
+struct HardwareUnit;
+
+impl Crc32Unit for HardwareUnit {
+    fn feed(&mut self, word: u32) {
+        unsafe {
+            write_register(reg(CRC_DR), word);
+        }
+    }
+
+    fn value(&self) -> u32 {
+        unsafe { read_register(reg(CRC_DR)) }
+    }
+}
+
+#[derive(Debug)]
+enum Engine {
+    /// Hardware unit, with up to three bytes waiting for a whole word.
+    Hardware(WordCrc32<HardwareUnit>),
+    Software(boot_format::Crc32),
+}
+
+/// A CRC-32 computation in progress.
+pub struct Crc32 {
+    engine: Engine,
+}
+
+impl Crc32 {
+    /// Function name: `Crc32::new`
+    ///
+    /// Description:
+    /// Starts a CRC-32 computation on the hardware unit, or in software if the unit is in use.
+    ///
+    /// Parameters:
+    /// - None
+    ///
+    /// Return:
+    /// - The computation, ready for `update`.
+    pub fn new() -> Self {
+        if HARDWARE_BUSY.swap(true, Ordering::Acquire) {
+            return Crc32::software();
+        }
+        unsafe {
+            reg_write_bit(RCC_AHB1ENR as *mut u32, RCC_AHB1ENR_CRCEN, true);
+            write_register(reg(CRC_CR), CR_RESET);
+        }
+        Crc32 { engine: Engine::Hardware(WordCrc32::new(HardwareUnit)) }
+    }
+
+    /// Function name: `Crc32::software`
+    ///
+    /// Description:
+    /// Starts a CRC-32 computation in software, leaving the hardware unit alone.
+    ///
+    /// Parameters:
+    /// - None
+    ///
+    /// Return:
+    /// - The computation, ready for `update`.
+    pub const fn software() -> Self {
+        Crc32 { engine: Engine::Software(boot_format::Crc32::new()) }
+    }
+
+    /// Whether the computation runs on the hardware unit.
+    pub fn is_hardware(&self) -> bool {
+        matches!(self.engine, Engine::Hardware(_))
+    }
+
+    /// Function name: `Crc32::update`
+    ///
+    /// Description:
+    /// Feeds `data` into the computation.
+    ///
+    /// Parameters:
+    /// - `data`: Next bytes of the message, of any length and alignment.
+    ///
+    /// Return:
+    /// - None
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.engine {
+            Engine::Hardware(crc) => crc.update(data),
+            Engine::Software(crc) => crc.update(data),
+        }
+    }
+
+    /// Function name: `Crc32::finalize`
+    ///
+    /// Description:
+    /// Ends the computation, releasing the hardware unit.
+    ///
+    /// Parameters:
+    /// - None
+    ///
+    /// Return:
+    /// - CRC-32 of all the bytes fed.
+    pub fn finalize(self) -> u32 {
+        // Drop releases the unit
+        match &self.engine {
+            Engine::Hardware(crc) => crc.finish(),
+            Engine::Software(crc) => crc.finish(),
+        }
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+impl Drop for Crc32 {
+    fn drop(&mut self) {
+        if self.is_hardware() {
+            HARDWARE_BUSY.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// Function name: `crc32`
+///
+/// Description:
+/// Computes the CRC-32 of `data` in one call (hardware unit if free, software otherwise).
+///
+/// Parameters:
+/// - `data`: Message bytes.
+///
+/// Return:
+/// - CRC-32 of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finalize()
+}
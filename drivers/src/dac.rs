@@ -0,0 +1,400 @@
+#![allow(dead_code)]
+
+/// # DAC Driver Module
+///
+/// This module drives the two 12-bit DAC channels of the STM32F407 (channel 1 on PA4, channel 2 on
+/// PA5): direct writes in any of the three data formats, conversions triggered by a timer or by
+/// software, arbitrary waveform playback from a circular DMA buffer, and the built-in noise and
+/// triangle generators.
+///
+/// ## Instance Conventions
+///
+/// - `channel: u32` — DAC channel (1 or 2).
+///
+/// ## Updating the Output
+///
+/// Without a trigger, a value written to a holding register reaches the output one APB1 cycle later.
+/// With a trigger, the output only changes on the trigger event, which is what paces DMA playback
+/// and the wave generators.
+///
+/// ## Waveform Playback
+///
+/// `dac_start_waveform` programs a basic or general-purpose timer to overflow at the sample rate,
+/// routes its update event to the DAC through TRGO, and lets DMA1 feed one sample per event from a
+/// circular buffer. If the DMA falls behind, the channel reports an underrun through the callback
+/// registered with `dac_set_underrun_callback` (from the shared TIM6/DAC interrupt).
+use crate::stm32f407_registers::*;
+use crate::read_write::{read_register, write_register, reg_write_bit, reg_write_bits};
+use crate::cortex_m4::enable_irq;
+use crate::dma::{dma_claim, DataSize, DmaConfig, Priority, ReadBuffer, Transfer};
+use crate::gpio::{gpio_configure_mode, GPIO_MODE_ANALOG};
+use crate::timer::{timer_init, timer_set_frequency, timer_set_trigger_output, timer_start, timer_stop, TriggerOutput};
+
+pub const DAC1: u32 = 1;
+pub const DAC2: u32 = 2;
+
+/// Largest 12-bit output code.
+pub const DAC_MAX: u16 = 0xFFF;
+
+const NUM_CHANNELS: usize = 2;
+const RCC_APB1ENR_DACEN: u32 = 29;
+
+// Register offsets
+const DAC_CR: u32 = 0x00;
+const DAC_SWTRIGR: u32 = 0x04;
+const DAC_DHR12R1: u32 = 0x08;
+const DAC_DHR12L1: u32 = 0x0C;
+const DAC_DHR8R1: u32 = 0x10;
+const DAC_DHR12RD: u32 = 0x20;
+const DAC_DHR12LD: u32 = 0x24;
+const DAC_DHR8RD: u32 = 0x28;
+const DAC_DOR1: u32 = 0x2C;
+const DAC_SR: u32 = 0x34;
+
+// CR bits, channel 1 (channel 2 is the same layout shifted by 16)
+const CR_EN: u32 = 0;
+const CR_BOFF: u32 = 1;
+const CR_TEN: u32 = 2;
+const CR_TSEL: u32 = 3;
+const CR_WAVE: u32 = 6;
+const CR_MAMP: u32 = 8;
+const CR_DMAEN: u32 = 12;
+const CR_DMAUDRIE: u32 = 13;
+
+// SR bits, channel 1
+const SR_DMAUDR: u32 = 13;
+
+/// Format of the value written to the DAC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    /// 12-bit value in bits 0–11.
+    Right12,
+    /// 12-bit value in bits 4–15 (a `u16` scaled to full range).
+    Left12,
+    /// 8-bit value in bits 0–7 (4 LSBs of the output are zero).
+    Right8,
+}
+
+/// Conversion trigger (RM0090 14.5.4, TSEL).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DacTrigger {
+    Tim6Trgo = 0b000,
+    Tim8Trgo = 0b001,
+    Tim7Trgo = 0b010,
+    Tim5Trgo = 0b011,
+    Tim2Trgo = 0b100,
+    Tim4Trgo = 0b101,
+    Exti9 = 0b110,
+    /// `dac_software_trigger`.
+    Software = 0b111,
+}
+
+/// Built-in wave generators. Both add to the value in the holding register at every trigger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wave {
+    /// Pseudo-random noise from a 12-bit LFSR, keeping its `bits` (1–12) lowest bits.
+    Noise { bits: u8 },
+    /// Triangle counting up to `2^bits - 1` (`bits` 1–12) and back down, one step per trigger.
+    Triangle { bits: u8 },
+}
+
+/// Callback invoked from interrupt context with the channel that underran.
+pub type DacCallback = fn(channel: u32);
+
+static mut UNDERRUN_CALLBACK: Option<DacCallback> = None;
+
+fn check_channel(channel: u32) {
+    assert!(channel == DAC1 || channel == DAC2, "Invalid DAC channel: {}. Valid range is 1 – 2.", channel);
+}
+
+fn reg(offset: u32) -> *mut u32 {
+    (DAC_BASE + offset) as *mut u32
+}
+
+/// Bit position of a channel-1 CR/SR bit for the given channel.
+fn bit(channel: u32, bit: u32) -> u32 {
+    bit + (channel - 1) * 16
+}
+
+fn dac_is_enabled() -> bool {
+    unsafe { read_register(RCC_APB1ENR as *mut u32) & (1 << RCC_APB1ENR_DACEN) != 0 }
+}
+
+/// Function name: `dac_init`
+///
+/// Description:
+/// Enables the DAC clock, puts the channel pin (PA4 / PA5) in analog mode and enables the channel
+/// with no trigger and no wave generation.
+///
+/// Parameters:
+/// - `channel`: DAC channel (1 or 2).
+/// - `output_buffer`: Enable the output buffer (lower output impedance, cannot reach the rails).
+///
+/// Return:
+/// - None
+pub fn dac_init(channel: u32, output_buffer: bool) {
+    check_channel(channel);
+    unsafe {
+        reg_write_bit(RCC_APB1ENR as *mut u32, RCC_APB1ENR_DACEN, true);
+    }
+    // PA4 / PA5
+    gpio_configure_mode(0, 3 + channel, GPIO_MODE_ANALOG);
+
+    unsafe {
+        reg_write_bits(reg(DAC_CR), 0, bit(channel, 0), 16);
+        reg_write_bit(reg(DAC_CR), bit(channel, CR_BOFF), !output_buffer);
+        reg_write_bit(reg(DAC_CR), bit(channel, CR_EN), true);
+    }
+}
+
+/// Disable the channel; its output goes high-impedance.
+pub fn dac_disable(channel: u32) {
+    check_channel(channel);
+    unsafe {
+        reg_write_bit(reg(DAC_CR), bit(channel, CR_EN), false);
+    }
+}
+
+/// Function name: `dac_write`
+///
+/// Description:
+/// Writes a value to the channel holding register. It reaches the output immediately, or at the
+/// next trigger if one is set.
+///
+/// Parameters:
+/// - `channel`: DAC channel (1 or 2).
+/// - `value`: Output code in the chosen format.
+/// - `alignment`: Data format.
+///
+/// Return:
+/// - None
+pub fn dac_write(channel: u32, value: u16, alignment: Alignment) {
+    check_channel(channel);
+    let offset = match alignment {
+        Alignment::Right12 => DAC_DHR12R1,
+        Alignment::Left12 => DAC_DHR12L1,
+        Alignment::Right8 => DAC_DHR8R1,
+    };
+    unsafe {
+        // The channel 2 holding registers follow the three of channel 1
+        write_register(reg(offset + (channel - 1) * 12), value as u32);
+    }
+}
+
+/// Function name: `dac_write_dual`
+///
+/// Description:
+/// Writes both channels with a single access, so that they change on the same cycle.
+///
+/// Parameters:
+/// - `value1`: Channel 1 code.
+/// - `value2`: Channel 2 code.
+/// - `alignment`: Data format of both values.
+///
+/// Return:
+/// - None
+pub fn dac_write_dual(value1: u16, value2: u16, alignment: Alignment) {
+    let (offset, shift) = match alignment {
+        Alignment::Right12 => (DAC_DHR12RD, 16),
+        Alignment::Left12 => (DAC_DHR12LD, 16),
+        Alignment::Right8 => (DAC_DHR8RD, 8),
+    };
+    unsafe {
+        write_register(reg(offset), ((value2 as u32) << shift) | value1 as u32);
+    }
+}
+
+/// Code currently driven on the output (12-bit, right-aligned).
+pub fn dac_read_output(channel: u32) -> u16 {
+    check_channel(channel);
+    unsafe { read_register(reg(DAC_DOR1 + (channel - 1) * 4)) as u16 }
+}
+
+/// Function name: `dac_set_trigger`
+///
+/// Description:
+/// Selects the event that transfers the holding register to the output. Required by DMA playback
+/// and by the wave generators.
+///
+/// Parameters:
+/// - `channel`: DAC channel (1 or 2).
+/// - `trigger`: Trigger source, or `None` to update on every write.
+///
+/// Return:
+/// - None
+pub fn dac_set_trigger(channel: u32, trigger: Option<DacTrigger>) {
+    check_channel(channel);
+    unsafe {
+        // TSEL may only change while TEN is clear
+        reg_write_bit(reg(DAC_CR), bit(channel, CR_TEN), false);
+        if let Some(trigger) = trigger {
+            reg_write_bits(reg(DAC_CR), trigger as u32, bit(channel, CR_TSEL), 3);
+            reg_write_bit(reg(DAC_CR), bit(channel, CR_TEN), true);
+        }
+    }
+}
+
+/// Trigger a conversion on a channel using `DacTrigger::Software`.
+pub fn dac_software_trigger(channel: u32) {
+    check_channel(channel);
+    unsafe {
+        reg_write_bit(reg(DAC_SWTRIGR), channel - 1, true);
+    }
+}
+
+/// Function name: `dac_set_wave`
+///
+/// Description:
+/// Enables a wave generator on the channel, or turns it off. The generated value is added to the
+/// holding register (the triangle offset or noise baseline) and advances at every trigger, so a
+/// trigger must be set with `dac_set_trigger`.
+///
+/// Parameters:
+/// - `channel`: DAC channel (1 or 2).
+/// - `wave`: Generator, or `None` for plain output.
+///
+/// Return:
+/// - None
+pub fn dac_set_wave(channel: u32, wave: Option<Wave>) {
+    check_channel(channel);
+    let (mode, bits) = match wave {
+        None => (0b00, 1),
+        Some(Wave::Noise { bits }) => (0b01, bits),
+        Some(Wave::Triangle { bits }) => (0b10, bits),
+    };
+    assert!((1..=12).contains(&bits), "Invalid DAC wave width: {} bits", bits);
+    unsafe {
+        // MAMP = n selects n + 1 bits of noise, or a triangle amplitude of 2^(n+1) - 1
+        reg_write_bits(reg(DAC_CR), bits as u32 - 1, bit(channel, CR_MAMP), 4);
+        reg_write_bits(reg(DAC_CR), mode, bit(channel, CR_WAVE), 2);
+    }
+}
+
+/// DAC trigger driven by the TRGO of a timer.
+fn trigger_for_timer(tim: u32) -> DacTrigger {
+    match tim {
+        2 => DacTrigger::Tim2Trgo,
+        4 => DacTrigger::Tim4Trgo,
+        5 => DacTrigger::Tim5Trgo,
+        6 => DacTrigger::Tim6Trgo,
+        7 => DacTrigger::Tim7Trgo,
+        8 => DacTrigger::Tim8Trgo,
+        _ => panic!("TIM{} cannot trigger the DAC", tim),
+    }
+}
+
+/// (controller, stream, channel) of the DMA request of each DAC channel (RM0090 table 42).
+fn dac_dma_stream(channel: u32) -> (u32, u32, u32) {
+    match channel {
+        1 => (1, 5, 7),
+        2 => (1, 6, 7),
+        _ => panic!("Invalid DAC channel: {}", channel),
+    }
+}
+
+/// Function name: `dac_start_waveform`
+///
+/// Description:
+/// Plays `samples` (12-bit, right-aligned) on the channel in a loop, one sample per overflow of
+/// `tim`. The timer is initialised and runs at `sample_rate_hz`; its DMA stream is claimed until the
+/// transfer is stopped. End playback with `dac_stop_waveform` and then `Transfer::stop`.
+///
+/// Parameters:
+/// - `channel`: DAC channel (1 or 2), initialised with `dac_init`.
+/// - `tim`: Pacing timer (2, 4, 5, 6, 7 or 8; TIM6/TIM7 are the natural choice).
+/// - `sample_rate_hz`: Samples per second.
+/// - `samples`: One period of the waveform.
+///
+/// Return:
+/// - The running circular DMA transfer.
+pub fn dac_start_waveform<B>(channel: u32, tim: u32, sample_rate_hz: u32, samples: B) -> Transfer<B>
+where
+    B: ReadBuffer<Word = u16>,
+{
+    check_channel(channel);
+    let trigger = trigger_for_timer(tim);
+    let (dma, stream, dma_channel) = dac_dma_stream(channel);
+    let stream = dma_claim(dma, stream).unwrap_or_else(|| panic!("DAC{} DMA stream already in use", channel));
+
+    timer_init(tim);
+    timer_set_frequency(tim, sample_rate_hz);
+    timer_set_trigger_output(tim, TriggerOutput::Update);
+
+    let config = DmaConfig::new(dma_channel)
+        .peripheral_size(DataSize::HalfWord)
+        .priority(Priority::High)
+        .circular(true);
+    let peripheral = DAC_BASE + DAC_DHR12R1 + (channel - 1) * 12;
+    // Safe: DHR12Rx is the data register of the channel behind the selected DMA request.
+    let transfer = unsafe { Transfer::memory_to_peripheral(stream, &config, peripheral, samples) };
+
+    dac_set_trigger(channel, Some(trigger));
+    unsafe {
+        write_register(reg(DAC_SR), 1 << bit(channel, SR_DMAUDR));
+        reg_write_bit(reg(DAC_CR), bit(channel, CR_DMAUDRIE), true);
+        reg_write_bit(reg(DAC_CR), bit(channel, CR_DMAEN), true);
+    }
+    enable_irq(TIM6_DAC_IRQ);
+    timer_start(tim);
+    transfer
+}
+
+/// Function name: `dac_stop_waveform`
+///
+/// Description:
+/// Stops the pacing timer and detaches the DMA request. The output holds the last sample.
+///
+/// Parameters:
+/// - `channel`: DAC channel (1 or 2).
+/// - `tim`: Timer passed to `dac_start_waveform`.
+///
+/// Return:
+/// - None
+pub fn dac_stop_waveform(channel: u32, tim: u32) {
+    check_channel(channel);
+    timer_stop(tim);
+    unsafe {
+        reg_write_bit(reg(DAC_CR), bit(channel, CR_DMAEN), false);
+        reg_write_bit(reg(DAC_CR), bit(channel, CR_DMAUDRIE), false);
+    }
+    dac_set_trigger(channel, None);
+}
+
+/// Register (or remove) the function called when a channel underruns during DMA playback.
+pub fn dac_set_underrun_callback(callback: Option<DacCallback>) {
+    unsafe {
+        UNDERRUN_CALLBACK = callback;
+    }
+}
+
+/// Function name: `dac_irq_handler`
+///
+/// Description:
+/// Clears the DMA underrun flags and reports them. An underrun stops the DMA request of the
+/// channel; playback must be restarted. Called from the TIM6/DAC shared interrupt.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - None
+pub fn dac_irq_handler() {
+    if !dac_is_enabled() {
+        return;
+    }
+    for channel in [DAC1, DAC2] {
+        let underrun = unsafe {
+            let sr = read_register(reg(DAC_SR));
+            let flag = 1 << bit(channel, SR_DMAUDR);
+            if sr & flag == 0 {
+                continue;
+            }
+            // rc_w1
+            write_register(reg(DAC_SR), flag);
+            UNDERRUN_CALLBACK
+        };
+        if let Some(callback) = underrun {
+            callback(channel);
+        }
+    }
+}
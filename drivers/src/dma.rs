@@ -0,0 +1,1020 @@
+#![allow(dead_code)]
+
+/// # DMA Controller Driver Module
+///
+/// This module drives the 16 streams of DMA1 and DMA2 on the STM32F407.
+///
+/// ## Instance Conventions
+///
+/// - `dma: u32` — controller number: `1` → DMA1, `2` → DMA2 (both on AHB1).
+/// - `stream: u32` — stream number within the controller (0–7).
+/// - `channel: u32` — request channel (0–7) selecting which peripheral drives the stream,
+///   from the request mapping of RM0090 tables 42/43 (e.g. SPI1_RX is DMA2 stream 0 channel 3).
+///
+/// ## Allocation
+///
+/// A stream must be claimed with `dma_claim` before use. The returned `DmaStream` is the only handle
+/// to it; dropping the handle stops the stream and frees it for another driver.
+///
+/// ## Transfers
+///
+/// - `Transfer` owns a `'static` buffer for the whole duration of a transfer, so the CPU cannot touch
+///   memory the controller is reading or writing. The buffer (and the stream) are handed back by
+///   `Transfer::wait` or `Transfer::stop`. Peripheral-to-memory, memory-to-peripheral and
+///   memory-to-memory (DMA2 only) transfers are supported, in normal or circular mode.
+/// - `DoubleBufferTransfer` runs a stream in double-buffer mode and gives access to the buffer the
+///   controller is not currently using.
+/// - `DmaStream::start` / `DmaStream::wait` are the raw, unsafe interface used by drivers that
+///   block on a transfer of a borrowed buffer (e.g. `spi`).
+///
+/// ## FIFO
+///
+/// Streams run in direct mode unless `DmaConfig::fifo` selects a FIFO threshold, which is also
+/// required for bursts and for different peripheral and memory data sizes.
+///
+/// ## Callbacks
+///
+/// `DmaStream::set_callback` registers a function called from the stream's interrupt with each
+/// half-transfer, transfer-complete and error event. Without a callback the stream's interrupt
+/// stays disabled and completion is polled.
+use core::sync::atomic::{compiler_fence, AtomicU16, AtomicU32, Ordering};
+use crate::stm32f407_registers::*;
+use crate::read_write::{read_register, write_register, reg_write_bit};
+use crate::cortex_m4::enable_irq;
+
+pub const DMA1: u32 = 1;
+pub const DMA2: u32 = 2;
+
+const NUM_STREAMS: usize = 16;
+
+// Controller register offsets
+const DMA_LISR: u32 = 0x00;
+const DMA_HISR: u32 = 0x04;
+const DMA_LIFCR: u32 = 0x08;
+const DMA_HIFCR: u32 = 0x0C;
+
+// Stream register offsets (stream x at 0x10 + 0x18 * x)
+const DMA_SXCR: u32 = 0x10;
+const DMA_SXNDTR: u32 = 0x14;
+const DMA_SXPAR: u32 = 0x18;
+const DMA_SXM0AR: u32 = 0x1C;
+const DMA_SXM1AR: u32 = 0x20;
+const DMA_SXFCR: u32 = 0x24;
+const DMA_STREAM_STRIDE: u32 = 0x18;
+
+// SxCR bits
+const CR_EN: u32 = 1 << 0;
+const CR_DMEIE: u32 = 1 << 1;
+const CR_TEIE: u32 = 1 << 2;
+const CR_HTIE: u32 = 1 << 3;
+const CR_TCIE: u32 = 1 << 4;
+const CR_DIR: u32 = 6;
+const CR_CIRC: u32 = 1 << 8;
+const CR_PINC: u32 = 1 << 9;
+const CR_MINC: u32 = 1 << 10;
+const CR_PSIZE: u32 = 11;
+const CR_MSIZE: u32 = 13;
+const CR_PL: u32 = 16;
+const CR_DBM: u32 = 1 << 18;
+const CR_CT: u32 = 1 << 19;
+const CR_PBURST: u32 = 21;
+const CR_MBURST: u32 = 23;
+const CR_CHSEL: u32 = 25;
+
+// SxFCR bits
+const FCR_FTH: u32 = 0;
+const FCR_DMDIS: u32 = 1 << 2;
+const FCR_FEIE: u32 = 1 << 7;
+
+// Stream flags, relative to the stream's offset in LISR/HISR
+const FLAG_FE: u32 = 1 << 0;
+const FLAG_DME: u32 = 1 << 2;
+const FLAG_TE: u32 = 1 << 3;
+const FLAG_HT: u32 = 1 << 4;
+const FLAG_TC: u32 = 1 << 5;
+const FLAG_ALL: u32 = FLAG_FE | FLAG_DME | FLAG_TE | FLAG_HT | FLAG_TC;
+
+/// Transfer direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    PeripheralToMemory = 0b00,
+    MemoryToPeripheral = 0b01,
+    /// DMA2 only. The peripheral address register holds the source.
+    MemoryToMemory = 0b10,
+}
+
+/// Size of one data item on the peripheral or memory side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataSize {
+    Byte = 0b00,
+    HalfWord = 0b01,
+    Word = 0b10,
+}
+
+impl DataSize {
+    fn bytes(self) -> usize {
+        1 << self as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Low = 0b00,
+    Medium = 0b01,
+    High = 0b10,
+    VeryHigh = 0b11,
+}
+
+/// FIFO level at which the stream moves data to memory (or refills from it).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FifoThreshold {
+    Quarter = 0b00,
+    Half = 0b01,
+    ThreeQuarters = 0b10,
+    Full = 0b11,
+}
+
+/// Burst length on the memory or peripheral side (FIFO mode only).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Burst {
+    Single = 0b00,
+    Incr4 = 0b01,
+    Incr8 = 0b10,
+    Incr16 = 0b11,
+}
+
+/// Stream events reported to callbacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaEvent {
+    HalfTransfer,
+    TransferComplete,
+    TransferError,
+    FifoError,
+    DirectModeError,
+}
+
+/// DMA errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaError {
+    /// Bus error on a memory or peripheral access; the stream was disabled by hardware.
+    Transfer,
+    /// The peripheral issued a request before the previous data was moved (direct mode).
+    DirectMode,
+    /// The controller switched to a buffer while the CPU was still accessing it.
+    BufferOverrun,
+}
+
+/// Callback invoked from interrupt context with the controller, stream and event.
+pub type DmaCallback = fn(dma: u32, stream: u32, event: DmaEvent);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaConfig {
+    pub channel: u32,
+    pub direction: Direction,
+    pub peripheral_size: DataSize,
+    pub memory_size: DataSize,
+    pub peripheral_increment: bool,
+    pub memory_increment: bool,
+    pub priority: Priority,
+    pub circular: bool,
+    /// `None` runs the stream in direct mode.
+    pub fifo: Option<FifoThreshold>,
+    pub peripheral_burst: Burst,
+    pub memory_burst: Burst,
+}
+
+impl DmaConfig {
+    /// Byte-sized, peripheral-to-memory, memory increment, medium priority, direct mode.
+    pub const fn new(channel: u32) -> Self {
+        DmaConfig {
+            channel,
+            direction: Direction::PeripheralToMemory,
+            peripheral_size: DataSize::Byte,
+            memory_size: DataSize::Byte,
+            peripheral_increment: false,
+            memory_increment: true,
+            priority: Priority::Medium,
+            circular: false,
+            fifo: None,
+            peripheral_burst: Burst::Single,
+            memory_burst: Burst::Single,
+        }
+    }
+
+    pub const fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Set both the peripheral and memory data size.
+    pub const fn data_size(mut self, size: DataSize) -> Self {
+        self.peripheral_size = size;
+        self.memory_size = size;
+        self
+    }
+
+    pub const fn peripheral_size(mut self, size: DataSize) -> Self {
+        self.peripheral_size = size;
+        self
+    }
+
+    pub const fn memory_size(mut self, size: DataSize) -> Self {
+        self.memory_size = size;
+        self
+    }
+
+    pub const fn peripheral_increment(mut self, increment: bool) -> Self {
+        self.peripheral_increment = increment;
+        self
+    }
+
+    pub const fn memory_increment(mut self, increment: bool) -> Self {
+        self.memory_increment = increment;
+        self
+    }
+
+    pub const fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub const fn circular(mut self, circular: bool) -> Self {
+        self.circular = circular;
+        self
+    }
+
+    pub const fn fifo(mut self, threshold: FifoThreshold) -> Self {
+        self.fifo = Some(threshold);
+        self
+    }
+
+    pub const fn bursts(mut self, peripheral: Burst, memory: Burst) -> Self {
+        self.peripheral_burst = peripheral;
+        self.memory_burst = memory;
+        self
+    }
+}
+
+/// Word types a `Transfer` buffer can be made of.
+pub trait DmaWord: Copy + 'static {
+    const SIZE: DataSize;
+}
+
+impl DmaWord for u8 {
+    const SIZE: DataSize = DataSize::Byte;
+}
+
+impl DmaWord for u16 {
+    const SIZE: DataSize = DataSize::HalfWord;
+}
+
+impl DmaWord for u32 {
+    const SIZE: DataSize = DataSize::Word;
+}
+
+/// A buffer the controller may read from for as long as the implementor exists.
+///
+/// # Safety
+/// The returned pointer and length must stay valid, and the memory must not move, until the
+/// buffer is dropped.
+pub unsafe trait ReadBuffer {
+    type Word: DmaWord;
+    fn read_buffer(&self) -> (*const Self::Word, usize);
+}
+
+/// A buffer the controller may write to for as long as the implementor exists.
+///
+/// # Safety
+/// Same requirements as `ReadBuffer`, and nothing else may access the memory.
+pub unsafe trait WriteBuffer {
+    type Word: DmaWord;
+    fn write_buffer(&mut self) -> (*mut Self::Word, usize);
+}
+
+unsafe impl<W: DmaWord> ReadBuffer for &'static [W] {
+    type Word = W;
+    fn read_buffer(&self) -> (*const W, usize) {
+        (self.as_ptr(), self.len())
+    }
+}
+
+unsafe impl<W: DmaWord> ReadBuffer for &'static mut [W] {
+    type Word = W;
+    fn read_buffer(&self) -> (*const W, usize) {
+        (self.as_ptr(), self.len())
+    }
+}
+
+unsafe impl<W: DmaWord> WriteBuffer for &'static mut [W] {
+    type Word = W;
+    fn write_buffer(&mut self) -> (*mut W, usize) {
+        (self.as_mut_ptr(), self.len())
+    }
+}
+
+unsafe impl<W: DmaWord, const N: usize> ReadBuffer for &'static [W; N] {
+    type Word = W;
+    fn read_buffer(&self) -> (*const W, usize) {
+        (self.as_ptr(), N)
+    }
+}
+
+unsafe impl<W: DmaWord, const N: usize> ReadBuffer for &'static mut [W; N] {
+    type Word = W;
+    fn read_buffer(&self) -> (*const W, usize) {
+        (self.as_ptr(), N)
+    }
+}
+
+unsafe impl<W: DmaWord, const N: usize> WriteBuffer for &'static mut [W; N] {
+    type Word = W;
+    fn write_buffer(&mut self) -> (*mut W, usize) {
+        (self.as_mut_ptr(), N)
+    }
+}
+
+static CLAIMED: AtomicU16 = AtomicU16::new(0);
+static LATCHED_ERRORS: [AtomicU32; NUM_STREAMS] = [const { AtomicU32::new(0) }; NUM_STREAMS];
+static mut CALLBACKS: [Option<DmaCallback>; NUM_STREAMS] = [None; NUM_STREAMS];
+
+/// Function name: `select_dma_base`
+///
+/// Description:
+/// Returns the base address of the given DMA controller.
+///
+/// Parameters:
+/// - `dma`: Controller number (1–2).
+///
+/// Return:
+/// - Base address (`u32`) of the controller.
+fn select_dma_base(dma: u32) -> u32 {
+    match dma {
+        1 => DMA1_BASE,
+        2 => DMA2_BASE,
+        _ => panic!("Invalid DMA: {}. Valid range is 1 – 2.", dma),
+    }
+}
+
+/// Index of a stream in the driver's static tables.
+fn stream_index(dma: u32, stream: u32) -> usize {
+    assert!((1..=2).contains(&dma) && stream < 8, "Invalid DMA stream: DMA{} stream {}", dma, stream);
+    ((dma - 1) * 8 + stream) as usize
+}
+
+/// Returns the NVIC IRQ number of a stream.
+fn stream_irq_number(dma: u32, stream: u32) -> u32 {
+    match (dma, stream) {
+        (1, 0..=6) => DMA1_STREAM0_IRQ + stream,
+        (1, 7) => DMA1_STREAM7_IRQ,
+        (2, 0..=4) => DMA2_STREAM0_IRQ + stream,
+        (2, 5..=7) => DMA2_STREAM5_IRQ + stream - 5,
+        _ => panic!("Invalid DMA stream: DMA{} stream {}", dma, stream),
+    }
+}
+
+/// Bit offset of a stream's flags within LISR/HISR.
+fn flag_shift(stream: u32) -> u32 {
+    [0, 6, 16, 22][(stream % 4) as usize]
+}
+
+/// Function name: `dma_claim`
+///
+/// Description:
+/// Reserves a stream, enabling the controller clock on first use.
+///
+/// Parameters:
+/// - `dma`: Controller number (1–2).
+/// - `stream`: Stream number (0–7).
+///
+/// Return:
+/// - The stream handle, or `None` if another driver already owns it.
+pub fn dma_claim(dma: u32, stream: u32) -> Option<DmaStream> {
+    let bit = 1 << stream_index(dma, stream);
+    if CLAIMED.fetch_or(bit, Ordering::AcqRel) & bit != 0 {
+        return None;
+    }
+
+    unsafe {
+        reg_write_bit(RCC_AHB1ENR as *mut u32, 20 + dma, true);
+    }
+    Some(DmaStream { dma, stream, base: select_dma_base(dma) })
+}
+
+/// Function name: `dma_is_claimed`
+///
+/// Description:
+/// Tells whether a stream is currently owned by a `DmaStream` handle.
+///
+/// Parameters:
+/// - `dma`: Controller number (1–2).
+/// - `stream`: Stream number (0–7).
+///
+/// Return:
+/// - `true` if the stream is in use.
+pub fn dma_is_claimed(dma: u32, stream: u32) -> bool {
+    CLAIMED.load(Ordering::Acquire) & (1 << stream_index(dma, stream)) != 0
+}
+
+/// Exclusive handle to one DMA stream.
+pub struct DmaStream {
+    dma: u32,
+    stream: u32,
+    base: u32,
+}
+
+impl DmaStream {
+    /// Controller number of this stream.
+    pub fn controller(&self) -> u32 {
+        self.dma
+    }
+
+    /// Stream number within its controller.
+    pub fn number(&self) -> u32 {
+        self.stream
+    }
+
+    fn index(&self) -> usize {
+        stream_index(self.dma, self.stream)
+    }
+
+    fn reg(&self, offset: u32) -> *mut u32 {
+        (self.base + offset + DMA_STREAM_STRIDE * self.stream) as *mut u32
+    }
+
+    /// Raw flags of this stream from LISR/HISR.
+    fn flags(&self) -> u32 {
+        let isr = if self.stream < 4 { DMA_LISR } else { DMA_HISR };
+        unsafe { (read_register((self.base + isr) as *mut u32) >> flag_shift(self.stream)) & FLAG_ALL }
+    }
+
+    fn clear_flags(&self, flags: u32) {
+        let ifcr = if self.stream < 4 { DMA_LIFCR } else { DMA_HIFCR };
+        unsafe {
+            write_register((self.base + ifcr) as *mut u32, (flags & FLAG_ALL) << flag_shift(self.stream));
+        }
+    }
+
+    /// Function name: `DmaStream::set_callback`
+    ///
+    /// Description:
+    /// Registers (or removes) the event callback. Takes effect at the next `start`.
+    ///
+    /// Parameters:
+    /// - `callback`: Function called from the stream interrupt, or `None`.
+    ///
+    /// Return:
+    /// - None
+    pub fn set_callback(&mut self, callback: Option<DmaCallback>) {
+        let idx = self.index();
+        unsafe {
+            CALLBACKS[idx] = callback;
+        }
+        if callback.is_some() {
+            enable_irq(stream_irq_number(self.dma, self.stream));
+        }
+    }
+
+    /// Function name: `DmaStream::start`
+    ///
+    /// Description:
+    /// Programs and enables the stream. Any transfer still running is aborted first.
+    ///
+    /// Parameters:
+    /// - `config`: Stream configuration.
+    /// - `peripheral`: Peripheral register address (source address for memory-to-memory).
+    /// - `memory0`: Memory address (destination for memory-to-memory).
+    /// - `memory1`: Second memory address, enabling double-buffer mode.
+    /// - `items`: Number of data items, in units of the peripheral data size.
+    ///
+    /// Return:
+    /// - None
+    ///
+    /// # Safety
+    /// The addresses must be valid for the whole transfer, and the memory must not be accessed by
+    /// the CPU until the transfer has completed or been aborted.
+    pub unsafe fn start(&mut self, config: &DmaConfig, peripheral: u32, memory0: u32, memory1: Option<u32>, items: u16) {
+        assert!(config.channel < 8, "Invalid DMA channel: {}", config.channel);
+        if config.direction == Direction::MemoryToMemory {
+            assert!(self.dma == DMA2, "Memory-to-memory transfers require DMA2");
+            assert!(!config.circular && memory1.is_none(), "Memory-to-memory transfers cannot be circular");
+        }
+
+        let fifo = match (config.fifo, config.direction) {
+            (Some(threshold), _) => Some(threshold),
+            // Direct mode is not allowed for memory-to-memory
+            (None, Direction::MemoryToMemory) => Some(FifoThreshold::Full),
+            (None, _) => None,
+        };
+
+        let mut cr = (config.channel << CR_CHSEL)
+            | ((config.direction as u32) << CR_DIR)
+            | ((config.peripheral_size as u32) << CR_PSIZE)
+            | ((config.memory_size as u32) << CR_MSIZE)
+            | ((config.priority as u32) << CR_PL);
+        if config.peripheral_increment {
+            cr |= CR_PINC;
+        }
+        if config.memory_increment {
+            cr |= CR_MINC;
+        }
+        if config.circular {
+            cr |= CR_CIRC;
+        }
+        if memory1.is_some() {
+            cr |= CR_DBM | CR_CIRC;
+        }
+        if fifo.is_some() {
+            cr |= ((config.peripheral_burst as u32) << CR_PBURST) | ((config.memory_burst as u32) << CR_MBURST);
+        }
+
+        let mut fcr = match fifo {
+            Some(threshold) => FCR_DMDIS | ((threshold as u32) << FCR_FTH),
+            None => 0,
+        };
+
+        let idx = self.index();
+        if unsafe { CALLBACKS[idx] }.is_some() {
+            cr |= CR_TCIE | CR_HTIE | CR_TEIE | CR_DMEIE;
+            if fifo.is_some() {
+                fcr |= FCR_FEIE;
+            }
+        }
+
+        self.abort();
+        self.clear_flags(FLAG_ALL);
+        LATCHED_ERRORS[idx].store(0, Ordering::Release);
+
+        unsafe {
+            write_register(self.reg(DMA_SXPAR), peripheral);
+            write_register(self.reg(DMA_SXM0AR), memory0);
+            write_register(self.reg(DMA_SXM1AR), memory1.unwrap_or(0));
+            write_register(self.reg(DMA_SXNDTR), items as u32);
+            write_register(self.reg(DMA_SXFCR), fcr);
+            write_register(self.reg(DMA_SXCR), cr);
+
+            // Make sure the CPU's writes to the buffers happen before the controller starts.
+            compiler_fence(Ordering::Release);
+            write_register(self.reg(DMA_SXCR), cr | CR_EN);
+        }
+    }
+
+    /// Function name: `DmaStream::abort`
+    ///
+    /// Description:
+    /// Disables the stream and waits until the controller has released it.
+    ///
+    /// Parameters:
+    /// - None
+    ///
+    /// Return:
+    /// - None
+    pub fn abort(&mut self) {
+        unsafe {
+            let cr = read_register(self.reg(DMA_SXCR));
+            write_register(self.reg(DMA_SXCR), cr & !CR_EN);
+            while read_register(self.reg(DMA_SXCR)) & CR_EN != 0 {}
+        }
+        compiler_fence(Ordering::Acquire);
+    }
+
+    /// `true` while the stream is enabled (always, in circular mode, until aborted).
+    pub fn is_busy(&self) -> bool {
+        unsafe { read_register(self.reg(DMA_SXCR)) & CR_EN != 0 }
+    }
+
+    /// Data items left to transfer (in circular mode: before the next wrap).
+    pub fn remaining(&self) -> u16 {
+        unsafe { read_register(self.reg(DMA_SXNDTR)) as u16 }
+    }
+
+    /// Memory buffer the controller is using in double-buffer mode (0 or 1).
+    pub fn current_target(&self) -> u32 {
+        unsafe { (read_register(self.reg(DMA_SXCR)) & CR_CT != 0) as u32 }
+    }
+
+    /// Error flags raised since `start`, whether or not the interrupt handler has cleared them.
+    fn errors(&self) -> Result<(), DmaError> {
+        let flags = self.flags() | LATCHED_ERRORS[self.index()].load(Ordering::Acquire);
+        if flags & FLAG_TE != 0 {
+            Err(DmaError::Transfer)
+        } else if flags & FLAG_DME != 0 {
+            Err(DmaError::DirectMode)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Function name: `DmaStream::wait`
+    ///
+    /// Description:
+    /// Busy-waits for a normal (non-circular) transfer to finish. On error the stream is disabled.
+    ///
+    /// Parameters:
+    /// - None
+    ///
+    /// Return:
+    /// - `Ok(())` once the controller has moved every item, or the error that stopped it.
+    pub fn wait(&mut self) -> Result<(), DmaError> {
+        loop {
+            if let Err(err) = self.errors() {
+                self.abort();
+                return Err(err);
+            }
+            if !self.is_busy() {
+                compiler_fence(Ordering::Acquire);
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Drop for DmaStream {
+    fn drop(&mut self) {
+        self.abort();
+        let idx = self.index();
+        unsafe {
+            CALLBACKS[idx] = None;
+        }
+        CLAIMED.fetch_and(!(1 << idx), Ordering::AcqRel);
+    }
+}
+
+/// Number of peripheral-sized items for `len` memory words of `memory_size`.
+fn item_count(len: usize, memory_size: DataSize, peripheral_size: DataSize) -> u16 {
+    let items = len * memory_size.bytes() / peripheral_size.bytes();
+    assert!(items <= u16::MAX as usize, "DMA transfer too long: {} items", items);
+    items as u16
+}
+
+/// A transfer in progress, owning its stream and buffer(s).
+pub struct Transfer<B> {
+    inner: Option<(DmaStream, B)>,
+}
+
+impl<B> Transfer<B> {
+    /// Function name: `Transfer::peripheral_to_memory`
+    ///
+    /// Description:
+    /// Starts receiving from a peripheral data register into `buffer`.
+    ///
+    /// Parameters:
+    /// - `stream`: Claimed stream with the peripheral's request mapping.
+    /// - `config`: Channel, peripheral size, priority, circular/FIFO options. Direction and memory
+    ///   size are taken from the call and the buffer.
+    /// - `peripheral`: Address of the peripheral data register.
+    /// - `buffer`: Destination buffer.
+    ///
+    /// Return:
+    /// - The running transfer.
+    ///
+    /// # Safety
+    /// `peripheral` must be the data register of the peripheral selected by `config.channel`.
+    pub unsafe fn peripheral_to_memory(mut stream: DmaStream, config: &DmaConfig, peripheral: u32, mut buffer: B) -> Self
+    where
+        B: WriteBuffer,
+    {
+        let (ptr, len) = buffer.write_buffer();
+        let config = config.direction(Direction::PeripheralToMemory).memory_size(B::Word::SIZE);
+        let items = item_count(len, config.memory_size, config.peripheral_size);
+        unsafe {
+            stream.start(&config, peripheral, ptr as u32, None, items);
+        }
+        Transfer { inner: Some((stream, buffer)) }
+    }
+
+    /// Function name: `Transfer::memory_to_peripheral`
+    ///
+    /// Description:
+    /// Starts sending `buffer` to a peripheral data register.
+    ///
+    /// Parameters:
+    /// - `stream`: Claimed stream with the peripheral's request mapping.
+    /// - `config`: As for `peripheral_to_memory`.
+    /// - `peripheral`: Address of the peripheral data register.
+    /// - `buffer`: Source buffer.
+    ///
+    /// Return:
+    /// - The running transfer.
+    ///
+    /// # Safety
+    /// `peripheral` must be the data register of the peripheral selected by `config.channel`.
+    pub unsafe fn memory_to_peripheral(mut stream: DmaStream, config: &DmaConfig, peripheral: u32, buffer: B) -> Self
+    where
+        B: ReadBuffer,
+    {
+        let (ptr, len) = buffer.read_buffer();
+        let config = config.direction(Direction::MemoryToPeripheral).memory_size(B::Word::SIZE);
+        let items = item_count(len, config.memory_size, config.peripheral_size);
+        unsafe {
+            stream.start(&config, peripheral, ptr as u32, None, items);
+        }
+        Transfer { inner: Some((stream, buffer)) }
+    }
+
+    /// `true` once a normal transfer has finished (successfully or not).
+    pub fn is_complete(&self) -> bool {
+        self.inner.as_ref().is_none_or(|(stream, _)| !stream.is_busy())
+    }
+
+    /// Items left to transfer.
+    pub fn remaining(&self) -> u16 {
+        self.inner.as_ref().map_or(0, |(stream, _)| stream.remaining())
+    }
+
+    /// Function name: `Transfer::wait`
+    ///
+    /// Description:
+    /// Waits for a normal transfer to finish and gives back the stream and the buffer.
+    /// Must not be used on circular transfers, which never finish; use `stop`.
+    ///
+    /// Parameters:
+    /// - None
+    ///
+    /// Return:
+    /// - `(buffer, stream, result)`.
+    pub fn wait(mut self) -> (B, DmaStream, Result<(), DmaError>) {
+        let (mut stream, buffer) = self.inner.take().unwrap();
+        let result = stream.wait();
+        (buffer, stream, result)
+    }
+
+    /// Function name: `Transfer::stop`
+    ///
+    /// Description:
+    /// Aborts the transfer and gives back the stream and the buffer.
+    ///
+    /// Parameters:
+    /// - None
+    ///
+    /// Return:
+    /// - `(buffer, stream)`.
+    pub fn stop(mut self) -> (B, DmaStream) {
+        let (mut stream, buffer) = self.inner.take().unwrap();
+        stream.abort();
+        (buffer, stream)
+    }
+}
+
+impl<S: ReadBuffer, D: WriteBuffer> Transfer<(S, D)> {
+    /// Function name: `Transfer::memory_to_memory`
+    ///
+    /// Description:
+    /// Copies `source` into `destination` (DMA2 streams only). Copies `min(len)` words of the
+    /// destination's word size.
+    ///
+    /// Parameters:
+    /// - `stream`: Claimed DMA2 stream.
+    /// - `config`: Priority and FIFO/burst options; sizes and direction come from the buffers.
+    /// - `source`: Buffer read by the controller.
+    /// - `destination`: Buffer written by the controller.
+    ///
+    /// Return:
+    /// - The running transfer, owning both buffers.
+    pub fn memory_to_memory(mut stream: DmaStream, config: &DmaConfig, source: S, mut destination: D) -> Self {
+        let (src, src_len) = source.read_buffer();
+        let (dst, dst_len) = destination.write_buffer();
+        let src_bytes = src_len * S::Word::SIZE.bytes();
+        let dst_bytes = dst_len * D::Word::SIZE.bytes();
+
+        let config = config
+            .direction(Direction::MemoryToMemory)
+            .peripheral_size(S::Word::SIZE)
+            .memory_size(D::Word::SIZE)
+            .peripheral_increment(true)
+            .memory_increment(true)
+            .circular(false);
+        let items = item_count(src_bytes.min(dst_bytes) / D::Word::SIZE.bytes(), config.memory_size, config.peripheral_size);
+        unsafe {
+            stream.start(&config, src as u32, dst as u32, None, items);
+        }
+        Transfer { inner: Some((stream, (source, destination))) }
+    }
+}
+
+impl<B> Drop for Transfer<B> {
+    fn drop(&mut self) {
+        if let Some((stream, _)) = self.inner.as_mut() {
+            stream.abort();
+        }
+    }
+}
+
+/// A peripheral-to-memory stream alternating between two buffers.
+pub struct DoubleBufferTransfer<B> {
+    stream: DmaStream,
+    buffers: [B; 2],
+}
+
+impl<B: WriteBuffer> DoubleBufferTransfer<B> {
+    /// Function name: `DoubleBufferTransfer::start`
+    ///
+    /// Description:
+    /// Starts filling `buffers[0]`, then `buffers[1]`, then `buffers[0]` again, ... until stopped.
+    /// With a callback, `TransferComplete` is reported each time one buffer is full.
+    ///
+    /// Parameters:
+    /// - `stream`: Claimed stream with the peripheral's request mapping.
+    /// - `config`: Channel, peripheral size, priority and FIFO options.
+    /// - `peripheral`: Address of the peripheral data register.
+    /// - `buffers`: Two buffers of the same length.
+    ///
+    /// Return:
+    /// - The running transfer.
+    ///
+    /// # Safety
+    /// `peripheral` must be the data register of the peripheral selected by `config.channel`.
+    pub unsafe fn start(mut stream: DmaStream, config: &DmaConfig, peripheral: u32, mut buffers: [B; 2]) -> Self {
+        let (ptr0, len0) = buffers[0].write_buffer();
+        let (ptr1, len1) = buffers[1].write_buffer();
+        assert_eq!(len0, len1, "Double-buffer DMA needs two buffers of the same length");
+
+        let config = config.direction(Direction::PeripheralToMemory).memory_size(B::Word::SIZE);
+        let items = item_count(len0, config.memory_size, config.peripheral_size);
+        unsafe {
+            stream.start(&config, peripheral, ptr0 as u32, Some(ptr1 as u32), items);
+        }
+        DoubleBufferTransfer { stream, buffers }
+    }
+
+    /// Function name: `DoubleBufferTransfer::with_inactive`
+    ///
+    /// Description:
+    /// Runs `f` on the buffer the controller is not filling. If the controller switched to that
+    /// buffer while `f` was running, its contents may be partly overwritten and an error is returned.
+    ///
+    /// Parameters:
+    /// - `f`: Function given the index (0 or 1) and the inactive buffer.
+    ///
+    /// Return:
+    /// - The result of `f`, or `Err(DmaError::BufferOverrun)`.
+    pub fn with_inactive<R>(&mut self, f: impl FnOnce(usize, &mut B) -> R) -> Result<R, DmaError> {
+        self.stream.errors()?;
+        let active = self.stream.current_target();
+        compiler_fence(Ordering::Acquire);
+
+        let inactive = 1 - active as usize;
+        let result = f(inactive, &mut self.buffers[inactive]);
+
+        compiler_fence(Ordering::Release);
+        if self.stream.current_target() != active {
+            return Err(DmaError::BufferOverrun);
+        }
+        Ok(result)
+    }
+
+    /// Function name: `DoubleBufferTransfer::stop`
+    ///
+    /// Description:
+    /// Aborts the transfer and gives back both buffers and the stream.
+    ///
+    /// Parameters:
+    /// - None
+    ///
+    /// Return:
+    /// - `(buffers, stream)`.
+    pub fn stop(mut self) -> ([B; 2], DmaStream) {
+        self.stream.abort();
+        (self.buffers, self.stream)
+    }
+}
+
+/// Function name: `dma_irq_handler`
+///
+/// Description:
+/// Handles one stream interrupt: clears its flags, latches errors for `DmaStream::wait`
+/// and reports each event to the registered callback.
+///
+/// Parameters:
+/// - `dma`: Controller number (1–2).
+/// - `stream`: Stream number (0–7).
+///
+/// Return:
+/// - None
+pub fn dma_irq_handler(dma: u32, stream: u32) {
+    let idx = stream_index(dma, stream);
+    let base = select_dma_base(dma);
+    let (isr, ifcr) = if stream < 4 { (DMA_LISR, DMA_LIFCR) } else { (DMA_HISR, DMA_HIFCR) };
+    let shift = flag_shift(stream);
+
+    let flags = unsafe {
+        let flags = (read_register((base + isr) as *mut u32) >> shift) & FLAG_ALL;
+        write_register((base + ifcr) as *mut u32, flags << shift);
+        flags
+    };
+    if flags & (FLAG_TE | FLAG_DME) != 0 {
+        LATCHED_ERRORS[idx].fetch_or(flags & (FLAG_TE | FLAG_DME), Ordering::AcqRel);
+    }
+
+    let callback = unsafe { CALLBACKS[idx] };
+    if let Some(callback) = callback {
+        const EVENTS: [(u32, DmaEvent); 5] = [
+            (FLAG_TE, DmaEvent::TransferError),
+            (FLAG_DME, DmaEvent::DirectModeError),
+            (FLAG_FE, DmaEvent::FifoError),
+            (FLAG_HT, DmaEvent::HalfTransfer),
+            (FLAG_TC, DmaEvent::TransferComplete),
+        ];
+        for (flag, event) in EVENTS {
+            if flags & flag != 0 {
+                callback(dma, stream, event);
+            }
+        }
+    }
+}
+
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA1_Stream0_Handler() {
+    dma_irq_handler(DMA1, 0);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA1_Stream1_Handler() {
+    dma_irq_handler(DMA1, 1);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA1_Stream2_Handler() {
+    dma_irq_handler(DMA1, 2);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA1_Stream3_Handler() {
+    dma_irq_handler(DMA1, 3);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA1_Stream4_Handler() {
+    dma_irq_handler(DMA1, 4);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA1_Stream5_Handler() {
+    dma_irq_handler(DMA1, 5);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA1_Stream6_Handler() {
+    dma_irq_handler(DMA1, 6);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA1_Stream7_Handler() {
+    dma_irq_handler(DMA1, 7);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA2_Stream0_Handler() {
+    dma_irq_handler(DMA2, 0);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA2_Stream1_Handler() {
+    dma_irq_handler(DMA2, 1);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA2_Stream2_Handler() {
+    dma_irq_handler(DMA2, 2);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA2_Stream3_Handler() {
+    dma_irq_handler(DMA2, 3);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA2_Stream4_Handler() {
+    dma_irq_handler(DMA2, 4);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA2_Stream5_Handler() {
+    dma_irq_handler(DMA2, 5);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA2_Stream6_Handler() {
+    dma_irq_handler(DMA2, 6);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn DMA2_Stream7_Handler() {
+    dma_irq_handler(DMA2, 7);
+}
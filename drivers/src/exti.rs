@@ -11,20 +11,63 @@
 ///   `0` → GPIOA, `1` → GPIOB, ..., `8` → GPIOI.
 ///
 /// - `pin: u32`  
-///   GPIO pin number or EXTI line number (valid range: `0` to `15`).
+///   GPIO pin number or EXTI line number (valid range: `0` to `15`). Functions that only touch the
+///   EXTI registers also accept the internal lines `16` to `22` (PVD, RTC alarm, USB wakeup, ...).
 ///
 /// - `status: bool`  
 ///   Boolean flag indicating enable (`true`) or disable (`false`).
 ///
-/// - `trigger_type: u32`  
-///   Interrupt trigger type:  
-///   `0` → Rising edge trigger  
-///   `1` → Falling edge trigger
+/// - `trigger: Trigger`  
+///   Edge(s) that set the line pending: `Rising`, `Falling` or `Both`.
 ///
+/// ## Handlers
+///
+/// This module owns `EXTI0_Handler` … `EXTI4_Handler`, `EXTI9_5_Handler` and `EXTI15_10_Handler`.
+/// They clear the pending flag of every line that fired and call the function registered for it
+/// with `on_line`, so the shared vectors serve any number of pins:
+///
+/// ```ignore
+/// configure_gpio_interrupt(0, 0, Trigger::Rising);
+/// on_line(0, button_pressed);
+/// ```
+///
+/// ## Events
+///
+/// A line can also generate an event instead of (or as well as) an interrupt, through
+/// `configure_event_mask_register`. Events wake the core from `WFE` without running a handler.
 use crate:: stm32f407_registers::*;
-use crate::read_write::{read_register, write_register};
+use crate::read_write::{read_register, write_register, reg_write_bit};
 use crate::cortex_m4::enable_irq;
 
+/// Number of EXTI lines on the STM32F407 (16 GPIO lines and 7 internal ones).
+pub const EXTI_LINES: u32 = 23;
+
+const EXTI_IMR: u32 = EXTI_BASE; // offset 0x00
+const EXTI_EMR: u32 = EXTI_BASE + 0x04;
+const EXTI_RTSR: u32 = EXTI_BASE + 0x08;
+const EXTI_FTSR: u32 = EXTI_BASE + 0x0C;
+const EXTI_SWIER: u32 = EXTI_BASE + 0x10;
+const EXTI_PR: u32 = EXTI_BASE + 0x14;
+
+/// Edge(s) that trigger a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Rising,
+    Falling,
+    Both,
+}
+
+/// Function called from interrupt context with the line that fired.
+pub type ExtiHandler = fn(line: u32);
+
+static mut HANDLERS: [Option<ExtiHandler>; 16] = [None; 16];
+
+fn check_line(pin: u32) {
+    if pin >= EXTI_LINES {
+        panic!("Invalid EXTI line: {}", pin);
+    }
+}
+
 
 
 /// Function name: configure_syscfgen_clock  
@@ -102,51 +145,31 @@ fn map_exti_line(port: u32, pin: u32){
 /// Function name: config_interrupt_trigger  
 ///  
 /// Description:  
-/// Configures the interrupt trigger type (rising or falling edge) for a specific EXTI pin by  
-/// modifying the EXTI_RTSR or EXTI_FTSR registers.  
+/// Configures the edge(s) that set a line pending by setting or clearing its bit in EXTI_RTSR  
+/// and EXTI_FTSR.  
 ///  
 /// # Parameters  
-/// - `pin`: EXTI line number (0–15)  
-/// - `trigger_type`: Trigger type (`0` = rising edge, `1` = falling edge)  
+/// - `pin`: EXTI line number (0–22)  
+/// - `trigger`: Rising edge, falling edge or both  
 ///  
 /// # Panics  
-/// Panics if `pin` is greater than 15 or if `trigger_type` is invalid.  
+/// Panics if `pin` is greater than 22.  
 ///  
 /// # Return  
 /// - None
-fn config_interrupt_trigger(pin: u32, trigger_type: u32){
-    
-    if pin > 15 {
-        panic!("Invalid EXTI pin: {}", pin);
-    }
-    let trigger_resister = if trigger_type == 0{
-        EXTI_BASE + 0x08 //rising trigger
-    } else if trigger_type == 1{
-        EXTI_BASE + 0x0C  // EXTI_FTSR (falling trigger)
-    } else {
-         panic!("Invalid trigger type: {}", trigger_type);
-    };
+pub fn config_interrupt_trigger(pin: u32, trigger: Trigger) {
+    check_line(pin);
 
-    let trigger_resister_address = trigger_resister as *mut u32;
+    let (rising, falling) = match trigger {
+        Trigger::Rising => (true, false),
+        Trigger::Falling => (false, true),
+        Trigger::Both => (true, true),
+    };
 
     unsafe {
-        let mut reg_value = read_register(trigger_resister_address);
-        let bit = 1<<pin;
-        if trigger_type == 0{ //rising
-            reg_value |= bit; 
-        }
-        else if  trigger_type == 1 { //falling
-            reg_value &= !bit;
-        }
-        else {
-           
-         panic!("Invalid trigger type: {}", trigger_type);
-        }
-        write_register(trigger_resister_address, reg_value);
+        reg_write_bit(EXTI_RTSR as *mut u32, pin, rising);
+        reg_write_bit(EXTI_FTSR as *mut u32, pin, falling);
     }
-
-    
-
 }
 
 
@@ -156,23 +179,21 @@ fn config_interrupt_trigger(pin: u32, trigger_type: u32){
 /// Enables or disables the interrupt mask for the specified EXTI pin by updating the EXTI_IMR register.  
 ///  
 /// # Parameters  
-/// - `pin`: EXTI line number (0–15)  
+/// - `pin`: EXTI line number (0–22)  
 /// - `status`: `true` to unmask (enable), `false` to mask (disable) the interrupt  
 ///  
 /// # Panics  
-/// Panics if `pin` is greater than 15.  
+/// Panics if `pin` is greater than 22.  
 ///  
 /// # Return  
 /// - None
 pub fn configure_interrupt_mask_register(pin: u32, status: bool) {
-    let exti_imr_addr = EXTI_BASE as *mut u32;
+    let exti_imr_addr = EXTI_IMR as *mut u32;
     
     unsafe {
         let mut imr_value = read_register(exti_imr_addr);
 
-        if pin > 15 {
-            panic!("Invalid EXTI pin: {}", pin);  
-        }
+        check_line(pin);
 
         if status {
             // Unmask interrupt (enable)
@@ -221,11 +242,11 @@ fn enable_nvic_interrupt(pin: u32) {
 /// # Parameters  
 /// - `port`: GPIO port number (0 for GPIOA, etc.)  
 /// - `pin`: GPIO pin number (0–15)  
-/// - `trigger_type`: Trigger type (`0` = rising edge, `1` = falling edge)  
+/// - `trigger`: Rising edge, falling edge or both  
 ///  
 /// # Return  
 /// - None
-pub fn configure_gpio_interrupt (port: u32, pin: u32, trigger_type: u32){
+pub fn configure_gpio_interrupt (port: u32, pin: u32, trigger: Trigger){
     //1. configure system configuration controller clock 
     configure_syscfgen_clock (true);
     //2. enable external interrupt configuration register
@@ -233,7 +254,7 @@ pub fn configure_gpio_interrupt (port: u32, pin: u32, trigger_type: u32){
     //3. Enabling interrupt mask register
     configure_interrupt_mask_register(pin, true);
     //4. select interrupt trigger type
-    config_interrupt_trigger(pin, trigger_type);
+    config_interrupt_trigger(pin, trigger);
     //5. NVIC enable IRQ
     enable_nvic_interrupt(pin);
 }
@@ -241,27 +262,201 @@ pub fn configure_gpio_interrupt (port: u32, pin: u32, trigger_type: u32){
 
 
 
+/// Function name: configure_gpio_event  
+///  
+/// Description:  
+/// Routes a GPIO pin to an EXTI event (no interrupt): enables the SYSCFG clock, maps the line,  
+/// sets the trigger and unmasks the event. Used to wake the core from `WFE`.  
+///  
+/// # Parameters  
+/// - `port`: GPIO port number (0 for GPIOA, etc.)  
+/// - `pin`: GPIO pin number (0–15)  
+/// - `trigger`: Rising edge, falling edge or both  
+///  
+/// # Return  
+/// - None
+pub fn configure_gpio_event(port: u32, pin: u32, trigger: Trigger) {
+    configure_syscfgen_clock(true);
+    map_exti_line(port, pin);
+    config_interrupt_trigger(pin, trigger);
+    configure_event_mask_register(pin, true);
+}
+
+
+/// Function name: configure_event_mask_register  
+///  
+/// Description:  
+/// Enables or disables event generation for the specified EXTI line (EXTI_EMR).  
+///  
+/// # Parameters  
+/// - `pin`: EXTI line number (0–22)  
+/// - `status`: `true` to unmask (enable), `false` to mask (disable) the event  
+///  
+/// # Panics  
+/// Panics if `pin` is greater than 22.  
+///  
+/// # Return  
+/// - None
+pub fn configure_event_mask_register(pin: u32, status: bool) {
+    check_line(pin);
+    unsafe {
+        reg_write_bit(EXTI_EMR as *mut u32, pin, status);
+    }
+}
+
+
+/// Function name: trigger_software_interrupt  
+///  
+/// Description:  
+/// Sets the line pending from software (EXTI_SWIER), as if its edge had occurred. The interrupt  
+/// or event is generated if the line is unmasked in EXTI_IMR / EXTI_EMR. The request is cleared  
+/// together with the pending flag.  
+///  
+/// # Parameters  
+/// - `pin`: EXTI line number (0–22)  
+///  
+/// # Return  
+/// - None
+pub fn trigger_software_interrupt(pin: u32) {
+    check_line(pin);
+    unsafe {
+        write_register(EXTI_SWIER as *mut u32, 1 << pin);
+    }
+}
+
+
+/// Returns `true` if the line is pending.
+pub fn is_exti_pending(pin: u32) -> bool {
+    check_line(pin);
+    unsafe { read_register(EXTI_PR as *mut u32) & (1 << pin) != 0 }
+}
+
+
 /// Function name: clear_exti_pending  
 ///  
 /// Description:  
 /// Clears the pending interrupt flag for the specified EXTI line by writing a `1` to the EXTI_PR register.  
 ///  
 /// # Parameters  
+/// - `pin`: EXTI line number (0–22)  
+///  
+/// # Panics  
+/// Panics if `pin` is greater than 22.  
+///  
+/// # Return  
+/// - None
+pub fn clear_exti_pending(pin: u32) {
+    check_line(pin);
+
+    let exti_pr_addr = EXTI_PR as *mut u32;
+
+    unsafe {
+        write_register(exti_pr_addr, 1 << pin);
+    }
+}
+
+
+/// Function name: on_line  
+///  
+/// Description:  
+/// Registers the function called when a GPIO line (0–15) fires. The same function may serve  
+/// several lines; it receives the line number. Replaces any previous handler of the line.  
+///  
+/// # Parameters  
 /// - `pin`: EXTI line number (0–15)  
+/// - `handler`: Function called from interrupt context  
 ///  
 /// # Panics  
 /// Panics if `pin` is greater than 15.  
 ///  
 /// # Return  
 /// - None
-pub fn clear_exti_pending(pin: u32) {
+pub fn on_line(pin: u32, handler: ExtiHandler) {
     if pin > 15 {
         panic!("Invalid EXTI pin: {}", pin);
     }
+    unsafe {
+        HANDLERS[pin as usize] = Some(handler);
+    }
+}
 
-    let exti_pr_addr = (EXTI_BASE + 0x14) as *mut u32; // EXTI_PR
 
+/// Removes the handler of a GPIO line; the line is still cleared when it fires.
+pub fn remove_line_handler(pin: u32) {
+    if pin > 15 {
+        panic!("Invalid EXTI pin: {}", pin);
+    }
     unsafe {
-        write_register(exti_pr_addr, 1 << pin);
+        HANDLERS[pin as usize] = None;
+    }
+}
+
+
+/// Function name: dispatch_lines  
+///  
+/// Description:  
+/// Clears and dispatches every unmasked, pending line in `first..=last`. Called by the EXTI  
+/// vectors; lines without a handler are only cleared.  
+///  
+/// # Parameters  
+/// - `first`, `last`: Range of lines served by the vector  
+///  
+/// # Return  
+/// - None
+fn dispatch_lines(first: u32, last: u32) {
+    let pending = unsafe { read_register(EXTI_PR as *mut u32) & read_register(EXTI_IMR as *mut u32) };
+
+    for line in first..=last {
+        if pending & (1 << line) == 0 {
+            continue;
+        }
+        clear_exti_pending(line);
+        let handler = unsafe { HANDLERS[line as usize] };
+        if let Some(handler) = handler {
+            handler(line);
+        }
     }
 }
+
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn EXTI0_Handler() {
+    dispatch_lines(0, 0);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn EXTI1_Handler() {
+    dispatch_lines(1, 1);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn EXTI2_Handler() {
+    dispatch_lines(2, 2);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn EXTI3_Handler() {
+    dispatch_lines(3, 3);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn EXTI4_Handler() {
+    dispatch_lines(4, 4);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn EXTI9_5_Handler() {
+    dispatch_lines(5, 9);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn EXTI15_10_Handler() {
+    dispatch_lines(10, 15);
+}
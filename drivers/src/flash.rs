@@ -0,0 +1,541 @@
+#![allow(dead_code)]
+
+/// # Flash Driver Module
+///
+/// This module erases and programs the internal flash of the STM32F407 (1 MB, single bank,
+/// sectors 0–11) and reads and writes its option bytes.
+///
+/// ## Sector Layout
+///
+/// | Sectors | Size   | Address range             |
+/// |---------|--------|---------------------------|
+/// | 0–3     | 16 KB  | 0x0800_0000 – 0x0800_FFFF |
+/// | 4       | 64 KB  | 0x0801_0000 – 0x0801_FFFF |
+/// | 5–11    | 128 KB | 0x0802_0000 – 0x080F_FFFF |
+///
+/// ## Programming Model
+///
+/// An erased sector reads as all ones; programming can only clear bits, so a location can be
+/// written once after each erase. The control register must be unlocked with the key sequence
+/// first: `flash_unlock` / `flash_lock` bracket the erase and program calls.
+///
+/// ## Parallelism
+///
+/// The number of bits programmed or erased at once depends on the supply voltage (RM0090 3.5.1),
+/// set with `flash_set_parallelism` (default x32, for 2.7–3.6 V). Byte, half-word and word writes
+/// are only allowed up to that width; `flash_program` splits a buffer into the widest writes the
+/// parallelism and the alignment allow.
+///
+/// ## Execution from RAM
+///
+/// The CPU stalls on any flash read while an operation is running. The routine that starts an
+/// operation and waits for its end is linked into `.data`, which the start-up code copies to RAM,
+/// so it keeps running without touching the bank being modified. Interrupt handlers located in
+/// flash are still held off until the operation completes (up to ~2 s for a 128 KB sector).
+///
+/// ## Errors
+///
+/// Every operation checks the status register and reports write protection (WRPERR), alignment
+/// (PGAERR), parallelism (PGPERR), sequence (PGSERR) and operation (OPERR) errors as `FlashError`.
+use core::sync::atomic::{AtomicU8, Ordering};
+use crate::stm32f407_registers::*;
+use crate::read_write::{read_register, write_register};
+
+/// Number of sectors.
+pub const FLASH_SECTORS: u32 = 12;
+
+/// First and one-past-last address of the flash memory.
+pub const FLASH_START: u32 = 0x0800_0000;
+pub const FLASH_END: u32 = 0x0810_0000;
+
+/// Value of an erased word.
+pub const ERASED_WORD: u32 = 0xFFFF_FFFF;
+
+// Register offsets
+const FLASH_ACR: u32 = 0x00;
+const FLASH_KEYR: u32 = 0x04;
+const FLASH_OPTKEYR: u32 = 0x08;
+const FLASH_SR: u32 = 0x0C;
+const FLASH_CR: u32 = 0x10;
+const FLASH_OPTCR: u32 = 0x14;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+const OPTKEY1: u32 = 0x0819_2A3B;
+const OPTKEY2: u32 = 0x4C5D_6E7F;
+
+// ACR bits
+const ACR_ICEN: u32 = 1 << 9;
+const ACR_DCEN: u32 = 1 << 10;
+const ACR_ICRST: u32 = 1 << 11;
+const ACR_DCRST: u32 = 1 << 12;
+
+// CR bits
+const CR_PG: u32 = 1 << 0;
+const CR_SER: u32 = 1 << 1;
+const CR_SNB_SHIFT: u32 = 3;
+const CR_PSIZE_SHIFT: u32 = 8;
+const CR_STRT: u32 = 1 << 16;
+const CR_LOCK: u32 = 1 << 31;
+
+// SR bits
+const SR_EOP: u32 = 1 << 0;
+const SR_OPERR: u32 = 1 << 1;
+const SR_WRPERR: u32 = 1 << 4;
+const SR_PGAERR: u32 = 1 << 5;
+const SR_PGPERR: u32 = 1 << 6;
+const SR_PGSERR: u32 = 1 << 7;
+const SR_BSY: u32 = 1 << 16;
+const SR_ERRORS: u32 = SR_OPERR | SR_WRPERR | SR_PGAERR | SR_PGPERR | SR_PGSERR;
+
+// OPTCR bits
+const OPTCR_OPTLOCK: u32 = 1 << 0;
+const OPTCR_OPTSTRT: u32 = 1 << 1;
+const OPTCR_BOR_LEV_SHIFT: u32 = 2;
+const OPTCR_WDG_SW: u32 = 1 << 5;
+const OPTCR_NRST_STOP: u32 = 1 << 6;
+const OPTCR_NRST_STDBY: u32 = 1 << 7;
+const OPTCR_RDP_SHIFT: u32 = 8;
+const OPTCR_NWRP_SHIFT: u32 = 16;
+const OPTCR_NWRP_MASK: u32 = 0xFFF;
+
+/// Read protection level 2 (RDP byte 0xCC): permanent, disables debug and option byte changes.
+const RDP_LEVEL_2: u8 = 0xCC;
+
+/// Error reported by the flash interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashError {
+    /// The sector is write-protected (WRPERR).
+    WriteProtected,
+    /// Programming within a 128-bit row was not aligned or crossed a row (PGAERR).
+    Alignment,
+    /// Access size did not match the programming parallelism (PGPERR).
+    Parallelism,
+    /// Programming without PG set or with the register locked (PGSERR).
+    Sequence,
+    /// Operation error (OPERR).
+    Operation,
+    /// The data read back differs from the data written (target not erased).
+    Verify,
+}
+
+/// Program/erase parallelism (CR.PSIZE), which depends on the supply voltage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Parallelism {
+    /// 1.8–2.1 V: 8 bits at a time.
+    X8 = 0,
+    /// 2.1–2.7 V: 16 bits at a time.
+    X16 = 1,
+    /// 2.7–3.6 V: 32 bits at a time.
+    X32 = 2,
+    /// 2.7–3.6 V with an external Vpp (8–9 V): 64 bits at a time. Erase only; programming is
+    /// done 32 bits at a time.
+    X64 = 3,
+}
+
+impl Parallelism {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Parallelism::X8,
+            1 => Parallelism::X16,
+            3 => Parallelism::X64,
+            _ => Parallelism::X32,
+        }
+    }
+
+    /// Widest write allowed, in bytes.
+    fn max_write(self) -> u32 {
+        match self {
+            Parallelism::X8 => 1,
+            Parallelism::X16 => 2,
+            Parallelism::X32 | Parallelism::X64 => 4,
+        }
+    }
+}
+
+static PARALLELISM: AtomicU8 = AtomicU8::new(Parallelism::X32 as u8);
+
+/// Option bytes (RM0090 3.6).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptionBytes {
+    /// Read protection: 0xAA = level 0 (none), 0xCC = level 2 (refused by this driver),
+    /// anything else = level 1.
+    pub read_protection: u8,
+    /// Brown-out reset threshold: 3 = off, 2 = level 1, 1 = level 2, 0 = level 3.
+    pub bor_level: u8,
+    /// Independent watchdog started by software (`true`) or by hardware at reset (`false`).
+    pub watchdog_software: bool,
+    /// Entering Stop mode does not reset (`true`) or generates a reset (`false`).
+    pub no_reset_on_stop: bool,
+    /// Entering Standby mode does not reset (`true`) or generates a reset (`false`).
+    pub no_reset_on_standby: bool,
+    /// Bit n set: sector n is write-protected.
+    pub write_protected: u16,
+}
+
+fn reg(offset: u32) -> *mut u32 {
+    (FLASH_INTERFACE_BASE + offset) as *mut u32
+}
+
+fn check_sector(sector: u32) {
+    assert!(sector < FLASH_SECTORS, "Invalid flash sector: {}. Valid range is 0 – 11.", sector);
+}
+
+/// Function name: `flash_sector_address`
+///
+/// Description:
+/// Returns the start address of a sector.
+///
+/// Parameters:
+/// - `sector`: Sector number (0 – 11).
+///
+/// Return:
+/// - Address of the first byte of the sector.
+pub fn flash_sector_address(sector: u32) -> u32 {
+    check_sector(sector);
+    match sector {
+        0..=3 => FLASH_START + sector * 0x4000,
+        4 => FLASH_START + 0x1_0000,
+        _ => FLASH_START + 0x2_0000 + (sector - 5) * 0x2_0000,
+    }
+}
+
+/// Size of a sector in bytes.
+pub fn flash_sector_size(sector: u32) -> u32 {
+    check_sector(sector);
+    match sector {
+        0..=3 => 0x4000,
+        4 => 0x1_0000,
+        _ => 0x2_0000,
+    }
+}
+
+/// Sector containing `address`, or `None` if it is outside the flash memory.
+pub fn flash_sector_of(address: u32) -> Option<u32> {
+    (0..FLASH_SECTORS).find(|&sector| {
+        let start = flash_sector_address(sector);
+        address >= start && address < start + flash_sector_size(sector)
+    })
+}
+
+/// Unlock the flash control register. Does nothing if it is already unlocked.
+pub fn flash_unlock() {
+    unsafe {
+        if read_register(reg(FLASH_CR)) & CR_LOCK != 0 {
+            write_register(reg(FLASH_KEYR), KEY1);
+            write_register(reg(FLASH_KEYR), KEY2);
+        }
+    }
+}
+
+/// Lock the flash control register until the next `flash_unlock`.
+pub fn flash_lock() {
+    unsafe {
+        let cr = read_register(reg(FLASH_CR));
+        write_register(reg(FLASH_CR), cr | CR_LOCK);
+    }
+}
+
+pub fn flash_is_locked() -> bool {
+    unsafe { read_register(reg(FLASH_CR)) & CR_LOCK != 0 }
+}
+
+/// Select the program/erase parallelism matching the supply voltage.
+pub fn flash_set_parallelism(parallelism: Parallelism) {
+    PARALLELISM.store(parallelism as u8, Ordering::Relaxed);
+}
+
+pub fn flash_parallelism() -> Parallelism {
+    Parallelism::from_u8(PARALLELISM.load(Ordering::Relaxed))
+}
+
+/// Store `value` (`size` = 1, 2 or 4 bytes) at `target`, then wait until BSY clears in `sr`.
+///
+/// Linked into `.data` so it runs from RAM while the flash is busy; it must not call any function
+/// (which would live in flash), hence the assembly.
+#[inline(never)]
+#[unsafe(link_section = ".data.flash_ram_routine")]
+unsafe extern "C" fn ram_write_and_wait(target: usize, value: usize, size: usize, sr: usize) {
+    unsafe {
+        core::arch::asm!(
+            "cmp {size}, #1",
+            "bne 2f",
+            "strb {value}, [{target}]",
+            "b 4f",
+            "2:",
+            "cmp {size}, #2",
+            "bne 3f",
+            "strh {value}, [{target}]",
+            "b 4f",
+            "3:",
+            "str {value}, [{target}]",
+            "4:",
+            "dsb",
+            "5:",
+            "ldr {tmp}, [{sr}]",
+            "tst {tmp}, #0x10000",
+            "bne 5b",
+            target = in(reg) target,
+            value = in(reg) value,
+            size = in(reg) size,
+            sr = in(reg) sr,
+            tmp = out(reg) _,
+            options(nostack),
+        );
+    }
+}
+
+fn wait_ready() {
+    unsafe { while read_register(reg(FLASH_SR)) & SR_BSY != 0 {} }
+}
+
+/// Clear the end-of-operation and error flags (write 1 to clear).
+fn clear_status() {
+    unsafe {
+        write_register(reg(FLASH_SR), SR_EOP | SR_ERRORS);
+    }
+}
+
+fn check_errors() -> Result<(), FlashError> {
+    let sr = unsafe { read_register(reg(FLASH_SR)) };
+    let result = if sr & SR_WRPERR != 0 {
+        Err(FlashError::WriteProtected)
+    } else if sr & SR_PGAERR != 0 {
+        Err(FlashError::Alignment)
+    } else if sr & SR_PGPERR != 0 {
+        Err(FlashError::Parallelism)
+    } else if sr & SR_PGSERR != 0 {
+        Err(FlashError::Sequence)
+    } else if sr & SR_OPERR != 0 {
+        Err(FlashError::Operation)
+    } else {
+        Ok(())
+    };
+    clear_status();
+    result
+}
+
+/// Reset the ART instruction and data caches, which may still hold the old content of an erased
+/// sector (RM0090 3.5.2: a cache can only be reset while disabled).
+fn flush_caches() {
+    unsafe {
+        let acr = read_register(reg(FLASH_ACR));
+        let enabled = acr & (ACR_ICEN | ACR_DCEN);
+        write_register(reg(FLASH_ACR), acr & !(ACR_ICEN | ACR_DCEN));
+        write_register(reg(FLASH_ACR), (acr & !(ACR_ICEN | ACR_DCEN)) | ACR_ICRST | ACR_DCRST);
+        write_register(reg(FLASH_ACR), (acr & !(ACR_ICEN | ACR_DCEN | ACR_ICRST | ACR_DCRST)) | enabled);
+    }
+}
+
+/// Function name: `flash_erase_sector`
+///
+/// Description:
+/// Erases a whole sector (all bytes read back as 0xFF) with the configured parallelism and waits
+/// for the end of the operation. The flash must be unlocked.
+///
+/// Parameters:
+/// - `sector`: Sector number (0 – 11).
+///
+/// Return:
+/// - `Err` if the flash interface reported an error.
+pub fn flash_erase_sector(sector: u32) -> Result<(), FlashError> {
+    check_sector(sector);
+    wait_ready();
+    clear_status();
+    let cr = (flash_parallelism() as u32) << CR_PSIZE_SHIFT | CR_SER | (sector << CR_SNB_SHIFT);
+    unsafe {
+        write_register(reg(FLASH_CR), cr);
+        ram_write_and_wait(reg(FLASH_CR) as usize, (cr | CR_STRT) as usize, 4, reg(FLASH_SR) as usize);
+        write_register(reg(FLASH_CR), 0);
+    }
+    flush_caches();
+    check_errors()
+}
+
+/// Program one byte, half-word or word (`size` 1, 2 or 4) at a suitably aligned address.
+fn program(address: u32, value: u32, size: u32) -> Result<(), FlashError> {
+    assert!(
+        address.is_multiple_of(size) && address >= FLASH_START && address + size <= FLASH_END,
+        "Invalid flash address: {:#010x} for a {}-byte write",
+        address,
+        size
+    );
+    assert!(
+        size <= flash_parallelism().max_write(),
+        "{}-byte flash write not allowed with {:?} parallelism",
+        size,
+        flash_parallelism()
+    );
+
+    wait_ready();
+    clear_status();
+    // PSIZE must match the access size
+    let psize = match size {
+        1 => Parallelism::X8,
+        2 => Parallelism::X16,
+        _ => Parallelism::X32,
+    };
+    unsafe {
+        write_register(reg(FLASH_CR), (psize as u32) << CR_PSIZE_SHIFT | CR_PG);
+        ram_write_and_wait(address as usize, value as usize, size as usize, reg(FLASH_SR) as usize);
+        write_register(reg(FLASH_CR), 0);
+    }
+    check_errors()?;
+
+    let mask = if size == 4 { u32::MAX } else { (1 << (8 * size)) - 1 };
+    let read_back = unsafe {
+        match size {
+            1 => core::ptr::read_volatile(address as *const u8) as u32,
+            2 => core::ptr::read_volatile(address as *const u16) as u32,
+            _ => core::ptr::read_volatile(address as *const u32),
+        }
+    };
+    if read_back != value & mask {
+        return Err(FlashError::Verify);
+    }
+    Ok(())
+}
+
+/// Program one byte. The flash must be unlocked.
+pub fn flash_program_byte(address: u32, value: u8) -> Result<(), FlashError> {
+    program(address, value as u32, 1)
+}
+
+/// Program one half-word at an even address. Needs x16 parallelism or more.
+pub fn flash_program_halfword(address: u32, value: u16) -> Result<(), FlashError> {
+    program(address, value as u32, 2)
+}
+
+/// Function name: `flash_program_word`
+///
+/// Description:
+/// Programs one 32-bit word and checks it by reading it back. The flash must be unlocked, the
+/// parallelism x32 or more and the word erased (or only bits being cleared).
+///
+/// Parameters:
+/// - `address`: Word-aligned flash address.
+/// - `value`: Word to write.
+///
+/// Return:
+/// - `Err` if the flash interface reported an error or the word reads back differently.
+pub fn flash_program_word(address: u32, value: u32) -> Result<(), FlashError> {
+    program(address, value, 4)
+}
+
+/// Program consecutive words starting at `address`, stopping at the first error.
+pub fn flash_program_words(address: u32, words: &[u32]) -> Result<(), FlashError> {
+    for (i, &word) in words.iter().enumerate() {
+        flash_program_word(address + 4 * i as u32, word)?;
+    }
+    Ok(())
+}
+
+/// Function name: `flash_program`
+///
+/// Description:
+/// Programs a byte buffer at any address, using the widest writes that the parallelism and the
+/// alignment allow. Stops at the first error.
+///
+/// Parameters:
+/// - `address`: Flash address of the first byte.
+/// - `data`: Bytes to write.
+///
+/// Return:
+/// - `Err` if the flash interface reported an error or the data reads back differently.
+pub fn flash_program(address: u32, data: &[u8]) -> Result<(), FlashError> {
+    let max = flash_parallelism().max_write();
+    let mut offset = 0usize;
+    while offset < data.len() {
+        let at = address + offset as u32;
+        let remaining = (data.len() - offset) as u32;
+        let size = [4, 2, 1].into_iter().find(|&s| s <= max && s <= remaining && at.is_multiple_of(s)).unwrap_or(1);
+        let mut bytes = [0u8; 4];
+        bytes[..size as usize].copy_from_slice(&data[offset..offset + size as usize]);
+        program(at, u32::from_le_bytes(bytes), size)?;
+        offset += size as usize;
+    }
+    Ok(())
+}
+
+// ---------- Option bytes ----------
+
+/// Function name: `flash_read_option_bytes`
+///
+/// Description:
+/// Reads the option bytes currently in effect.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - The option bytes.
+pub fn flash_read_option_bytes() -> OptionBytes {
+    let optcr = unsafe { read_register(reg(FLASH_OPTCR)) };
+    OptionBytes {
+        read_protection: (optcr >> OPTCR_RDP_SHIFT) as u8,
+        bor_level: ((optcr >> OPTCR_BOR_LEV_SHIFT) & 0x3) as u8,
+        watchdog_software: optcr & OPTCR_WDG_SW != 0,
+        no_reset_on_stop: optcr & OPTCR_NRST_STOP != 0,
+        no_reset_on_standby: optcr & OPTCR_NRST_STDBY != 0,
+        // nWRP bits are active low
+        write_protected: (!(optcr >> OPTCR_NWRP_SHIFT) & OPTCR_NWRP_MASK) as u16,
+    }
+}
+
+/// Function name: `flash_write_option_bytes`
+///
+/// Description:
+/// Programs new option bytes. They take effect immediately, except the read protection level,
+/// which applies after the next reset. Lowering the read protection from level 1 to level 0
+/// mass-erases the flash. Level 2 (permanent) is refused.
+///
+/// Parameters:
+/// - `options`: New option bytes.
+///
+/// Return:
+/// - `Err` if the flash interface reported an error.
+pub fn flash_write_option_bytes(options: &OptionBytes) -> Result<(), FlashError> {
+    assert!(
+        options.read_protection != RDP_LEVEL_2,
+        "Read protection level 2 is permanent and not supported by this driver"
+    );
+    assert!(options.bor_level < 4, "Invalid BOR level: {}. Valid range is 0 – 3.", options.bor_level);
+
+    let mut optcr = ((!options.write_protected as u32) & OPTCR_NWRP_MASK) << OPTCR_NWRP_SHIFT
+        | (options.read_protection as u32) << OPTCR_RDP_SHIFT
+        | (options.bor_level as u32) << OPTCR_BOR_LEV_SHIFT;
+    if options.watchdog_software {
+        optcr |= OPTCR_WDG_SW;
+    }
+    if options.no_reset_on_stop {
+        optcr |= OPTCR_NRST_STOP;
+    }
+    if options.no_reset_on_standby {
+        optcr |= OPTCR_NRST_STDBY;
+    }
+
+    wait_ready();
+    clear_status();
+    unsafe {
+        if read_register(reg(FLASH_OPTCR)) & OPTCR_OPTLOCK != 0 {
+            write_register(reg(FLASH_OPTKEYR), OPTKEY1);
+            write_register(reg(FLASH_OPTKEYR), OPTKEY2);
+        }
+        write_register(reg(FLASH_OPTCR), optcr);
+        ram_write_and_wait(reg(FLASH_OPTCR) as usize, (optcr | OPTCR_OPTSTRT) as usize, 4, reg(FLASH_SR) as usize);
+        write_register(reg(FLASH_OPTCR), optcr | OPTCR_OPTLOCK);
+    }
+    check_errors()
+}
+
+/// Set or clear the write protection of one sector, keeping the other option bytes.
+pub fn flash_set_write_protection(sector: u32, protected: bool) -> Result<(), FlashError> {
+    check_sector(sector);
+    let mut options = flash_read_option_bytes();
+    if protected {
+        options.write_protected |= 1 << sector;
+    } else {
+        options.write_protected &= !(1 << sector);
+    }
+    flash_write_option_bytes(&options)
+}
@@ -44,6 +44,20 @@
 /// - `true` → High  
 /// - `false` → Low
 ///
+/// ### `af: u32` (used in `gpio_set_alternate_function`)
+/// - `0` to `15` → AF0..AF15 as listed in the datasheet alternate function mapping table  
+///   (e.g. `7` → USART1..3, `5` → SPI1/SPI2, `4` → I2C1..3, `2` → TIM3..5)
+///
+/// ### `mask: u16` (used in the port-wide functions)
+/// - Bit `n` selects pin `n` of the port; pins whose bit is clear are left untouched.
+///
+/// ## Atomicity
+///
+/// `gpio_write`, `toggle_gpio`, `gpio_set`, `gpio_reset` and `gpio_write_port_masked` go through the
+/// BSRR register, so a task and an ISR driving different pins of the same port can never undo each
+/// other's writes. The configuration functions still use read-modify-write and should be called
+/// during initialisation or with interrupts masked.
+///
 /// ## Safety
 ///
 /// While most functions in this module are `pub`, they internally perform `unsafe` register access.  
@@ -52,6 +66,22 @@
 //use crate::drivers::gpio_drive::gpio_macro::*;
 use crate:: stm32f407_registers::*;
 use crate::read_write::{read_register, write_register, reg_write_bit,reg_write_bits};
+use crate::cortex_m4::{disable_global_interrupt, enable_global_interrupt, is_global_interrupt_enabled};
+
+/// `mode` values for `gpio_configure_mode`.
+pub const GPIO_MODE_INPUT: u32 = 0;
+pub const GPIO_MODE_GP_OUTPUT: u32 = 1;
+pub const GPIO_MODE_ALTERNATE: u32 = 2;
+pub const GPIO_MODE_ANALOG: u32 = 3;
+
+/// `output_type` values for `gpio_output_type_configure`.
+pub const GPIO_OUTPUT_PUSH_PULL: u32 = 0;
+pub const GPIO_OUTPUT_OPEN_DRAIN: u32 = 1;
+
+/// `pull_up_down` values for `gpio_pulup_puldown_configure`.
+pub const GPIO_NO_PULL: u32 = 0;
+pub const GPIO_PULL_UP: u32 = 1;
+pub const GPIO_PULL_DOWN: u32 = 2;
  
 
 
@@ -245,6 +275,7 @@ pub fn gpio_read(port: u32, pin: u32) -> bool {
 ///  
 /// Description:  
 /// Sets the output state of a GPIO pin to high or low.  
+/// Uses the BSRR register so the write is atomic with respect to the other pins of the port.  
 ///  
 /// Safety:  
 /// Unsafe due to direct memory writes.  
@@ -257,13 +288,10 @@ pub fn gpio_read(port: u32, pin: u32) -> bool {
 /// Return:  
 /// - None
 pub fn gpio_write(port: u32, pin: u32, status: bool) {
-    assert!(pin < 16);
-
-    let gpio_base = select_gpio_base(port);
-    let odr_addr = (gpio_base + 0x14) as *mut u32;
-
-    unsafe {
-        reg_write_bit(odr_addr, pin, status);
+    if status {
+        gpio_set(port, pin);
+    } else {
+        gpio_reset(port, pin);
     }
 }
 
@@ -271,6 +299,8 @@ pub fn gpio_write(port: u32, pin: u32, status: bool) {
 ///  
 /// Description:  
 /// Toggles the current output state of a GPIO pin.  
+/// The current level is sampled from ODR and the opposite level is written through BSRR,
+/// so other pins of the port are never touched.  
 ///  
 /// Safety:  
 /// Unsafe due to raw register reads/writes.  
@@ -286,10 +316,215 @@ pub fn toggle_gpio(port: u32, pin: u32) {
 
     let gpio_base = select_gpio_base(port);
     let odr_addr = (gpio_base + 0x14) as *mut u32;
+    let bsrr_addr = (gpio_base + 0x18) as *mut u32;
 
     unsafe {
         let value = read_register(odr_addr);
-        let toggled = value ^ (1 << pin);
-        write_register(odr_addr, toggled);
+        if value & (1 << pin) != 0 {
+            write_register(bsrr_addr, 1 << (pin + 16));
+        } else {
+            write_register(bsrr_addr, 1 << pin);
+        }
+    }
+}
+
+/// Function name: `gpio_set`  
+///  
+/// Description:  
+/// Drives a GPIO pin high by writing its BS bit in the BSRR register.  
+/// The write is a single store, so it is atomic with respect to tasks and ISRs.  
+///  
+/// Safety:  
+/// Unsafe due to direct memory writes.  
+///  
+/// Parameters:  
+/// - `port`: GPIO port number.  
+/// - `pin`: GPIO pin number (0–15).  
+///  
+/// Return:  
+/// - None
+pub fn gpio_set(port: u32, pin: u32) {
+    assert!(pin < 16);
+
+    let gpio_base = select_gpio_base(port);
+    let bsrr_addr = (gpio_base + 0x18) as *mut u32;
+
+    unsafe {
+        write_register(bsrr_addr, 1 << pin);
+    }
+}
+
+/// Function name: `gpio_reset`  
+///  
+/// Description:  
+/// Drives a GPIO pin low by writing its BR bit (upper half) in the BSRR register.  
+/// The write is a single store, so it is atomic with respect to tasks and ISRs.  
+///  
+/// Safety:  
+/// Unsafe due to direct memory writes.  
+///  
+/// Parameters:  
+/// - `port`: GPIO port number.  
+/// - `pin`: GPIO pin number (0–15).  
+///  
+/// Return:  
+/// - None
+pub fn gpio_reset(port: u32, pin: u32) {
+    assert!(pin < 16);
+
+    let gpio_base = select_gpio_base(port);
+    let bsrr_addr = (gpio_base + 0x18) as *mut u32;
+
+    unsafe {
+        write_register(bsrr_addr, 1 << (pin + 16));
+    }
+}
+
+/// Function name: `gpio_read_port`  
+///  
+/// Description:  
+/// Reads the input levels of all 16 pins of a GPIO port at once.  
+///  
+/// Safety:  
+/// Unsafe due to volatile memory access.  
+///  
+/// Parameters:  
+/// - `port`: GPIO port number.  
+///  
+/// Return:  
+/// - `u16`: bit `n` is the level of pin `n`.
+pub fn gpio_read_port(port: u32) -> u16 {
+    let gpio_base = select_gpio_base(port);
+    let idr_addr = (gpio_base + 0x10) as *mut u32;
+
+    unsafe { read_register(idr_addr) as u16 }
+}
+
+/// Function name: `gpio_write_port_masked`  
+///  
+/// Description:  
+/// Writes several pins of a GPIO port in a single BSRR store.  
+/// Pins selected by `mask` take the corresponding bit of `value`; all other pins keep their state.  
+///  
+/// Safety:  
+/// Unsafe due to direct memory writes.  
+///  
+/// Parameters:  
+/// - `port`: GPIO port number.  
+/// - `mask`: Pins to update (bit `n` → pin `n`).  
+/// - `value`: New levels for the selected pins.  
+///  
+/// Return:  
+/// - None
+pub fn gpio_write_port_masked(port: u32, mask: u16, value: u16) {
+    let gpio_base = select_gpio_base(port);
+    let bsrr_addr = (gpio_base + 0x18) as *mut u32;
+
+    let set_bits = (value & mask) as u32;
+    let reset_bits = (!value & mask) as u32;
+
+    unsafe {
+        write_register(bsrr_addr, set_bits | (reset_bits << 16));
+    }
+}
+
+/// Function name: `gpio_set_alternate_function`  
+///  
+/// Description:  
+/// Selects the alternate function (AF0..AF15) routed to a GPIO pin by programming the  
+/// AFRL (pins 0–7) or AFRH (pins 8–15) register.  
+/// The pin must also be put in alternate mode with `gpio_configure_mode(port, pin, 2)`.  
+///  
+/// Safety:  
+/// Unsafe due to direct register access.  
+///  
+/// Parameters:  
+/// - `port`: GPIO port number.  
+/// - `pin`: GPIO pin number (0–15).  
+/// - `af`: Alternate function number (0–15).  
+///  
+/// Return:  
+/// - None
+pub fn gpio_set_alternate_function(port: u32, pin: u32, af: u32) {
+    assert!(pin < 16);
+    assert!(af <= 15);
+
+    configure_gpio_clock(port, true);
+    let gpio_base = select_gpio_base(port);
+    let afr_addr = if pin < 8 {
+        (gpio_base + 0x20) as *mut u32 // AFRL
+    } else {
+        (gpio_base + 0x24) as *mut u32 // AFRH
+    };
+
+    unsafe {
+        reg_write_bits(afr_addr, af, (pin % 8) * 4, 4);
+    }
+}
+
+/// Function name: `gpio_lock_pins`  
+///  
+/// Description:  
+/// Freezes the configuration (MODER, OTYPER, OSPEEDR, PUPDR, AFRL/AFRH) of the pins selected by  
+/// `mask` until the next reset, using the LCKR write sequence (LCKK=1, LCKK=0, LCKK=1, read, read).  
+/// Output levels can still be changed through ODR/BSRR.  
+///  
+/// Safety:  
+/// Unsafe due to direct register access. The sequence must not be interrupted by another  
+/// write to the same LCKR, so it is performed with interrupts masked (PRIMASK is restored  
+/// afterwards, so the function can be called with interrupts already masked).  
+///  
+/// Parameters:  
+/// - `port`: GPIO port number.  
+/// - `mask`: Pins to lock (bit `n` → pin `n`).  
+///  
+/// Return:  
+/// - `bool`: `true` if the lock key (LCKK) reads back as set, i.e. the lock is active.
+pub fn gpio_lock_pins(port: u32, mask: u16) -> bool {
+    const LCKK: u32 = 1 << 16;
+
+    let gpio_base = select_gpio_base(port);
+    let lckr_addr = (gpio_base + 0x1C) as *mut u32;
+    let pins = mask as u32;
+
+    let enabled = is_global_interrupt_enabled();
+    disable_global_interrupt();
+    let locked = unsafe {
+        write_register(lckr_addr, LCKK | pins);
+        write_register(lckr_addr, pins);
+        write_register(lckr_addr, LCKK | pins);
+        let _ = read_register(lckr_addr);
+        read_register(lckr_addr) & LCKK != 0
+    };
+    if enabled {
+        enable_global_interrupt();
+    }
+
+    locked
+}
+
+/// Function name: `gpio_is_locked`  
+///  
+/// Description:  
+/// Reports whether the configuration of a GPIO pin has been frozen by `gpio_lock_pins`.  
+///  
+/// Safety:  
+/// Unsafe due to volatile memory access.  
+///  
+/// Parameters:  
+/// - `port`: GPIO port number.  
+/// - `pin`: GPIO pin number (0–15).  
+///  
+/// Return:  
+/// - `bool`: `true` if the port lock is active and the pin is part of it.
+pub fn gpio_is_locked(port: u32, pin: u32) -> bool {
+    assert!(pin < 16);
+
+    let gpio_base = select_gpio_base(port);
+    let lckr_addr = (gpio_base + 0x1C) as *mut u32;
+
+    unsafe {
+        let value = read_register(lckr_addr);
+        (value & (1 << 16)) != 0 && (value & (1 << pin)) != 0
     }
 }
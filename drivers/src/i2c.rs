@@ -0,0 +1,620 @@
+#![allow(dead_code)]
+
+/// # I2C Master Driver Module
+///
+/// This module drives I2C1, I2C2 and I2C3 of the STM32F407 as a polled bus master implementing
+/// `embedded_hal::i2c::I2c` for both 7-bit and 10-bit addresses.
+///
+/// ## Instance Conventions
+///
+/// - `i2c: u32` — peripheral number: `1` → I2C1, `2` → I2C2, `3` → I2C3 (all on APB1).
+///
+/// CR2.FREQ, CCR and TRISE are computed from the APB1 clock reported by `rcc::clocks()`.
+///
+/// ## Pins
+///
+/// `I2c::new` puts the SCL/SDA pins in alternate function 4, open-drain with pull-ups
+/// (e.g. PB6/PB7 for I2C1, PB10/PB11 for I2C2, PA8/PC9 for I2C3).
+///
+/// ## Timeouts
+///
+/// The driver has no notion of time of its own: every wait loop compares a tick counter supplied at
+/// construction (normally `kernel::os::get_tick_count`) against `I2cConfig::timeout_ticks`.
+///
+/// ## Errors and Recovery
+///
+/// NACK, arbitration loss, bus error and overrun are reported as `I2cError` variants. After a bus
+/// error or a timeout the peripheral is reset, and if a slave is still holding SDA low the bus is
+/// recovered by clocking SCL as a GPIO (up to 9 pulses) and issuing a STOP condition.
+use embedded_hal::i2c::{self, ErrorKind, ErrorType, NoAcknowledgeSource, Operation, SevenBitAddress, TenBitAddress};
+use crate::stm32f407_registers::*;
+use crate::rcc::clocks;
+use crate::read_write::{read_register, write_register, reg_write_bit};
+use crate::gpio::{
+    gpio_configure_mode, gpio_output_speed_configure, gpio_output_type_configure, gpio_pulup_puldown_configure,
+    gpio_read, gpio_set_alternate_function, gpio_write, GPIO_OUTPUT_OPEN_DRAIN, GPIO_PULL_UP,
+};
+
+pub const I2C1: u32 = 1;
+pub const I2C2: u32 = 2;
+pub const I2C3: u32 = 3;
+
+const GPIO_MODE_INPUT: u32 = 0;
+const GPIO_MODE_GP_OUTPUT: u32 = 1;
+const GPIO_MODE_ALTERNATE: u32 = 2;
+const GPIO_SPEED_HIGH: u32 = 3;
+const GPIO_AF_I2C: u32 = 4;
+
+// Register offsets
+const I2C_CR1: u32 = 0x00;
+const I2C_CR2: u32 = 0x04;
+const I2C_DR: u32 = 0x10;
+const I2C_SR1: u32 = 0x14;
+const I2C_SR2: u32 = 0x18;
+const I2C_CCR: u32 = 0x1C;
+const I2C_TRISE: u32 = 0x20;
+
+// CR1 bits
+const CR1_PE: u32 = 0;
+const CR1_START: u32 = 8;
+const CR1_STOP: u32 = 9;
+const CR1_ACK: u32 = 10;
+const CR1_POS: u32 = 11;
+const CR1_SWRST: u32 = 15;
+
+// SR1 bits
+const SR1_SB: u32 = 1 << 0;
+const SR1_ADDR: u32 = 1 << 1;
+const SR1_BTF: u32 = 1 << 2;
+const SR1_ADD10: u32 = 1 << 3;
+const SR1_RXNE: u32 = 1 << 6;
+const SR1_TXE: u32 = 1 << 7;
+const SR1_BERR: u32 = 1 << 8;
+const SR1_ARLO: u32 = 1 << 9;
+const SR1_AF: u32 = 1 << 10;
+const SR1_OVR: u32 = 1 << 11;
+
+// SR2 bits
+const SR2_BUSY: u32 = 1 << 1;
+
+// CCR bits
+const CCR_FS: u32 = 1 << 15;
+
+/// I2C errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum I2cError {
+    /// The slave did not acknowledge its address or a data byte.
+    Nack(NoAcknowledgeSource),
+    /// Another master won arbitration.
+    ArbitrationLoss,
+    /// Misplaced START/STOP detected on the bus.
+    Bus,
+    /// A received byte was lost.
+    Overrun,
+    /// A flag did not appear within `I2cConfig::timeout_ticks`.
+    Timeout,
+}
+
+impl i2c::Error for I2cError {
+    fn kind(&self) -> ErrorKind {
+        match *self {
+            I2cError::Nack(source) => ErrorKind::NoAcknowledge(source),
+            I2cError::ArbitrationLoss => ErrorKind::ArbitrationLoss,
+            I2cError::Bus => ErrorKind::Bus,
+            I2cError::Overrun => ErrorKind::Overrun,
+            I2cError::Timeout => ErrorKind::Other,
+        }
+    }
+}
+
+/// SCL and SDA pins (GPIO port index and pin number, see `gpio`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct I2cPins {
+    pub scl_port: u32,
+    pub scl_pin: u32,
+    pub sda_port: u32,
+    pub sda_pin: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct I2cConfig {
+    /// SCL frequency: up to 100 kHz uses standard mode, up to 400 kHz fast mode.
+    pub frequency_hz: u32,
+    /// Maximum time to wait for any bus event, in ticks of the supplied tick counter.
+    pub timeout_ticks: u32,
+}
+
+impl Default for I2cConfig {
+    fn default() -> Self {
+        I2cConfig { frequency_hz: 100_000, timeout_ticks: 10 }
+    }
+}
+
+/// Tick counter used for timeouts (e.g. `kernel::os::get_tick_count`).
+pub type TickSource = fn() -> u32;
+
+/// How a read or write sequence ends.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum End {
+    Stop,
+    Restart,
+}
+
+/// Function name: `select_i2c_base`
+///
+/// Description:
+/// Returns the base address of the given I2C instance.
+///
+/// Parameters:
+/// - `i2c`: Instance number (1–3).
+///
+/// Return:
+/// - Base address (`u32`) of the peripheral.
+fn select_i2c_base(i2c: u32) -> u32 {
+    match i2c {
+        1 => I2C1_BASE,
+        2 => I2C2_BASE,
+        3 => I2C3_BASE,
+        _ => panic!("Invalid I2C: {}. Valid range is 1 – 3.", i2c),
+    }
+}
+
+/// Function name: `i2c_compute_timing`
+///
+/// Description:
+/// Computes the CCR and TRISE register values for the requested SCL frequency
+/// (standard mode up to 100 kHz, fast mode with Tlow/Thigh = 2 above).
+///
+/// Parameters:
+/// - `pclk_hz`: APB1 clock in Hz (2–42 MHz).
+/// - `frequency_hz`: SCL frequency (at most 400 kHz).
+///
+/// Return:
+/// - `(ccr, trise)` register values.
+pub fn i2c_compute_timing(pclk_hz: u32, frequency_hz: u32) -> (u32, u32) {
+    assert!(frequency_hz > 0 && frequency_hz <= 400_000, "I2C frequency must be 1..=400000 Hz");
+    let freq_mhz = pclk_hz / 1_000_000;
+
+    if frequency_hz <= 100_000 {
+        // Thigh = Tlow = CCR * Tpclk; max rise time 1000 ns
+        let ccr = pclk_hz.div_ceil(2 * frequency_hz).max(4);
+        (ccr, freq_mhz + 1)
+    } else {
+        // Fast mode, DUTY=0: Thigh = CCR * Tpclk, Tlow = 2 * CCR * Tpclk; max rise time 300 ns
+        let ccr = pclk_hz.div_ceil(3 * frequency_hz).max(1);
+        (CCR_FS | ccr, freq_mhz * 300 / 1000 + 1)
+    }
+}
+
+/// I2C bus master.
+pub struct I2c {
+    i2c: u32,
+    base: u32,
+    pins: I2cPins,
+    config: I2cConfig,
+    pclk_hz: u32,
+    tick: TickSource,
+}
+
+impl I2c {
+    /// Function name: `I2c::new`
+    ///
+    /// Description:
+    /// Configures the pins, enables and resets the peripheral and programs its timing.
+    ///
+    /// Parameters:
+    /// - `i2c`: Instance number (1–3).
+    /// - `pins`: SCL/SDA pins.
+    /// - `config`: Bus frequency and timeout.
+    /// - `tick`: Tick counter used for timeouts.
+    ///
+    /// Return:
+    /// - The configured bus.
+    pub fn new(i2c: u32, pins: I2cPins, config: &I2cConfig, tick: TickSource) -> Self {
+        let pclk_hz = clocks().pclk1();
+        let bus = I2c { i2c, base: select_i2c_base(i2c), pins, config: *config, pclk_hz, tick };
+
+        unsafe {
+            reg_write_bit(RCC_APB1ENR as *mut u32, 20 + i2c, true);
+        }
+        bus.configure_pins();
+        bus.init_peripheral();
+        bus
+    }
+
+    fn configure_pins(&self) {
+        for (port, pin) in [(self.pins.scl_port, self.pins.scl_pin), (self.pins.sda_port, self.pins.sda_pin)] {
+            gpio_set_alternate_function(port, pin, GPIO_AF_I2C);
+            gpio_output_type_configure(port, pin, GPIO_OUTPUT_OPEN_DRAIN);
+            gpio_output_speed_configure(port, pin, GPIO_SPEED_HIGH);
+            gpio_pulup_puldown_configure(port, pin, GPIO_PULL_UP);
+            gpio_configure_mode(port, pin, GPIO_MODE_ALTERNATE);
+        }
+    }
+
+    /// Software-reset the peripheral and program FREQ, CCR and TRISE.
+    fn init_peripheral(&self) {
+        let (ccr, trise) = i2c_compute_timing(self.pclk_hz, self.config.frequency_hz);
+        let cr1 = self.reg(I2C_CR1);
+
+        unsafe {
+            write_register(cr1, 1 << CR1_SWRST);
+            write_register(cr1, 0);
+            write_register(self.reg(I2C_CR2), (self.pclk_hz / 1_000_000) & 0x3F);
+            write_register(self.reg(I2C_CCR), ccr);
+            write_register(self.reg(I2C_TRISE), trise);
+            write_register(cr1, 1 << CR1_PE);
+        }
+    }
+
+    fn reg(&self, offset: u32) -> *mut u32 {
+        (self.base + offset) as *mut u32
+    }
+
+    fn read_reg(&self, offset: u32) -> u32 {
+        unsafe { read_register(self.reg(offset)) }
+    }
+
+    fn set_cr1_bit(&self, bit: u32, value: bool) {
+        unsafe {
+            reg_write_bit(self.reg(I2C_CR1), bit, value);
+        }
+    }
+
+    /// Instance number of this bus.
+    pub fn instance(&self) -> u32 {
+        self.i2c
+    }
+
+    fn timed_out(&self, start: u32) -> bool {
+        (self.tick)().wrapping_sub(start) > self.config.timeout_ticks
+    }
+
+    /// Turn error flags in SR1 into an `I2cError`, clearing them.
+    fn check_errors(&self, sr1: u32, nack: NoAcknowledgeSource) -> Result<(), I2cError> {
+        let error = if sr1 & SR1_AF != 0 {
+            // The slave is released with a STOP
+            self.set_cr1_bit(CR1_STOP, true);
+            Some((SR1_AF, I2cError::Nack(nack)))
+        } else if sr1 & SR1_ARLO != 0 {
+            Some((SR1_ARLO, I2cError::ArbitrationLoss))
+        } else if sr1 & SR1_BERR != 0 {
+            Some((SR1_BERR, I2cError::Bus))
+        } else if sr1 & SR1_OVR != 0 {
+            Some((SR1_OVR, I2cError::Overrun))
+        } else {
+            None
+        };
+
+        match error {
+            Some((flag, err)) => {
+                unsafe {
+                    write_register(self.reg(I2C_SR1), !flag & 0xFFFF);
+                }
+                Err(err)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Wait until any bit of `mask` is set in SR1.
+    fn wait_sr1(&self, mask: u32, nack: NoAcknowledgeSource) -> Result<u32, I2cError> {
+        let start = (self.tick)();
+        loop {
+            let sr1 = self.read_reg(I2C_SR1);
+            self.check_errors(sr1, nack)?;
+            if sr1 & mask != 0 {
+                return Ok(sr1);
+            }
+            if self.timed_out(start) {
+                return Err(I2cError::Timeout);
+            }
+        }
+    }
+
+    fn wait_not_busy(&self) -> Result<(), I2cError> {
+        let start = (self.tick)();
+        while self.read_reg(I2C_SR2) & SR2_BUSY != 0 {
+            if self.timed_out(start) {
+                return Err(I2cError::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait until the STOP condition has been sent (hardware clears CR1.STOP).
+    fn wait_stop(&self) -> Result<(), I2cError> {
+        let start = (self.tick)();
+        while self.read_reg(I2C_CR1) & (1 << CR1_STOP) != 0 {
+            if self.timed_out(start) {
+                return Err(I2cError::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reading SR1 then SR2 clears ADDR.
+    fn clear_addr(&self) {
+        let _ = self.read_reg(I2C_SR1);
+        let _ = self.read_reg(I2C_SR2);
+    }
+
+    fn write_dr(&self, byte: u8) {
+        unsafe {
+            write_register(self.reg(I2C_DR), byte as u32);
+        }
+    }
+
+    fn read_dr(&self) -> u8 {
+        self.read_reg(I2C_DR) as u8
+    }
+
+    fn start(&self) -> Result<(), I2cError> {
+        self.set_cr1_bit(CR1_START, true);
+        self.wait_sr1(SR1_SB, NoAcknowledgeSource::Unknown)?;
+        Ok(())
+    }
+
+    fn end(&self, end: End) {
+        match end {
+            End::Stop => self.set_cr1_bit(CR1_STOP, true),
+            End::Restart => self.set_cr1_bit(CR1_START, true),
+        }
+    }
+
+    /// Send START and the slave address. Returns with ADDR set but not cleared.
+    fn address(&self, address: Address, read: bool) -> Result<(), I2cError> {
+        self.start()?;
+        match address {
+            Address::Seven(addr) => {
+                self.write_dr((addr << 1) | read as u8);
+            }
+            Address::Ten(addr) => {
+                let header = 0xF0 | ((addr >> 7) as u8 & 0x06);
+                self.write_dr(header);
+                self.wait_sr1(SR1_ADD10, NoAcknowledgeSource::Address)?;
+                self.write_dr(addr as u8);
+                if read {
+                    // A 10-bit read is addressed in write mode, then switched with a repeated START
+                    self.wait_sr1(SR1_ADDR, NoAcknowledgeSource::Address)?;
+                    self.clear_addr();
+                    self.start()?;
+                    self.write_dr(header | 1);
+                }
+            }
+        }
+        self.wait_sr1(SR1_ADDR, NoAcknowledgeSource::Address)?;
+        Ok(())
+    }
+
+    fn write_group(&self, ops: &[Operation<'_>], end: End) -> Result<(), I2cError> {
+        self.clear_addr();
+        let mut sent = false;
+        for op in ops {
+            if let Operation::Write(bytes) = op {
+                for &byte in bytes.iter() {
+                    self.wait_sr1(SR1_TXE, NoAcknowledgeSource::Data)?;
+                    self.write_dr(byte);
+                    sent = true;
+                }
+            }
+        }
+        // BTF never sets without a byte sent: an address-only write (a probe) ends right away
+        if sent {
+            self.wait_sr1(SR1_BTF, NoAcknowledgeSource::Data)?;
+        }
+        self.end(end);
+        Ok(())
+    }
+
+    /// Receive into a group of adjacent `Read` operations, following the N=1 / N=2 / N>2 sequences
+    /// of RM0090 section 27.3.3 so that NACK and STOP/START land on the last byte.
+    fn read_group(&self, ops: &mut [Operation<'_>], end: End) -> Result<(), I2cError> {
+        let total: usize = ops.iter().map(|op| if let Operation::Read(buf) = op { buf.len() } else { 0 }).sum();
+        let mut index = 0;
+        let mut store = |ops: &mut [Operation<'_>], byte: u8| {
+            let mut offset = index;
+            for op in ops.iter_mut() {
+                if let Operation::Read(buf) = op {
+                    if offset < buf.len() {
+                        buf[offset] = byte;
+                        break;
+                    }
+                    offset -= buf.len();
+                }
+            }
+            index += 1;
+        };
+
+        match total {
+            0 => {
+                self.clear_addr();
+                self.end(end);
+            }
+            1 => {
+                self.set_cr1_bit(CR1_ACK, false);
+                self.clear_addr();
+                self.end(end);
+                self.wait_sr1(SR1_RXNE, NoAcknowledgeSource::Data)?;
+                store(ops, self.read_dr());
+            }
+            2 => {
+                self.set_cr1_bit(CR1_POS, true);
+                self.set_cr1_bit(CR1_ACK, false);
+                self.clear_addr();
+                let result = self.wait_sr1(SR1_BTF, NoAcknowledgeSource::Data);
+                self.set_cr1_bit(CR1_POS, false);
+                result?;
+                self.end(end);
+                store(ops, self.read_dr());
+                store(ops, self.read_dr());
+            }
+            _ => {
+                self.set_cr1_bit(CR1_ACK, true);
+                self.clear_addr();
+                for _ in 0..total - 3 {
+                    self.wait_sr1(SR1_RXNE, NoAcknowledgeSource::Data)?;
+                    store(ops, self.read_dr());
+                }
+                // Byte N-2 in DR, N-1 in the shift register
+                self.wait_sr1(SR1_BTF, NoAcknowledgeSource::Data)?;
+                self.set_cr1_bit(CR1_ACK, false);
+                store(ops, self.read_dr());
+                self.end(end);
+                store(ops, self.read_dr());
+                self.wait_sr1(SR1_RXNE, NoAcknowledgeSource::Data)?;
+                store(ops, self.read_dr());
+            }
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, address: Address, operations: &mut [Operation<'_>]) -> Result<(), I2cError> {
+        self.wait_not_busy()?;
+
+        let mut first = 0;
+        while first < operations.len() {
+            let read = matches!(operations[first], Operation::Read(_));
+            let mut last = first + 1;
+            while last < operations.len() && matches!(operations[last], Operation::Read(_)) == read {
+                last += 1;
+            }
+            let end = if last == operations.len() { End::Stop } else { End::Restart };
+
+            // A restart was already requested by the previous group; `address` waits for SB.
+            if first == 0 {
+                self.address(address, read)?;
+            } else {
+                self.wait_sr1(SR1_SB, NoAcknowledgeSource::Unknown)?;
+                self.send_address_after_restart(address, read)?;
+            }
+
+            if read {
+                self.read_group(&mut operations[first..last], end)?;
+            } else {
+                self.write_group(&operations[first..last], end)?;
+            }
+            first = last;
+        }
+
+        self.wait_stop()
+    }
+
+    /// Address phase after a repeated START generated by the previous group.
+    fn send_address_after_restart(&self, address: Address, read: bool) -> Result<(), I2cError> {
+        match address {
+            Address::Seven(addr) => self.write_dr((addr << 1) | read as u8),
+            Address::Ten(addr) => {
+                let header = 0xF0 | ((addr >> 7) as u8 & 0x06);
+                if read {
+                    // The full address was already sent by the preceding write group
+                    self.write_dr(header | 1);
+                } else {
+                    self.write_dr(header);
+                    self.wait_sr1(SR1_ADD10, NoAcknowledgeSource::Address)?;
+                    self.write_dr(addr as u8);
+                }
+            }
+        }
+        self.wait_sr1(SR1_ADDR, NoAcknowledgeSource::Address)?;
+        Ok(())
+    }
+
+    fn transaction_inner(&mut self, address: Address, operations: &mut [Operation<'_>]) -> Result<(), I2cError> {
+        if operations.is_empty() {
+            return Ok(());
+        }
+        let result = self.run(address, operations);
+        match result {
+            Err(I2cError::Bus) | Err(I2cError::Timeout) => {
+                let _ = self.recover_bus();
+            }
+            Err(I2cError::Nack(_)) => {
+                let _ = self.wait_stop();
+            }
+            _ => {}
+        }
+        self.set_cr1_bit(CR1_POS, false);
+        result
+    }
+
+    fn half_bit_delay(&self) {
+        // ~5 us at 100 kHz; the loop body takes at least one cycle.
+        for _ in 0..self.pclk_hz / 200_000 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Function name: `I2c::recover_bus`
+    ///
+    /// Description:
+    /// Frees a bus stuck with SDA held low by a slave that lost track of the transfer: SCL is driven
+    /// as an open-drain GPIO for up to 9 clock pulses until SDA is released, a STOP condition is
+    /// generated by hand, then the pins are returned to the peripheral and it is reset.
+    ///
+    /// Parameters:
+    /// - None
+    ///
+    /// Return:
+    /// - `Ok(())` if SDA is high afterwards, `Err(I2cError::Bus)` otherwise.
+    pub fn recover_bus(&mut self) -> Result<(), I2cError> {
+        let I2cPins { scl_port, scl_pin, sda_port, sda_pin } = self.pins;
+        self.set_cr1_bit(CR1_PE, false);
+
+        gpio_write(scl_port, scl_pin, true);
+        gpio_write(sda_port, sda_pin, true);
+        gpio_configure_mode(scl_port, scl_pin, GPIO_MODE_GP_OUTPUT);
+        gpio_configure_mode(sda_port, sda_pin, GPIO_MODE_INPUT);
+        self.half_bit_delay();
+
+        for _ in 0..9 {
+            if gpio_read(sda_port, sda_pin) {
+                break;
+            }
+            gpio_write(scl_port, scl_pin, false);
+            self.half_bit_delay();
+            gpio_write(scl_port, scl_pin, true);
+            self.half_bit_delay();
+        }
+
+        // STOP: SDA rises while SCL is high
+        gpio_write(scl_port, scl_pin, false);
+        gpio_write(sda_port, sda_pin, false);
+        gpio_configure_mode(sda_port, sda_pin, GPIO_MODE_GP_OUTPUT);
+        self.half_bit_delay();
+        gpio_write(scl_port, scl_pin, true);
+        self.half_bit_delay();
+        gpio_write(sda_port, sda_pin, true);
+        self.half_bit_delay();
+
+        let released = gpio_read(sda_port, sda_pin);
+
+        self.configure_pins();
+        self.init_peripheral();
+
+        if released { Ok(()) } else { Err(I2cError::Bus) }
+    }
+}
+
+/// Slave address in either addressing mode.
+#[derive(Clone, Copy)]
+enum Address {
+    Seven(u8),
+    Ten(u16),
+}
+
+impl ErrorType for I2c {
+    type Error = I2cError;
+}
+
+impl i2c::I2c<SevenBitAddress> for I2c {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), I2cError> {
+        self.transaction_inner(Address::Seven(address & 0x7F), operations)
+    }
+}
+
+impl i2c::I2c<TenBitAddress> for I2c {
+    fn transaction(&mut self, address: u16, operations: &mut [Operation<'_>]) -> Result<(), I2cError> {
+        self.transaction_inner(Address::Ten(address & 0x3FF), operations)
+    }
+}
@@ -0,0 +1,160 @@
+#![allow(dead_code)]
+
+/// # Independent Watchdog (IWDG) Driver Module
+///
+/// This module drives the independent watchdog of the STM32F407: a 12-bit down-counter clocked by
+/// the internal LSI oscillator, which resets the MCU when it reaches zero. Being clocked
+/// independently of the main clock tree, it keeps running if the system clock fails, and it also
+/// counts while the CPU is stalled on a flash erase.
+///
+/// ## Timing
+///
+/// The counter runs at LSI / prescaler (prescaler 4 – 256) and restarts from the reload value
+/// (0 – 4095) at every `iwdg_feed`, giving timeouts from 0.125 ms to ~32.8 s at the nominal
+/// 32 kHz. The LSI is not trimmed and varies between 17 and 47 kHz (datasheet), so the actual
+/// timeout can be up to ~30 % shorter than requested: leave margin.
+///
+/// ## Lifetime
+///
+/// Once started, the watchdog can only be stopped by a reset. The prescaler and reload value can
+/// be changed afterwards by calling `iwdg_start` again. `iwdg_freeze_on_debug` stops the counter
+/// while a debugger halts the core.
+use crate::stm32f407_registers::*;
+use crate::read_write::{read_register, write_register, reg_write_bit};
+
+/// Nominal LSI frequency.
+pub const LSI_HZ: u32 = 32_000;
+
+/// Longest timeout, with the largest prescaler and reload value at the nominal LSI frequency.
+pub const IWDG_MAX_TIMEOUT_MS: u32 = 256 * (RELOAD_MAX + 1) / (LSI_HZ / 1000);
+
+const RELOAD_MAX: u32 = 0xFFF;
+
+// Register offsets
+const IWDG_KR: u32 = 0x00;
+const IWDG_PR: u32 = 0x04;
+const IWDG_RLR: u32 = 0x08;
+const IWDG_SR: u32 = 0x0C;
+
+// KR keys
+const KEY_RELOAD: u32 = 0xAAAA;
+const KEY_ACCESS: u32 = 0x5555;
+const KEY_START: u32 = 0xCCCC;
+
+// SR bits: prescaler / reload value update in progress (in the LSI domain)
+const SR_PVU: u32 = 1 << 0;
+const SR_RVU: u32 = 1 << 1;
+
+// DBGMCU_APB1_FZ bit
+const DBG_IWDG_STOP: u32 = 12;
+
+/// Prescaler and reload value for a timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IwdgConfig {
+    /// LSI divider: 4, 8, 16, 32, 64, 128 or 256.
+    pub prescaler: u32,
+    /// Counter value after a feed (0 – 4095).
+    pub reload: u32,
+}
+
+impl IwdgConfig {
+    /// Timeout at the nominal LSI frequency.
+    pub const fn timeout_ms(&self) -> u32 {
+        self.prescaler * (self.reload + 1) / (LSI_HZ / 1000)
+    }
+
+    /// PR register value (divider 4 << PR).
+    fn pr(&self) -> u32 {
+        self.prescaler.trailing_zeros() - 2
+    }
+}
+
+fn reg(offset: u32) -> *mut u32 {
+    (IWDG_BASE + offset) as *mut u32
+}
+
+/// Function name: `iwdg_compute`
+///
+/// Description:
+/// Finds the smallest prescaler (finest resolution) whose reload value reaches `timeout_ms`.
+///
+/// Parameters:
+/// - `timeout_ms`: Timeout at the nominal LSI frequency, 1 – `IWDG_MAX_TIMEOUT_MS`.
+///
+/// Return:
+/// - The configuration, or `None` if the timeout is out of range.
+pub fn iwdg_compute(timeout_ms: u32) -> Option<IwdgConfig> {
+    if timeout_ms == 0 || timeout_ms > IWDG_MAX_TIMEOUT_MS {
+        return None;
+    }
+    let lsi_ticks = timeout_ms as u64 * LSI_HZ as u64 / 1000;
+    (2..=8).map(|shift| 1u32 << shift).find_map(|prescaler| {
+        let reload = lsi_ticks.div_ceil(prescaler as u64).saturating_sub(1);
+        (reload <= RELOAD_MAX as u64).then_some(IwdgConfig { prescaler, reload: reload as u32 })
+    })
+}
+
+/// Wait until the LSI domain has taken the last prescaler and reload values.
+fn wait_updated() {
+    unsafe { while read_register(reg(IWDG_SR)) & (SR_PVU | SR_RVU) != 0 {} }
+}
+
+/// Function name: `iwdg_start`
+///
+/// Description:
+/// Starts the watchdog (which turns the LSI on) with a timeout of `timeout_ms`, or changes the
+/// timeout of the running watchdog. The counter is reloaded with the new value on return.
+/// Panics if the timeout is out of range.
+///
+/// Parameters:
+/// - `timeout_ms`: Timeout at the nominal LSI frequency, 1 – `IWDG_MAX_TIMEOUT_MS`.
+///
+/// Return:
+/// - The configuration applied.
+pub fn iwdg_start(timeout_ms: u32) -> IwdgConfig {
+    let config = iwdg_compute(timeout_ms).unwrap_or_else(|| {
+        panic!("Invalid IWDG timeout: {} ms. Valid range is 1 – {} ms.", timeout_ms, IWDG_MAX_TIMEOUT_MS)
+    });
+    unsafe {
+        write_register(reg(IWDG_KR), KEY_START);
+        write_register(reg(IWDG_KR), KEY_ACCESS);
+        wait_updated();
+        write_register(reg(IWDG_PR), config.pr());
+        write_register(reg(IWDG_RLR), config.reload);
+        wait_updated();
+        write_register(reg(IWDG_KR), KEY_RELOAD);
+    }
+    config
+}
+
+/// Function name: `iwdg_feed`
+///
+/// Description:
+/// Reloads the counter, restarting the timeout. Safe to call from an interrupt.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - None
+pub fn iwdg_feed() {
+    unsafe {
+        write_register(reg(IWDG_KR), KEY_RELOAD);
+    }
+}
+
+/// Function name: `iwdg_freeze_on_debug`
+///
+/// Description:
+/// Selects whether the counter stops while the core is halted by a debugger (DBG_IWDG_STOP).
+///
+/// Parameters:
+/// - `freeze`: `true` to stop the counter on a debug halt.
+///
+/// Return:
+/// - None
+pub fn iwdg_freeze_on_debug(freeze: bool) {
+    unsafe {
+        reg_write_bit(DBGMCU_APB1_FZ as *mut u32, DBG_IWDG_STOP, freeze);
+    }
+}
@@ -5,5 +5,22 @@
 pub mod gpio;
 pub mod stm32f407_registers;
 pub mod exti;
+pub mod flash;
+pub mod adc;
 pub mod cortex_m4;
+pub mod crc;
+pub mod dac;
+pub mod dma;
+pub mod i2c;
+pub mod iwdg;
+pub mod rcc;
 pub mod read_write;
+pub mod pwr;
+pub mod ring_buffer;
+pub mod rng;
+pub mod rtc;
+pub mod spi;
+pub mod timer;
+pub mod usart;
+pub mod vector_table;
+pub mod wwdg;
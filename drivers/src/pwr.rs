@@ -0,0 +1,210 @@
+#![allow(dead_code)]
+
+/// # Power Controller (PWR) Driver Module
+///
+/// This module puts the STM32F407 into its low-power modes, from the lightest to the deepest:
+///
+/// - **Sleep** (`pwr_sleep`): the core clock stops, peripherals keep running. Any enabled
+///   interrupt wakes the core, which continues after the call.
+/// - **Stop** (`pwr_stop`): every clock of the 1.2 V domain stops and the HSI, HSE and PLL are
+///   turned off; SRAM and registers are kept. Only an EXTI line wakes the MCU: a GPIO interrupt,
+///   the RTC alarm (line 17) or wakeup timer (line 22), ... A USART cannot receive meanwhile.
+///   On wakeup the MCU runs from the HSI; `pwr_stop` restores the frozen clock tree
+///   (`rcc::restore_clocks`) before the waking interrupt is served.
+/// - **Standby** (`pwr_standby`): the 1.2 V domain is powered off, so SRAM and registers are lost
+///   (the backup domain, with the RTC and its backup registers, is kept). The MCU restarts from
+///   reset on a rising edge on the WKUP pin (PA0), an RTC alarm or wakeup event, NRST or an IWDG
+///   reset; `pwr_woke_from_standby` then tells this start from a cold one.
+///
+/// The independent watchdog keeps counting in Stop and Standby: the time spent there must stay
+/// below its timeout.
+///
+/// ## Sleep-on-exit
+///
+/// With `pwr_set_sleep_on_exit(true)` the core goes back to Sleep as soon as it returns from the
+/// last active interrupt to Thread mode, without running Thread mode code in between. This suits
+/// an entirely interrupt-driven program; under the kernel scheduler, which returns to the tasks
+/// through PendSV, it would stop the tasks from running.
+///
+/// ## Stop Regulator
+///
+/// In Stop the voltage regulator stays in main mode (fastest wakeup) or switches to low-power
+/// mode (lowest consumption, longer wakeup). The flash can also be powered down, adding to the
+/// wakeup time as well.
+use crate::stm32f407_registers::*;
+use crate::read_write::{read_register, write_register, reg_write_bit};
+use crate::cortex_m4::{
+    disable_global_interrupt, enable_global_interrupt, is_global_interrupt_enabled, set_sleep_deep,
+    set_sleep_on_exit, wait_for_interrupt,
+};
+use crate::rcc::restore_clocks;
+use crate::rtc::rtc_set_wakeup;
+
+const RCC_APB1ENR_PWREN: u32 = 28;
+
+// Register offsets
+const PWR_CR: u32 = 0x00;
+const PWR_CSR: u32 = 0x04;
+
+// CR bits
+const CR_LPDS: u32 = 0;
+const CR_PDDS: u32 = 1;
+const CR_CWUF: u32 = 2;
+const CR_CSBF: u32 = 3;
+const CR_FPDS: u32 = 9;
+
+// CSR bits
+const CSR_WUF: u32 = 1 << 0;
+const CSR_SBF: u32 = 1 << 1;
+const CSR_EWUP: u32 = 8;
+
+/// Voltage regulator mode in Stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopRegulator {
+    /// Main regulator on: shortest wakeup.
+    Main,
+    /// Low-power regulator: lowest consumption, wakeup a few tens of µs longer.
+    LowPower,
+}
+
+fn reg(offset: u32) -> *mut u32 {
+    (PWR_BASE + offset) as *mut u32
+}
+
+fn enable_clock() {
+    unsafe {
+        reg_write_bit(RCC_APB1ENR as *mut u32, RCC_APB1ENR_PWREN, true);
+    }
+}
+
+/// Function name: `pwr_sleep`
+///
+/// Description:
+/// Enters Sleep until an interrupt occurs. With interrupts masked by PRIMASK, an enabled
+/// interrupt still wakes the core and is served once they are unmasked.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - None
+pub fn pwr_sleep() {
+    set_sleep_deep(false);
+    wait_for_interrupt();
+}
+
+/// Function name: `pwr_set_sleep_on_exit`
+///
+/// Description:
+/// Selects whether the core re-enters Sleep on return from interrupt to Thread mode (see the
+/// module documentation).
+///
+/// Parameters:
+/// - `enable`: `true` to sleep on exit.
+///
+/// Return:
+/// - None
+pub fn pwr_set_sleep_on_exit(enable: bool) {
+    set_sleep_on_exit(enable);
+}
+
+/// Function name: `pwr_stop`
+///
+/// Description:
+/// Enters Stop until an EXTI line wakes the MCU, then restores the clock tree set by
+/// `RccConfig::freeze`. Interrupts are masked (PRIMASK) around the sequence, so the waking
+/// interrupt runs only after the clocks are back, on return from this function.
+///
+/// Parameters:
+/// - `regulator`: Regulator mode during Stop.
+/// - `flash_power_down`: `true` to power the flash down during Stop.
+///
+/// Return:
+/// - None
+pub fn pwr_stop(regulator: StopRegulator, flash_power_down: bool) {
+    enable_clock();
+    let enabled = is_global_interrupt_enabled();
+    disable_global_interrupt();
+    unsafe {
+        let mut cr = read_register(reg(PWR_CR)) & !(1 << CR_PDDS | 1 << CR_LPDS | 1 << CR_FPDS);
+        if regulator == StopRegulator::LowPower {
+            cr |= 1 << CR_LPDS;
+        }
+        if flash_power_down {
+            cr |= 1 << CR_FPDS;
+        }
+        write_register(reg(PWR_CR), cr);
+    }
+    set_sleep_deep(true);
+    wait_for_interrupt();
+    set_sleep_deep(false);
+    restore_clocks();
+    if enabled {
+        enable_global_interrupt();
+    }
+}
+
+/// Function name: `pwr_standby`
+///
+/// Description:
+/// Enters Standby. The MCU restarts from reset when a selected wakeup source fires (or on NRST
+/// or an IWDG reset). An RTC wakeup requires the RTC to run (`rtc::rtc_init`); alarms set with
+/// `rtc::rtc_set_alarm` also wake the MCU.
+///
+/// Parameters:
+/// - `wakeup_pin`: `true` to wake on a rising edge on the WKUP pin (PA0), which must be low now.
+/// - `rtc_wakeup_ms`: Wake after this delay with the RTC wakeup timer, or `None`.
+///
+/// Return:
+/// - Never returns.
+pub fn pwr_standby(wakeup_pin: bool, rtc_wakeup_ms: Option<u32>) -> ! {
+    enable_clock();
+    if let Some(delay_ms) = rtc_wakeup_ms {
+        rtc_set_wakeup(delay_ms, None);
+    }
+    disable_global_interrupt();
+    unsafe {
+        reg_write_bit(reg(PWR_CSR), CSR_EWUP, wakeup_pin);
+        // A wakeup flag still set would end Standby at once
+        reg_write_bit(reg(PWR_CR), CR_CWUF, true);
+        reg_write_bit(reg(PWR_CR), CR_PDDS, true);
+    }
+    set_sleep_deep(true);
+    loop {
+        wait_for_interrupt();
+    }
+}
+
+/// Function name: `pwr_woke_from_standby`
+///
+/// Description:
+/// Reports whether the MCU was in Standby before the last reset (SBF). The flag stays set until
+/// `pwr_clear_wakeup_flags`.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - `true` if the MCU restarted from Standby.
+pub fn pwr_woke_from_standby() -> bool {
+    enable_clock();
+    unsafe { read_register(reg(PWR_CSR)) & CSR_SBF != 0 }
+}
+
+/// Function name: `pwr_clear_wakeup_flags`
+///
+/// Description:
+/// Clears the Standby and wakeup flags, normally once at start-up after reading them.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - None
+pub fn pwr_clear_wakeup_flags() {
+    enable_clock();
+    unsafe {
+        let cr = read_register(reg(PWR_CR));
+        write_register(reg(PWR_CR), cr | 1 << CR_CSBF | 1 << CR_CWUF);
+    }
+}
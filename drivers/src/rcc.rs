@@ -0,0 +1,608 @@
+#![allow(dead_code)]
+
+/// # RCC Clock Tree Driver Module
+///
+/// This module configures the STM32F407 system clock: oscillator (HSI or HSE), main PLL,
+/// AHB/APB prescalers, Flash wait states and the regulator voltage scale.
+///
+/// ## Usage
+///
+/// The clock tree is configured once, early in `main` and before any peripheral is initialised:
+///
+/// ```ignore
+/// let clocks = RccConfig::new().hse(8_000_000).sysclk(168_000_000).freeze();
+/// ```
+///
+/// `freeze` returns a `Clocks` value holding every bus frequency and records it globally, so
+/// drivers (baud rates, I2C timing, SysTick reload, ...) read their input clock from `clocks()`
+/// instead of assuming a constant. Until `freeze` is called, `clocks()` describes the reset state:
+/// everything running from the 16 MHz HSI.
+///
+/// ## Limits (RM0090 section 6, VDD = 3.3 V)
+///
+/// - SYSCLK/HCLK at most 168 MHz, APB1 at most 42 MHz, APB2 at most 84 MHz.
+/// - PLL input (after /M) 1–2 MHz, VCO 100–432 MHz, N 50–432, P in {2, 4, 6, 8}, Q 2–15.
+/// - One Flash wait state per 30 MHz of HCLK.
+///
+/// ## Low-Power Modes
+///
+/// Stop mode turns the HSE and the PLL off and wakes up running from the HSI. `restore_clocks`
+/// starts them again and switches SYSCLK back, leaving the frozen frequencies in effect (the
+/// prescalers and PLL dividers are kept across Stop). `pwr::pwr_stop` calls it on wakeup.
+///
+/// ## Reset Cause
+///
+/// `reset_cause` reads the reset flags of RCC_CSR, which accumulate across resets until
+/// `clear_reset_flags` is called (normally once, at start-up, after reading them).
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::stm32f407_registers::*;
+use crate::read_write::{read_register, write_register, reg_write_bit};
+
+pub const HSI_HZ: u32 = 16_000_000;
+
+pub const SYSCLK_MAX_HZ: u32 = 168_000_000;
+pub const PCLK1_MAX_HZ: u32 = 42_000_000;
+pub const PCLK2_MAX_HZ: u32 = 84_000_000;
+pub const PLL48_HZ: u32 = 48_000_000;
+
+// Register addresses
+const RCC_CR: u32 = RCC_BASE; // offset 0x00
+const RCC_PLLCFGR: u32 = RCC_BASE + 0x04;
+const RCC_CFGR: u32 = RCC_BASE + 0x08;
+const RCC_CSR: u32 = RCC_BASE + 0x74;
+
+// CR bits
+const CR_HSION: u32 = 0;
+const CR_HSIRDY: u32 = 1 << 1;
+const CR_HSEON: u32 = 16;
+const CR_HSERDY: u32 = 1 << 17;
+const CR_HSEBYP: u32 = 18;
+const CR_PLLON: u32 = 24;
+const CR_PLLRDY: u32 = 1 << 25;
+
+// PLLCFGR fields
+const PLLCFGR_M: u32 = 0;
+const PLLCFGR_N: u32 = 6;
+const PLLCFGR_P: u32 = 16;
+const PLLCFGR_SRC_HSE: u32 = 1 << 22;
+const PLLCFGR_Q: u32 = 24;
+
+// CFGR fields
+const CFGR_SW_MASK: u32 = 0b11;
+const CFGR_SWS_SHIFT: u32 = 2;
+const CFGR_HPRE: u32 = 4;
+const CFGR_PPRE1: u32 = 10;
+const CFGR_PPRE2: u32 = 13;
+const CFGR_PRESCALER_MASK: u32 = (0xF << CFGR_HPRE) | (0x7 << CFGR_PPRE1) | (0x7 << CFGR_PPRE2);
+
+const SW_HSI: u32 = 0b00;
+const SW_HSE: u32 = 0b01;
+const SW_PLL: u32 = 0b10;
+
+// CSR reset flags
+const CSR_RMVF: u32 = 24;
+const CSR_BORRSTF: u32 = 1 << 25;
+const CSR_PINRSTF: u32 = 1 << 26;
+const CSR_PORRSTF: u32 = 1 << 27;
+const CSR_SFTRSTF: u32 = 1 << 28;
+const CSR_IWDGRSTF: u32 = 1 << 29;
+const CSR_WWDGRSTF: u32 = 1 << 30;
+const CSR_LPWRRSTF: u32 = 1 << 31;
+
+// Flash access control
+const FLASH_ACR: u32 = FLASH_INTERFACE_BASE; // offset 0x00
+const ACR_LATENCY_MASK: u32 = 0x7;
+const ACR_PRFTEN: u32 = 1 << 8;
+const ACR_ICEN: u32 = 1 << 9;
+const ACR_DCEN: u32 = 1 << 10;
+
+// Power controller: voltage scale 1 is required above 144 MHz
+const PWR_CR: u32 = PWR_BASE; // offset 0x00
+const PWR_CR_VOS: u32 = 14;
+const RCC_APB1ENR_PWREN: u32 = 28;
+
+/// Iterations to wait for an oscillator or the PLL to become ready.
+const READY_TIMEOUT: u32 = 1_000_000;
+
+/// Clock source feeding SYSCLK or the PLL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Oscillator {
+    /// Internal 16 MHz RC oscillator.
+    Hsi,
+    /// External crystal (or external clock with `bypass`), 4–26 MHz.
+    Hse { frequency_hz: u32, bypass: bool },
+}
+
+impl Oscillator {
+    pub const fn frequency_hz(self) -> u32 {
+        match self {
+            Oscillator::Hsi => HSI_HZ,
+            Oscillator::Hse { frequency_hz, .. } => frequency_hz,
+        }
+    }
+}
+
+/// Main PLL dividers: VCO = input / M * N, SYSCLK = VCO / P, PLL48CK = VCO / Q.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PllConfig {
+    pub m: u32,
+    pub n: u32,
+    pub p: u32,
+    pub q: u32,
+}
+
+impl PllConfig {
+    pub const fn vco_hz(&self, input_hz: u32) -> u32 {
+        input_hz / self.m * self.n
+    }
+}
+
+/// Requested clock tree. Frequencies left unset take the highest value allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RccConfig {
+    pub oscillator: Oscillator,
+    pub sysclk_hz: Option<u32>,
+    pub hclk_hz: Option<u32>,
+    pub pclk1_hz: Option<u32>,
+    pub pclk2_hz: Option<u32>,
+}
+
+impl RccConfig {
+    /// HSI, no PLL: the reset clock tree.
+    pub const fn new() -> Self {
+        RccConfig { oscillator: Oscillator::Hsi, sysclk_hz: None, hclk_hz: None, pclk1_hz: None, pclk2_hz: None }
+    }
+
+    /// Use an external crystal of `frequency_hz`.
+    pub const fn hse(mut self, frequency_hz: u32) -> Self {
+        self.oscillator = Oscillator::Hse { frequency_hz, bypass: false };
+        self
+    }
+
+    /// Use an external clock signal of `frequency_hz` on OSC_IN.
+    pub const fn hse_bypass(mut self, frequency_hz: u32) -> Self {
+        self.oscillator = Oscillator::Hse { frequency_hz, bypass: true };
+        self
+    }
+
+    /// SYSCLK frequency; the PLL is used unless it equals the oscillator frequency.
+    pub const fn sysclk(mut self, hz: u32) -> Self {
+        self.sysclk_hz = Some(hz);
+        self
+    }
+
+    pub const fn hclk(mut self, hz: u32) -> Self {
+        self.hclk_hz = Some(hz);
+        self
+    }
+
+    pub const fn pclk1(mut self, hz: u32) -> Self {
+        self.pclk1_hz = Some(hz);
+        self
+    }
+
+    pub const fn pclk2(mut self, hz: u32) -> Self {
+        self.pclk2_hz = Some(hz);
+        self
+    }
+
+    /// Function name: `RccConfig::freeze`
+    ///
+    /// Description:
+    /// Applies the configuration and records the resulting frequencies. Can only be called once.
+    /// Panics if the requested frequencies cannot be reached or the HSE/PLL fails to start.
+    ///
+    /// Parameters:
+    /// - None
+    ///
+    /// Return:
+    /// - The bus frequencies now in effect.
+    pub fn freeze(self) -> Clocks {
+        assert!(!FROZEN.swap(true, Ordering::AcqRel), "Clock configuration is already frozen");
+
+        let plan = rcc_plan(&self);
+        apply(&self, &plan);
+
+        unsafe {
+            CLOCKS = plan.clocks;
+            OSCILLATOR = self.oscillator;
+            SYSCLK_SOURCE = match (plan.pll, self.oscillator) {
+                (Some(_), _) => SW_PLL,
+                (None, Oscillator::Hse { .. }) => SW_HSE,
+                (None, Oscillator::Hsi) => SW_HSI,
+            };
+        }
+        plan.clocks
+    }
+}
+
+impl Default for RccConfig {
+    fn default() -> Self {
+        RccConfig::new()
+    }
+}
+
+/// Frequencies of the configured clock tree, in Hz.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clocks {
+    sysclk: u32,
+    hclk: u32,
+    pclk1: u32,
+    pclk2: u32,
+    ppre1: u32,
+    ppre2: u32,
+    pll48clk: Option<u32>,
+}
+
+impl Clocks {
+    /// Reset state: HSI, no prescaling.
+    pub const RESET: Clocks = Clocks {
+        sysclk: HSI_HZ,
+        hclk: HSI_HZ,
+        pclk1: HSI_HZ,
+        pclk2: HSI_HZ,
+        ppre1: 1,
+        ppre2: 1,
+        pll48clk: None,
+    };
+
+    pub const fn sysclk(&self) -> u32 {
+        self.sysclk
+    }
+
+    /// AHB clock: core, SysTick, memories, DMA and GPIO.
+    pub const fn hclk(&self) -> u32 {
+        self.hclk
+    }
+
+    pub const fn hclk_mhz(&self) -> u32 {
+        self.hclk / 1_000_000
+    }
+
+    /// APB1 peripheral clock (USART2..5, SPI2/3, I2C, TIM2..7, TIM12..14, DAC).
+    pub const fn pclk1(&self) -> u32 {
+        self.pclk1
+    }
+
+    /// APB2 peripheral clock (USART1/6, SPI1, ADC, TIM1/8..11, SYSCFG).
+    pub const fn pclk2(&self) -> u32 {
+        self.pclk2
+    }
+
+    /// Timer kernel clock on APB1: twice PCLK1 when APB1 is divided.
+    pub const fn timclk1(&self) -> u32 {
+        if self.ppre1 == 1 { self.pclk1 } else { self.pclk1 * 2 }
+    }
+
+    /// Timer kernel clock on APB2: twice PCLK2 when APB2 is divided.
+    pub const fn timclk2(&self) -> u32 {
+        if self.ppre2 == 1 { self.pclk2 } else { self.pclk2 * 2 }
+    }
+
+    /// PLL48CK (USB OTG FS, SDIO, RNG), when the PLL is running.
+    pub const fn pll48clk(&self) -> Option<u32> {
+        self.pll48clk
+    }
+}
+
+static FROZEN: AtomicBool = AtomicBool::new(false);
+static mut CLOCKS: Clocks = Clocks::RESET;
+/// Oscillator and SYSCLK source selected by `freeze`, for `restore_clocks`.
+static mut OSCILLATOR: Oscillator = Oscillator::Hsi;
+static mut SYSCLK_SOURCE: u32 = SW_HSI;
+
+/// Function name: `clocks`
+///
+/// Description:
+/// Returns the frequencies set by `RccConfig::freeze`, or the reset state if it has not run.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - Current `Clocks`.
+pub fn clocks() -> Clocks {
+    if FROZEN.load(Ordering::Acquire) {
+        unsafe { CLOCKS }
+    } else {
+        Clocks::RESET
+    }
+}
+
+/// Function name: `restore_clocks`
+///
+/// Description:
+/// Restarts the HSE and the PLL and switches SYSCLK back to the source selected by `freeze`,
+/// after Stop mode left the MCU running from the HSI. Does nothing before `freeze`. Panics if the
+/// HSE or the PLL fails to start.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - None
+pub fn restore_clocks() {
+    if !FROZEN.load(Ordering::Acquire) {
+        return;
+    }
+    let cr = RCC_CR as *mut u32;
+    unsafe {
+        if matches!(OSCILLATOR, Oscillator::Hse { .. }) {
+            reg_write_bit(cr, CR_HSEON, true);
+            wait_ready(RCC_CR, CR_HSERDY, "HSE");
+        }
+        if SYSCLK_SOURCE == SW_PLL {
+            reg_write_bit(cr, CR_PLLON, true);
+            wait_ready(RCC_CR, CR_PLLRDY, "PLL");
+        }
+        switch_sysclk(SYSCLK_SOURCE);
+    }
+}
+
+/// Function name: `pll_compute`
+///
+/// Description:
+/// Finds PLL dividers producing exactly `sysclk_hz` from `input_hz`, preferring a 2 MHz PLL input
+/// (lower jitter) and the lowest VCO frequency. Q is chosen so PLL48CK does not exceed 48 MHz.
+///
+/// Parameters:
+/// - `input_hz`: HSI or HSE frequency.
+/// - `sysclk_hz`: Target SYSCLK.
+///
+/// Return:
+/// - The dividers, or `None` if the frequency cannot be reached exactly.
+pub fn pll_compute(input_hz: u32, sysclk_hz: u32) -> Option<PllConfig> {
+    let m_min = input_hz.div_ceil(2_000_000).max(2);
+    for m in m_min..=63 {
+        let vco_in = input_hz / m;
+        if vco_in < 1_000_000 {
+            break;
+        }
+        if !input_hz.is_multiple_of(m) {
+            continue;
+        }
+        for p in [2, 4, 6, 8] {
+            let vco = sysclk_hz as u64 * p;
+            if !(100_000_000..=432_000_000).contains(&vco) || !vco.is_multiple_of(vco_in as u64) {
+                continue;
+            }
+            let n = (vco / vco_in as u64) as u32;
+            if !(50..=432).contains(&n) {
+                continue;
+            }
+            let q = (vco as u32).div_ceil(PLL48_HZ).clamp(2, 15);
+            return Some(PllConfig { m, n, p: p as u32, q });
+        }
+    }
+    None
+}
+
+/// HPRE encoding of an AHB divider.
+fn hpre_bits(div: u32) -> Option<u32> {
+    match div {
+        1 => Some(0),
+        2 => Some(0b1000),
+        4 => Some(0b1001),
+        8 => Some(0b1010),
+        16 => Some(0b1011),
+        64 => Some(0b1100),
+        128 => Some(0b1101),
+        256 => Some(0b1110),
+        512 => Some(0b1111),
+        _ => None,
+    }
+}
+
+/// PPRE encoding of an APB divider.
+fn ppre_bits(div: u32) -> Option<u32> {
+    match div {
+        1 => Some(0),
+        2 => Some(0b100),
+        4 => Some(0b101),
+        8 => Some(0b110),
+        16 => Some(0b111),
+        _ => None,
+    }
+}
+
+/// Smallest APB divider keeping `hclk / div` at or below `target`.
+fn apb_divider(hclk: u32, target: u32) -> u32 {
+    [1, 2, 4, 8, 16]
+        .into_iter()
+        .find(|div| hclk / div <= target)
+        .unwrap_or_else(|| panic!("APB clock {} Hz unreachable from HCLK {} Hz", target, hclk))
+}
+
+/// Flash wait states for `hclk` at 3.3 V.
+pub const fn flash_latency(hclk_hz: u32) -> u32 {
+    (hclk_hz - 1) / 30_000_000
+}
+
+/// Register values and resulting frequencies for a configuration.
+struct Plan {
+    pll: Option<PllConfig>,
+    cfgr_prescalers: u32,
+    latency: u32,
+    clocks: Clocks,
+}
+
+fn rcc_plan(config: &RccConfig) -> Plan {
+    let input = config.oscillator.frequency_hz();
+    let sysclk = config.sysclk_hz.unwrap_or(input);
+    assert!(sysclk <= SYSCLK_MAX_HZ, "SYSCLK {} Hz above {} Hz", sysclk, SYSCLK_MAX_HZ);
+
+    let pll = if sysclk == input {
+        None
+    } else {
+        Some(pll_compute(input, sysclk).unwrap_or_else(|| panic!("No PLL setting gives {} Hz from {} Hz", sysclk, input)))
+    };
+
+    let hclk = config.hclk_hz.unwrap_or(sysclk);
+    let hpre = sysclk / hclk;
+    let hpre_field = match hpre_bits(hpre) {
+        Some(bits) if sysclk.is_multiple_of(hclk) => bits,
+        _ => panic!("HCLK {} Hz is not SYSCLK {} Hz / 1..512", hclk, sysclk),
+    };
+
+    let ppre1 = apb_divider(hclk, config.pclk1_hz.unwrap_or(PCLK1_MAX_HZ).min(PCLK1_MAX_HZ));
+    let ppre2 = apb_divider(hclk, config.pclk2_hz.unwrap_or(PCLK2_MAX_HZ).min(PCLK2_MAX_HZ));
+    let cfgr_prescalers = (hpre_field << CFGR_HPRE)
+        | (ppre_bits(ppre1).unwrap() << CFGR_PPRE1)
+        | (ppre_bits(ppre2).unwrap() << CFGR_PPRE2);
+
+    Plan {
+        pll,
+        cfgr_prescalers,
+        latency: flash_latency(hclk),
+        clocks: Clocks {
+            sysclk,
+            hclk,
+            pclk1: hclk / ppre1,
+            pclk2: hclk / ppre2,
+            ppre1,
+            ppre2,
+            pll48clk: pll.map(|pll| pll.vco_hz(input) / pll.q),
+        },
+    }
+}
+
+fn wait_ready(addr: u32, flag: u32, what: &str) {
+    for _ in 0..READY_TIMEOUT {
+        if unsafe { read_register(addr as *mut u32) } & flag != 0 {
+            return;
+        }
+    }
+    panic!("{} did not become ready", what);
+}
+
+fn set_flash_latency(latency: u32) {
+    let acr = FLASH_ACR as *mut u32;
+    unsafe {
+        let value = read_register(acr) & !ACR_LATENCY_MASK;
+        write_register(acr, value | latency | ACR_PRFTEN | ACR_ICEN | ACR_DCEN);
+        // The new latency must be in effect before the clock changes (RM0090 3.5.1)
+        while read_register(acr) & ACR_LATENCY_MASK != latency {}
+    }
+}
+
+/// Select the SYSCLK source and wait until the switch is done.
+fn switch_sysclk(sw: u32) {
+    let cfgr = RCC_CFGR as *mut u32;
+    unsafe {
+        let value = read_register(cfgr) & !CFGR_SW_MASK;
+        write_register(cfgr, value | sw);
+        while (read_register(cfgr) >> CFGR_SWS_SHIFT) & CFGR_SW_MASK != sw {}
+    }
+}
+
+fn apply(config: &RccConfig, plan: &Plan) {
+    let cr = RCC_CR as *mut u32;
+    let cfgr = RCC_CFGR as *mut u32;
+
+    unsafe {
+        // Run from HSI while the tree is reconfigured
+        reg_write_bit(cr, CR_HSION, true);
+        wait_ready(RCC_CR, CR_HSIRDY, "HSI");
+        switch_sysclk(SW_HSI);
+        reg_write_bit(cr, CR_PLLON, false);
+        while read_register(cr) & CR_PLLRDY != 0 {}
+
+        if let Oscillator::Hse { bypass, .. } = config.oscillator {
+            reg_write_bit(cr, CR_HSEON, false);
+            reg_write_bit(cr, CR_HSEBYP, bypass);
+            reg_write_bit(cr, CR_HSEON, true);
+            wait_ready(RCC_CR, CR_HSERDY, "HSE");
+        }
+
+        // Regulator scale 1 for frequencies above 144 MHz
+        reg_write_bit(RCC_APB1ENR as *mut u32, RCC_APB1ENR_PWREN, true);
+        reg_write_bit(PWR_CR as *mut u32, PWR_CR_VOS, true);
+
+        // Wait states and bus dividers are set while still on HSI, before SYSCLK speeds up
+        set_flash_latency(plan.latency);
+        let value = read_register(cfgr) & !CFGR_PRESCALER_MASK;
+        write_register(cfgr, value | plan.cfgr_prescalers);
+
+        match plan.pll {
+            Some(pll) => {
+                let mut pllcfgr = (pll.m << PLLCFGR_M)
+                    | (pll.n << PLLCFGR_N)
+                    | ((pll.p / 2 - 1) << PLLCFGR_P)
+                    | (pll.q << PLLCFGR_Q);
+                if matches!(config.oscillator, Oscillator::Hse { .. }) {
+                    pllcfgr |= PLLCFGR_SRC_HSE;
+                }
+                write_register(RCC_PLLCFGR as *mut u32, pllcfgr);
+                reg_write_bit(cr, CR_PLLON, true);
+                wait_ready(RCC_CR, CR_PLLRDY, "PLL");
+                switch_sysclk(SW_PLL);
+            }
+            None => {
+                if matches!(config.oscillator, Oscillator::Hse { .. }) {
+                    switch_sysclk(SW_HSE);
+                }
+            }
+        }
+    }
+}
+
+/// What caused the last reset, from the RCC_CSR flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetCause {
+    /// Entering Standby or Stop while the option bytes forbid it.
+    LowPower,
+    WindowWatchdog,
+    IndependentWatchdog,
+    /// `system_reset` (SYSRESETREQ), including a reset by a debugger.
+    Software,
+    PowerOn,
+    /// Supply below the brown-out threshold.
+    Brownout,
+    /// NRST pin.
+    Pin,
+    /// No flag set (they were cleared since the last reset).
+    Unknown,
+}
+
+/// Function name: `reset_cause`
+///
+/// Description:
+/// Reads the reset flags. Every reset also sets the pin flag (and a power-on reset the brown-out
+/// flag), so the most specific flag set is reported.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - The cause of the last reset.
+pub fn reset_cause() -> ResetCause {
+    let csr = unsafe { read_register(RCC_CSR as *mut u32) };
+    [
+        (CSR_LPWRRSTF, ResetCause::LowPower),
+        (CSR_WWDGRSTF, ResetCause::WindowWatchdog),
+        (CSR_IWDGRSTF, ResetCause::IndependentWatchdog),
+        (CSR_SFTRSTF, ResetCause::Software),
+        (CSR_PORRSTF, ResetCause::PowerOn),
+        (CSR_BORRSTF, ResetCause::Brownout),
+        (CSR_PINRSTF, ResetCause::Pin),
+    ]
+    .into_iter()
+    .find(|&(flag, _)| csr & flag != 0)
+    .map_or(ResetCause::Unknown, |(_, cause)| cause)
+}
+
+/// Function name: `clear_reset_flags`
+///
+/// Description:
+/// Clears the reset flags (RMVF) so that the next reset reports only its own cause.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - None
+pub fn clear_reset_flags() {
+    unsafe {
+        reg_write_bit(RCC_CSR as *mut u32, CSR_RMVF, true);
+    }
+}
@@ -0,0 +1,102 @@
+#![allow(dead_code)]
+
+/// # Lock-Free Byte Ring Buffer
+///
+/// This module provides a fixed-size single-producer / single-consumer byte queue used to pass data
+/// between interrupt handlers and tasks (e.g. USART RX/TX buffering) without disabling interrupts.
+///
+/// ## Usage Rules
+///
+/// - Exactly one context may call `push` and exactly one context may call `pop` at any time
+///   (typically an ISR on one side and a task on the other).
+/// - `N` must be a power of two; one slot is kept empty to distinguish "full" from "empty",
+///   so the usable capacity is `N - 1` bytes.
+///
+/// ## Safety
+///
+/// The head index is only written by the producer and the tail index only by the consumer.
+/// Release/acquire ordering on those indices guarantees that a byte is fully written before the
+/// consumer can observe it.
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct RingBuffer<const N: usize> {
+    data: UnsafeCell<[u8; N]>,
+    head: AtomicUsize, // next slot to write (producer)
+    tail: AtomicUsize, // next slot to read (consumer)
+}
+
+// SAFETY: access to `data` is partitioned between one producer and one consumer by `head`/`tail`.
+unsafe impl<const N: usize> Sync for RingBuffer<N> {}
+
+impl<const N: usize> RingBuffer<N> {
+    const MASK: usize = {
+        assert!(N.is_power_of_two(), "RingBuffer size must be a power of two");
+        N - 1
+    };
+
+    /// Creates an empty ring buffer.
+    pub const fn new() -> Self {
+        RingBuffer {
+            data: UnsafeCell::new([0; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Appends a byte. Returns `false` (and drops the byte) if the buffer is full.
+    pub fn push(&self, byte: u8) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        let next = (head + 1) & Self::MASK;
+        if next == self.tail.load(Ordering::Acquire) {
+            return false;
+        }
+        unsafe {
+            (*self.data.get())[head] = byte;
+        }
+        self.head.store(next, Ordering::Release);
+        true
+    }
+
+    /// Removes and returns the oldest byte, or `None` if the buffer is empty.
+    pub fn pop(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+        let byte = unsafe { (*self.data.get())[tail] };
+        self.tail.store((tail + 1) & Self::MASK, Ordering::Release);
+        Some(byte)
+    }
+
+    /// Number of bytes currently stored.
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        head.wrapping_sub(tail) & Self::MASK
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == Self::MASK
+    }
+
+    /// Maximum number of bytes the buffer can hold.
+    pub const fn capacity(&self) -> usize {
+        Self::MASK
+    }
+
+    /// Discards all stored bytes. Must only be called from the consumer side.
+    pub fn clear(&self) {
+        self.tail.store(self.head.load(Ordering::Acquire), Ordering::Release);
+    }
+}
+
+impl<const N: usize> Default for RingBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
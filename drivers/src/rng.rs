@@ -0,0 +1,240 @@
+#![allow(dead_code)]
+
+/// # Random Number Generator (RNG) Driver Module
+///
+/// This module drives the true random number generator of the STM32F407, which delivers a 32-bit
+/// random number from analog noise every 40 periods of its clock, the PLL48CK output of the main
+/// PLL (`rcc::Clocks::pll48clk`).
+///
+/// ## Error Detection
+///
+/// Every read checks the status reported by the hardware:
+///
+/// - **Clock error**: the RNG clock is below HCLK / 16 (or stopped). The numbers read meanwhile
+///   are not trustworthy; the clock tree must be fixed.
+/// - **Seed error**: the noise source produced a bad sequence (the same bit for more than 64 cycles
+///   or a run of more than 32 alternating bits). The generator is restarted and the values read
+///   before are discarded.
+///
+/// In addition, as required by FIPS PUB 140-2, the first number after starting or restarting the
+/// generator is only kept as a reference, and each number is compared with the previous one: two
+/// identical consecutive numbers fail (continuous random number generator test).
+///
+/// ## rand_core
+///
+/// `Rng` implements `rand_core::RngCore`: `try_fill_bytes` reports errors, while the infallible
+/// methods retry after a seed error or a repeated value and panic on a persistent failure.
+///
+/// The driver keeps the reference value in a static: use a single `Rng`, from one task.
+use core::num::NonZeroU32;
+use rand_core::{impls, Error, RngCore};
+use crate::stm32f407_registers::*;
+use crate::read_write::{read_register, write_register, reg_write_bit};
+use crate::rcc::clocks;
+
+const RCC_AHB2ENR_RNGEN: u32 = 6;
+
+// Register offsets
+const RNG_CR: u32 = 0x00;
+const RNG_SR: u32 = 0x04;
+const RNG_DR: u32 = 0x08;
+
+// CR bits
+const CR_RNGEN: u32 = 2;
+
+// SR bits
+const SR_DRDY: u32 = 1 << 0;
+const SR_CECS: u32 = 1 << 1;
+const SR_SECS: u32 = 1 << 2;
+const SR_CEIS: u32 = 1 << 5;
+const SR_SEIS: u32 = 1 << 6;
+
+/// Iterations to wait for a number (one takes 40 RNG clock periods, under 1 µs).
+const READY_TIMEOUT: u32 = 100_000;
+
+/// Attempts of the infallible `RngCore` methods before giving up.
+const RETRIES: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RngError {
+    /// The PLL does not run, so there is no RNG clock.
+    NoClock,
+    /// The RNG clock is too slow (clock error).
+    ClockError,
+    /// The noise source failed (seed error); the generator was restarted.
+    SeedError,
+    /// The same number was produced twice in a row.
+    Repeated,
+    /// No number became ready.
+    Timeout,
+}
+
+impl RngError {
+    /// Code carried by a `rand_core::Error`.
+    const fn code(self) -> u32 {
+        Error::CUSTOM_START
+            + match self {
+                RngError::NoClock => 0,
+                RngError::ClockError => 1,
+                RngError::SeedError => 2,
+                RngError::Repeated => 3,
+                RngError::Timeout => 4,
+            }
+    }
+}
+
+impl From<RngError> for Error {
+    fn from(error: RngError) -> Self {
+        // CUSTOM_START is not zero
+        Error::from(NonZeroU32::new(error.code()).unwrap())
+    }
+}
+
+/// Previous number, for the continuous test; `None` right after a (re)start.
+static mut PREVIOUS: Option<u32> = None;
+
+fn reg(offset: u32) -> *mut u32 {
+    (RNG_BASE + offset) as *mut u32
+}
+
+/// Restart the generator after a seed error (RM0090 24.3.2).
+fn restart() {
+    unsafe {
+        let sr = read_register(reg(RNG_SR));
+        write_register(reg(RNG_SR), sr & !SR_SEIS);
+        reg_write_bit(reg(RNG_CR), CR_RNGEN, false);
+        reg_write_bit(reg(RNG_CR), CR_RNGEN, true);
+        PREVIOUS = None;
+    }
+}
+
+/// Function name: `rng_init`
+///
+/// Description:
+/// Enables the RNG clock and starts the generator, then reads the first number as the reference
+/// of the continuous test. The clock tree must be frozen with the PLL running.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - `Err` if the RNG clock is missing or too slow, or the first number cannot be read.
+pub fn rng_init() -> Result<(), RngError> {
+    let clocks = clocks();
+    let rng_clock = clocks.pll48clk().ok_or(RngError::NoClock)?;
+    if rng_clock < clocks.hclk() / 16 {
+        return Err(RngError::ClockError);
+    }
+    unsafe {
+        reg_write_bit(RCC_AHB2ENR as *mut u32, RCC_AHB2ENR_RNGEN, true);
+        PREVIOUS = None;
+        reg_write_bit(reg(RNG_CR), CR_RNGEN, true);
+    }
+    // Sets the reference and reports a failure right away
+    rng_read().map(|_| ())
+}
+
+/// Read the data register once it is ready, checking the error flags.
+fn read_raw() -> Result<u32, RngError> {
+    for _ in 0..READY_TIMEOUT {
+        let sr = unsafe { read_register(reg(RNG_SR)) };
+        if sr & (SR_SECS | SR_SEIS) != 0 {
+            restart();
+            return Err(RngError::SeedError);
+        }
+        if sr & (SR_CECS | SR_CEIS) != 0 {
+            unsafe {
+                write_register(reg(RNG_SR), sr & !SR_CEIS);
+            }
+            return Err(RngError::ClockError);
+        }
+        if sr & SR_DRDY != 0 {
+            return Ok(unsafe { read_register(reg(RNG_DR)) });
+        }
+    }
+    Err(RngError::Timeout)
+}
+
+/// Function name: `rng_read`
+///
+/// Description:
+/// Reads a 32-bit random number, which passed the hardware checks and the continuous test.
+/// After a seed error or a repeated number, the next call can succeed again.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - The random number, or the error detected.
+pub fn rng_read() -> Result<u32, RngError> {
+    loop {
+        let value = read_raw()?;
+        let previous = unsafe { PREVIOUS };
+        unsafe {
+            PREVIOUS = Some(value);
+        }
+        match previous {
+            // First number after a (re)start: reference only
+            None => continue,
+            Some(previous) if previous == value => return Err(RngError::Repeated),
+            Some(_) => return Ok(value),
+        }
+    }
+}
+
+/// The hardware generator as a `rand_core::RngCore`.
+pub struct Rng {
+    _private: (),
+}
+
+impl Rng {
+    /// Function name: `Rng::new`
+    ///
+    /// Description:
+    /// Starts the generator (`rng_init`).
+    ///
+    /// Parameters:
+    /// - None
+    ///
+    /// Return:
+    /// - The generator, or the error `rng_init` reported.
+    pub fn new() -> Result<Self, RngError> {
+        rng_init()?;
+        Ok(Rng { _private: () })
+    }
+
+    /// A number, retrying after transient errors; panics on a persistent failure.
+    fn next_checked(&mut self) -> u32 {
+        let mut error = RngError::Timeout;
+        for _ in 0..RETRIES {
+            match rng_read() {
+                Ok(value) => return value,
+                Err(err @ (RngError::SeedError | RngError::Repeated)) => error = err,
+                Err(err) => panic!("RNG failure: {:?}", err),
+            }
+        }
+        panic!("RNG failure: {:?} {} times in a row", error, RETRIES);
+    }
+}
+
+impl RngCore for Rng {
+    fn next_u32(&mut self) -> u32 {
+        self.next_checked()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_u32(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = rng_read()?.to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,830 @@
+#![allow(dead_code)]
+
+/// # RTC Driver Module
+///
+/// This module drives the real-time clock of the STM32F407: calendar (date and time of day in BCD),
+/// alarms A and B, the periodic wakeup timer, the 20 backup registers and smooth calibration.
+///
+/// ## Backup Domain
+///
+/// The RTC, its clock selection and the backup registers live in the backup domain, which keeps
+/// running through resets (and on VBAT when VDD is off). It is write-protected after reset:
+/// `rtc_init` enables write access (PWR_CR.DBP) and leaves it enabled. Its clock is the external
+/// 32.768 kHz crystal (LSE) or the internal ~32 kHz RC oscillator (LSI: 17 – 47 kHz depending on
+/// the part and temperature, and stopped when VDD is off, so only a fallback).
+///
+/// The clock source can only be changed by resetting the whole backup domain, which also clears
+/// the calendar and the backup registers. `rtc_init` therefore keeps a running calendar clocked
+/// from the requested source untouched, so the time survives resets.
+///
+/// ## Calendar
+///
+/// `DateTime` holds a date of years 2000 – 2099 and a 24-hour time. The weekday stored in the
+/// calendar is derived from the date.
+///
+/// ## Interrupts
+///
+/// - Alarms A and B (EXTI line 17, `RTC_Alarm_Handler`): `rtc_set_alarm` with a `AlarmMatch` on
+///   any combination of day, hour, minute and second.
+/// - Wakeup timer (EXTI line 22, `RTC_WKUP_Handler`): `rtc_set_wakeup` with a period from ~0.5 ms
+///   to 36 hours. Both lines also wake the MCU from Stop and Standby.
+///
+/// Callbacks run in interrupt context.
+///
+/// ## Calibration
+///
+/// `rtc_set_calibration` corrects the frequency of the RTC clock from −487 ppm to +488 ppm in
+/// steps of ~0.954 ppm, by masking or adding clock pulses over every 32-second cycle.
+use crate::stm32f407_registers::*;
+use crate::read_write::{read_register, write_register, reg_write_bit, reg_write_bits};
+use crate::cortex_m4::enable_irq;
+use crate::exti::{clear_exti_pending, config_interrupt_trigger, configure_interrupt_mask_register, Trigger};
+
+/// Number of 32-bit backup registers.
+pub const RTC_BACKUP_REGISTERS: u32 = 20;
+
+pub const LSE_HZ: u32 = 32_768;
+/// Nominal LSI frequency.
+pub const LSI_HZ: u32 = 32_000;
+
+/// EXTI lines wired to the RTC.
+pub const EXTI_LINE_RTC_ALARM: u32 = 17;
+pub const EXTI_LINE_RTC_WAKEUP: u32 = 22;
+
+// Clock control (RCC) and power control (PWR)
+const RCC_BDCR: u32 = RCC_BASE + 0x70;
+const RCC_CSR: u32 = RCC_BASE + 0x74;
+const RCC_APB1ENR_PWREN: u32 = 28;
+const PWR_CR_DBP: u32 = 8;
+
+// BDCR bits
+const BDCR_LSEON: u32 = 0;
+const BDCR_LSERDY: u32 = 1 << 1;
+const BDCR_LSEBYP: u32 = 2;
+const BDCR_RTCSEL: u32 = 8;
+const BDCR_RTCEN: u32 = 15;
+const BDCR_BDRST: u32 = 16;
+
+const RTCSEL_LSE: u32 = 0b01;
+const RTCSEL_LSI: u32 = 0b10;
+
+// CSR bits
+const CSR_LSION: u32 = 0;
+const CSR_LSIRDY: u32 = 1 << 1;
+
+// Register offsets
+const RTC_TR: u32 = 0x00;
+const RTC_DR: u32 = 0x04;
+const RTC_CR: u32 = 0x08;
+const RTC_ISR: u32 = 0x0C;
+const RTC_PRER: u32 = 0x10;
+const RTC_WUTR: u32 = 0x14;
+const RTC_ALRMAR: u32 = 0x1C;
+const RTC_ALRMBR: u32 = 0x20;
+const RTC_WPR: u32 = 0x24;
+const RTC_SSR: u32 = 0x28;
+const RTC_CALR: u32 = 0x3C;
+const RTC_ALRMASSR: u32 = 0x44;
+const RTC_ALRMBSSR: u32 = 0x48;
+const RTC_BKP0R: u32 = 0x50;
+
+// CR bits
+const CR_WUCKSEL: u32 = 0;
+const CR_FMT: u32 = 6;
+const CR_ALRAE: u32 = 8;
+const CR_WUTE: u32 = 10;
+const CR_ALRAIE: u32 = 12;
+const CR_WUTIE: u32 = 14;
+
+// ISR bits
+const ISR_ALRAWF: u32 = 1 << 0;
+const ISR_WUTWF: u32 = 1 << 2;
+const ISR_INITS: u32 = 1 << 4;
+const ISR_RSF: u32 = 1 << 5;
+const ISR_INITF: u32 = 1 << 6;
+const ISR_INIT: u32 = 1 << 7;
+const ISR_ALRAF: u32 = 1 << 8;
+const ISR_WUTF: u32 = 1 << 10;
+const ISR_RECALPF: u32 = 1 << 16;
+/// Flags cleared by writing 0 (RSF, ALRAF, ALRBF, WUTF, TSF, TSOVF, TAMP1F, TAMP2F).
+const ISR_RC_W0: u32 = ISR_RSF | 0x7F00;
+
+// WPR keys
+const WPR_KEY1: u32 = 0xCA;
+const WPR_KEY2: u32 = 0x53;
+const WPR_LOCK: u32 = 0xFF;
+
+// ALRMxR mask bits
+const ALRM_MSK1: u32 = 1 << 7;
+const ALRM_MSK2: u32 = 1 << 15;
+const ALRM_MSK3: u32 = 1 << 23;
+const ALRM_WDSEL: u32 = 1 << 30;
+const ALRM_MSK4: u32 = 1 << 31;
+
+// CALR fields
+const CALR_CALP: u32 = 1 << 15;
+const CALR_CALM_MASK: u32 = 0x1FF;
+
+// Wakeup clock selection
+const WUCKSEL_RTC_DIV16: u32 = 0b000;
+const WUCKSEL_SPRE: u32 = 0b100;
+const WUCKSEL_SPRE_EXTENDED: u32 = 0b110;
+
+/// Iterations to wait for the LSE to start (it takes up to ~2 s).
+const LSE_TIMEOUT: u32 = 50_000_000;
+/// Iterations to wait for the LSI or an RTC flag.
+const READY_TIMEOUT: u32 = 1_000_000;
+
+/// Calibration cycle length in RTC clock pulses (2^20, 32 s at 32.768 kHz).
+const CALIBRATION_CYCLE: i64 = 1 << 20;
+
+/// RTC clock source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcClock {
+    /// 32.768 kHz crystal on OSC32_IN / OSC32_OUT.
+    Lse,
+    /// External 32.768 kHz clock signal on OSC32_IN.
+    LseBypass,
+    /// Internal RC oscillator.
+    Lsi,
+}
+
+impl RtcClock {
+    pub const fn frequency_hz(self) -> u32 {
+        match self {
+            RtcClock::Lse | RtcClock::LseBypass => LSE_HZ,
+            RtcClock::Lsi => LSI_HZ,
+        }
+    }
+
+    /// Asynchronous and synchronous prescalers giving the 1 Hz calendar clock.
+    const fn prescalers(self) -> (u32, u32) {
+        (128, self.frequency_hz() / 128)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcError {
+    /// The oscillator did not start (e.g. no LSE crystal fitted).
+    ClockNotReady(RtcClock),
+}
+
+/// Calendar date and time (24-hour).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    /// 2000 – 2099.
+    pub year: u16,
+    /// 1 – 12.
+    pub month: u8,
+    /// 1 – 31.
+    pub day: u8,
+    /// 0 – 23.
+    pub hour: u8,
+    /// 0 – 59.
+    pub minute: u8,
+    /// 0 – 59.
+    pub second: u8,
+}
+
+const fn is_leap_year(year: u16) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+const fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to the given date (proleptic Gregorian calendar).
+const fn days_from_civil(year: u16, month: u8, day: u8) -> i64 {
+    let y = if month <= 2 { year as i64 - 1 } else { year as i64 };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+impl DateTime {
+    /// Earliest date the calendar holds, 2000-01-01 00:00:00.
+    pub const MIN: DateTime = DateTime { year: 2000, month: 1, day: 1, hour: 0, minute: 0, second: 0 };
+
+    /// A date and time, or `None` if any field is out of range (including the day of the month).
+    pub const fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Option<Self> {
+        if year < 2000 || year > 2099 || month < 1 || month > 12 || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        if hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+        Some(DateTime { year, month, day, hour, minute, second })
+    }
+
+    /// Day of the week, 1 (Monday) – 7 (Sunday), as in the calendar registers.
+    pub const fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday
+        ((days_from_civil(self.year, self.month, self.day) + 3).rem_euclid(7) + 1) as u8
+    }
+
+    /// Seconds since 1970-01-01 00:00:00 (the calendar holds no time zone: UTC if set so).
+    pub const fn to_unix(&self) -> u32 {
+        let days = days_from_civil(self.year, self.month, self.day);
+        (days * 86_400 + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64) as u32
+    }
+
+    /// Date and time `seconds` after 1970-01-01 00:00:00, `None` outside years 2000 – 2099.
+    pub const fn from_unix(seconds: u32) -> Option<Self> {
+        let days = (seconds / 86_400) as i64;
+        let rem = seconds % 86_400;
+        // Inverse of `days_from_civil`
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u8;
+        let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }) as u16;
+        DateTime::new(year, month, day, (rem / 3600) as u8, (rem / 60 % 60) as u8, (rem % 60) as u8)
+    }
+}
+
+impl core::fmt::Display for DateTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Day an alarm matches on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlarmDay {
+    /// Day of the month, 1 – 31.
+    Date(u8),
+    /// Day of the week, 1 (Monday) – 7 (Sunday).
+    Weekday(u8),
+}
+
+/// Calendar fields an alarm compares; `None` matches any value. With every field `None`, the
+/// alarm fires every second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlarmMatch {
+    pub day: Option<AlarmDay>,
+    pub hour: Option<u8>,
+    pub minute: Option<u8>,
+    pub second: Option<u8>,
+}
+
+impl AlarmMatch {
+    /// Every day at the given time.
+    pub const fn daily(hour: u8, minute: u8, second: u8) -> Self {
+        AlarmMatch { day: None, hour: Some(hour), minute: Some(minute), second: Some(second) }
+    }
+
+    /// ALRMxR value.
+    fn register(&self) -> u32 {
+        let field = |value: Option<u8>, max: u8, mask: u32, shift: u32| match value {
+            Some(value) => {
+                assert!(value <= max, "Invalid RTC alarm field: {}. Valid range is 0 – {}.", value, max);
+                bcd(value) << shift
+            }
+            None => mask,
+        };
+        let day = match self.day {
+            Some(AlarmDay::Date(date)) => {
+                assert!((1..=31).contains(&date), "Invalid RTC alarm date: {}. Valid range is 1 – 31.", date);
+                bcd(date) << 24
+            }
+            Some(AlarmDay::Weekday(weekday)) => {
+                assert!((1..=7).contains(&weekday), "Invalid RTC alarm weekday: {}. Valid range is 1 – 7.", weekday);
+                ALRM_WDSEL | (weekday as u32) << 24
+            }
+            None => ALRM_MSK4,
+        };
+        day | field(self.hour, 23, ALRM_MSK3, 16) | field(self.minute, 59, ALRM_MSK2, 8) | field(self.second, 59, ALRM_MSK1, 0)
+    }
+}
+
+/// One of the two alarms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcAlarm {
+    A = 0,
+    B = 1,
+}
+
+/// Callback invoked from interrupt context with the alarm that fired.
+pub type RtcAlarmCallback = fn(alarm: RtcAlarm);
+/// Callback invoked from interrupt context at each wakeup timer period.
+pub type RtcWakeupCallback = fn();
+
+static mut ALARM_CALLBACKS: [Option<RtcAlarmCallback>; 2] = [None; 2];
+static mut WAKEUP_CALLBACK: Option<RtcWakeupCallback> = None;
+
+fn reg(offset: u32) -> *mut u32 {
+    (RTC_BASE + offset) as *mut u32
+}
+
+const fn bcd(value: u8) -> u32 {
+    (((value / 10) << 4) | (value % 10)) as u32
+}
+
+const fn from_bcd(value: u32) -> u8 {
+    ((value >> 4) * 10 + (value & 0xF)) as u8
+}
+
+/// Wait until `condition` holds, panicking after `READY_TIMEOUT` iterations.
+fn wait_for(condition: impl Fn() -> bool, what: &str) {
+    for _ in 0..READY_TIMEOUT {
+        if condition() {
+            return;
+        }
+    }
+    panic!("RTC timeout waiting for {} (is the RTC clock running?)", what);
+}
+
+fn isr() -> u32 {
+    unsafe { read_register(reg(RTC_ISR)) }
+}
+
+/// Clear ISR flags `flags` (write 0), leaving the other flags and INIT unchanged.
+fn clear_flags(flags: u32) {
+    unsafe {
+        write_register(reg(RTC_ISR), (isr() & ISR_INIT) | (ISR_RC_W0 & !flags));
+    }
+}
+
+/// Remove the write protection of the RTC registers for the duration of `f`.
+fn unprotected<R>(f: impl FnOnce() -> R) -> R {
+    unsafe {
+        write_register(reg(RTC_WPR), WPR_KEY1);
+        write_register(reg(RTC_WPR), WPR_KEY2);
+    }
+    let result = f();
+    unsafe {
+        write_register(reg(RTC_WPR), WPR_LOCK);
+    }
+    result
+}
+
+/// Run `f` in initialization mode (calendar stopped), then restart the calendar.
+fn in_init_mode(f: impl FnOnce()) {
+    unprotected(|| {
+        unsafe {
+            write_register(reg(RTC_ISR), ISR_INIT | ISR_RC_W0);
+        }
+        wait_for(|| isr() & ISR_INITF != 0, "initialization mode");
+        f();
+        unsafe {
+            write_register(reg(RTC_ISR), ISR_RC_W0);
+        }
+    });
+    rtc_wait_sync();
+}
+
+/// Enable write access to the backup domain.
+fn backup_access() {
+    unsafe {
+        reg_write_bit(RCC_APB1ENR as *mut u32, RCC_APB1ENR_PWREN, true);
+        reg_write_bit(PWR_BASE as *mut u32, PWR_CR_DBP, true);
+    }
+}
+
+fn start_oscillator(clock: RtcClock) -> Result<(), RtcError> {
+    let bdcr = RCC_BDCR as *mut u32;
+    unsafe {
+        let ready = match clock {
+            RtcClock::Lsi => {
+                reg_write_bit(RCC_CSR as *mut u32, CSR_LSION, true);
+                (0..READY_TIMEOUT).any(|_| read_register(RCC_CSR as *mut u32) & CSR_LSIRDY != 0)
+            }
+            RtcClock::Lse | RtcClock::LseBypass => {
+                let bypass = clock == RtcClock::LseBypass;
+                let running = read_register(bdcr) & BDCR_LSERDY != 0;
+                if !running || (read_register(bdcr) & (1 << BDCR_LSEBYP) != 0) != bypass {
+                    // LSEBYP can only be changed with the LSE off
+                    reg_write_bit(bdcr, BDCR_LSEON, false);
+                    reg_write_bit(bdcr, BDCR_LSEBYP, bypass);
+                    reg_write_bit(bdcr, BDCR_LSEON, true);
+                }
+                let ready = (0..LSE_TIMEOUT).any(|_| read_register(bdcr) & BDCR_LSERDY != 0);
+                if !ready {
+                    reg_write_bit(bdcr, BDCR_LSEON, false);
+                }
+                ready
+            }
+        };
+        if ready { Ok(()) } else { Err(RtcError::ClockNotReady(clock)) }
+    }
+}
+
+/// Function name: `rtc_init`
+///
+/// Description:
+/// Enables backup domain access, starts the clock source and the RTC. A calendar already running
+/// from `clock` keeps its time; otherwise the backup domain is reset if another source was
+/// selected (clearing the backup registers), the prescalers are set for a 1 Hz calendar clock and
+/// the calendar starts at `DateTime::MIN`, not yet set (see `rtc_is_set`).
+///
+/// Parameters:
+/// - `clock`: RTC clock source.
+///
+/// Return:
+/// - `Err` if the oscillator did not start; nothing is changed then, so another source can be
+///   tried (e.g. LSI on a board without LSE crystal).
+pub fn rtc_init(clock: RtcClock) -> Result<(), RtcError> {
+    backup_access();
+    start_oscillator(clock)?;
+
+    let bdcr = RCC_BDCR as *mut u32;
+    let rtcsel = match clock {
+        RtcClock::Lse | RtcClock::LseBypass => RTCSEL_LSE,
+        RtcClock::Lsi => RTCSEL_LSI,
+    };
+    unsafe {
+        let current = (read_register(bdcr) >> BDCR_RTCSEL) & 0b11;
+        if current != rtcsel {
+            if current != 0 {
+                // The selection is locked until a backup domain reset, which also stops the LSE
+                let lse = read_register(bdcr) & (1 << BDCR_LSEON | 1 << BDCR_LSEBYP);
+                reg_write_bit(bdcr, BDCR_BDRST, true);
+                reg_write_bit(bdcr, BDCR_BDRST, false);
+                write_register(bdcr, lse);
+                start_oscillator(clock)?;
+            }
+            reg_write_bits(bdcr, rtcsel, BDCR_RTCSEL, 2);
+        }
+        reg_write_bit(bdcr, BDCR_RTCEN, true);
+    }
+
+    let (asynchronous, synchronous) = clock.prescalers();
+    let prer = (asynchronous - 1) << 16 | (synchronous - 1);
+    if isr() & ISR_INITS == 0 || unsafe { read_register(reg(RTC_PRER)) } != prer {
+        in_init_mode(|| unsafe {
+            // Two separate writes, synchronous prescaler first (RM0090 26.3.5)
+            write_register(reg(RTC_PRER), synchronous - 1);
+            write_register(reg(RTC_PRER), prer);
+            reg_write_bit(reg(RTC_CR), CR_FMT, false);
+        });
+    }
+    Ok(())
+}
+
+/// Function name: `rtc_clock`
+///
+/// Description:
+/// Reads the RTC clock source selected in the backup domain.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - The source, or `None` if no source is selected (backup domain reset).
+pub fn rtc_clock() -> Option<RtcClock> {
+    let bdcr = unsafe { read_register(RCC_BDCR as *mut u32) };
+    match (bdcr >> BDCR_RTCSEL) & 0b11 {
+        RTCSEL_LSE if bdcr & (1 << BDCR_LSEBYP) != 0 => Some(RtcClock::LseBypass),
+        RTCSEL_LSE => Some(RtcClock::Lse),
+        RTCSEL_LSI => Some(RtcClock::Lsi),
+        _ => None,
+    }
+}
+
+/// Function name: `rtc_is_set`
+///
+/// Description:
+/// Reports whether the calendar has been set since the backup domain was last reset (a year
+/// other than 2000 is stored, ISR.INITS).
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - `true` if the calendar holds a date that was set.
+pub fn rtc_is_set() -> bool {
+    isr() & ISR_INITS != 0
+}
+
+/// Function name: `rtc_wait_sync`
+///
+/// Description:
+/// Waits until the calendar shadow registers have been updated from the counters (RSF). Needed
+/// after leaving Stop or Standby before reading the calendar; done by `rtc_init` and
+/// `rtc_set_datetime` themselves.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - None
+pub fn rtc_wait_sync() {
+    unprotected(|| clear_flags(ISR_RSF));
+    wait_for(|| isr() & ISR_RSF != 0, "calendar synchronization");
+}
+
+/// Function name: `rtc_set_datetime`
+///
+/// Description:
+/// Sets the calendar. The sub-second counter restarts, so the next second starts one second later.
+///
+/// Parameters:
+/// - `datetime`: New date and time.
+///
+/// Return:
+/// - None
+pub fn rtc_set_datetime(datetime: &DateTime) {
+    let tr = bcd(datetime.hour) << 16 | bcd(datetime.minute) << 8 | bcd(datetime.second);
+    let dr = bcd((datetime.year - 2000) as u8) << 16
+        | (datetime.weekday() as u32) << 13
+        | bcd(datetime.month) << 8
+        | bcd(datetime.day);
+    in_init_mode(|| unsafe {
+        write_register(reg(RTC_TR), tr);
+        write_register(reg(RTC_DR), dr);
+    });
+}
+
+/// Function name: `rtc_datetime`
+///
+/// Description:
+/// Reads the calendar (TR then DR, which the hardware keeps consistent).
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - Current date and time.
+pub fn rtc_datetime() -> DateTime {
+    rtc_datetime_ms().0
+}
+
+/// Function name: `rtc_datetime_ms`
+///
+/// Description:
+/// Reads the calendar with the milliseconds elapsed in the current second (from the sub-second
+/// counter), e.g. for timestamps.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - Current date and time, and milliseconds (0 – 999).
+pub fn rtc_datetime_ms() -> (DateTime, u32) {
+    unsafe {
+        // Reading SSR locks TR and DR until DR is read
+        let ssr = read_register(reg(RTC_SSR)) & 0xFFFF;
+        let tr = read_register(reg(RTC_TR));
+        let dr = read_register(reg(RTC_DR));
+        let synchronous = (read_register(reg(RTC_PRER)) & 0x7FFF) + 1;
+        let datetime = DateTime {
+            year: 2000 + from_bcd((dr >> 16) & 0xFF) as u16,
+            month: from_bcd((dr >> 8) & 0x1F),
+            day: from_bcd(dr & 0x3F),
+            hour: from_bcd((tr >> 16) & 0x3F),
+            minute: from_bcd((tr >> 8) & 0x7F),
+            second: from_bcd(tr & 0x7F),
+        };
+        // The sub-second counter counts down from the synchronous prescaler value
+        let elapsed = synchronous.saturating_sub(1).saturating_sub(ssr);
+        (datetime, elapsed * 1000 / synchronous)
+    }
+}
+
+fn alarm_offset(alarm: RtcAlarm) -> u32 {
+    match alarm {
+        RtcAlarm::A => RTC_ALRMAR,
+        RtcAlarm::B => RTC_ALRMBR,
+    }
+}
+
+/// Function name: `rtc_set_alarm`
+///
+/// Description:
+/// Programs an alarm and enables its interrupt: `callback` runs each time the calendar matches
+/// `when`. Panics if a field of `when` is out of range.
+///
+/// Parameters:
+/// - `alarm`: Alarm A or B.
+/// - `when`: Calendar fields to match.
+/// - `callback`: Function called from interrupt context.
+///
+/// Return:
+/// - None
+pub fn rtc_set_alarm(alarm: RtcAlarm, when: AlarmMatch, callback: RtcAlarmCallback) {
+    let value = when.register();
+    let index = alarm as u32;
+    unsafe {
+        ALARM_CALLBACKS[index as usize] = Some(callback);
+    }
+    unprotected(|| unsafe {
+        reg_write_bit(reg(RTC_CR), CR_ALRAE + index, false);
+        wait_for(|| isr() & (ISR_ALRAWF << index) != 0, "alarm write access");
+        write_register(reg(alarm_offset(alarm)), value);
+        // Sub-seconds not compared
+        write_register(reg(RTC_ALRMASSR + 4 * index), 0);
+        clear_flags(ISR_ALRAF << index);
+        let cr = read_register(reg(RTC_CR));
+        write_register(reg(RTC_CR), cr | 1 << (CR_ALRAIE + index) | 1 << (CR_ALRAE + index));
+    });
+    clear_exti_pending(EXTI_LINE_RTC_ALARM);
+    config_interrupt_trigger(EXTI_LINE_RTC_ALARM, Trigger::Rising);
+    configure_interrupt_mask_register(EXTI_LINE_RTC_ALARM, true);
+    enable_irq(RTC_ALARM_IRQ);
+}
+
+/// Function name: `rtc_disable_alarm`
+///
+/// Description:
+/// Stops an alarm and removes its callback.
+///
+/// Parameters:
+/// - `alarm`: Alarm A or B.
+///
+/// Return:
+/// - None
+pub fn rtc_disable_alarm(alarm: RtcAlarm) {
+    let index = alarm as u32;
+    unprotected(|| unsafe {
+        let cr = read_register(reg(RTC_CR));
+        write_register(reg(RTC_CR), cr & !(1 << (CR_ALRAIE + index) | 1 << (CR_ALRAE + index)));
+        clear_flags(ISR_ALRAF << index);
+    });
+    unsafe {
+        ALARM_CALLBACKS[index as usize] = None;
+    }
+}
+
+/// Function name: `rtc_set_wakeup`
+///
+/// Description:
+/// Starts the periodic wakeup timer and enables its interrupt. Periods up to 32 s count the RTC
+/// clock / 16 (0.49 ms resolution with the LSE); longer ones count seconds, up to 131072 s.
+/// Panics if the period is zero or too long, or if `rtc_init` has not selected a clock.
+///
+/// Parameters:
+/// - `period_ms`: Wakeup period.
+/// - `callback`: Function called from interrupt context at every period, or `None` (e.g. to only
+///   wake the MCU from a low-power mode).
+///
+/// Return:
+/// - The period applied, in milliseconds.
+pub fn rtc_set_wakeup(period_ms: u32, callback: Option<RtcWakeupCallback>) -> u32 {
+    let clock_hz = rtc_clock().expect("RTC clock not selected").frequency_hz();
+    let div16_ticks = (period_ms as u64 * clock_hz as u64 / 16).div_ceil(1000);
+    let seconds = period_ms.div_ceil(1000);
+    let (wucksel, wutr, applied_ms) = match div16_ticks {
+        1..=0x10000 => (WUCKSEL_RTC_DIV16, div16_ticks as u32 - 1, (div16_ticks * 16 * 1000 / clock_hz as u64) as u32),
+        _ if (1..=0x10000).contains(&seconds) => (WUCKSEL_SPRE, seconds - 1, seconds * 1000),
+        _ if (0x10001..=0x20000).contains(&seconds) => (WUCKSEL_SPRE_EXTENDED, seconds - 1 - 0x10000, seconds * 1000),
+        _ => panic!("Invalid RTC wakeup period: {} ms", period_ms),
+    };
+    unsafe {
+        WAKEUP_CALLBACK = callback;
+    }
+    unprotected(|| unsafe {
+        reg_write_bit(reg(RTC_CR), CR_WUTE, false);
+        wait_for(|| isr() & ISR_WUTWF != 0, "wakeup timer write access");
+        write_register(reg(RTC_WUTR), wutr);
+        reg_write_bits(reg(RTC_CR), wucksel, CR_WUCKSEL, 3);
+        clear_flags(ISR_WUTF);
+        let cr = read_register(reg(RTC_CR));
+        write_register(reg(RTC_CR), cr | 1 << CR_WUTIE | 1 << CR_WUTE);
+    });
+    clear_exti_pending(EXTI_LINE_RTC_WAKEUP);
+    config_interrupt_trigger(EXTI_LINE_RTC_WAKEUP, Trigger::Rising);
+    configure_interrupt_mask_register(EXTI_LINE_RTC_WAKEUP, true);
+    enable_irq(RTC_WKUP_IRQ);
+    applied_ms
+}
+
+/// Function name: `rtc_disable_wakeup`
+///
+/// Description:
+/// Stops the wakeup timer and removes its callback.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - None
+pub fn rtc_disable_wakeup() {
+    unprotected(|| unsafe {
+        let cr = read_register(reg(RTC_CR));
+        write_register(reg(RTC_CR), cr & !(1 << CR_WUTIE | 1 << CR_WUTE));
+        clear_flags(ISR_WUTF);
+    });
+    unsafe {
+        WAKEUP_CALLBACK = None;
+    }
+}
+
+/// Function name: `rtc_backup_read`
+///
+/// Description:
+/// Reads a backup register. Backup registers keep their value through resets, Standby and on
+/// VBAT, until the backup domain is reset.
+///
+/// Parameters:
+/// - `index`: Register number (0 – 19).
+///
+/// Return:
+/// - Register value.
+pub fn rtc_backup_read(index: u32) -> u32 {
+    assert!(index < RTC_BACKUP_REGISTERS, "Invalid RTC backup register: {}. Valid range is 0 – 19.", index);
+    unsafe { read_register(reg(RTC_BKP0R + 4 * index)) }
+}
+
+/// Function name: `rtc_backup_write`
+///
+/// Description:
+/// Writes a backup register (requires `rtc_init` for backup domain access).
+///
+/// Parameters:
+/// - `index`: Register number (0 – 19).
+/// - `value`: Value to store.
+///
+/// Return:
+/// - None
+pub fn rtc_backup_write(index: u32, value: u32) {
+    assert!(index < RTC_BACKUP_REGISTERS, "Invalid RTC backup register: {}. Valid range is 0 – 19.", index);
+    unsafe {
+        write_register(reg(RTC_BKP0R + 4 * index), value);
+    }
+}
+
+/// Function name: `rtc_set_calibration`
+///
+/// Description:
+/// Applies a smooth calibration: the RTC clock is corrected by `ppb` parts per billion, positive
+/// to make a slow clock run faster. The correction is rounded to the nearest step (~954 ppb).
+/// Panics outside −487 000 – +488 000 ppb.
+///
+/// Parameters:
+/// - `ppb`: Frequency correction in parts per billion.
+///
+/// Return:
+/// - The correction applied, in parts per billion.
+pub fn rtc_set_calibration(ppb: i32) -> i32 {
+    // Each pulse masked (CALM) or added (CALP adds 512) per 2^20-pulse cycle
+    let pulses = (ppb as i64 * CALIBRATION_CYCLE + if ppb < 0 { -500_000_000 } else { 500_000_000 }) / 1_000_000_000;
+    assert!((-511..=512).contains(&pulses), "Invalid RTC calibration: {} ppb. Valid range is -487000 – 488000.", ppb);
+    let calr = if pulses > 0 { CALR_CALP | (512 - pulses) as u32 } else { (-pulses) as u32 };
+    wait_for(|| isr() & ISR_RECALPF == 0, "calibration update");
+    unprotected(|| unsafe {
+        write_register(reg(RTC_CALR), calr);
+    });
+    rtc_calibration()
+}
+
+/// Function name: `rtc_calibration`
+///
+/// Description:
+/// Reads the smooth calibration in effect.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - The correction, in parts per billion.
+pub fn rtc_calibration() -> i32 {
+    let calr = unsafe { read_register(reg(RTC_CALR)) };
+    let plus = if calr & CALR_CALP != 0 { 512 } else { 0 };
+    let pulses = plus - (calr & CALR_CALM_MASK) as i64;
+    (pulses * 1_000_000_000 / CALIBRATION_CYCLE) as i32
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn RTC_Alarm_Handler() {
+    let flags = isr();
+    for alarm in [RtcAlarm::A, RtcAlarm::B] {
+        let flag = ISR_ALRAF << alarm as u32;
+        if flags & flag != 0 {
+            unprotected(|| clear_flags(flag));
+            if let Some(callback) = unsafe { ALARM_CALLBACKS[alarm as usize] } {
+                callback(alarm);
+            }
+        }
+    }
+    clear_exti_pending(EXTI_LINE_RTC_ALARM);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn RTC_WKUP_Handler() {
+    if isr() & ISR_WUTF != 0 {
+        unprotected(|| clear_flags(ISR_WUTF));
+        if let Some(callback) = unsafe { WAKEUP_CALLBACK } {
+            callback();
+        }
+    }
+    clear_exti_pending(EXTI_LINE_RTC_WAKEUP);
+}
@@ -0,0 +1,528 @@
+#![allow(dead_code)]
+
+/// # SPI Master Driver Module
+///
+/// This module drives SPI1, SPI2 and SPI3 of the STM32F407 in master mode with software slave
+/// management (the NSS pin is not used; each device gets its own chip-select GPIO, see
+/// `kernel::bus::SharedSpiDevice`).
+///
+/// ## Instance Conventions
+///
+/// - `spi: u32` — peripheral number: `1` → SPI1 (APB2), `2` → SPI2 (APB1), `3` → SPI3 (APB1).
+///
+/// ## Pins
+///
+/// SCK/MISO/MOSI must be put in alternate mode by the caller: AF5 for SPI1/SPI2, AF6 for SPI3
+/// (e.g. PA5/PA6/PA7 for SPI1).
+///
+/// ## Frames
+///
+/// `Spi` implements `embedded_hal::spi::SpiBus` for both `u8` and `u16` words. The frame format
+/// (CR1.DFF) is switched automatically to match the word type of each call.
+///
+/// ## DMA
+///
+/// When `SpiConfig::dma` is set, `read`, `write`, `transfer_in_place` and equal-length `transfer`
+/// calls of at least `SPI_DMA_THRESHOLD` words are performed by the DMA controller (RX and TX
+/// streams from the fixed request mapping of RM0090 table 42/43, claimed from the `dma` driver by
+/// `Spi::new`). Shorter transfers are polled, since setting up two streams costs more than shifting
+/// a few words.
+use embedded_hal::spi::{self, ErrorKind, ErrorType, SpiBus};
+use crate::stm32f407_registers::*;
+use crate::read_write::{read_register, write_register, reg_write_bit, reg_write_bits};
+use crate::dma::{dma_claim, DataSize, Direction, DmaConfig, DmaStream, Priority, DMA1, DMA2};
+
+pub const SPI1: u32 = 1;
+pub const SPI2: u32 = 2;
+pub const SPI3: u32 = 3;
+
+/// Transfers shorter than this (in words) are always polled.
+pub const SPI_DMA_THRESHOLD: usize = 16;
+
+// Register offsets
+const SPI_CR1: u32 = 0x00;
+const SPI_CR2: u32 = 0x04;
+const SPI_SR: u32 = 0x08;
+const SPI_DR: u32 = 0x0C;
+
+// CR1 bits
+const CR1_CPHA: u32 = 0;
+const CR1_CPOL: u32 = 1;
+const CR1_MSTR: u32 = 2;
+const CR1_BR: u32 = 3;
+const CR1_SPE: u32 = 6;
+const CR1_LSBFIRST: u32 = 7;
+const CR1_SSI: u32 = 8;
+const CR1_SSM: u32 = 9;
+const CR1_DFF: u32 = 11;
+
+// CR2 bits
+const CR2_RXDMAEN: u32 = 0;
+const CR2_TXDMAEN: u32 = 1;
+
+// SR bits
+const SR_RXNE: u32 = 1 << 0;
+const SR_TXE: u32 = 1 << 1;
+const SR_MODF: u32 = 1 << 5;
+const SR_OVR: u32 = 1 << 6;
+const SR_BSY: u32 = 1 << 7;
+
+/// Clock polarity / phase combinations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpiMode {
+    /// CPOL=0, CPHA=0
+    Mode0,
+    /// CPOL=0, CPHA=1
+    Mode1,
+    /// CPOL=1, CPHA=0
+    Mode2,
+    /// CPOL=1, CPHA=1
+    Mode3,
+}
+
+/// SCK = PCLK / divider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaudPrescaler {
+    Div2 = 0,
+    Div4 = 1,
+    Div8 = 2,
+    Div16 = 3,
+    Div32 = 4,
+    Div64 = 5,
+    Div128 = 6,
+    Div256 = 7,
+}
+
+impl BaudPrescaler {
+    /// Smallest divider giving an SCK frequency not above `max_hz`.
+    pub fn for_frequency(pclk_hz: u32, max_hz: u32) -> Self {
+        const ALL: [BaudPrescaler; 8] = [
+            BaudPrescaler::Div2,
+            BaudPrescaler::Div4,
+            BaudPrescaler::Div8,
+            BaudPrescaler::Div16,
+            BaudPrescaler::Div32,
+            BaudPrescaler::Div64,
+            BaudPrescaler::Div128,
+            BaudPrescaler::Div256,
+        ];
+        for prescaler in ALL {
+            if pclk_hz >> (prescaler as u32 + 1) <= max_hz {
+                return prescaler;
+            }
+        }
+        BaudPrescaler::Div256
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpiConfig {
+    pub mode: SpiMode,
+    pub prescaler: BaudPrescaler,
+    pub lsb_first: bool,
+    /// Use DMA for bulk transfers.
+    pub dma: bool,
+}
+
+impl Default for SpiConfig {
+    fn default() -> Self {
+        SpiConfig { mode: SpiMode::Mode0, prescaler: BaudPrescaler::Div16, lsb_first: false, dma: false }
+    }
+}
+
+/// SPI errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpiError {
+    /// Received data was not read before the next word arrived.
+    Overrun,
+    /// Mode fault (NSS pulled low while in master mode).
+    ModeFault,
+    /// The DMA controller reported a transfer error.
+    Dma,
+}
+
+impl spi::Error for SpiError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            SpiError::Overrun => ErrorKind::Overrun,
+            SpiError::ModeFault => ErrorKind::ModeFault,
+            SpiError::Dma => ErrorKind::Other,
+        }
+    }
+}
+
+/// Word types supported by the bus (8 or 16-bit frames).
+pub trait SpiWord: Copy + Default + 'static {
+    const SIXTEEN_BIT: bool;
+    fn to_dr(self) -> u32;
+    fn from_dr(value: u32) -> Self;
+}
+
+impl SpiWord for u8 {
+    const SIXTEEN_BIT: bool = false;
+    fn to_dr(self) -> u32 {
+        self as u32
+    }
+    fn from_dr(value: u32) -> Self {
+        value as u8
+    }
+}
+
+impl SpiWord for u16 {
+    const SIXTEEN_BIT: bool = true;
+    fn to_dr(self) -> u32 {
+        self as u32
+    }
+    fn from_dr(value: u32) -> Self {
+        value as u16
+    }
+}
+
+/// Function name: `select_spi_base`
+///
+/// Description:
+/// Returns the base address of the given SPI instance.
+///
+/// Parameters:
+/// - `spi`: Instance number (1–3).
+///
+/// Return:
+/// - Base address (`u32`) of the peripheral.
+fn select_spi_base(spi: u32) -> u32 {
+    match spi {
+        1 => SPI1_BASE,
+        2 => SPI2_BASE,
+        3 => SPI3_BASE,
+        _ => panic!("Invalid SPI: {}. Valid range is 1 – 3.", spi),
+    }
+}
+
+/// Function name: `configure_spi_clock`
+///
+/// Description:
+/// Enables or disables the APB clock of the given SPI instance.
+///
+/// Parameters:
+/// - `spi`: Instance number (1–3).
+/// - `enable`: `true` to enable, `false` to disable.
+///
+/// Return:
+/// - None
+fn configure_spi_clock(spi: u32, enable: bool) {
+    let (enr_addr, bit) = match spi {
+        1 => (RCC_APB2ENR, 12),
+        2 => (RCC_APB1ENR, 14),
+        3 => (RCC_APB1ENR, 15),
+        _ => panic!("Invalid SPI: {}", spi),
+    };
+
+    unsafe {
+        reg_write_bit(enr_addr as *mut u32, bit, enable);
+    }
+}
+
+// ---------- DMA request mapping (RM0090 tables 42/43) ----------
+
+/// (controller, stream, channel) for the RX and TX requests of each SPI.
+fn spi_dma_streams(spi: u32) -> ((u32, u32, u32), (u32, u32, u32)) {
+    match spi {
+        1 => ((DMA2, 0, 3), (DMA2, 3, 3)),
+        2 => ((DMA1, 3, 0), (DMA1, 4, 0)),
+        3 => ((DMA1, 0, 0), (DMA1, 5, 0)),
+        _ => panic!("Invalid SPI: {}", spi),
+    }
+}
+
+/// Claimed RX/TX streams of a DMA-enabled bus.
+struct SpiDma {
+    rx: DmaStream,
+    rx_channel: u32,
+    tx: DmaStream,
+    tx_channel: u32,
+}
+
+/// SPI master bus.
+pub struct Spi {
+    spi: u32,
+    base: u32,
+    dma: Option<SpiDma>,
+}
+
+impl Spi {
+    /// Function name: `Spi::new`
+    ///
+    /// Description:
+    /// Enables the peripheral clock and configures the instance as master with software NSS,
+    /// the requested clock mode, prescaler and bit order, 8-bit frames.
+    ///
+    /// Parameters:
+    /// - `spi`: Instance number (1–3).
+    /// - `config`: Bus configuration.
+    ///
+    /// Return:
+    /// - The configured bus.
+    pub fn new(spi: u32, config: &SpiConfig) -> Self {
+        let base = select_spi_base(spi);
+        configure_spi_clock(spi, true);
+
+        let (cpol, cpha) = match config.mode {
+            SpiMode::Mode0 => (0, 0),
+            SpiMode::Mode1 => (0, 1),
+            SpiMode::Mode2 => (1, 0),
+            SpiMode::Mode3 => (1, 1),
+        };
+
+        let cr1 = (cpha << CR1_CPHA)
+            | (cpol << CR1_CPOL)
+            | (1 << CR1_MSTR)
+            | ((config.prescaler as u32) << CR1_BR)
+            | ((config.lsb_first as u32) << CR1_LSBFIRST)
+            | (1 << CR1_SSI)
+            | (1 << CR1_SSM);
+
+        unsafe {
+            write_register((base + SPI_CR1) as *mut u32, cr1);
+            write_register((base + SPI_CR2) as *mut u32, 0);
+            reg_write_bit((base + SPI_CR1) as *mut u32, CR1_SPE, true);
+        }
+
+        let dma = config.dma.then(|| {
+            let ((rx_dma, rx_stream, rx_channel), (tx_dma, tx_stream, tx_channel)) = spi_dma_streams(spi);
+            let rx = dma_claim(rx_dma, rx_stream).expect("SPI RX DMA stream already in use");
+            let tx = dma_claim(tx_dma, tx_stream).expect("SPI TX DMA stream already in use");
+            SpiDma { rx, rx_channel, tx, tx_channel }
+        });
+
+        Spi { spi, base, dma }
+    }
+
+    /// Instance number of this bus.
+    pub fn instance(&self) -> u32 {
+        self.spi
+    }
+
+    /// Change the SCK prescaler (e.g. slower clock for a device on a shared bus).
+    pub fn set_prescaler(&mut self, prescaler: BaudPrescaler) {
+        self.wait_idle();
+        let cr1_addr = (self.base + SPI_CR1) as *mut u32;
+        unsafe {
+            reg_write_bit(cr1_addr, CR1_SPE, false);
+            reg_write_bits(cr1_addr, prescaler as u32, CR1_BR, 3);
+            reg_write_bit(cr1_addr, CR1_SPE, true);
+        }
+    }
+
+    fn sr(&self) -> u32 {
+        unsafe { read_register((self.base + SPI_SR) as *mut u32) }
+    }
+
+    fn check_errors(&self) -> Result<(), SpiError> {
+        let sr = self.sr();
+        if sr & SR_OVR != 0 {
+            // Cleared by reading DR then SR
+            unsafe {
+                let _ = read_register((self.base + SPI_DR) as *mut u32);
+            }
+            let _ = self.sr();
+            return Err(SpiError::Overrun);
+        }
+        if sr & SR_MODF != 0 {
+            // Cleared by reading SR then writing CR1
+            unsafe {
+                reg_write_bit((self.base + SPI_CR1) as *mut u32, CR1_MSTR, true);
+                reg_write_bit((self.base + SPI_CR1) as *mut u32, CR1_SPE, true);
+            }
+            return Err(SpiError::ModeFault);
+        }
+        Ok(())
+    }
+
+    fn wait_idle(&self) {
+        while self.sr() & SR_TXE == 0 {}
+        while self.sr() & SR_BSY != 0 {}
+    }
+
+    /// Switch CR1.DFF to the frame size of `W` if needed (only allowed while SPE=0).
+    fn set_frame<W: SpiWord>(&mut self) {
+        let cr1_addr = (self.base + SPI_CR1) as *mut u32;
+        unsafe {
+            let sixteen = read_register(cr1_addr) & (1 << CR1_DFF) != 0;
+            if sixteen != W::SIXTEEN_BIT {
+                self.wait_idle();
+                reg_write_bit(cr1_addr, CR1_SPE, false);
+                reg_write_bit(cr1_addr, CR1_DFF, W::SIXTEEN_BIT);
+                reg_write_bit(cr1_addr, CR1_SPE, true);
+            }
+        }
+    }
+
+    /// Shift one word out and return the word shifted in.
+    fn exchange<W: SpiWord>(&mut self, word: W) -> Result<W, SpiError> {
+        let dr_addr = (self.base + SPI_DR) as *mut u32;
+        while self.sr() & SR_TXE == 0 {}
+        unsafe {
+            write_register(dr_addr, word.to_dr());
+        }
+        loop {
+            let sr = self.sr();
+            if sr & SR_RXNE != 0 {
+                break;
+            }
+            if sr & (SR_OVR | SR_MODF) != 0 {
+                self.check_errors()?;
+            }
+        }
+        Ok(W::from_dr(unsafe { read_register(dr_addr) }))
+    }
+
+    fn use_dma(&self, len: usize) -> bool {
+        self.dma.is_some() && (SPI_DMA_THRESHOLD..=u16::MAX as usize).contains(&len)
+    }
+
+    /// Run a full-duplex DMA transfer of `len` words. A null `tx` sends `fill` repeatedly; a null
+    /// `rx` discards received words into a scratch word.
+    fn dma_transfer<W: SpiWord>(&mut self, rx: *mut W, tx: *const W, len: usize) -> Result<(), SpiError> {
+        let dr = self.base + SPI_DR;
+        let cr2_addr = (self.base + SPI_CR2) as *mut u32;
+        let dma = self.dma.as_mut().unwrap();
+
+        let mut scratch = W::default();
+        let fill = W::default();
+        let (rx_addr, rx_inc) = if rx.is_null() { (&mut scratch as *mut W as u32, false) } else { (rx as u32, true) };
+        let (tx_addr, tx_inc) = if tx.is_null() { (&fill as *const W as u32, false) } else { (tx as u32, true) };
+
+        let size = if W::SIXTEEN_BIT { DataSize::HalfWord } else { DataSize::Byte };
+        let rx_config = DmaConfig::new(dma.rx_channel).data_size(size).memory_increment(rx_inc).priority(Priority::High);
+        let tx_config = DmaConfig::new(dma.tx_channel)
+            .direction(Direction::MemoryToPeripheral)
+            .data_size(size)
+            .memory_increment(tx_inc)
+            .priority(Priority::High);
+
+        // The buffers outlive the transfer: both streams are waited for (or aborted) below.
+        unsafe {
+            dma.rx.start(&rx_config, dr, rx_addr, None, len as u16);
+            dma.tx.start(&tx_config, dr, tx_addr, None, len as u16);
+            // RX request first so no received word is missed
+            reg_write_bit(cr2_addr, CR2_RXDMAEN, true);
+            reg_write_bit(cr2_addr, CR2_TXDMAEN, true);
+        }
+
+        let tx_result = dma.tx.wait();
+        let rx_result = if tx_result.is_ok() {
+            dma.rx.wait()
+        } else {
+            dma.rx.abort();
+            Ok(())
+        };
+
+        unsafe {
+            reg_write_bit(cr2_addr, CR2_TXDMAEN, false);
+            reg_write_bit(cr2_addr, CR2_RXDMAEN, false);
+        }
+
+        tx_result.map_err(|_| SpiError::Dma)?;
+        rx_result.map_err(|_| SpiError::Dma)?;
+        self.check_errors()
+    }
+
+    fn read_words<W: SpiWord>(&mut self, words: &mut [W]) -> Result<(), SpiError> {
+        self.set_frame::<W>();
+        if self.use_dma(words.len()) {
+            return self.dma_transfer(words.as_mut_ptr(), core::ptr::null(), words.len());
+        }
+        for word in words.iter_mut() {
+            *word = self.exchange(W::default())?;
+        }
+        Ok(())
+    }
+
+    fn write_words<W: SpiWord>(&mut self, words: &[W]) -> Result<(), SpiError> {
+        self.set_frame::<W>();
+        if self.use_dma(words.len()) {
+            return self.dma_transfer(core::ptr::null_mut(), words.as_ptr(), words.len());
+        }
+        for &word in words {
+            self.exchange(word)?;
+        }
+        Ok(())
+    }
+
+    fn transfer_words<W: SpiWord>(&mut self, read: &mut [W], write: &[W]) -> Result<(), SpiError> {
+        self.set_frame::<W>();
+        if read.len() == write.len() && self.use_dma(read.len()) {
+            return self.dma_transfer(read.as_mut_ptr(), write.as_ptr(), read.len());
+        }
+        let len = read.len().max(write.len());
+        for i in 0..len {
+            let out = write.get(i).copied().unwrap_or_default();
+            let word = self.exchange(out)?;
+            if let Some(slot) = read.get_mut(i) {
+                *slot = word;
+            }
+        }
+        Ok(())
+    }
+
+    fn transfer_in_place_words<W: SpiWord>(&mut self, words: &mut [W]) -> Result<(), SpiError> {
+        self.set_frame::<W>();
+        if self.use_dma(words.len()) {
+            let ptr = words.as_mut_ptr();
+            return self.dma_transfer(ptr, ptr as *const W, words.len());
+        }
+        for word in words.iter_mut() {
+            *word = self.exchange(*word)?;
+        }
+        Ok(())
+    }
+}
+
+impl ErrorType for Spi {
+    type Error = SpiError;
+}
+
+impl SpiBus<u8> for Spi {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), SpiError> {
+        self.read_words(words)
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), SpiError> {
+        self.write_words(words)
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), SpiError> {
+        self.transfer_words(read, write)
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), SpiError> {
+        self.transfer_in_place_words(words)
+    }
+
+    fn flush(&mut self) -> Result<(), SpiError> {
+        self.wait_idle();
+        self.check_errors()
+    }
+}
+
+impl SpiBus<u16> for Spi {
+    fn read(&mut self, words: &mut [u16]) -> Result<(), SpiError> {
+        self.read_words(words)
+    }
+
+    fn write(&mut self, words: &[u16]) -> Result<(), SpiError> {
+        self.write_words(words)
+    }
+
+    fn transfer(&mut self, read: &mut [u16], write: &[u16]) -> Result<(), SpiError> {
+        self.transfer_words(read, write)
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u16]) -> Result<(), SpiError> {
+        self.transfer_in_place_words(words)
+    }
+
+    fn flush(&mut self) -> Result<(), SpiError> {
+        self.wait_idle();
+        self.check_errors()
+    }
+}
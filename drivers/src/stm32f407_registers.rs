@@ -3,6 +3,31 @@
 
 //RCC register
 pub const RCC_BASE: u32 =    0x4002_3800;
+pub const RCC_AHB1ENR: u32 = RCC_BASE + 0x30;
+pub const RCC_AHB2ENR: u32 = RCC_BASE + 0x34;
+pub const RCC_APB1ENR: u32 = RCC_BASE + 0x40;
+pub const RCC_APB2ENR: u32 = RCC_BASE + 0x44;
+pub const RCC_APB1RSTR: u32 = RCC_BASE + 0x20;
+pub const RCC_APB2RSTR: u32 = RCC_BASE + 0x24;
+
+
+//Flash interface and power controller
+pub const FLASH_INTERFACE_BASE: u32 = 0x4002_3C00;
+pub const PWR_BASE: u32 = 0x4000_7000;
+
+
+//Watchdogs
+pub const IWDG_BASE: u32 = 0x4000_3000;
+pub const WWDG_BASE: u32 = 0x4000_2C00;
+
+
+//CRC calculation unit and random number generator
+pub const CRC_BASE: u32 = 0x4002_3000;
+pub const RNG_BASE: u32 = 0x5006_0800;
+
+
+//Real-time clock (backup domain)
+pub const RTC_BASE: u32 = 0x4000_2800;
 
 
 //system config register
@@ -20,6 +45,60 @@ pub const GPIO_H_BASE: u32 = 0x4002_1C00;
 pub const GPIO_I_BASE: u32 = 0x4002_2000;
 
 
+//USART / UART registers
+pub const USART1_BASE: u32 = 0x4001_1000;
+pub const USART2_BASE: u32 = 0x4000_4400;
+pub const USART3_BASE: u32 = 0x4000_4800;
+pub const UART4_BASE: u32 = 0x4000_4C00;
+pub const UART5_BASE: u32 = 0x4000_5000;
+pub const USART6_BASE: u32 = 0x4001_1400;
+
+
+//SPI registers
+pub const SPI1_BASE: u32 = 0x4001_3000;
+pub const SPI2_BASE: u32 = 0x4000_3800;
+pub const SPI3_BASE: u32 = 0x4000_3C00;
+
+
+//I2C registers
+pub const I2C1_BASE: u32 = 0x4000_5400;
+pub const I2C2_BASE: u32 = 0x4000_5800;
+pub const I2C3_BASE: u32 = 0x4000_5C00;
+
+
+//Timer registers
+pub const TIM1_BASE: u32 = 0x4001_0000;
+pub const TIM2_BASE: u32 = 0x4000_0000;
+pub const TIM3_BASE: u32 = 0x4000_0400;
+pub const TIM4_BASE: u32 = 0x4000_0800;
+pub const TIM5_BASE: u32 = 0x4000_0C00;
+pub const TIM6_BASE: u32 = 0x4000_1000;
+pub const TIM7_BASE: u32 = 0x4000_1400;
+pub const TIM8_BASE: u32 = 0x4001_0400;
+pub const TIM9_BASE: u32 = 0x4001_4000;
+pub const TIM10_BASE: u32 = 0x4001_4400;
+pub const TIM11_BASE: u32 = 0x4001_4800;
+pub const TIM12_BASE: u32 = 0x4000_1800;
+pub const TIM13_BASE: u32 = 0x4000_1C00;
+pub const TIM14_BASE: u32 = 0x4000_2000;
+
+
+//ADC registers
+pub const ADC1_BASE: u32 = 0x4001_2000;
+pub const ADC2_BASE: u32 = 0x4001_2100;
+pub const ADC3_BASE: u32 = 0x4001_2200;
+pub const ADC_COMMON_BASE: u32 = 0x4001_2300;
+
+
+//DAC registers
+pub const DAC_BASE: u32 = 0x4000_7400;
+
+
+//DMA registers
+pub const DMA1_BASE: u32 = 0x4002_6000;
+pub const DMA2_BASE: u32 = 0x4002_6400;
+
+
 //exti register
 pub const EXTI_BASE : u32 = 0x4001_3C00;
 
@@ -27,13 +106,68 @@ pub const EXTI_BASE : u32 = 0x4001_3C00;
 pub const NVIC_BASE : u32 = 0xE000_E100;
 pub const NVIC_ISER: u32 = NVIC_BASE;
 pub const NVIC_ICER: u32 = NVIC_BASE+ 0x80;
+pub const NVIC_ISPR: u32 = NVIC_BASE + 0x100;
+pub const NVIC_ICPR: u32 = NVIC_BASE + 0x180;
+pub const NVIC_IABR: u32 = NVIC_BASE + 0x200;
 pub const NVIC_IPR: u32 = 0xE000_E400;
 
 
 //SCB
+pub const SCB_ICSR: u32 = 0xE000_ED04;
+pub const SCB_VTOR: u32 = 0xE000_ED08;
 pub const SCB_AIRCR_BASE: u32 = 0xE000_ED0C;
+pub const SCB_SCR: u32 = 0xE000_ED10;
+pub const SCB_CCR: u32 = 0xE000_ED14;
+pub const SCB_SHPR_BASE: u32 = 0xE000_ED18;
+pub const SCB_SHCSR: u32 = 0xE000_ED24;
+pub const SCB_CFSR: u32 = 0xE000_ED28;
+pub const SCB_HFSR: u32 = 0xE000_ED2C;
+pub const SCB_MMFAR: u32 = 0xE000_ED34;
+pub const SCB_BFAR: u32 = 0xE000_ED38;
 
 //Systic
 pub const SYSTICK_BASE : u32 = 0xE000_E010;
 
+//Debug MCU: peripherals frozen while the core is halted by a debugger
+pub const DBGMCU_APB1_FZ: u32 = 0xE004_2008;
+
 
+//IRQ numbers (position in the vector table, see vector_table.rs)
+pub const WWDG_IRQ: u32 = 0;
+pub const RTC_WKUP_IRQ: u32 = 3;
+pub const RTC_ALARM_IRQ: u32 = 41;
+pub const EXTI0_IRQ: u32 = 6;
+pub const EXTI1_IRQ: u32 = 7;
+pub const EXTI2_IRQ: u32 = 8;
+pub const EXTI3_IRQ: u32 = 9;
+pub const EXTI4_IRQ: u32 = 10;
+pub const EXTI9_5_IRQ: u32 = 23;
+pub const EXTI15_10_IRQ: u32 = 40;
+pub const USART1_IRQ: u32 = 37;
+pub const USART2_IRQ: u32 = 38;
+pub const USART3_IRQ: u32 = 39;
+pub const UART4_IRQ: u32 = 52;
+pub const UART5_IRQ: u32 = 53;
+pub const USART6_IRQ: u32 = 71;
+pub const SPI1_IRQ: u32 = 35;
+pub const SPI2_IRQ: u32 = 36;
+pub const SPI3_IRQ: u32 = 51;
+pub const DMA1_STREAM0_IRQ: u32 = 11;
+pub const DMA1_STREAM7_IRQ: u32 = 47;
+pub const DMA2_STREAM0_IRQ: u32 = 56;
+pub const DMA2_STREAM5_IRQ: u32 = 68;
+pub const TIM1_BRK_TIM9_IRQ: u32 = 24;
+pub const TIM1_UP_TIM10_IRQ: u32 = 25;
+pub const TIM1_TRG_COM_TIM11_IRQ: u32 = 26;
+pub const TIM1_CC_IRQ: u32 = 27;
+pub const TIM2_IRQ: u32 = 28;
+pub const TIM3_IRQ: u32 = 29;
+pub const TIM4_IRQ: u32 = 30;
+pub const TIM8_BRK_TIM12_IRQ: u32 = 43;
+pub const TIM8_UP_TIM13_IRQ: u32 = 44;
+pub const TIM8_TRG_COM_TIM14_IRQ: u32 = 45;
+pub const TIM8_CC_IRQ: u32 = 46;
+pub const TIM5_IRQ: u32 = 50;
+pub const TIM6_DAC_IRQ: u32 = 54;
+pub const TIM7_IRQ: u32 = 55;
+pub const ADC_IRQ: u32 = 18;
@@ -0,0 +1,1009 @@
+#![allow(dead_code)]
+
+/// # Timer Driver Module
+///
+/// This module drives TIM1–TIM14 of the STM32F407: up-counting timebase with update interrupts, PWM
+/// output, complementary outputs with dead-time (TIM1/TIM8), input capture, PWM-input measurement and
+/// quadrature encoder mode.
+///
+/// ## Instance Conventions
+///
+/// - `tim: u32` — timer number (1–14), matching the reference manual naming.
+/// - `channel: u32` — capture/compare channel (1–4).
+///
+/// | Timer            | Bus  | Counter | Channels | Notes                              |
+/// |------------------|------|---------|----------|------------------------------------|
+/// | TIM1, TIM8       | APB2 | 16-bit  | 4        | advanced: complementary, dead-time |
+/// | TIM2, TIM5       | APB1 | 32-bit  | 4        |                                    |
+/// | TIM3, TIM4       | APB1 | 16-bit  | 4        |                                    |
+/// | TIM6, TIM7       | APB1 | 16-bit  | 0        | basic: timebase only               |
+/// | TIM9             | APB2 | 16-bit  | 2        |                                    |
+/// | TIM10, TIM11     | APB2 | 16-bit  | 1        |                                    |
+/// | TIM12            | APB1 | 16-bit  | 2        |                                    |
+/// | TIM13, TIM14     | APB1 | 16-bit  | 1        |                                    |
+///
+/// The counter clock is the APB timer clock from `rcc::clocks()` (twice PCLK when the APB bus is
+/// divided), so `RccConfig::freeze` must run first.
+///
+/// ## Pins
+///
+/// Channel pins must be put in alternate mode by the caller, with `timer_alternate_function(tim)`
+/// as the AF number (e.g. PD12..PD15 = TIM4 CH1..CH4, AF2).
+///
+/// ## Interrupts
+///
+/// `timer_set_callback` registers a function called from interrupt context for update and
+/// capture/compare events. TIM1/TIM8 interrupts are shared with TIM9..TIM14 and TIM6 with the DAC;
+/// the shared vectors dispatch to every timer behind them.
+use embedded_hal::pwm::{ErrorType, SetDutyCycle};
+use crate::stm32f407_registers::*;
+use crate::read_write::{read_register, write_register, reg_write_bit, reg_write_bits};
+use crate::cortex_m4::enable_irq;
+use crate::rcc::clocks;
+
+pub const TIM1: u32 = 1;
+pub const TIM2: u32 = 2;
+pub const TIM3: u32 = 3;
+pub const TIM4: u32 = 4;
+pub const TIM5: u32 = 5;
+pub const TIM6: u32 = 6;
+pub const TIM7: u32 = 7;
+pub const TIM8: u32 = 8;
+pub const TIM9: u32 = 9;
+pub const TIM10: u32 = 10;
+pub const TIM11: u32 = 11;
+pub const TIM12: u32 = 12;
+pub const TIM13: u32 = 13;
+pub const TIM14: u32 = 14;
+
+const NUM_TIMERS: usize = 14;
+
+// Register offsets
+const TIM_CR1: u32 = 0x00;
+const TIM_CR2: u32 = 0x04;
+const TIM_SMCR: u32 = 0x08;
+const TIM_DIER: u32 = 0x0C;
+const TIM_SR: u32 = 0x10;
+const TIM_EGR: u32 = 0x14;
+const TIM_CCMR1: u32 = 0x18;
+const TIM_CCER: u32 = 0x20;
+const TIM_CNT: u32 = 0x24;
+const TIM_PSC: u32 = 0x28;
+const TIM_ARR: u32 = 0x2C;
+const TIM_CCR1: u32 = 0x34;
+const TIM_BDTR: u32 = 0x44;
+
+// CR1 bits
+const CR1_CEN: u32 = 0;
+const CR1_DIR: u32 = 4;
+const CR1_ARPE: u32 = 7;
+
+// CR2 fields
+const CR2_MMS: u32 = 4;
+
+// SMCR fields
+const SMCR_SMS: u32 = 0;
+const SMCR_TS: u32 = 4;
+const SMS_ENCODER_TI1: u32 = 0b001;
+const SMS_ENCODER_TI2: u32 = 0b010;
+const SMS_ENCODER_BOTH: u32 = 0b011;
+const SMS_RESET: u32 = 0b100;
+const TS_TI1FP1: u32 = 0b101;
+
+// DIER / SR bits
+const DIER_UIE: u32 = 1 << 0;
+const SR_UIF: u32 = 1 << 0;
+const SR_CC1IF: u32 = 1 << 1;
+const SR_CC1OF: u32 = 1 << 9;
+
+// EGR bits
+const EGR_UG: u32 = 0;
+
+// CCMR fields (per channel, 8 bits)
+const CCMR_CCS_OUTPUT: u32 = 0b00;
+const CCMR_CCS_TI_DIRECT: u32 = 0b01;
+const CCMR_CCS_TI_INDIRECT: u32 = 0b10;
+const CCMR_OCPE: u32 = 1 << 3;
+const CCMR_OCM_PWM1: u32 = 0b110 << 4;
+const CCMR_ICF: u32 = 4;
+
+// CCER bits (per channel, 4 bits)
+const CCER_CCE: u32 = 1 << 0;
+const CCER_CCP: u32 = 1 << 1;
+const CCER_CCNE: u32 = 1 << 2;
+const CCER_CCNP: u32 = 1 << 3;
+
+// BDTR bits
+const BDTR_DTG_MASK: u32 = 0xFF;
+const BDTR_MOE: u32 = 1 << 15;
+
+/// Edge(s) latching the counter into a capture register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureEdge {
+    Rising,
+    Falling,
+    Both,
+}
+
+/// Which encoder inputs are counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderMode {
+    /// Count on TI1 edges only (x2 resolution).
+    Ti1,
+    /// Count on TI2 edges only (x2 resolution).
+    Ti2,
+    /// Count on both inputs (x4 resolution).
+    Both,
+}
+
+/// Signal sent on TRGO to the ADC, DAC or other timers (CR2.MMS).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerOutput {
+    Reset = 0b000,
+    Enable = 0b001,
+    Update = 0b010,
+    ComparePulse = 0b011,
+    Oc1Ref = 0b100,
+    Oc2Ref = 0b101,
+    Oc3Ref = 0b110,
+    Oc4Ref = 0b111,
+}
+
+/// Events reported to timer callbacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerEvent {
+    /// Counter overflow / update.
+    Update,
+    /// Capture or compare on the given channel.
+    CaptureCompare(u32),
+}
+
+/// Callback invoked from interrupt context with the timer number and event.
+pub type TimerCallback = fn(tim: u32, event: TimerEvent);
+
+static mut CALLBACKS: [Option<TimerCallback>; NUM_TIMERS] = [None; NUM_TIMERS];
+
+/// Function name: `select_timer_base`
+///
+/// Description:
+/// Returns the base address of the given timer.
+///
+/// Parameters:
+/// - `tim`: Timer number (1–14).
+///
+/// Return:
+/// - Base address (`u32`) of the peripheral.
+fn select_timer_base(tim: u32) -> u32 {
+    match tim {
+        1 => TIM1_BASE,
+        2 => TIM2_BASE,
+        3 => TIM3_BASE,
+        4 => TIM4_BASE,
+        5 => TIM5_BASE,
+        6 => TIM6_BASE,
+        7 => TIM7_BASE,
+        8 => TIM8_BASE,
+        9 => TIM9_BASE,
+        10 => TIM10_BASE,
+        11 => TIM11_BASE,
+        12 => TIM12_BASE,
+        13 => TIM13_BASE,
+        14 => TIM14_BASE,
+        _ => panic!("Invalid timer: TIM{}. Valid range is 1 – 14.", tim),
+    }
+}
+
+fn reg(tim: u32, offset: u32) -> *mut u32 {
+    (select_timer_base(tim) + offset) as *mut u32
+}
+
+/// Number of capture/compare channels of a timer.
+pub fn timer_channel_count(tim: u32) -> u32 {
+    match tim {
+        1..=5 | 8 => 4,
+        9 | 12 => 2,
+        10 | 11 | 13 | 14 => 1,
+        6 | 7 => 0,
+        _ => panic!("Invalid timer: TIM{}", tim),
+    }
+}
+
+/// `true` for the 32-bit counters (TIM2, TIM5).
+pub fn timer_is_32bit(tim: u32) -> bool {
+    tim == 2 || tim == 5
+}
+
+/// `true` for the advanced-control timers (TIM1, TIM8).
+fn timer_is_advanced(tim: u32) -> bool {
+    tim == 1 || tim == 8
+}
+
+/// Largest auto-reload value.
+fn timer_max_arr(tim: u32) -> u32 {
+    if timer_is_32bit(tim) { u32::MAX } else { 0xFFFF }
+}
+
+fn check_channel(tim: u32, channel: u32) {
+    assert!(
+        channel >= 1 && channel <= timer_channel_count(tim),
+        "TIM{} has no channel {}",
+        tim,
+        channel
+    );
+}
+
+/// GPIO alternate function routing channel pins to the timer.
+pub fn timer_alternate_function(tim: u32) -> u32 {
+    match tim {
+        1 | 2 => 1,
+        3..=5 => 2,
+        8..=11 => 3,
+        12..=14 => 9,
+        _ => panic!("TIM{} has no pins", tim),
+    }
+}
+
+/// Function name: `timer_clock_hz`
+///
+/// Description:
+/// Returns the counter input clock (before the prescaler) of a timer.
+///
+/// Parameters:
+/// - `tim`: Timer number (1–14).
+///
+/// Return:
+/// - Timer kernel clock in Hz.
+pub fn timer_clock_hz(tim: u32) -> u32 {
+    match tim {
+        1 | 8..=11 => clocks().timclk2(),
+        _ => clocks().timclk1(),
+    }
+}
+
+/// Returns the NVIC IRQ numbers serving a timer (TIM1/TIM8 have four).
+fn timer_irq_numbers(tim: u32) -> &'static [u32] {
+    match tim {
+        1 => &[TIM1_BRK_TIM9_IRQ, TIM1_UP_TIM10_IRQ, TIM1_TRG_COM_TIM11_IRQ, TIM1_CC_IRQ],
+        2 => &[TIM2_IRQ],
+        3 => &[TIM3_IRQ],
+        4 => &[TIM4_IRQ],
+        5 => &[TIM5_IRQ],
+        6 => &[TIM6_DAC_IRQ],
+        7 => &[TIM7_IRQ],
+        8 => &[TIM8_BRK_TIM12_IRQ, TIM8_UP_TIM13_IRQ, TIM8_TRG_COM_TIM14_IRQ, TIM8_CC_IRQ],
+        9 => &[TIM1_BRK_TIM9_IRQ],
+        10 => &[TIM1_UP_TIM10_IRQ],
+        11 => &[TIM1_TRG_COM_TIM11_IRQ],
+        12 => &[TIM8_BRK_TIM12_IRQ],
+        13 => &[TIM8_UP_TIM13_IRQ],
+        14 => &[TIM8_TRG_COM_TIM14_IRQ],
+        _ => panic!("Invalid timer: TIM{}", tim),
+    }
+}
+
+/// RCC enable register and bit of a timer.
+fn timer_clock_enable_bit(tim: u32) -> (u32, u32) {
+    match tim {
+        1 => (RCC_APB2ENR, 0),
+        8 => (RCC_APB2ENR, 1),
+        9..=11 => (RCC_APB2ENR, 16 + tim - 9),
+        2..=7 => (RCC_APB1ENR, tim - 2),
+        12..=14 => (RCC_APB1ENR, 6 + tim - 12),
+        _ => panic!("Invalid timer: TIM{}", tim),
+    }
+}
+
+/// Function name: `configure_timer_clock`
+///
+/// Description:
+/// Enables or disables the APB clock of the given timer.
+///
+/// Parameters:
+/// - `tim`: Timer number (1–14).
+/// - `enable`: `true` to enable, `false` to disable.
+///
+/// Return:
+/// - None
+fn configure_timer_clock(tim: u32, enable: bool) {
+    let (enr_addr, bit) = timer_clock_enable_bit(tim);
+    unsafe {
+        reg_write_bit(enr_addr as *mut u32, bit, enable);
+    }
+}
+
+/// Function name: `timer_compute_timebase`
+///
+/// Description:
+/// Computes prescaler and auto-reload values giving an update rate as close as possible to
+/// `frequency_hz`, keeping the prescaler as small as possible (finest PWM resolution).
+///
+/// Parameters:
+/// - `clock_hz`: Timer kernel clock.
+/// - `frequency_hz`: Requested update frequency.
+/// - `max_arr`: Largest auto-reload value of the timer.
+///
+/// Return:
+/// - `(psc, arr)` register values.
+pub fn timer_compute_timebase(clock_hz: u32, frequency_hz: u32, max_arr: u32) -> (u32, u32) {
+    assert!(frequency_hz > 0 && frequency_hz <= clock_hz, "Timer frequency out of range: {} Hz", frequency_hz);
+    let ticks = (clock_hz / frequency_hz) as u64;
+    let psc = (ticks - 1) / (max_arr as u64 + 1);
+    assert!(psc <= 0xFFFF, "Timer frequency too low: {} Hz", frequency_hz);
+    let arr = (ticks / (psc + 1)).max(1) - 1;
+    (psc as u32, arr as u32)
+}
+
+/// Function name: `timer_init`
+///
+/// Description:
+/// Enables the timer clock and sets it up as a stopped up-counter with auto-reload preload.
+///
+/// Parameters:
+/// - `tim`: Timer number (1–14).
+///
+/// Return:
+/// - None
+pub fn timer_init(tim: u32) {
+    configure_timer_clock(tim, true);
+    unsafe {
+        write_register(reg(tim, TIM_CR1), 1 << CR1_ARPE);
+        write_register(reg(tim, TIM_DIER), 0);
+        write_register(reg(tim, TIM_SR), 0);
+    }
+}
+
+/// Function name: `timer_set_frequency`
+///
+/// Description:
+/// Sets the update (overflow) frequency, which is also the PWM frequency.
+///
+/// Parameters:
+/// - `tim`: Timer number (1–14).
+/// - `frequency_hz`: Update frequency.
+///
+/// Return:
+/// - None
+pub fn timer_set_frequency(tim: u32, frequency_hz: u32) {
+    let (psc, arr) = timer_compute_timebase(timer_clock_hz(tim), frequency_hz, timer_max_arr(tim));
+    timer_set_timebase(tim, psc, arr);
+}
+
+/// Function name: `timer_set_timebase`
+///
+/// Description:
+/// Writes the prescaler and auto-reload registers and loads them immediately.
+///
+/// Parameters:
+/// - `tim`: Timer number (1–14).
+/// - `psc`: Prescaler (counter clock = timer clock / (psc + 1)).
+/// - `arr`: Auto-reload (period = arr + 1 counter ticks).
+///
+/// Return:
+/// - None
+pub fn timer_set_timebase(tim: u32, psc: u32, arr: u32) {
+    assert!(psc <= 0xFFFF && arr <= timer_max_arr(tim), "Invalid timebase for TIM{}", tim);
+    unsafe {
+        write_register(reg(tim, TIM_PSC), psc);
+        write_register(reg(tim, TIM_ARR), arr);
+
+        // Generate an update to load PSC, without reporting it as an interrupt
+        let dier = read_register(reg(tim, TIM_DIER));
+        write_register(reg(tim, TIM_DIER), dier & !DIER_UIE);
+        reg_write_bit(reg(tim, TIM_EGR), EGR_UG, true);
+        let sr = read_register(reg(tim, TIM_SR));
+        write_register(reg(tim, TIM_SR), sr & !SR_UIF);
+        write_register(reg(tim, TIM_DIER), dier);
+    }
+}
+
+/// Auto-reload value (period - 1).
+pub fn timer_get_auto_reload(tim: u32) -> u32 {
+    unsafe { read_register(reg(tim, TIM_ARR)) }
+}
+
+pub fn timer_start(tim: u32) {
+    unsafe {
+        reg_write_bit(reg(tim, TIM_CR1), CR1_CEN, true);
+    }
+}
+
+pub fn timer_stop(tim: u32) {
+    unsafe {
+        reg_write_bit(reg(tim, TIM_CR1), CR1_CEN, false);
+    }
+}
+
+pub fn timer_get_counter(tim: u32) -> u32 {
+    unsafe { read_register(reg(tim, TIM_CNT)) }
+}
+
+pub fn timer_set_counter(tim: u32, value: u32) {
+    unsafe {
+        write_register(reg(tim, TIM_CNT), value);
+    }
+}
+
+/// Function name: `timer_set_trigger_output`
+///
+/// Description:
+/// Selects what the timer sends on TRGO (e.g. `Update` to start an ADC or DAC conversion at every
+/// overflow). Not available on TIM9–TIM14.
+///
+/// Parameters:
+/// - `tim`: Timer number (1–8).
+/// - `output`: TRGO source.
+///
+/// Return:
+/// - None
+pub fn timer_set_trigger_output(tim: u32, output: TriggerOutput) {
+    assert!((1..=8).contains(&tim), "TIM{} has no trigger output", tim);
+    unsafe {
+        reg_write_bits(reg(tim, TIM_CR2), output as u32, CR2_MMS, 3);
+    }
+}
+
+/// Function name: `timer_set_callback`
+///
+/// Description:
+/// Registers (or removes) the interrupt callback and enables the timer's interrupt vector(s).
+///
+/// Parameters:
+/// - `tim`: Timer number (1–14).
+/// - `callback`: Function called from interrupt context, or `None`.
+///
+/// Return:
+/// - None
+pub fn timer_set_callback(tim: u32, callback: Option<TimerCallback>) {
+    let idx = (tim - 1) as usize;
+    let _ = select_timer_base(tim);
+    unsafe {
+        CALLBACKS[idx] = callback;
+    }
+    if callback.is_some() {
+        for &irq in timer_irq_numbers(tim) {
+            enable_irq(irq);
+        }
+    }
+}
+
+/// Function name: `timer_listen_update`
+///
+/// Description:
+/// Enables or disables the update interrupt.
+///
+/// Parameters:
+/// - `tim`: Timer number (1–14).
+/// - `enable`: `true` to raise `TimerEvent::Update` on each overflow.
+///
+/// Return:
+/// - None
+pub fn timer_listen_update(tim: u32, enable: bool) {
+    unsafe {
+        reg_write_bit(reg(tim, TIM_DIER), 0, enable);
+    }
+}
+
+/// Function name: `timer_listen_capture_compare`
+///
+/// Description:
+/// Enables or disables the capture/compare interrupt of a channel.
+///
+/// Parameters:
+/// - `tim`: Timer number (1–14).
+/// - `channel`: Channel (1–4).
+/// - `enable`: `true` to raise `TimerEvent::CaptureCompare(channel)`.
+///
+/// Return:
+/// - None
+pub fn timer_listen_capture_compare(tim: u32, channel: u32, enable: bool) {
+    check_channel(tim, channel);
+    unsafe {
+        reg_write_bit(reg(tim, TIM_DIER), channel, enable);
+    }
+}
+
+/// Write the 8-bit CCMR field of a channel.
+fn set_ccmr(tim: u32, channel: u32, value: u32) {
+    let offset = TIM_CCMR1 + ((channel - 1) / 2) * 4;
+    let shift = ((channel - 1) % 2) * 8;
+    unsafe {
+        reg_write_bits(reg(tim, offset), value, shift, 8);
+    }
+}
+
+/// Write the 4-bit CCER field of a channel.
+fn set_ccer(tim: u32, channel: u32, value: u32) {
+    unsafe {
+        reg_write_bits(reg(tim, TIM_CCER), value, (channel - 1) * 4, 4);
+    }
+}
+
+fn ccr(tim: u32, channel: u32) -> *mut u32 {
+    reg(tim, TIM_CCR1 + (channel - 1) * 4)
+}
+
+// ---------- PWM ----------
+
+/// Function name: `timer_pwm_init`
+///
+/// Description:
+/// Configures a channel for edge-aligned PWM mode 1 (output high while CNT < CCR), starting at 0%
+/// duty. On TIM1/TIM8 the main output enable is set as well.
+///
+/// Parameters:
+/// - `tim`: Timer number.
+/// - `channel`: Channel (1–4).
+/// - `active_low`: Invert the output polarity.
+///
+/// Return:
+/// - None
+pub fn timer_pwm_init(tim: u32, channel: u32, active_low: bool) {
+    check_channel(tim, channel);
+    unsafe {
+        write_register(ccr(tim, channel), 0);
+    }
+    set_ccmr(tim, channel, CCMR_CCS_OUTPUT | CCMR_OCPE | CCMR_OCM_PWM1);
+
+    let mut ccer = CCER_CCE;
+    if active_low {
+        ccer |= CCER_CCP;
+    }
+    set_ccer(tim, channel, ccer);
+
+    if timer_is_advanced(tim) {
+        unsafe {
+            let bdtr = read_register(reg(tim, TIM_BDTR));
+            write_register(reg(tim, TIM_BDTR), bdtr | BDTR_MOE);
+        }
+    }
+}
+
+/// Function name: `timer_pwm_set_duty`
+///
+/// Description:
+/// Sets the compare value of a PWM channel; takes effect at the next update event.
+///
+/// Parameters:
+/// - `tim`: Timer number.
+/// - `channel`: Channel (1–4).
+/// - `duty`: Compare value, 0 (always off) to `timer_pwm_max_duty(tim)` (always on).
+///
+/// Return:
+/// - None
+pub fn timer_pwm_set_duty(tim: u32, channel: u32, duty: u32) {
+    check_channel(tim, channel);
+    let duty = duty.min(timer_pwm_max_duty(tim));
+    unsafe {
+        write_register(ccr(tim, channel), duty);
+    }
+}
+
+/// Current compare value of a channel.
+pub fn timer_pwm_get_duty(tim: u32, channel: u32) -> u32 {
+    check_channel(tim, channel);
+    unsafe { read_register(ccr(tim, channel)) }
+}
+
+/// Compare value for 100% duty (ARR + 1, saturated for 32-bit timers).
+pub fn timer_pwm_max_duty(tim: u32) -> u32 {
+    timer_get_auto_reload(tim).saturating_add(1)
+}
+
+/// Set the duty cycle in percent (0–100).
+pub fn timer_pwm_set_duty_percent(tim: u32, channel: u32, percent: u32) {
+    let max = timer_pwm_max_duty(tim) as u64;
+    timer_pwm_set_duty(tim, channel, (max * percent.min(100) as u64 / 100) as u32);
+}
+
+/// Function name: `timer_pwm_enable_complementary`
+///
+/// Description:
+/// Enables the complementary output (CHxN) of a PWM channel on TIM1/TIM8 (channels 1–3).
+///
+/// Parameters:
+/// - `tim`: `TIM1` or `TIM8`.
+/// - `channel`: Channel (1–3).
+/// - `active_low`: Invert the complementary output polarity.
+///
+/// Return:
+/// - None
+pub fn timer_pwm_enable_complementary(tim: u32, channel: u32, active_low: bool) {
+    assert!(timer_is_advanced(tim) && (1..=3).contains(&channel), "TIM{} CH{} has no complementary output", tim, channel);
+    unsafe {
+        let shift = (channel - 1) * 4;
+        let mut ccer = read_register(reg(tim, TIM_CCER));
+        ccer &= !((CCER_CCNE | CCER_CCNP) << shift);
+        ccer |= CCER_CCNE << shift;
+        if active_low {
+            ccer |= CCER_CCNP << shift;
+        }
+        write_register(reg(tim, TIM_CCER), ccer);
+    }
+}
+
+/// Function name: `timer_dead_time_bits`
+///
+/// Description:
+/// Encodes a dead-time in timer clock ticks into the BDTR.DTG field (RM0090 17.4.18), rounding up
+/// to the next representable value.
+///
+/// Parameters:
+/// - `ticks`: Dead-time in timer clock periods (at most 1008).
+///
+/// Return:
+/// - DTG value.
+pub fn timer_dead_time_bits(ticks: u32) -> u8 {
+    match ticks {
+        0..=127 => ticks as u8,
+        128..=254 => 0x80 | (ticks.div_ceil(2) - 64) as u8,
+        255..=504 => 0xC0 | (ticks.div_ceil(8).max(32) - 32) as u8,
+        505..=1008 => 0xE0 | (ticks.div_ceil(16).max(32) - 32) as u8,
+        _ => panic!("Dead-time of {} ticks is too long (max 1008)", ticks),
+    }
+}
+
+/// Function name: `timer_set_dead_time_ns`
+///
+/// Description:
+/// Sets the dead-time inserted between a PWM output and its complement on TIM1/TIM8.
+///
+/// Parameters:
+/// - `tim`: `TIM1` or `TIM8`.
+/// - `ns`: Dead-time in nanoseconds (up to 1008 timer clock periods, ~6 us at 168 MHz).
+///
+/// Return:
+/// - None
+pub fn timer_set_dead_time_ns(tim: u32, ns: u32) {
+    assert!(timer_is_advanced(tim), "TIM{} has no dead-time generator", tim);
+    let ticks = (ns as u64 * timer_clock_hz(tim) as u64).div_ceil(1_000_000_000) as u32;
+    let dtg = timer_dead_time_bits(ticks) as u32;
+    unsafe {
+        let bdtr = read_register(reg(tim, TIM_BDTR));
+        write_register(reg(tim, TIM_BDTR), (bdtr & !BDTR_DTG_MASK) | dtg);
+    }
+}
+
+/// One PWM channel, usable through `embedded_hal::pwm::SetDutyCycle`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PwmChannel {
+    tim: u32,
+    channel: u32,
+}
+
+impl PwmChannel {
+    /// Configure `channel` of `tim` for PWM (see `timer_pwm_init`). The timebase must already be set.
+    pub fn new(tim: u32, channel: u32, active_low: bool) -> Self {
+        timer_pwm_init(tim, channel, active_low);
+        PwmChannel { tim, channel }
+    }
+
+    /// Raw compare-value scale of the channel divided into the 16-bit `SetDutyCycle` range.
+    fn scale(&self) -> u32 {
+        timer_pwm_max_duty(self.tim).div_ceil(u16::MAX as u32).max(1)
+    }
+}
+
+impl ErrorType for PwmChannel {
+    type Error = core::convert::Infallible;
+}
+
+impl SetDutyCycle for PwmChannel {
+    fn max_duty_cycle(&self) -> u16 {
+        (timer_pwm_max_duty(self.tim) / self.scale()) as u16
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        timer_pwm_set_duty(self.tim, self.channel, duty as u32 * self.scale());
+        Ok(())
+    }
+}
+
+// ---------- Input capture ----------
+
+fn capture_polarity(edge: CaptureEdge) -> u32 {
+    match edge {
+        CaptureEdge::Rising => 0,
+        CaptureEdge::Falling => CCER_CCP,
+        CaptureEdge::Both => CCER_CCP | CCER_CCNP,
+    }
+}
+
+/// Function name: `timer_capture_init`
+///
+/// Description:
+/// Configures a channel to capture the counter on edges of its own input pin.
+///
+/// Parameters:
+/// - `tim`: Timer number.
+/// - `channel`: Channel (1–4).
+/// - `edge`: Edge(s) triggering a capture.
+/// - `filter`: Digital input filter (0–15, see RM0090 ICxF).
+///
+/// Return:
+/// - None
+pub fn timer_capture_init(tim: u32, channel: u32, edge: CaptureEdge, filter: u32) {
+    check_channel(tim, channel);
+    set_ccer(tim, channel, 0);
+    set_ccmr(tim, channel, CCMR_CCS_TI_DIRECT | ((filter & 0xF) << CCMR_ICF));
+    set_ccer(tim, channel, CCER_CCE | capture_polarity(edge));
+}
+
+/// Function name: `timer_capture_read`
+///
+/// Description:
+/// Returns the last captured counter value if a new capture happened since the previous call.
+///
+/// Parameters:
+/// - `tim`: Timer number.
+/// - `channel`: Channel (1–4).
+///
+/// Return:
+/// - `Some(value)` for a new capture (reading clears the flag), `None` otherwise.
+pub fn timer_capture_read(tim: u32, channel: u32) -> Option<u32> {
+    check_channel(tim, channel);
+    let flag = SR_CC1IF << (channel - 1);
+    let overcapture = SR_CC1OF << (channel - 1);
+    unsafe {
+        let sr = read_register(reg(tim, TIM_SR));
+        if sr & flag == 0 {
+            return None;
+        }
+        // Reading CCR clears CCxIF; a missed capture is dropped silently
+        let value = read_register(ccr(tim, channel));
+        if sr & overcapture != 0 {
+            write_register(reg(tim, TIM_SR), !overcapture & 0xFFFF);
+        }
+        Some(value)
+    }
+}
+
+/// Function name: `timer_pwm_input_init`
+///
+/// Description:
+/// Measures an external signal on CH1: CH1 captures the period on rising edges, CH2 the high time
+/// on falling edges, and the counter is reset on every rising edge (slave reset mode on TI1FP1).
+/// Available on timers with a slave controller and two channels (TIM1–5, TIM8, TIM9, TIM12).
+///
+/// Parameters:
+/// - `tim`: Timer number.
+/// - `psc`: Counter prescaler; the period must fit in the counter at the resulting tick rate.
+/// - `filter`: Digital input filter (0–15).
+///
+/// Return:
+/// - None
+pub fn timer_pwm_input_init(tim: u32, psc: u32, filter: u32) {
+    assert!(matches!(tim, 1..=5 | 8 | 9 | 12), "TIM{} does not support PWM input", tim);
+    timer_set_timebase(tim, psc, timer_max_arr(tim));
+
+    set_ccer(tim, 1, 0);
+    set_ccer(tim, 2, 0);
+    set_ccmr(tim, 1, CCMR_CCS_TI_DIRECT | ((filter & 0xF) << CCMR_ICF));
+    set_ccmr(tim, 2, CCMR_CCS_TI_INDIRECT | ((filter & 0xF) << CCMR_ICF));
+    set_ccer(tim, 1, CCER_CCE);
+    set_ccer(tim, 2, CCER_CCE | CCER_CCP);
+
+    unsafe {
+        write_register(reg(tim, TIM_SMCR), (TS_TI1FP1 << SMCR_TS) | (SMS_RESET << SMCR_SMS));
+    }
+}
+
+/// Function name: `timer_pwm_input_read`
+///
+/// Description:
+/// Returns the latest period and high time measured by `timer_pwm_input_init`.
+///
+/// Parameters:
+/// - `tim`: Timer number.
+///
+/// Return:
+/// - `Some((period_ticks, high_ticks))` after a new period, `None` otherwise.
+pub fn timer_pwm_input_read(tim: u32) -> Option<(u32, u32)> {
+    let period = timer_capture_read(tim, 1)?;
+    let high = unsafe { read_register(ccr(tim, 2)) };
+    Some((period + 1, high + 1))
+}
+
+/// Function name: `timer_measure_frequency`
+///
+/// Description:
+/// Converts the latest PWM-input measurement to a frequency and duty cycle.
+///
+/// Parameters:
+/// - `tim`: Timer number.
+///
+/// Return:
+/// - `Some((frequency_hz, duty_percent))`, or `None` if no new period was measured.
+pub fn timer_measure_frequency(tim: u32) -> Option<(u32, u32)> {
+    let (period, high) = timer_pwm_input_read(tim)?;
+    let psc = unsafe { read_register(reg(tim, TIM_PSC)) };
+    let tick_hz = timer_clock_hz(tim) / (psc + 1);
+    Some((tick_hz / period, (high as u64 * 100 / period as u64) as u32))
+}
+
+// ---------- Encoder ----------
+
+/// Function name: `timer_encoder_init`
+///
+/// Description:
+/// Puts the timer in quadrature encoder mode on CH1/CH2 (TIM1–5, TIM8). The counter counts up or
+/// down with the encoder and wraps at the full counter range.
+///
+/// Parameters:
+/// - `tim`: Timer number.
+/// - `mode`: Counted inputs.
+/// - `filter`: Digital input filter (0–15).
+///
+/// Return:
+/// - None
+pub fn timer_encoder_init(tim: u32, mode: EncoderMode, filter: u32) {
+    assert!(matches!(tim, 1..=5 | 8), "TIM{} does not support encoder mode", tim);
+    let sms = match mode {
+        EncoderMode::Ti1 => SMS_ENCODER_TI1,
+        EncoderMode::Ti2 => SMS_ENCODER_TI2,
+        EncoderMode::Both => SMS_ENCODER_BOTH,
+    };
+
+    timer_set_timebase(tim, 0, timer_max_arr(tim));
+    set_ccer(tim, 1, 0);
+    set_ccer(tim, 2, 0);
+    set_ccmr(tim, 1, CCMR_CCS_TI_DIRECT | ((filter & 0xF) << CCMR_ICF));
+    set_ccmr(tim, 2, CCMR_CCS_TI_DIRECT | ((filter & 0xF) << CCMR_ICF));
+    set_ccer(tim, 1, CCER_CCE);
+    set_ccer(tim, 2, CCER_CCE);
+
+    unsafe {
+        write_register(reg(tim, TIM_SMCR), sms << SMCR_SMS);
+        write_register(reg(tim, TIM_CNT), 0);
+    }
+}
+
+/// Encoder position (counter value).
+pub fn timer_encoder_position(tim: u32) -> u32 {
+    timer_get_counter(tim)
+}
+
+/// `true` if the encoder last moved backwards (counter counting down).
+pub fn timer_encoder_is_reversed(tim: u32) -> bool {
+    unsafe { read_register(reg(tim, TIM_CR1)) & (1 << CR1_DIR) != 0 }
+}
+
+// ---------- Interrupts ----------
+
+/// Function name: `timer_irq_handler`
+///
+/// Description:
+/// Clears the pending, enabled update and capture/compare flags of a timer and reports them to its
+/// callback. Called from the timer vectors below.
+///
+/// Parameters:
+/// - `tim`: Timer number (1–14).
+///
+/// Return:
+/// - None
+pub fn timer_irq_handler(tim: u32) {
+    let idx = (tim - 1) as usize;
+    let (flags, callback) = unsafe {
+        let sr = read_register(reg(tim, TIM_SR));
+        let dier = read_register(reg(tim, TIM_DIER));
+        // Update and CC1..CC4 flags share bit positions with their enables
+        let flags = sr & dier & 0x1F;
+        if flags == 0 {
+            return;
+        }
+        write_register(reg(tim, TIM_SR), !flags & 0xFFFF);
+        (flags, CALLBACKS[idx])
+    };
+
+    if let Some(callback) = callback {
+        if flags & SR_UIF != 0 {
+            callback(tim, TimerEvent::Update);
+        }
+        for channel in 1..=4 {
+            if flags & (SR_CC1IF << (channel - 1)) != 0 {
+                callback(tim, TimerEvent::CaptureCompare(channel));
+            }
+        }
+    }
+}
+
+/// `true` once `timer_init` has enabled the timer's clock; the registers of an unclocked timer
+/// must not be accessed.
+fn timer_is_enabled(tim: u32) -> bool {
+    let (enr_addr, bit) = timer_clock_enable_bit(tim);
+    unsafe { read_register(enr_addr as *mut u32) & (1 << bit) != 0 }
+}
+
+/// Dispatch a vector shared by several timers.
+fn shared_irq(timers: &[u32]) {
+    for &tim in timers {
+        if timer_is_enabled(tim) {
+            timer_irq_handler(tim);
+        }
+    }
+}
+
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn TIM1_BRK_TIM9_Handler() {
+    shared_irq(&[TIM1, TIM9]);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn TIM1_UP_TIM10_Handler() {
+    shared_irq(&[TIM1, TIM10]);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn TIM1_TRG_COM_TIM11_Handler() {
+    shared_irq(&[TIM1, TIM11]);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn TIM1_CC_Handler() {
+    shared_irq(&[TIM1]);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn TIM2_Handler() {
+    timer_irq_handler(TIM2);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn TIM3_Handler() {
+    timer_irq_handler(TIM3);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn TIM4_Handler() {
+    timer_irq_handler(TIM4);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn TIM5_Handler() {
+    timer_irq_handler(TIM5);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn TIM6_DAC_Handler() {
+    shared_irq(&[TIM6]);
+    crate::dac::dac_irq_handler();
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn TIM7_Handler() {
+    timer_irq_handler(TIM7);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn TIM8_BRK_TIM12_Handler() {
+    shared_irq(&[TIM8, TIM12]);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn TIM8_UP_TIM13_Handler() {
+    shared_irq(&[TIM8, TIM13]);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn TIM8_TRG_COM_TIM14_Handler() {
+    shared_irq(&[TIM8, TIM14]);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn TIM8_CC_Handler() {
+    shared_irq(&[TIM8]);
+}
@@ -0,0 +1,606 @@
+#![allow(dead_code)]
+
+/// # USART / UART Driver Module
+///
+/// This module provides an interrupt-driven serial driver for USART1, USART2, USART3, UART4, UART5 and
+/// USART6 of the STM32F407. Received bytes are collected by the IRQ handler into an RX ring buffer, and
+/// bytes queued by `usart_write` are drained from a TX ring buffer by the TXE interrupt.
+///
+/// ## Instance Conventions
+///
+/// - `usart: u32` — peripheral number, matching the reference manual naming:
+///   - `1` → USART1 (APB2)
+///   - `2` → USART2 (APB1)
+///   - `3` → USART3 (APB1)
+///   - `4` → UART4 (APB1)
+///   - `5` → UART5 (APB1)
+///   - `6` → USART6 (APB2)
+///
+/// The baud-rate divider is computed from the APB clock of the instance as reported by `rcc::clocks()`,
+/// so `RccConfig::freeze` must run before `usart_init`.
+///
+/// ## Pins
+///
+/// The driver does not touch GPIO. TX/RX pins must be put in alternate mode and routed with
+/// `gpio_set_alternate_function` (AF7 for USART1..3, AF8 for UART4/5 and USART6), e.g. PA2/PA3 for USART2.
+///
+/// ## Errors
+///
+/// Overrun, framing, noise and parity conditions detected by the IRQ handler are latched and reported by
+/// the next `usart_read`/`usart_read_byte` call as a `UsartError`, after which they are cleared.
+/// Bytes received with a framing or parity error are discarded.
+///
+/// ## Notifications
+///
+/// `usart_set_notify` registers callbacks invoked from interrupt context whenever a byte is received or
+/// TX buffer space is freed. The kernel uses them to release semaphores so that tasks can sleep while
+/// waiting for serial data.
+use core::sync::atomic::{AtomicU8, Ordering};
+use crate::stm32f407_registers::*;
+use crate::read_write::{read_register, write_register, reg_write_bit, reg_write_bits};
+use crate::ring_buffer::RingBuffer;
+use crate::cortex_m4::enable_irq;
+use crate::rcc::clocks;
+
+pub const USART1: u32 = 1;
+pub const USART2: u32 = 2;
+pub const USART3: u32 = 3;
+pub const UART4: u32 = 4;
+pub const UART5: u32 = 5;
+pub const USART6: u32 = 6;
+
+/// Size of each per-instance RX and TX ring buffer (one slot is kept free).
+pub const USART_BUFFER_SIZE: usize = 256;
+
+const NUM_USARTS: usize = 6;
+
+// Register offsets
+const USART_SR: u32 = 0x00;
+const USART_DR: u32 = 0x04;
+const USART_BRR: u32 = 0x08;
+const USART_CR1: u32 = 0x0C;
+const USART_CR2: u32 = 0x10;
+const USART_CR3: u32 = 0x14;
+
+// SR bits
+const SR_PE: u32 = 1 << 0;
+const SR_FE: u32 = 1 << 1;
+const SR_NF: u32 = 1 << 2;
+const SR_ORE: u32 = 1 << 3;
+const SR_RXNE: u32 = 1 << 5;
+const SR_TC: u32 = 1 << 6;
+const SR_TXE: u32 = 1 << 7;
+
+// CR1 bits
+const CR1_RE: u32 = 2;
+const CR1_TE: u32 = 3;
+const CR1_RXNEIE: u32 = 5;
+const CR1_TXEIE: u32 = 7;
+const CR1_PEIE: u32 = 8;
+const CR1_PS: u32 = 9;
+const CR1_PCE: u32 = 10;
+const CR1_M: u32 = 12;
+const CR1_UE: u32 = 13;
+
+// Latched error flags
+const ERR_OVERRUN: u8 = 1 << 0;
+const ERR_FRAMING: u8 = 1 << 1;
+const ERR_NOISE: u8 = 1 << 2;
+const ERR_PARITY: u8 = 1 << 3;
+
+/// Parity bit appended to each 8-bit data frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+/// Number of stop bits (CR2 STOP field).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Half,
+    Two,
+    OneAndHalf,
+}
+
+/// Line configuration. Data is always 8 bits; enabling parity adds a 9th (parity) bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsartConfig {
+    pub baud_rate: u32,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl UsartConfig {
+    /// 8N1 at the given baud rate.
+    pub const fn new(baud_rate: u32) -> Self {
+        UsartConfig { baud_rate, parity: Parity::None, stop_bits: StopBits::One }
+    }
+
+    pub const fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    pub const fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+}
+
+impl Default for UsartConfig {
+    fn default() -> Self {
+        UsartConfig::new(115_200)
+    }
+}
+
+/// Receive errors reported by the hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsartError {
+    /// A byte was lost because DR or the RX ring buffer was not emptied in time.
+    Overrun,
+    /// A stop bit was not detected (wrong baud rate, line break or noise).
+    Framing,
+    /// Noise was detected while sampling a received byte.
+    Noise,
+    /// The received parity bit did not match.
+    Parity,
+}
+
+/// Callback invoked from interrupt context with the instance number.
+pub type UsartNotify = fn(usart: u32);
+
+static RX_BUFFERS: [RingBuffer<USART_BUFFER_SIZE>; NUM_USARTS] = [const { RingBuffer::new() }; NUM_USARTS];
+static TX_BUFFERS: [RingBuffer<USART_BUFFER_SIZE>; NUM_USARTS] = [const { RingBuffer::new() }; NUM_USARTS];
+static ERRORS: [AtomicU8; NUM_USARTS] = [const { AtomicU8::new(0) }; NUM_USARTS];
+
+static mut RX_NOTIFY: [Option<UsartNotify>; NUM_USARTS] = [None; NUM_USARTS];
+static mut TX_NOTIFY: [Option<UsartNotify>; NUM_USARTS] = [None; NUM_USARTS];
+
+
+/// Function name: `select_usart_base`
+///
+/// Description:
+/// Returns the base address of the given USART/UART instance.
+///
+/// Parameters:
+/// - `usart`: Instance number (1–6).
+///
+/// Return:
+/// - Base address (`u32`) of the peripheral.
+fn select_usart_base(usart: u32) -> u32 {
+    match usart {
+        1 => USART1_BASE,
+        2 => USART2_BASE,
+        3 => USART3_BASE,
+        4 => UART4_BASE,
+        5 => UART5_BASE,
+        6 => USART6_BASE,
+        _ => panic!("Invalid USART: {}. Valid range is 1 – 6.", usart),
+    }
+}
+
+/// Index of the instance in the driver's static tables.
+fn usart_index(usart: u32) -> usize {
+    assert!((1..=6).contains(&usart), "Invalid USART: {}", usart);
+    (usart - 1) as usize
+}
+
+/// Returns the NVIC IRQ number of the given instance.
+fn usart_irq_number(usart: u32) -> u32 {
+    match usart {
+        1 => USART1_IRQ,
+        2 => USART2_IRQ,
+        3 => USART3_IRQ,
+        4 => UART4_IRQ,
+        5 => UART5_IRQ,
+        6 => USART6_IRQ,
+        _ => panic!("Invalid USART: {}", usart),
+    }
+}
+
+/// APB clock of the given instance (USART1/6 on APB2, the others on APB1).
+fn usart_pclk(usart: u32) -> u32 {
+    match usart {
+        1 | 6 => clocks().pclk2(),
+        _ => clocks().pclk1(),
+    }
+}
+
+/// Function name: `configure_usart_clock`
+///
+/// Description:
+/// Enables or disables the APB clock of the given instance (USART1/6 on APB2, the others on APB1).
+///
+/// Parameters:
+/// - `usart`: Instance number (1–6).
+/// - `enable`: `true` to enable, `false` to disable.
+///
+/// Return:
+/// - None
+fn configure_usart_clock(usart: u32, enable: bool) {
+    let (enr_addr, bit) = match usart {
+        1 => (RCC_APB2ENR, 4),
+        2 => (RCC_APB1ENR, 17),
+        3 => (RCC_APB1ENR, 18),
+        4 => (RCC_APB1ENR, 19),
+        5 => (RCC_APB1ENR, 20),
+        6 => (RCC_APB2ENR, 5),
+        _ => panic!("Invalid USART: {}", usart),
+    };
+
+    unsafe {
+        reg_write_bit(enr_addr as *mut u32, bit, enable);
+    }
+}
+
+/// Function name: `usart_compute_brr`
+///
+/// Description:
+/// Computes the BRR value for 16x oversampling. BRR holds USARTDIV in 12.4 fixed point, which is
+/// `pclk / baud` rounded to the nearest integer.
+///
+/// Parameters:
+/// - `pclk_hz`: Peripheral clock in Hz.
+/// - `baud_rate`: Desired baud rate.
+///
+/// Return:
+/// - Value to write into USART_BRR.
+pub const fn usart_compute_brr(pclk_hz: u32, baud_rate: u32) -> u32 {
+    assert!(baud_rate > 0);
+    (pclk_hz + baud_rate / 2) / baud_rate
+}
+
+/// Function name: `usart_init`
+///
+/// Description:
+/// Enables the peripheral clock, programs baud rate, parity and stop bits, enables the transmitter,
+/// receiver and RX interrupt, clears the ring buffers and enables the IRQ in the NVIC.
+///
+/// Parameters:
+/// - `usart`: Instance number (1–6).
+/// - `config`: Line configuration.
+///
+/// Return:
+/// - None
+pub fn usart_init(usart: u32, config: &UsartConfig) {
+    let idx = usart_index(usart);
+    configure_usart_clock(usart, true);
+
+    let base = select_usart_base(usart);
+    let cr1_addr = (base + USART_CR1) as *mut u32;
+    let cr2_addr = (base + USART_CR2) as *mut u32;
+    let cr3_addr = (base + USART_CR3) as *mut u32;
+    let brr_addr = (base + USART_BRR) as *mut u32;
+
+    RX_BUFFERS[idx].clear();
+    while TX_BUFFERS[idx].pop().is_some() {}
+    ERRORS[idx].store(0, Ordering::Relaxed);
+
+    let mut cr1 = (1 << CR1_TE) | (1 << CR1_RE) | (1 << CR1_RXNEIE);
+    match config.parity {
+        Parity::None => {}
+        Parity::Even => cr1 |= (1 << CR1_M) | (1 << CR1_PCE) | (1 << CR1_PEIE),
+        Parity::Odd => cr1 |= (1 << CR1_M) | (1 << CR1_PCE) | (1 << CR1_PS) | (1 << CR1_PEIE),
+    }
+
+    let stop = match config.stop_bits {
+        StopBits::One => 0b00,
+        StopBits::Half => 0b01,
+        StopBits::Two => 0b10,
+        StopBits::OneAndHalf => 0b11,
+    };
+
+    unsafe {
+        // Disable while reconfiguring
+        write_register(cr1_addr, 0);
+        reg_write_bits(cr2_addr, stop, 12, 2);
+        write_register(cr3_addr, 0);
+        write_register(brr_addr, usart_compute_brr(usart_pclk(usart), config.baud_rate));
+        write_register(cr1_addr, cr1);
+        reg_write_bit(cr1_addr, CR1_UE, true);
+    }
+
+    enable_irq(usart_irq_number(usart));
+}
+
+/// Function name: `usart_set_notify`
+///
+/// Description:
+/// Registers callbacks run from the IRQ handler after a byte has been stored in the RX buffer (`rx`)
+/// or after bytes have been moved out of the TX buffer (`tx`). Pass `None` to remove a callback.
+///
+/// Parameters:
+/// - `usart`: Instance number (1–6).
+/// - `rx`: Receive notification.
+/// - `tx`: Transmit-space notification.
+///
+/// Return:
+/// - None
+pub fn usart_set_notify(usart: u32, rx: Option<UsartNotify>, tx: Option<UsartNotify>) {
+    let idx = usart_index(usart);
+    unsafe {
+        RX_NOTIFY[idx] = rx;
+        TX_NOTIFY[idx] = tx;
+    }
+}
+
+/// Function name: `usart_take_error`
+///
+/// Description:
+/// Returns and clears the latched receive error of highest severity, if any.
+///
+/// Parameters:
+/// - `usart`: Instance number (1–6).
+///
+/// Return:
+/// - `Err(UsartError)` if an error was latched, `Ok(())` otherwise.
+pub fn usart_take_error(usart: u32) -> Result<(), UsartError> {
+    let flags = ERRORS[usart_index(usart)].swap(0, Ordering::AcqRel);
+    if flags & ERR_OVERRUN != 0 {
+        Err(UsartError::Overrun)
+    } else if flags & ERR_FRAMING != 0 {
+        Err(UsartError::Framing)
+    } else if flags & ERR_PARITY != 0 {
+        Err(UsartError::Parity)
+    } else if flags & ERR_NOISE != 0 {
+        Err(UsartError::Noise)
+    } else {
+        Ok(())
+    }
+}
+
+/// Function name: `usart_read_byte`
+///
+/// Description:
+/// Takes one byte from the RX ring buffer without blocking.
+///
+/// Parameters:
+/// - `usart`: Instance number (1–6).
+///
+/// Return:
+/// - `Ok(Some(byte))` if data was available, `Ok(None)` if the buffer is empty, or the latched error.
+pub fn usart_read_byte(usart: u32) -> Result<Option<u8>, UsartError> {
+    usart_take_error(usart)?;
+    Ok(RX_BUFFERS[usart_index(usart)].pop())
+}
+
+/// Function name: `usart_read`
+///
+/// Description:
+/// Copies as many buffered bytes as are available (up to `buf.len()`) without blocking.
+///
+/// Parameters:
+/// - `usart`: Instance number (1–6).
+/// - `buf`: Destination buffer.
+///
+/// Return:
+/// - Number of bytes copied (may be 0), or the latched error.
+pub fn usart_read(usart: u32, buf: &mut [u8]) -> Result<usize, UsartError> {
+    usart_take_error(usart)?;
+    let rx = &RX_BUFFERS[usart_index(usart)];
+    let mut count = 0;
+    while count < buf.len() {
+        match rx.pop() {
+            Some(byte) => {
+                buf[count] = byte;
+                count += 1;
+            }
+            None => break,
+        }
+    }
+    Ok(count)
+}
+
+/// Function name: `usart_rx_available`
+///
+/// Description:
+/// Returns the number of received bytes waiting in the RX buffer.
+///
+/// Parameters:
+/// - `usart`: Instance number (1–6).
+///
+/// Return:
+/// - Number of buffered bytes.
+pub fn usart_rx_available(usart: u32) -> usize {
+    RX_BUFFERS[usart_index(usart)].len()
+}
+
+/// Function name: `usart_write`
+///
+/// Description:
+/// Queues bytes into the TX ring buffer and enables the TXE interrupt. Stops at the first byte that
+/// does not fit; the caller may retry with the remainder once space is available.
+///
+/// Parameters:
+/// - `usart`: Instance number (1–6).
+/// - `data`: Bytes to send.
+///
+/// Return:
+/// - Number of bytes queued.
+pub fn usart_write(usart: u32, data: &[u8]) -> usize {
+    let tx = &TX_BUFFERS[usart_index(usart)];
+    let mut count = 0;
+    for &byte in data {
+        if !tx.push(byte) {
+            break;
+        }
+        count += 1;
+    }
+
+    if count > 0 {
+        let cr1_addr = (select_usart_base(usart) + USART_CR1) as *mut u32;
+        unsafe {
+            reg_write_bit(cr1_addr, CR1_TXEIE, true);
+        }
+    }
+    count
+}
+
+/// Function name: `usart_tx_free`
+///
+/// Description:
+/// Returns the free space in the TX ring buffer.
+///
+/// Parameters:
+/// - `usart`: Instance number (1–6).
+///
+/// Return:
+/// - Number of bytes that can be queued without blocking.
+pub fn usart_tx_free(usart: u32) -> usize {
+    let tx = &TX_BUFFERS[usart_index(usart)];
+    tx.capacity() - tx.len()
+}
+
+/// Function name: `usart_is_tx_idle`
+///
+/// Description:
+/// Reports whether every queued byte has been shifted out on the line (TX buffer empty and TC set).
+///
+/// Parameters:
+/// - `usart`: Instance number (1–6).
+///
+/// Return:
+/// - `true` if the transmitter is idle.
+pub fn usart_is_tx_idle(usart: u32) -> bool {
+    let sr_addr = (select_usart_base(usart) + USART_SR) as *mut u32;
+    TX_BUFFERS[usart_index(usart)].is_empty() && unsafe { read_register(sr_addr) & SR_TC != 0 }
+}
+
+/// Function name: `usart_write_polling`
+///
+/// Description:
+/// Sends bytes by polling TXE, bypassing the interrupt machinery. Bytes still waiting in the TX ring
+/// buffer are sent first so the output order is preserved. Intended for contexts where interrupts may
+/// be disabled, such as panic and fault handlers.
+///
+/// Parameters:
+/// - `usart`: Instance number (1–6).
+/// - `data`: Bytes to send.
+///
+/// Return:
+/// - None
+pub fn usart_write_polling(usart: u32, data: &[u8]) {
+    let idx = usart_index(usart);
+    let base = select_usart_base(usart);
+    let sr_addr = (base + USART_SR) as *mut u32;
+    let dr_addr = (base + USART_DR) as *mut u32;
+    let cr1_addr = (base + USART_CR1) as *mut u32;
+
+    unsafe {
+        reg_write_bit(cr1_addr, CR1_TXEIE, false);
+
+        let pending = core::iter::from_fn(|| TX_BUFFERS[idx].pop());
+        for byte in pending.chain(data.iter().copied()) {
+            while read_register(sr_addr) & SR_TXE == 0 {}
+            write_register(dr_addr, byte as u32);
+        }
+        while read_register(sr_addr) & SR_TC == 0 {}
+    }
+}
+
+/// Function name: `usart_irq_handler`
+///
+/// Description:
+/// Services RXNE/error and TXE events of an instance: stores received bytes, latches error flags,
+/// feeds the transmitter from the TX buffer and runs the registered notifications.
+/// Called from the `USARTx_Handler`/`UARTx_Handler` vectors below.
+///
+/// Parameters:
+/// - `usart`: Instance number (1–6).
+///
+/// Return:
+/// - None
+pub fn usart_irq_handler(usart: u32) {
+    let idx = usart_index(usart);
+    let base = select_usart_base(usart);
+    let sr_addr = (base + USART_SR) as *mut u32;
+    let dr_addr = (base + USART_DR) as *mut u32;
+    let cr1_addr = (base + USART_CR1) as *mut u32;
+
+    unsafe {
+        let sr = read_register(sr_addr);
+
+        if sr & (SR_RXNE | SR_ORE | SR_FE | SR_NF | SR_PE) != 0 {
+            // Reading SR then DR clears RXNE and the error flags
+            let byte = read_register(dr_addr) as u8;
+
+            let mut errors = 0;
+            if sr & SR_ORE != 0 {
+                errors |= ERR_OVERRUN;
+            }
+            if sr & SR_FE != 0 {
+                errors |= ERR_FRAMING;
+            }
+            if sr & SR_NF != 0 {
+                errors |= ERR_NOISE;
+            }
+            if sr & SR_PE != 0 {
+                errors |= ERR_PARITY;
+            }
+
+            let corrupted = sr & (SR_FE | SR_PE) != 0;
+            if sr & SR_RXNE != 0 && !corrupted && !RX_BUFFERS[idx].push(byte) {
+                errors |= ERR_OVERRUN;
+            }
+            if errors != 0 {
+                ERRORS[idx].fetch_or(errors, Ordering::AcqRel);
+            }
+
+            if let Some(notify) = RX_NOTIFY[idx] {
+                notify(usart);
+            }
+        }
+
+        let cr1 = read_register(cr1_addr);
+        if sr & SR_TXE != 0 && cr1 & (1 << CR1_TXEIE) != 0 {
+            match TX_BUFFERS[idx].pop() {
+                Some(byte) => write_register(dr_addr, byte as u32),
+                None => reg_write_bit(cr1_addr, CR1_TXEIE, false),
+            }
+
+            if let Some(notify) = TX_NOTIFY[idx] {
+                notify(usart);
+            }
+        }
+    }
+}
+
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn USART1_Handler() {
+    usart_irq_handler(USART1);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn USART2_Handler() {
+    usart_irq_handler(USART2);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn USART3_Handler() {
+    usart_irq_handler(USART3);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn UART4_Handler() {
+    usart_irq_handler(UART4);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn UART5_Handler() {
+    usart_irq_handler(UART5);
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn USART6_Handler() {
+    usart_irq_handler(USART6);
+}
@@ -0,0 +1,206 @@
+#![allow(dead_code)]
+
+/// # STM32F407 Interrupt Vector Table
+///
+/// This module provides the device-specific part of the vector table (IRQ 0 to IRQ 81) that
+/// `cortex-m-rt` places right after the core exceptions when its `device` feature is enabled.
+///
+/// ## Handler Naming
+///
+/// Every IRQ jumps to an `extern "C"` function named `<IRQ>_Handler` (e.g. `CAN1_RX0_Handler`,
+/// `USART2_Handler`). `device.x` provides a weak alias to `DefaultHandler` for each of them, so an
+/// application or driver only has to define the handlers it actually uses:
+///
+/// ```ignore
+/// #[allow(non_snake_case)]
+/// #[unsafe(no_mangle)]
+/// extern "C" fn CAN1_RX0_Handler() {
+///     can_receive();
+/// }
+/// ```
+///
+/// The IRQ number of each handler is its index in `__INTERRUPTS` and matches the `*_IRQ`
+/// constants in `stm32f407_registers`.
+///
+/// ## `Vector`
+///
+/// Entry of the interrupt vector table: either a handler or a reserved slot.
+pub union Vector {
+    handler: unsafe extern "C" fn(),
+    reserved: usize,
+}
+
+unsafe extern "C" {
+    fn WWDG_Handler();
+    fn PVD_Handler();
+    fn TAMP_STAMP_Handler();
+    fn RTC_WKUP_Handler();
+    fn FLASH_Handler();
+    fn RCC_Handler();
+    fn EXTI0_Handler();
+    fn EXTI1_Handler();
+    fn EXTI2_Handler();
+    fn EXTI3_Handler();
+    fn EXTI4_Handler();
+    fn DMA1_Stream0_Handler();
+    fn DMA1_Stream1_Handler();
+    fn DMA1_Stream2_Handler();
+    fn DMA1_Stream3_Handler();
+    fn DMA1_Stream4_Handler();
+    fn DMA1_Stream5_Handler();
+    fn DMA1_Stream6_Handler();
+    fn ADC_Handler();
+    fn CAN1_TX_Handler();
+    fn CAN1_RX0_Handler();
+    fn CAN1_RX1_Handler();
+    fn CAN1_SCE_Handler();
+    fn EXTI9_5_Handler();
+    fn TIM1_BRK_TIM9_Handler();
+    fn TIM1_UP_TIM10_Handler();
+    fn TIM1_TRG_COM_TIM11_Handler();
+    fn TIM1_CC_Handler();
+    fn TIM2_Handler();
+    fn TIM3_Handler();
+    fn TIM4_Handler();
+    fn I2C1_EV_Handler();
+    fn I2C1_ER_Handler();
+    fn I2C2_EV_Handler();
+    fn I2C2_ER_Handler();
+    fn SPI1_Handler();
+    fn SPI2_Handler();
+    fn USART1_Handler();
+    fn USART2_Handler();
+    fn USART3_Handler();
+    fn EXTI15_10_Handler();
+    fn RTC_Alarm_Handler();
+    fn OTG_FS_WKUP_Handler();
+    fn TIM8_BRK_TIM12_Handler();
+    fn TIM8_UP_TIM13_Handler();
+    fn TIM8_TRG_COM_TIM14_Handler();
+    fn TIM8_CC_Handler();
+    fn DMA1_Stream7_Handler();
+    fn FSMC_Handler();
+    fn SDIO_Handler();
+    fn TIM5_Handler();
+    fn SPI3_Handler();
+    fn UART4_Handler();
+    fn UART5_Handler();
+    fn TIM6_DAC_Handler();
+    fn TIM7_Handler();
+    fn DMA2_Stream0_Handler();
+    fn DMA2_Stream1_Handler();
+    fn DMA2_Stream2_Handler();
+    fn DMA2_Stream3_Handler();
+    fn DMA2_Stream4_Handler();
+    fn ETH_Handler();
+    fn ETH_WKUP_Handler();
+    fn CAN2_TX_Handler();
+    fn CAN2_RX0_Handler();
+    fn CAN2_RX1_Handler();
+    fn CAN2_SCE_Handler();
+    fn OTG_FS_Handler();
+    fn DMA2_Stream5_Handler();
+    fn DMA2_Stream6_Handler();
+    fn DMA2_Stream7_Handler();
+    fn USART6_Handler();
+    fn I2C3_EV_Handler();
+    fn I2C3_ER_Handler();
+    fn OTG_HS_EP1_OUT_Handler();
+    fn OTG_HS_EP1_IN_Handler();
+    fn OTG_HS_WKUP_Handler();
+    fn OTG_HS_Handler();
+    fn DCMI_Handler();
+    fn CRYP_Handler();
+    fn HASH_RNG_Handler();
+    fn FPU_Handler();
+}
+
+/// Number of device interrupts on the STM32F407.
+pub const NUM_IRQS: usize = 82;
+
+#[unsafe(link_section = ".vector_table.interrupts")]
+#[unsafe(no_mangle)]
+pub static __INTERRUPTS: [Vector; NUM_IRQS] = [
+    Vector { handler: WWDG_Handler }, // 0
+    Vector { handler: PVD_Handler }, // 1
+    Vector { handler: TAMP_STAMP_Handler }, // 2
+    Vector { handler: RTC_WKUP_Handler }, // 3
+    Vector { handler: FLASH_Handler }, // 4
+    Vector { handler: RCC_Handler }, // 5
+    Vector { handler: EXTI0_Handler }, // 6
+    Vector { handler: EXTI1_Handler }, // 7
+    Vector { handler: EXTI2_Handler }, // 8
+    Vector { handler: EXTI3_Handler }, // 9
+    Vector { handler: EXTI4_Handler }, // 10
+    Vector { handler: DMA1_Stream0_Handler }, // 11
+    Vector { handler: DMA1_Stream1_Handler }, // 12
+    Vector { handler: DMA1_Stream2_Handler }, // 13
+    Vector { handler: DMA1_Stream3_Handler }, // 14
+    Vector { handler: DMA1_Stream4_Handler }, // 15
+    Vector { handler: DMA1_Stream5_Handler }, // 16
+    Vector { handler: DMA1_Stream6_Handler }, // 17
+    Vector { handler: ADC_Handler }, // 18
+    Vector { handler: CAN1_TX_Handler }, // 19
+    Vector { handler: CAN1_RX0_Handler }, // 20
+    Vector { handler: CAN1_RX1_Handler }, // 21
+    Vector { handler: CAN1_SCE_Handler }, // 22
+    Vector { handler: EXTI9_5_Handler }, // 23
+    Vector { handler: TIM1_BRK_TIM9_Handler }, // 24
+    Vector { handler: TIM1_UP_TIM10_Handler }, // 25
+    Vector { handler: TIM1_TRG_COM_TIM11_Handler }, // 26
+    Vector { handler: TIM1_CC_Handler }, // 27
+    Vector { handler: TIM2_Handler }, // 28
+    Vector { handler: TIM3_Handler }, // 29
+    Vector { handler: TIM4_Handler }, // 30
+    Vector { handler: I2C1_EV_Handler }, // 31
+    Vector { handler: I2C1_ER_Handler }, // 32
+    Vector { handler: I2C2_EV_Handler }, // 33
+    Vector { handler: I2C2_ER_Handler }, // 34
+    Vector { handler: SPI1_Handler }, // 35
+    Vector { handler: SPI2_Handler }, // 36
+    Vector { handler: USART1_Handler }, // 37
+    Vector { handler: USART2_Handler }, // 38
+    Vector { handler: USART3_Handler }, // 39
+    Vector { handler: EXTI15_10_Handler }, // 40
+    Vector { handler: RTC_Alarm_Handler }, // 41
+    Vector { handler: OTG_FS_WKUP_Handler }, // 42
+    Vector { handler: TIM8_BRK_TIM12_Handler }, // 43
+    Vector { handler: TIM8_UP_TIM13_Handler }, // 44
+    Vector { handler: TIM8_TRG_COM_TIM14_Handler }, // 45
+    Vector { handler: TIM8_CC_Handler }, // 46
+    Vector { handler: DMA1_Stream7_Handler }, // 47
+    Vector { handler: FSMC_Handler }, // 48
+    Vector { handler: SDIO_Handler }, // 49
+    Vector { handler: TIM5_Handler }, // 50
+    Vector { handler: SPI3_Handler }, // 51
+    Vector { handler: UART4_Handler }, // 52
+    Vector { handler: UART5_Handler }, // 53
+    Vector { handler: TIM6_DAC_Handler }, // 54
+    Vector { handler: TIM7_Handler }, // 55
+    Vector { handler: DMA2_Stream0_Handler }, // 56
+    Vector { handler: DMA2_Stream1_Handler }, // 57
+    Vector { handler: DMA2_Stream2_Handler }, // 58
+    Vector { handler: DMA2_Stream3_Handler }, // 59
+    Vector { handler: DMA2_Stream4_Handler }, // 60
+    Vector { handler: ETH_Handler }, // 61
+    Vector { handler: ETH_WKUP_Handler }, // 62
+    Vector { handler: CAN2_TX_Handler }, // 63
+    Vector { handler: CAN2_RX0_Handler }, // 64
+    Vector { handler: CAN2_RX1_Handler }, // 65
+    Vector { handler: CAN2_SCE_Handler }, // 66
+    Vector { handler: OTG_FS_Handler }, // 67
+    Vector { handler: DMA2_Stream5_Handler }, // 68
+    Vector { handler: DMA2_Stream6_Handler }, // 69
+    Vector { handler: DMA2_Stream7_Handler }, // 70
+    Vector { handler: USART6_Handler }, // 71
+    Vector { handler: I2C3_EV_Handler }, // 72
+    Vector { handler: I2C3_ER_Handler }, // 73
+    Vector { handler: OTG_HS_EP1_OUT_Handler }, // 74
+    Vector { handler: OTG_HS_EP1_IN_Handler }, // 75
+    Vector { handler: OTG_HS_WKUP_Handler }, // 76
+    Vector { handler: OTG_HS_Handler }, // 77
+    Vector { handler: DCMI_Handler }, // 78
+    Vector { handler: CRYP_Handler }, // 79
+    Vector { handler: HASH_RNG_Handler }, // 80
+    Vector { handler: FPU_Handler }, // 81
+];
@@ -0,0 +1,249 @@
+#![allow(dead_code)]
+
+/// # Window Watchdog (WWDG) Driver Module
+///
+/// This module drives the window watchdog of the STM32F407: a 7-bit down-counter clocked from
+/// PCLK1 / 4096 / 2^WDGTB that resets the MCU when it rolls over from 0x40 to 0x3F, and also when
+/// it is refreshed too early, while still above the window value. It catches code that runs too
+/// fast (skipped work, a tight loop calling the refresh) as well as code that hangs.
+///
+/// ## Timing
+///
+/// One counter tick lasts 4096 × 2^WDGTB / PCLK1 (97.5 µs – 780 µs at PCLK1 = 42 MHz) and the
+/// counter spans at most 64 ticks, so timeouts range from ~0.1 ms to ~50 ms: the window watchdog
+/// suits a periodic, tightly timed refresh (e.g. from a control loop), not a supervisor that must
+/// survive flash erases (see `iwdg`).
+///
+/// `wwdg_start` takes the timeout and the window as durations after a refresh:
+///
+/// ```text
+/// refresh         window_us                   timeout_us
+///    |---- refresh resets ----|---- refresh allowed ----|  reset
+/// ```
+///
+/// ## Early Warning
+///
+/// `wwdg_set_early_warning` enables the early wakeup interrupt, raised one tick before the reset
+/// (counter at 0x40). Its callback runs in interrupt context and may save state or refresh the
+/// watchdog as a last resort. The interrupt can only be disabled again by a reset.
+///
+/// ## Lifetime
+///
+/// Once started, the watchdog can only be stopped by a reset. `wwdg_freeze_on_debug` stops the
+/// counter while a debugger halts the core.
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::stm32f407_registers::*;
+use crate::read_write::{read_register, write_register, reg_write_bit};
+use crate::cortex_m4::enable_irq;
+use crate::rcc::clocks;
+
+const RCC_APB1ENR_WWDGEN: u32 = 11;
+
+/// Counter value at which the next tick resets (T6 clears).
+const COUNTER_MIN: u32 = 0x40;
+const COUNTER_MAX: u32 = 0x7F;
+
+// Register offsets
+const WWDG_CR: u32 = 0x00;
+const WWDG_CFR: u32 = 0x04;
+const WWDG_SR: u32 = 0x08;
+
+// CR bits
+const CR_T_MASK: u32 = 0x7F;
+const CR_WDGA: u32 = 1 << 7;
+
+// CFR fields
+const CFR_W_MASK: u32 = 0x7F;
+const CFR_WDGTB: u32 = 7;
+const CFR_EWI: u32 = 9;
+
+// SR bits
+const SR_EWIF: u32 = 1 << 0;
+
+// DBGMCU_APB1_FZ bit
+const DBG_WWDG_STOP: u32 = 11;
+
+/// Callback invoked from interrupt context when the counter reaches 0x40.
+pub type WwdgCallback = fn();
+
+static mut EARLY_WARNING_CALLBACK: Option<WwdgCallback> = None;
+
+/// Counter value written by `wwdg_refresh` (the hardware does not keep it).
+static RELOAD: AtomicU32 = AtomicU32::new(COUNTER_MAX);
+
+/// Counter settings for a timeout and a window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WwdgConfig {
+    /// Timer base WDGTB (0 – 3): one tick is 4096 × 2^prescaler PCLK1 cycles.
+    pub prescaler: u32,
+    /// Counter value after a refresh (0x40 – 0x7F).
+    pub counter: u32,
+    /// Refreshing while the counter is above this value resets (0x7F: no window).
+    pub window: u32,
+}
+
+impl WwdgConfig {
+    /// Length of one counter tick in nanoseconds at `pclk1_hz`.
+    pub const fn tick_ns(&self, pclk1_hz: u32) -> u64 {
+        (4096u64 << self.prescaler) * 1_000_000_000 / pclk1_hz as u64
+    }
+
+    /// Time from a refresh to the reset, in microseconds.
+    pub const fn timeout_us(&self, pclk1_hz: u32) -> u32 {
+        ((self.counter - (COUNTER_MIN - 1)) as u64 * self.tick_ns(pclk1_hz) / 1000) as u32
+    }
+
+    /// Time from a refresh until the next refresh is allowed, in microseconds.
+    pub const fn window_us(&self, pclk1_hz: u32) -> u32 {
+        let closed = self.counter.saturating_sub(self.window);
+        (closed as u64 * self.tick_ns(pclk1_hz) / 1000) as u32
+    }
+}
+
+fn reg(offset: u32) -> *mut u32 {
+    (WWDG_BASE + offset) as *mut u32
+}
+
+/// Function name: `wwdg_compute`
+///
+/// Description:
+/// Finds the smallest timer base (finest resolution) reaching `timeout_us` at `pclk1_hz`, then
+/// the window value closing refreshes for at least `window_us` after each refresh.
+///
+/// Parameters:
+/// - `pclk1_hz`: APB1 clock.
+/// - `timeout_us`: Time from a refresh to the reset.
+/// - `window_us`: Time after a refresh during which a refresh resets (0: no window). Must be
+///   shorter than the timeout.
+///
+/// Return:
+/// - The configuration, or `None` if the timeout or the window is out of range.
+pub fn wwdg_compute(pclk1_hz: u32, timeout_us: u32, window_us: u32) -> Option<WwdgConfig> {
+    if timeout_us == 0 {
+        return None;
+    }
+    let span = COUNTER_MAX - (COUNTER_MIN - 1);
+    (0..4).find_map(|prescaler| {
+        let tick_ns = (4096u64 << prescaler) * 1_000_000_000 / pclk1_hz as u64;
+        let ticks = (timeout_us as u64 * 1000).div_ceil(tick_ns) as u32;
+        if ticks > span {
+            return None;
+        }
+        let counter = COUNTER_MIN - 1 + ticks;
+        let closed = (window_us as u64 * 1000).div_ceil(tick_ns) as u32;
+        let window = if closed == 0 { COUNTER_MAX } else { counter.checked_sub(closed)? };
+        (window >= COUNTER_MIN).then_some(WwdgConfig { prescaler, counter, window })
+    })
+}
+
+/// Function name: `wwdg_start`
+///
+/// Description:
+/// Enables the WWDG clock and starts the watchdog with the counter loaded. Panics if the timeout
+/// or the window cannot be reached at the current PCLK1 (`rcc::clocks`).
+///
+/// Parameters:
+/// - `timeout_us`: Time from a refresh to the reset.
+/// - `window_us`: Time after a refresh during which a refresh resets (0: no window).
+///
+/// Return:
+/// - The configuration applied.
+pub fn wwdg_start(timeout_us: u32, window_us: u32) -> WwdgConfig {
+    let pclk1 = clocks().pclk1();
+    let config = wwdg_compute(pclk1, timeout_us, window_us).unwrap_or_else(|| {
+        panic!("Invalid WWDG timing: timeout {} us, window {} us at PCLK1 {} Hz", timeout_us, window_us, pclk1)
+    });
+    unsafe {
+        reg_write_bit(RCC_APB1ENR as *mut u32, RCC_APB1ENR_WWDGEN, true);
+        let ewi = read_register(reg(WWDG_CFR)) & (1 << CFR_EWI);
+        write_register(reg(WWDG_CFR), ewi | config.prescaler << CFR_WDGTB | config.window);
+        RELOAD.store(config.counter, Ordering::Relaxed);
+        write_register(reg(WWDG_CR), CR_WDGA | config.counter);
+    }
+    config
+}
+
+/// Function name: `wwdg_refresh`
+///
+/// Description:
+/// Reloads the counter with the value given to `wwdg_start`. Resets the MCU if called before
+/// the window opens.
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - None
+pub fn wwdg_refresh() {
+    unsafe {
+        write_register(reg(WWDG_CR), CR_WDGA | RELOAD.load(Ordering::Relaxed));
+    }
+}
+
+/// Function name: `wwdg_counter`
+///
+/// Description:
+/// Reads the current counter value (0x40 – 0x7F while running).
+///
+/// Parameters:
+/// - None
+///
+/// Return:
+/// - Counter value.
+pub fn wwdg_counter() -> u32 {
+    unsafe { read_register(reg(WWDG_CR)) & CR_T_MASK }
+}
+
+/// Function name: `wwdg_set_early_warning`
+///
+/// Description:
+/// Registers the early warning callback and enables the early wakeup interrupt, raised when the
+/// counter reaches 0x40. `None` only removes the callback: the interrupt stays enabled until the
+/// next reset. Call before or after `wwdg_start`.
+///
+/// Parameters:
+/// - `callback`: Function called from interrupt context, or `None`.
+///
+/// Return:
+/// - None
+pub fn wwdg_set_early_warning(callback: Option<WwdgCallback>) {
+    unsafe {
+        EARLY_WARNING_CALLBACK = callback;
+        if callback.is_some() {
+            reg_write_bit(RCC_APB1ENR as *mut u32, RCC_APB1ENR_WWDGEN, true);
+            write_register(reg(WWDG_SR), 0);
+            reg_write_bit(reg(WWDG_CFR), CFR_EWI, true);
+        }
+    }
+    if callback.is_some() {
+        enable_irq(WWDG_IRQ);
+    }
+}
+
+/// Function name: `wwdg_freeze_on_debug`
+///
+/// Description:
+/// Selects whether the counter stops while the core is halted by a debugger (DBG_WWDG_STOP).
+///
+/// Parameters:
+/// - `freeze`: `true` to stop the counter on a debug halt.
+///
+/// Return:
+/// - None
+pub fn wwdg_freeze_on_debug(freeze: bool) {
+    unsafe {
+        reg_write_bit(DBGMCU_APB1_FZ as *mut u32, DBG_WWDG_STOP, freeze);
+    }
+}
+
+#[allow(non_snake_case)]
+#[unsafe(no_mangle)]
+extern "C" fn WWDG_Handler() {
+    unsafe {
+        // EWIF is cleared by writing 0
+        write_register(reg(WWDG_SR), read_register(reg(WWDG_SR)) & !SR_EWIF);
+        if let Some(callback) = EARLY_WARNING_CALLBACK {
+            callback();
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Firmware slots and test boot, as seen from the running image.
+//!
+//! The bootloader starts a newly installed image in test mode (see `boot-format`): the image must
+//! call `confirm` once it knows it works, otherwise the bootloader rolls back to the previous image
+//! at the next reset. `request_test_boot` arms the test boot of an image stored in the other slot
+//! with `erase_slot` and `write_slot`, e.g. by a firmware update (`update`).
+
+use boot_format::{
+    check_slot, scan_state, BootRecord, BootState, ImageError, ImageHeader, Slot, BOOT_STATE_ADDRESS,
+    BOOT_STATE_SECTOR, BOOT_STATE_SIZE, SLOT_SECTORS, SLOT_SIZE,
+};
+use drivers::cortex_m4::get_vector_table;
+use drivers::flash::{
+    flash_erase_sector, flash_lock, flash_program, flash_program_words, flash_unlock, FlashError,
+};
+
+/// Why a boot state change was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootError {
+    /// The running image was not started from a slot (no bootloader).
+    NotInSlot,
+    /// The target slot is the running one.
+    RunningSlot,
+    /// The target slot does not hold a valid image.
+    Image(ImageError),
+    Flash(FlashError),
+}
+
+/// Slot of the running image, from the vector table the bootloader set up.
+pub fn running_slot() -> Option<Slot> {
+    Slot::containing(get_vector_table())
+}
+
+fn state_log() -> &'static [u32] {
+    unsafe { core::slice::from_raw_parts(BOOT_STATE_ADDRESS as *const u32, BOOT_STATE_SIZE as usize / 4) }
+}
+
+/// Current boot state, `None` if none is recorded.
+pub fn state() -> Option<BootRecord> {
+    scan_state(state_log()).0
+}
+
+/// Whether the running image is on a test boot and must `confirm`.
+pub fn is_test_boot() -> bool {
+    matches!(state(), Some(BootRecord { state: BootState::Testing, slot, .. }) if Some(slot) == running_slot())
+}
+
+fn slot_contents(slot: Slot) -> &'static [u8] {
+    unsafe { core::slice::from_raw_parts(slot.address() as *const u8, SLOT_SIZE as usize) }
+}
+
+/// Header of the image in `slot`, after checking the whole image (CRC-32 and SHA-256).
+pub fn image(slot: Slot) -> Result<ImageHeader, ImageError> {
+    check_slot(slot, slot_contents(slot))
+}
+
+/// Header of the image in `slot`, without checking the image itself.
+pub fn header(slot: Slot) -> Option<ImageHeader> {
+    ImageHeader::parse(slot_contents(slot)).ok()
+}
+
+/// Erase `slot`, which must not be the running one. Stalls the CPU for 2–8 s.
+pub fn erase_slot(slot: Slot) -> Result<(), BootError> {
+    if Some(slot) == running_slot() {
+        return Err(BootError::RunningSlot);
+    }
+    flash_unlock();
+    let first = slot.first_sector();
+    let result = (first..first + SLOT_SECTORS).try_for_each(flash_erase_sector);
+    flash_lock();
+    result.map_err(BootError::Flash)
+}
+
+/// Program `data` at `offset` of `slot`, which must not be the running one.
+pub fn write_slot(slot: Slot, offset: u32, data: &[u8]) -> Result<(), BootError> {
+    if Some(slot) == running_slot() {
+        return Err(BootError::RunningSlot);
+    }
+    assert!(offset as usize + data.len() <= SLOT_SIZE as usize, "write beyond slot {}", slot);
+    flash_unlock();
+    let result = flash_program(slot.address() + offset, data);
+    flash_lock();
+    result.map_err(BootError::Flash)
+}
+
+/// Append `record` to the boot state log, erasing the log first when it is full.
+fn record_state(record: BootRecord) -> Result<(), BootError> {
+    let (_, next) = scan_state(state_log());
+    flash_unlock();
+    let result = match next {
+        Some(index) => Ok(index),
+        None => flash_erase_sector(BOOT_STATE_SECTOR).map(|_| 0),
+    }
+    .and_then(|index| flash_program_words(BOOT_STATE_ADDRESS + 4 * index as u32, &record.encode()));
+    flash_lock();
+    result.map_err(BootError::Flash)
+}
+
+/// Make the running image the one to boot. Does nothing unless it is on a test boot.
+pub fn confirm() -> Result<(), BootError> {
+    let running = running_slot().ok_or(BootError::NotInSlot)?;
+    match state() {
+        Some(BootRecord { state: BootState::Testing, slot, previous, .. }) if slot == running => {
+            record_state(BootRecord { state: BootState::Confirmed, slot, previous, flags: 0 })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Test-boot the image in `slot` at the next reset. The running image stays the fallback until
+/// the new one confirms.
+pub fn request_test_boot(slot: Slot) -> Result<(), BootError> {
+    let running = running_slot().ok_or(BootError::NotInSlot)?;
+    if slot == running {
+        return Err(BootError::RunningSlot);
+    }
+    image(slot).map_err(BootError::Image)?;
+    record_state(BootRecord { state: BootState::Pending, slot, previous: running, flags: 0 })
+}
@@ -0,0 +1,71 @@
+//! Sharing buses between tasks.
+//!
+//! A bus driver (e.g. `drivers::spi::Spi`) is placed in a kernel `Mutex`; each device on the bus gets
+//! its own handle that locks the mutex for the duration of a transaction, so device drivers written
+//! against `embedded_hal` traits can run in different tasks without corrupting each other's transfers.
+
+use embedded_hal::spi::{ErrorType, Operation, SpiBus, SpiDevice};
+use drivers::gpio::{
+    gpio_configure_mode, gpio_output_type_configure, gpio_write, GPIO_MODE_GP_OUTPUT, GPIO_MODE_INPUT,
+    GPIO_OUTPUT_PUSH_PULL,
+};
+use drivers::spi::{Spi, SpiError};
+use drivers::rcc::clocks;
+use crate::sync::Mutex;
+
+/// Busy-wait for at least `ns` nanoseconds.
+fn delay_ns(ns: u32) {
+    let cycles = (ns as u64 * clocks().hclk_mhz() as u64).div_ceil(1000);
+    cortex_m::asm::delay(cycles.min(u32::MAX as u64) as u32);
+}
+
+/// One device on a shared SPI bus, selected by an active-low chip-select GPIO.
+pub struct SharedSpiDevice<'a> {
+    bus: &'a Mutex<Spi>,
+    cs_port: u32,
+    cs_pin: u32,
+}
+
+impl<'a> SharedSpiDevice<'a> {
+    /// Configure `cs_port`/`cs_pin` as a push-pull output, deselected (high).
+    pub fn new(bus: &'a Mutex<Spi>, cs_port: u32, cs_pin: u32) -> Self {
+        // Configuring the pin turns the port clock on; ODR is only written once it runs, and
+        // before the pin drives it, so the device is never selected by accident.
+        gpio_configure_mode(cs_port, cs_pin, GPIO_MODE_INPUT);
+        gpio_write(cs_port, cs_pin, true);
+        gpio_output_type_configure(cs_port, cs_pin, GPIO_OUTPUT_PUSH_PULL);
+        gpio_configure_mode(cs_port, cs_pin, GPIO_MODE_GP_OUTPUT);
+        SharedSpiDevice { bus, cs_port, cs_pin }
+    }
+}
+
+impl ErrorType for SharedSpiDevice<'_> {
+    type Error = SpiError;
+}
+
+impl<W: Copy + 'static> SpiDevice<W> for SharedSpiDevice<'_>
+where
+    Spi: SpiBus<W>,
+{
+    fn transaction(&mut self, operations: &mut [Operation<'_, W>]) -> Result<(), SpiError> {
+        let mut bus = self.bus.lock();
+        gpio_write(self.cs_port, self.cs_pin, false);
+
+        let result = operations.iter_mut().try_for_each(|op| match op {
+            Operation::Read(words) => bus.read(words),
+            Operation::Write(words) => bus.write(words),
+            Operation::Transfer(read, write) => bus.transfer(read, write),
+            Operation::TransferInPlace(words) => bus.transfer_in_place(words),
+            Operation::DelayNs(ns) => {
+                bus.flush()?;
+                delay_ns(*ns);
+                Ok(())
+            }
+        });
+
+        // Always deselect, even after an error.
+        let flushed = bus.flush();
+        gpio_write(self.cs_port, self.cs_pin, true);
+        result.and(flushed)
+    }
+}
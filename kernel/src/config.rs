@@ -0,0 +1,134 @@
+//! Persistent configuration.
+//!
+//! Small named values (calibration data, user settings...) kept in a `kvstore` store on the two
+//! internal flash sectors `CONFIG_SECTORS`. Values are byte strings; `get_u32`/`set_u32` store
+//! integers little-endian. Writes go through a mutex and may stall the caller for a sector erase
+//! (1–2 s) when the active sector is full, so keep them out of time-critical tasks.
+//!
+//! `init` must be called once before the other functions, which fail with `ConfigError::NotMounted`
+//! until then.
+
+use core::ptr::read_volatile;
+use drivers::flash::{
+    flash_erase_sector, flash_lock, flash_program, flash_sector_address, flash_sector_size, flash_unlock,
+    FlashError,
+};
+use kvstore::{Flash, Store};
+use crate::os_config::CONFIG_SECTORS;
+use crate::sync::Mutex;
+
+pub use kvstore::{MAX_KEY_LEN, MAX_VALUE_LEN};
+
+/// Why a configuration access failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// `init` has not been called.
+    NotMounted,
+    Store(kvstore::Error<FlashError>),
+}
+
+impl From<kvstore::Error<FlashError>> for ConfigError {
+    fn from(err: kvstore::Error<FlashError>) -> Self {
+        ConfigError::Store(err)
+    }
+}
+
+/// The configuration sectors of internal flash.
+pub struct InternalFlash;
+
+impl InternalFlash {
+    fn address(sector: usize, offset: u32) -> u32 {
+        flash_sector_address(CONFIG_SECTORS[sector]) + offset
+    }
+}
+
+impl Flash for InternalFlash {
+    type Error = FlashError;
+
+    fn sector_size(&self) -> u32 {
+        flash_sector_size(CONFIG_SECTORS[0])
+    }
+
+    fn read(&self, sector: usize, offset: u32, buf: &mut [u8]) {
+        let address = Self::address(sector, offset);
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { read_volatile((address + i as u32) as *const u8) };
+        }
+    }
+
+    fn write(&mut self, sector: usize, offset: u32, data: &[u8]) -> Result<(), FlashError> {
+        flash_unlock();
+        let result = flash_program(Self::address(sector, offset), data);
+        flash_lock();
+        result
+    }
+
+    fn erase(&mut self, sector: usize) -> Result<(), FlashError> {
+        flash_unlock();
+        let result = flash_erase_sector(CONFIG_SECTORS[sector]);
+        flash_lock();
+        result
+    }
+}
+
+static STORE: Mutex<Option<Store<InternalFlash>>> = Mutex::new(None);
+
+/// Open the store, recovering from an interrupted write or formatting blank sectors.
+pub fn init() -> Result<(), ConfigError> {
+    let store = Store::mount(InternalFlash)?;
+    *STORE.lock() = Some(store);
+    Ok(())
+}
+
+fn with_store<T>(f: impl FnOnce(&mut Store<InternalFlash>) -> Result<T, kvstore::Error<FlashError>>) -> Result<T, ConfigError> {
+    let mut store = STORE.lock();
+    let store = store.as_mut().ok_or(ConfigError::NotMounted)?;
+    Ok(f(store)?)
+}
+
+/// Copy the value of `key` into `buf`. Returns its length, or `None` if `key` is not set.
+pub fn get(key: &str, buf: &mut [u8]) -> Result<Option<usize>, ConfigError> {
+    with_store(|store| store.get(key, buf))
+}
+
+/// Set `key` to `value`.
+pub fn set(key: &str, value: &[u8]) -> Result<(), ConfigError> {
+    with_store(|store| store.set(key, value))
+}
+
+/// Remove `key`. Returns whether it was set.
+pub fn delete(key: &str) -> Result<bool, ConfigError> {
+    with_store(|store| store.delete(key))
+}
+
+/// Integer value of `key`, or `None` if it is not set or not 4 bytes long.
+pub fn get_u32(key: &str) -> Result<Option<u32>, ConfigError> {
+    let mut bytes = [0u8; 4];
+    Ok(match get(key, &mut bytes) {
+        Ok(Some(4)) => Some(u32::from_le_bytes(bytes)),
+        Ok(_) | Err(ConfigError::Store(kvstore::Error::BufferTooSmall)) => None,
+        Err(err) => return Err(err),
+    })
+}
+
+pub fn set_u32(key: &str, value: u32) -> Result<(), ConfigError> {
+    set(key, &value.to_le_bytes())
+}
+
+/// Call `f` with every key and the length of its value.
+pub fn keys(f: impl FnMut(&str, usize)) -> Result<(), ConfigError> {
+    with_store(|store| {
+        store.keys(f);
+        Ok(())
+    })
+}
+
+/// Bytes used in the active sector (including superseded values) and sector size.
+pub fn usage() -> Result<(u32, u32), ConfigError> {
+    with_store(|store| Ok((store.used(), store.capacity())))
+}
+
+/// Delete every key.
+pub fn erase() -> Result<(), ConfigError> {
+    with_store(|store| store.format())
+}
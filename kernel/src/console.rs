@@ -0,0 +1,289 @@
+//! Kernel console and logger.
+//!
+//! The console is a `Serial` port protected by a kernel `Mutex`, so output from different tasks is
+//! never interleaved mid-line. It backs:
+//! - the `kprint!` / `kprintln!` macros,
+//! - a `log` crate backend (`log::info!`, `log::warn!`, ...) with a global level, per-module level
+//!   overrides and a `[tick]` timestamp taken from the kernel tick counter,
+//! - the panic and fault handlers, through `panic_print` / `emergency_print`, which bypass the
+//!   mutex and poll the UART.
+//!
+//! ISRs cannot sleep on the mutex. Output produced in interrupt context, or while deferred mode is
+//! enabled, is formatted into a lock-free buffer instead and written out later by `flush_deferred`
+//! (called automatically by the next task-context print, or periodically by a task).
+
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use crate::critical;
+use drivers::cortex_m4::{disable_global_interrupt, is_in_interrupt};
+use drivers::ring_buffer::RingBuffer;
+use drivers::usart::usart_write_polling;
+use log::{LevelFilter, Log, Metadata, Record};
+use crate::os::get_tick_count;
+use crate::serial::{Serial, SerialWriter};
+use crate::sync::Mutex;
+
+/// Size of the buffer holding deferred output (power of two).
+pub const DEFERRED_BUFFER_SIZE: usize = 1024;
+
+/// Maximum number of per-module level overrides.
+pub const MAX_MODULE_FILTERS: usize = 8;
+
+static CONSOLE: Mutex<Option<Serial>> = Mutex::new(None);
+static CONSOLE_USART: AtomicU32 = AtomicU32::new(0);
+
+static DEFERRED: RingBuffer<DEFERRED_BUFFER_SIZE> = RingBuffer::new();
+static DEFERRED_MODE: AtomicBool = AtomicBool::new(false);
+static DEFERRED_DROPPED: AtomicU32 = AtomicU32::new(0);
+
+static MODULE_FILTERS: Mutex<[Option<(&'static str, LevelFilter)>; MAX_MODULE_FILTERS]> =
+    Mutex::new([None; MAX_MODULE_FILTERS]);
+static DEFAULT_LEVEL: AtomicU32 = AtomicU32::new(LevelFilter::Info as u32);
+
+/// Appends to the deferred buffer, counting bytes that do not fit.
+struct DeferredWriter;
+
+impl Write for DeferredWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if byte == b'\n' && !DEFERRED.push(b'\r') {
+                DEFERRED_DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+            if !DEFERRED.push(byte) {
+                DEFERRED_DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes straight to the UART data register, for panic/fault context.
+struct PollingWriter(u32);
+
+impl Write for PollingWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                usart_write_polling(self.0, b"\r\n");
+            }
+            usart_write_polling(self.0, line.as_bytes());
+        }
+        Ok(())
+    }
+}
+
+/// Use `serial` as the console. Output printed before this call is discarded.
+pub fn init(serial: Serial) {
+    CONSOLE_USART.store(serial.usart(), Ordering::Release);
+    *CONSOLE.lock() = Some(serial);
+}
+
+/// The serial port used as console, if `init` has been called.
+pub fn serial() -> Option<Serial> {
+    *CONSOLE.lock()
+}
+
+/// Route all output through the deferred buffer (`true`) or write directly from tasks (`false`).
+/// In deferred mode printing never blocks; a task must call `flush_deferred` regularly.
+pub fn set_deferred(enabled: bool) {
+    DEFERRED_MODE.store(enabled, Ordering::Release);
+}
+
+/// Number of bytes lost because the deferred buffer was full.
+pub fn deferred_dropped() -> u32 {
+    DEFERRED_DROPPED.load(Ordering::Relaxed)
+}
+
+/// Write buffered deferred output to the console. Must be called from a task.
+pub fn flush_deferred() {
+    if DEFERRED.is_empty() {
+        return;
+    }
+    if let Some(serial) = CONSOLE.lock().as_ref() {
+        write_deferred(serial);
+    }
+}
+
+fn write_deferred(serial: &Serial) {
+    let mut chunk = [0u8; 32];
+    loop {
+        let mut len = 0;
+        while len < chunk.len() {
+            match DEFERRED.pop() {
+                Some(byte) => {
+                    chunk[len] = byte;
+                    len += 1;
+                }
+                None => break,
+            }
+        }
+        if len == 0 {
+            break;
+        }
+        serial.write(&chunk[..len]);
+    }
+}
+
+fn write_deferred_fmt(args: fmt::Arguments) {
+    // Several ISRs (or an ISR and a task) may produce concurrently; the ring buffer only
+    // supports a single producer.
+    critical::free(|| {
+        let _ = DeferredWriter.write_fmt(args);
+    });
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    if is_in_interrupt() || DEFERRED_MODE.load(Ordering::Acquire) {
+        write_deferred_fmt(args);
+        return;
+    }
+
+    if let Some(serial) = CONSOLE.lock().as_ref() {
+        write_deferred(serial);
+        let _ = SerialWriter(serial).write_fmt(args);
+    }
+}
+
+/// Print to the kernel console.
+#[macro_export]
+macro_rules! kprint {
+    ($($arg:tt)*) => {
+        $crate::console::_print(format_args!($($arg)*))
+    };
+}
+
+/// Print to the kernel console, with a newline.
+#[macro_export]
+macro_rules! kprintln {
+    () => {
+        $crate::kprint!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::console::_print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// Print a panic report with interrupts disabled, polling the console UART.
+/// Safe to call even if a task panicked while holding the console mutex.
+pub fn panic_print(info: &PanicInfo) {
+    disable_global_interrupt();
+
+    let usart = CONSOLE_USART.load(Ordering::Acquire);
+    if usart == 0 {
+        return;
+    }
+
+    let mut out = PollingWriter(usart);
+    let _ = write!(out, "\n[{:>8}] PANIC", get_tick_count());
+    if let Some(location) = info.location() {
+        let _ = write!(out, " at {}:{}:{}", location.file(), location.line(), location.column());
+    }
+    let _ = writeln!(out, ": {}", info.message());
+}
+
+/// Print `args` by polling the console UART, bypassing the mutex and the deferred buffer.
+/// For fault handlers and other contexts where the kernel can no longer be trusted.
+pub fn emergency_print(args: fmt::Arguments) {
+    let usart = CONSOLE_USART.load(Ordering::Acquire);
+    if usart == 0 {
+        return;
+    }
+    let _ = PollingWriter(usart).write_fmt(args);
+}
+
+// ---------- log crate backend ----------
+
+struct KernelLogger;
+
+static LOGGER: KernelLogger = KernelLogger;
+
+fn level_from_u32(value: u32) -> LevelFilter {
+    match value {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Level that applies to `module`: the longest matching override, or the default level.
+fn level_for(module: &str) -> LevelFilter {
+    let default = level_from_u32(DEFAULT_LEVEL.load(Ordering::Relaxed));
+    if is_in_interrupt() {
+        return default;
+    }
+
+    let filters = match MODULE_FILTERS.try_lock() {
+        Some(filters) => filters,
+        None => return default,
+    };
+
+    let mut best: Option<(&str, LevelFilter)> = None;
+    for (prefix, level) in filters.iter().flatten() {
+        let matches = module == *prefix
+            || (module.starts_with(prefix) && module[prefix.len()..].starts_with("::"));
+        if matches && best.is_none_or(|(p, _)| prefix.len() > p.len()) {
+            best = Some((prefix, *level));
+        }
+    }
+    best.map_or(default, |(_, level)| level)
+}
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        _print(format_args!(
+            "[{:>8}] {:<5} {}: {}\n",
+            get_tick_count(),
+            record.level(),
+            record.module_path().unwrap_or(record.target()),
+            record.args()
+        ));
+    }
+
+    fn flush(&self) {
+        if !is_in_interrupt() {
+            flush_deferred();
+        }
+    }
+}
+
+/// Install the console as the `log` backend with `level` as the default level.
+pub fn init_logger(level: LevelFilter) {
+    DEFAULT_LEVEL.store(level as u32, Ordering::Relaxed);
+    // Let the per-module overrides decide; the `log` macros still skip anything above Trace.
+    log::set_max_level(LevelFilter::Trace);
+    let _ = log::set_logger(&LOGGER);
+}
+
+/// Change the default log level.
+pub fn set_log_level(level: LevelFilter) {
+    DEFAULT_LEVEL.store(level as u32, Ordering::Relaxed);
+}
+
+/// Override the log level for `module` and its submodules (e.g. `"app::shell"`).
+/// Returns `false` if the override table is full.
+pub fn set_module_level(module: &'static str, level: LevelFilter) -> bool {
+    let mut filters = MODULE_FILTERS.lock();
+    if let Some(entry) = filters.iter_mut().find(|f| matches!(f, Some((m, _)) if *m == module)) {
+        *entry = Some((module, level));
+        return true;
+    }
+    match filters.iter_mut().find(|f| f.is_none()) {
+        Some(slot) => {
+            *slot = Some((module, level));
+            true
+        }
+        None => false,
+    }
+}
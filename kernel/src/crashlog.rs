@@ -0,0 +1,171 @@
+//! Persistent crash log in internal flash.
+//!
+//! Once `init` has been called, every fault report is saved as a `crashlog_format` record in flash
+//! sector `CRASHLOG_SECTOR`, together with the last kernel trace events and the top of every task
+//! stack. Records are appended one after the other and survive resets and power loss until the
+//! log is erased, typically after retrieving them with the shell `crash` command or the
+//! `tools/crashdump` host tool. When the sector is full new crashes are only kept in the `.noinit`
+//! report of `fault` (erasing takes too long to be done from the fault handler).
+
+use core::ptr::read_volatile;
+use crashlog_format::{
+    DecodeError, FaultInfo, Record, RecordBuilder, StackSnapshot, TraceEvent, ERASED, FLAG_BFAR_VALID,
+    FLAG_FRAME_VALID, FLAG_MMFAR_VALID, FLAG_ON_PSP, RECORD_SIZE, RECORD_WORDS, STACK_SLOTS, STACK_WORDS,
+    TRACE_EVENTS,
+};
+use drivers::flash::{
+    flash_erase_sector, flash_lock, flash_program_words, flash_sector_address, flash_sector_size, flash_unlock,
+    FlashError,
+};
+use crate::console::emergency_print;
+use crate::fault::{set_fault_hook, FaultReport};
+use crate::os::task_info;
+use crate::os_config::{task_stack_start, CRASHLOG_SECTOR, MAX_TASK, SIZE_TASK_STACK};
+use crate::trace;
+
+/// Why a crash could not be saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrashLogError {
+    /// Every slot is used: the log must be erased.
+    Full,
+    Flash(FlashError),
+}
+
+/// Record being written, assembled here rather than on the (small) fault handler stack.
+static mut BUFFER: [u32; RECORD_WORDS] = [0; RECORD_WORDS];
+
+/// Save every fault report from now on.
+pub fn init() {
+    set_fault_hook(Some(save_fault));
+}
+
+fn base() -> u32 {
+    flash_sector_address(CRASHLOG_SECTOR)
+}
+
+/// Number of records the sector can hold.
+pub fn capacity() -> usize {
+    flash_sector_size(CRASHLOG_SECTOR) as usize / RECORD_SIZE
+}
+
+/// Words of slot `index`, read in place from flash.
+pub fn raw(index: usize) -> &'static [u32] {
+    assert!(index < capacity(), "crash log slot out of range");
+    let address = base() + (index * RECORD_SIZE) as u32;
+    unsafe { core::slice::from_raw_parts(address as *const u32, RECORD_WORDS) }
+}
+
+/// Number of slots in use, including records that fail their CRC. Records are appended in order,
+/// so the first erased slot ends the log.
+pub fn len() -> usize {
+    (0..capacity()).find(|&i| raw(i)[0] == ERASED).unwrap_or(capacity())
+}
+
+pub fn is_empty() -> bool {
+    len() == 0
+}
+
+/// Record in slot `index` (0 = oldest).
+pub fn record(index: usize) -> Result<Record<'static>, DecodeError> {
+    if index >= capacity() {
+        return Err(DecodeError::Empty);
+    }
+    Record::parse(raw(index))
+}
+
+/// Erase every record. Stalls the CPU for the duration of the sector erase (1–2 s).
+pub fn erase() -> Result<(), FlashError> {
+    flash_unlock();
+    let result = flash_erase_sector(CRASHLOG_SECTOR);
+    flash_lock();
+    result
+}
+
+fn fault_info(report: &FaultReport) -> FaultInfo {
+    let mut flags = 0;
+    if report.on_psp {
+        flags |= FLAG_ON_PSP;
+    }
+    if report.frame_valid {
+        flags |= FLAG_FRAME_VALID;
+    }
+    if report.mmfar.is_some() {
+        flags |= FLAG_MMFAR_VALID;
+    }
+    if report.bfar.is_some() {
+        flags |= FLAG_BFAR_VALID;
+    }
+    let r = &report.frame;
+    FaultInfo {
+        kind: report.kind as u32,
+        task: report.task,
+        flags,
+        exc_return: report.exc_return,
+        sp: report.sp,
+        frame: [r.r0, r.r1, r.r2, r.r3, r.r12, r.lr, r.pc, r.xpsr],
+        cfsr: report.cfsr,
+        hfsr: report.hfsr,
+        mmfar: report.mmfar.unwrap_or(0),
+        bfar: report.bfar.unwrap_or(0),
+        tick: report.tick,
+    }
+}
+
+/// Top of the stack of `task`: from the live stack pointer for the task that faulted, from the
+/// PSP saved at its last switch for the others.
+fn stack_snapshot(task: usize, report: &FaultReport) -> Option<StackSnapshot> {
+    let info = task_info(task)?;
+    let sp = if task == report.task as usize && report.on_psp { report.sp } else { info.psp };
+
+    let top = task_stack_start(task);
+    let mut words = [0u32; STACK_WORDS];
+    let mut depth = 0;
+    if sp >= top - SIZE_TASK_STACK && sp < top && sp.is_multiple_of(4) {
+        depth = (((top - sp) / 4) as usize).min(STACK_WORDS);
+        for (i, word) in words.iter_mut().take(depth).enumerate() {
+            *word = unsafe { read_volatile((sp + 4 * i as u32) as *const u32) };
+        }
+    }
+    Some(StackSnapshot::new(task as u32, info.name, sp, &words[..depth]))
+}
+
+/// Append a record for `report`. Returns its sequence number.
+pub fn save(report: &FaultReport) -> Result<u32, CrashLogError> {
+    let used = len();
+    if used == capacity() {
+        return Err(CrashLogError::Full);
+    }
+    let seq = (0..used)
+        .rev()
+        .find_map(|i| record(i).ok())
+        .map_or(0, |last| last.seq().wrapping_add(1));
+
+    let buffer = &raw mut BUFFER;
+    let words = unsafe { &mut *buffer };
+    let mut builder = RecordBuilder::new(words, seq, &fault_info(report));
+
+    let mut events = [TraceEvent::EMPTY; TRACE_EVENTS];
+    let count = trace::snapshot(&mut events);
+    for event in &events[..count] {
+        builder.push_trace(*event);
+    }
+    for task in 0..MAX_TASK.min(STACK_SLOTS) {
+        if let Some(snapshot) = stack_snapshot(task, report) {
+            builder.push_stack(&snapshot);
+        }
+    }
+    builder.finish();
+
+    flash_unlock();
+    let result = flash_program_words(base() + (used * RECORD_SIZE) as u32, words);
+    flash_lock();
+    result.map(|_| seq).map_err(CrashLogError::Flash)
+}
+
+/// Fault hook installed by `init`.
+fn save_fault(report: &FaultReport) {
+    match save(report) {
+        Ok(seq) => emergency_print(format_args!("crash log: saved record #{}\n", seq)),
+        Err(err) => emergency_print(format_args!("crash log: not saved ({:?})\n", err)),
+    }
+}
@@ -0,0 +1,72 @@
+//! Kernel critical sections.
+//!
+//! A critical section raises BASEPRI to `MAX_SYSCALL_PRIORITY` instead of setting PRIMASK: the
+//! scheduler exceptions and every interrupt allowed to use the kernel are held off, while more
+//! urgent interrupts (priority value below `MAX_SYSCALL_PRIORITY`, e.g. motor control) keep their
+//! latency. Sections nest: the previous BASEPRI is saved on entry and restored on exit, and entry
+//! never lowers a threshold already raised further.
+//!
+//! This is also the `critical-section` crate implementation of the firmware, so libraries built on
+//! it (heapless, embedded-hal drivers, ...) get the same behaviour.
+//!
+//! Code guarded this way must not be shared with interrupts above `MAX_SYSCALL_PRIORITY`: those
+//! are not masked. Such interrupts must not call any kernel function.
+
+use core::sync::atomic::{compiler_fence, Ordering};
+use drivers::cortex_m4::{get_active_exception, get_active_priority, get_basepri, set_basepri, set_basepri_max};
+use crate::os_config::MAX_SYSCALL_PRIORITY;
+
+/// Enter a critical section, returning the BASEPRI value to pass to `exit`.
+pub fn enter() -> u8 {
+    let previous = get_basepri();
+    set_basepri_max(MAX_SYSCALL_PRIORITY);
+    compiler_fence(Ordering::SeqCst);
+    previous
+}
+
+/// Leave a critical section entered with `enter`, restoring the previous masking level.
+pub fn exit(previous: u8) {
+    compiler_fence(Ordering::SeqCst);
+    set_basepri(previous);
+}
+
+/// Run `f` inside a critical section.
+pub fn free<R>(f: impl FnOnce() -> R) -> R {
+    let previous = enter();
+    let result = f();
+    exit(previous);
+    result
+}
+
+/// `true` if the caller may use the kernel: task context, or an exception whose priority is
+/// `MAX_SYSCALL_PRIORITY` or lower.
+pub fn is_syscall_allowed() -> bool {
+    match get_active_exception() {
+        0 => true,
+        // NMI and HardFault have fixed priorities above every configurable one
+        _ => get_active_priority().is_some_and(|priority| priority >= MAX_SYSCALL_PRIORITY),
+    }
+}
+
+/// Panic if called from an interrupt too urgent to use the kernel (see `MAX_SYSCALL_PRIORITY`).
+/// Checked by the `*_from_isr` functions.
+pub fn assert_syscall_allowed() {
+    assert!(
+        is_syscall_allowed(),
+        "kernel call from an ISR above MAX_SYSCALL_PRIORITY ({:?})",
+        get_active_priority()
+    );
+}
+
+struct KernelCriticalSection;
+critical_section::set_impl!(KernelCriticalSection);
+
+unsafe impl critical_section::Impl for KernelCriticalSection {
+    unsafe fn acquire() -> critical_section::RawRestoreState {
+        enter()
+    }
+
+    unsafe fn release(previous: critical_section::RawRestoreState) {
+        exit(previous);
+    }
+}
@@ -0,0 +1,301 @@
+//! Fault handling.
+//!
+//! HardFault, MemManage, BusFault and UsageFault enter `kernel_fault_entry` through the trampolines
+//! in `os_assembly.s`, which pass the stack holding the exception frame. The handler builds a
+//! `FaultReport` (stacked registers, fault status and address registers, faulting task), stores it
+//! in a `.noinit` RAM slot that survives a reset, prints it on the console and applies the
+//! configured `FaultPolicy`.
+//!
+//! After a reset, `last_fault` returns the preserved report (e.g. to log it at start-up) until
+//! `clear_last_fault` is called.
+
+#![allow(clippy::empty_loop)]
+
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+use crashlog_format::fault_causes;
+use drivers::cortex_m4::{set_pendsv, system_reset};
+use drivers::read_write::{read_register, write_register};
+use drivers::stm32f407_registers::{SCB_BFAR, SCB_CFSR, SCB_HFSR, SCB_MMFAR};
+use crate::os::{current_task, get_tick_count, kill_task, task_info};
+use crate::os_config::{SRAM_END, SRAM_START};
+
+/// Exception that reported the fault.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    HardFault = 0,
+    MemManage = 1,
+    BusFault = 2,
+    UsageFault = 3,
+}
+
+impl FaultKind {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            1 => FaultKind::MemManage,
+            2 => FaultKind::BusFault,
+            3 => FaultKind::UsageFault,
+            _ => FaultKind::HardFault,
+        }
+    }
+}
+
+/// What to do once a fault has been reported.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultPolicy {
+    /// Stop everything, leaving the system for a debugger.
+    Halt = 0,
+    /// Stop the faulting task for good and keep the others running. Faults that cannot be pinned
+    /// on a task (handler mode, idle task, corrupted stack frame) reset instead.
+    KillTask = 1,
+    /// Reset the MCU.
+    Reset = 2,
+}
+
+/// Registers pushed by the core on exception entry.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StackedFrame {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+/// Everything known about a fault. `repr(C)` so it can be stored raw in RAM or flash.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FaultReport {
+    pub kind: FaultKind,
+    /// Index of the running task (0 = idle).
+    pub task: u32,
+    /// The frame was on the process stack (task code) rather than the main stack (ISR or init).
+    pub on_psp: bool,
+    /// The frame could be read: the stack pointer pointed into RAM and no stacking error occurred.
+    pub frame_valid: bool,
+    pub exc_return: u32,
+    /// Stack pointer at exception entry (address of the stacked frame).
+    pub sp: u32,
+    pub frame: StackedFrame,
+    pub cfsr: u32,
+    pub hfsr: u32,
+    /// Faulting data address, if MMFAR is valid.
+    pub mmfar: Option<u32>,
+    /// Faulting bus address, if BFAR is valid.
+    pub bfar: Option<u32>,
+    /// Kernel tick at the time of the fault.
+    pub tick: u32,
+}
+
+// CFSR bits: MemManage [7:0], BusFault [15:8], UsageFault [31:16]
+const CFSR_MMARVALID: u32 = 1 << 7;
+const CFSR_MSTKERR: u32 = 1 << 4;
+const CFSR_MUNSTKERR: u32 = 1 << 3;
+const CFSR_BFARVALID: u32 = 1 << 15;
+const CFSR_STKERR: u32 = 1 << 12;
+const CFSR_UNSTKERR: u32 = 1 << 11;
+
+impl FaultReport {
+    /// Descriptions of the status bits set in CFSR and HFSR.
+    pub fn causes(&self) -> impl Iterator<Item = &'static str> {
+        fault_causes(self.cfsr, self.hfsr)
+    }
+
+    /// Name of the faulting task, if the index is valid.
+    pub fn task_name(&self) -> Option<&'static str> {
+        task_info(self.task as usize).map(|info| info.name)
+    }
+}
+
+impl fmt::Display for FaultReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} in task {}", self.kind, self.task)?;
+        if let Some(name) = self.task_name() {
+            write!(f, " ({})", name)?;
+        }
+        writeln!(f, " on {} at tick {}", if self.on_psp { "PSP" } else { "MSP" }, self.tick)?;
+
+        if self.frame_valid {
+            let r = &self.frame;
+            writeln!(f, "  PC   {:#010x}  LR  {:#010x}  xPSR {:#010x}", r.pc, r.lr, r.xpsr)?;
+            writeln!(f, "  R0   {:#010x}  R1  {:#010x}  R2   {:#010x}  R3 {:#010x}", r.r0, r.r1, r.r2, r.r3)?;
+            writeln!(f, "  R12  {:#010x}  SP  {:#010x}", r.r12, self.sp)?;
+        } else {
+            writeln!(f, "  stacked frame unavailable (SP {:#010x})", self.sp)?;
+        }
+        writeln!(f, "  CFSR {:#010x}  HFSR {:#010x}  EXC_RETURN {:#010x}", self.cfsr, self.hfsr, self.exc_return)?;
+        if let Some(address) = self.mmfar {
+            writeln!(f, "  MMFAR {:#010x}", address)?;
+        }
+        if let Some(address) = self.bfar {
+            writeln!(f, "  BFAR  {:#010x}", address)?;
+        }
+        for cause in self.causes() {
+            writeln!(f, "  - {}", cause)?;
+        }
+        Ok(())
+    }
+}
+
+// ---------- Report preserved across reset ----------
+
+const REPORT_MAGIC: u32 = 0xFA17_0C0D;
+
+#[repr(C)]
+struct NoInitSlot {
+    magic: u32,
+    report: FaultReport,
+    /// Bitwise complement of `magic`, written last: a torn write is detected.
+    check: u32,
+}
+
+/// Not zeroed at start-up (see the `.noinit` output section in memory.x).
+#[unsafe(link_section = ".noinit.fault_report")]
+static mut LAST_FAULT: core::mem::MaybeUninit<NoInitSlot> = core::mem::MaybeUninit::uninit();
+
+fn slot() -> *mut NoInitSlot {
+    (&raw mut LAST_FAULT).cast()
+}
+
+/// Report of the fault that caused the last reset (or of the last fault since), if any.
+pub fn last_fault() -> Option<FaultReport> {
+    let slot = slot();
+    unsafe {
+        let magic = core::ptr::read_volatile(&raw const (*slot).magic);
+        let check = core::ptr::read_volatile(&raw const (*slot).check);
+        if magic != REPORT_MAGIC || check != !REPORT_MAGIC {
+            return None;
+        }
+        // The magic guarantees the report (and its enum field) was written by `store_report`
+        Some(core::ptr::read_volatile(&raw const (*slot).report))
+    }
+}
+
+/// Forget the preserved report.
+pub fn clear_last_fault() {
+    let slot = slot();
+    unsafe {
+        core::ptr::write_volatile(&raw mut (*slot).magic, 0);
+        core::ptr::write_volatile(&raw mut (*slot).check, 0);
+    }
+}
+
+fn store_report(report: &FaultReport) {
+    let slot = slot();
+    unsafe {
+        core::ptr::write_volatile(&raw mut (*slot).check, 0);
+        core::ptr::write_volatile(&raw mut (*slot).magic, REPORT_MAGIC);
+        core::ptr::write_volatile(&raw mut (*slot).report, *report);
+        core::ptr::write_volatile(&raw mut (*slot).check, !REPORT_MAGIC);
+    }
+}
+
+// ---------- Policy ----------
+
+static POLICY: AtomicU8 = AtomicU8::new(FaultPolicy::Halt as u8);
+
+/// Hook called with every report before the policy is applied (e.g. to save it to flash).
+static mut REPORT_HOOK: Option<fn(&FaultReport)> = None;
+
+/// Select what happens after a fault (default `FaultPolicy::Halt`).
+pub fn set_fault_policy(policy: FaultPolicy) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+pub fn fault_policy() -> FaultPolicy {
+    match POLICY.load(Ordering::Relaxed) {
+        1 => FaultPolicy::KillTask,
+        2 => FaultPolicy::Reset,
+        _ => FaultPolicy::Halt,
+    }
+}
+
+/// Register a function called from the fault handler with each report, before the policy is
+/// applied. It runs in fault context: it must not block, allocate or use the kernel.
+pub fn set_fault_hook(hook: Option<fn(&FaultReport)>) {
+    unsafe {
+        REPORT_HOOK = hook;
+    }
+}
+
+/// Read the stacked frame if `sp` points into SRAM.
+fn read_frame(sp: *const u32) -> Option<StackedFrame> {
+    let address = sp as u32;
+    let size = core::mem::size_of::<StackedFrame>() as u32;
+    if address < SRAM_START || address > SRAM_END - size || !address.is_multiple_of(4) {
+        return None;
+    }
+    Some(unsafe { core::ptr::read_volatile(sp as *const StackedFrame) })
+}
+
+/// Task code jumps here when its task has been killed; it waits for PendSV to switch it out
+/// for good.
+extern "C" fn dead_task_trap() -> ! {
+    loop {
+        set_pendsv();
+    }
+}
+
+/// Called by the fault trampolines in `os_assembly.s` with the stack holding the exception frame,
+/// the EXC_RETURN value and the fault kind. Returns (resuming at a patched PC) only after killing
+/// the faulting task.
+///
+/// # Safety
+/// Only to be entered from the fault trampolines, with `sp` the active stack at exception entry.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn kernel_fault_entry(sp: *mut u32, exc_return: u32, kind: u32) {
+    let cfsr = unsafe { read_register(SCB_CFSR as *mut u32) };
+    let hfsr = unsafe { read_register(SCB_HFSR as *mut u32) };
+    let stacking_error = cfsr & (CFSR_MSTKERR | CFSR_MUNSTKERR | CFSR_STKERR | CFSR_UNSTKERR) != 0;
+    let frame = if stacking_error { None } else { read_frame(sp) };
+
+    let report = FaultReport {
+        kind: FaultKind::from_u32(kind),
+        task: current_task() as u32,
+        on_psp: exc_return & (1 << 2) != 0,
+        frame_valid: frame.is_some(),
+        exc_return,
+        sp: sp as u32,
+        frame: frame.unwrap_or_default(),
+        cfsr,
+        hfsr,
+        mmfar: (cfsr & CFSR_MMARVALID != 0).then(|| unsafe { read_register(SCB_MMFAR as *mut u32) }),
+        bfar: (cfsr & CFSR_BFARVALID != 0).then(|| unsafe { read_register(SCB_BFAR as *mut u32) }),
+        tick: get_tick_count(),
+    };
+
+    // Status registers are write-1-to-clear
+    unsafe {
+        write_register(SCB_CFSR as *mut u32, cfsr);
+        write_register(SCB_HFSR as *mut u32, hfsr);
+    }
+
+    store_report(&report);
+    crate::console::emergency_print(format_args!("\n*** FAULT: {}", report));
+    if let Some(hook) = unsafe { REPORT_HOOK } {
+        hook(&report);
+    }
+
+    match fault_policy() {
+        FaultPolicy::Halt => loop {},
+        FaultPolicy::Reset => system_reset(),
+        FaultPolicy::KillTask => {
+            if !report.on_psp || !report.frame_valid || report.task == 0 {
+                system_reset();
+            }
+            kill_task(report.task as usize);
+            // Resume the task in a loop that yields forever; PendSV switches it out for good
+            unsafe {
+                *sp.add(6) = dead_task_trap as *const () as u32;
+                *sp.add(7) = 0x0100_0000; // Thumb bit only
+            }
+            set_pendsv();
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Button input service.
+//!
+//! Buttons registered with `add_button` are watched by EXTI on both edges. An edge starts sampling
+//! the pin on every kernel tick (through the tick hook, see `os::set_tick_hook`) until the button's
+//! `ButtonMachine` (`debounce` crate) is idle again, so an untouched button costs nothing. Debounced
+//! presses, releases and gestures are queued as `InputEvent`s for tasks to read with `next_event`.
+//!
+//! ```ignore
+//! let user = input::add_button(PORTA, 0, false, ButtonConfig::default());
+//! loop {
+//!     if let Some(event) = input::next_event(WAIT_FOREVER) {
+//!         if event.button == user && event.event == ButtonEvent::Click { led_toggle(1); }
+//!     }
+//! }
+//! ```
+//!
+//! The service owns the kernel tick hook and the EXTI handlers of the lines it uses. Each EXTI line
+//! serves a single pin number, so two buttons cannot share a pin number on different ports.
+//!
+//! While a button is sampled the service holds a `PowerLock` blocking Stop, which would stop the
+//! tick; an idle button wakes the system from Stop through its EXTI line.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use debounce::ButtonMachine;
+use crate::critical;
+use drivers::exti::{configure_gpio_interrupt, on_line, Trigger};
+use drivers::gpio::{
+    gpio_configure_mode, gpio_pulup_puldown_configure, gpio_read, GPIO_MODE_INPUT, GPIO_NO_PULL, GPIO_PULL_UP,
+};
+use crate::os::set_tick_hook;
+use crate::os_config::KERNEL_TICK_PERIOD_MS;
+use crate::power::{PowerLock, PowerMode};
+use crate::queue::Queue;
+
+pub use debounce::{ButtonConfig, ButtonEvent};
+
+/// Most buttons the service can watch.
+pub const MAX_BUTTONS: usize = 8;
+
+/// Events buffered before new ones are dropped.
+pub const EVENT_QUEUE_LEN: usize = 16;
+
+/// Event of a registered button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    /// Identifier returned by `add_button`.
+    pub button: usize,
+    pub event: ButtonEvent,
+    /// Kernel tick at which the event was detected.
+    pub tick: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Button {
+    port: u32,
+    pin: u32,
+    active_low: bool,
+    machine: ButtonMachine,
+}
+
+/// Registered buttons (accessed inside `critical::free`)
+static mut BUTTONS: [Option<Button>; MAX_BUTTONS] = [None; MAX_BUTTONS];
+
+/// Bit i set => button i is being sampled on every tick
+static ACTIVE: AtomicU32 = AtomicU32::new(0);
+
+/// Events lost because the queue was full
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+static EVENTS: Queue<InputEvent, EVENT_QUEUE_LEN> = Queue::new();
+
+static SAMPLING_LOCK: PowerLock = PowerLock::busy_while("input", PowerMode::Stop, sampling);
+
+/// Whether a button is being sampled on every tick.
+fn sampling() -> bool {
+    ACTIVE.load(Ordering::Acquire) != 0
+}
+
+/// Register a button on `port`/`pin` and start watching it. `active_low` buttons (switching to
+/// ground) get the internal pull-up; others are expected to have an external pull-down, like the
+/// user button of the STM32F4-Discovery. Returns the button identifier used in `InputEvent`.
+/// Panics if all slots are used or the pin number is already taken.
+pub fn add_button(port: u32, pin: u32, active_low: bool, config: ButtonConfig) -> usize {
+    gpio_configure_mode(port, pin, GPIO_MODE_INPUT);
+    gpio_pulup_puldown_configure(port, pin, if active_low { GPIO_PULL_UP } else { GPIO_NO_PULL });
+
+    let id = critical::free(|| unsafe {
+        let mut free = None;
+        #[allow(clippy::needless_range_loop)]
+        for id in 0..MAX_BUTTONS {
+            match BUTTONS[id] {
+                Some(b) => assert!(b.pin != pin, "EXTI line {} already used by a button", pin),
+                None => free = free.or(Some(id)),
+            }
+        }
+        let id = free.expect("Too many buttons");
+        BUTTONS[id] = Some(Button { port, pin, active_low, machine: ButtonMachine::new(config) });
+        id
+    });
+
+    SAMPLING_LOCK.register();
+    set_tick_hook(Some(poll));
+    on_line(pin, on_edge);
+    configure_gpio_interrupt(port, pin, Trigger::Both);
+    // Pick up a button already held at start-up
+    ACTIVE.fetch_or(1 << id, Ordering::AcqRel);
+    id
+}
+
+/// Debounced state of a button.
+pub fn is_pressed(button: usize) -> bool {
+    button < MAX_BUTTONS
+        && critical::free(|| unsafe { BUTTONS[button] }).is_some_and(|b| b.machine.is_pressed())
+}
+
+/// Wait up to `timeout_ticks` (`WAIT_FOREVER` never times out) for the next button event.
+pub fn next_event(timeout_ticks: u32) -> Option<InputEvent> {
+    EVENTS.receive(timeout_ticks)
+}
+
+/// Next button event, if any, without blocking.
+pub fn try_next_event() -> Option<InputEvent> {
+    EVENTS.try_receive()
+}
+
+/// Number of events dropped because no task consumed them in time.
+pub fn dropped_events() -> u32 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// EXTI handler: start sampling the button on this line.
+fn on_edge(line: u32) {
+    let mask = critical::free(|| unsafe {
+        (0..MAX_BUTTONS)
+            .filter(|&id| BUTTONS[id].is_some_and(|b| b.pin == line))
+            .fold(0, |mask, id| mask | (1 << id))
+    });
+    ACTIVE.fetch_or(mask, Ordering::AcqRel);
+}
+
+/// Tick hook: sample the active buttons and queue their events.
+fn poll(tick: u32) {
+    let active = ACTIVE.load(Ordering::Acquire);
+    if active == 0 {
+        return;
+    }
+    let now_ms = tick.wrapping_mul(KERNEL_TICK_PERIOD_MS);
+
+    #[allow(clippy::needless_range_loop)]
+    for id in 0..MAX_BUTTONS {
+        if active & (1 << id) == 0 {
+            continue;
+        }
+        // Cleared inside the critical section so that an edge arriving meanwhile is not lost
+        critical::free(|| unsafe {
+            let Some(mut button) = BUTTONS[id] else {
+                ACTIVE.fetch_and(!(1 << id), Ordering::AcqRel);
+                return;
+            };
+            let pressed = gpio_read(button.port, button.pin) != button.active_low;
+            button.machine.update(pressed, now_ms, |event| {
+                if EVENTS.send_from_isr(InputEvent { button: id, event, tick }).is_err() {
+                    DROPPED.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+            if button.machine.is_idle() {
+                ACTIVE.fetch_and(!(1 << id), Ordering::AcqRel);
+            }
+            BUTTONS[id] = Some(button);
+        });
+    }
+}
@@ -2,6 +2,22 @@
 #![no_std]
 
 
+pub mod boot;
+pub mod bus;
+pub mod config;
+pub mod console;
+pub mod crashlog;
+pub mod critical;
+pub mod fault;
+pub mod input;
 pub mod os;
 pub mod os_config;
-pub mod systick;
\ No newline at end of file
+pub mod power;
+pub mod queue;
+pub mod serial;
+pub mod shell;
+pub mod sync;
+pub mod systick;
+pub mod trace;
+pub mod update;
+pub mod watchdog;
@@ -1,15 +1,22 @@
 #![allow(clippy::empty_loop)]
 
-use core::ptr::{read_volatile, write_volatile};
+use core::ptr::read_volatile;
+use core::sync::atomic::{AtomicBool, Ordering};
 use cortex_m_rt::{exception};
 use crate::os_config::*;
 use crate::systick::{SysTick};
-use cortex_m::interrupt;
-
-pub const CORE_CLOCK_MHZ: u32 = 16; 
-
-/// Addresses for System Control Block ICSR register (PendSV set-pending bit)
-const SCB_ICSR: *mut u32 = 0xE000_ED04 as *mut u32;
+use crate::critical;
+use crate::trace;
+use crate::watchdog;
+use crashlog_format::TraceKind;
+use drivers::cortex_m4::{
+    enable_fault_handlers, get_interrupt_priority, set_interrupt_priority, set_pendsv, set_system_handler_priority,
+    SystemHandler, LOWEST_PRIORITY, NUM_IRQS,
+};
+
+/// Priorities (0..=15) of the scheduler exceptions
+const PENDSV_PRIORITY: u8 = LOWEST_PRIORITY;
+const SYSTICK_PRIORITY: u8 = LOWEST_PRIORITY - 1;
 
 // == External assembly symbols (implemented in context_switch.s) ==
 unsafe extern "C" {
@@ -22,6 +29,15 @@ unsafe extern "C" {
 static mut CURRENT_TASK_IDX: usize = 0;
 static mut GLOBAL_TICK_COUNT: u32 = 0;
 
+/// Ticks during which each task was the running one (for CPU usage statistics)
+static mut TASK_RUN_TICKS: [u32; MAX_TASK] = [0; MAX_TASK];
+
+/// Function called from the SysTick handler on every kernel tick (see `set_tick_hook`)
+static mut TICK_HOOK: Option<fn(u32)> = None;
+
+/// Set once `scheduler_init` runs the tasks on the PSP; PendSV must not run before
+static SCHEDULER_STARTED: AtomicBool = AtomicBool::new(false);
+
 // ---------- Low-level helpers (called from assembly) ----------
 
 #[unsafe(no_mangle)]
@@ -51,7 +67,7 @@ pub extern "C" fn update_to_next_task() {
 
         for _ in 0..n-1 {   // only user tasks
             if TASKS[i].current_state == TASK_READY_STATE {
-                let p = TASKS[i].priority as usize;
+                let p = TASKS[i].priority;
                 if p < best {
                     best = p;
                     next = i;
@@ -61,6 +77,9 @@ pub extern "C" fn update_to_next_task() {
             i = ((i - 1 + 1) % (n - 1)) + 1;
         }
 
+        if next != cur {
+            trace::record(TraceKind::Switch, next as u16);
+        }
         CURRENT_TASK_IDX = next; // commit once
     }
 }
@@ -73,27 +92,210 @@ pub extern "C" fn update_to_next_task() {
 
 
 
-/// Trigger a PendSV to request a context switch.
+/// Trigger a PendSV to request a context switch. Does nothing before the scheduler has started
+/// (e.g. a mutex released during initialisation), as PendSV would switch from an unset PSP.
 pub fn schedule() {
+    if SCHEDULER_STARTED.load(Ordering::Acquire) {
+        set_pendsv();
+    }
+}
+
+/// Number of kernel ticks elapsed since `scheduler_init` (wraps around).
+pub fn get_tick_count() -> u32 {
+    unsafe { read_volatile(&raw const GLOBAL_TICK_COUNT) }
+}
+
+/// Index of the task currently running (0 = idle task).
+pub fn current_task() -> usize {
+    unsafe { read_volatile(&raw const CURRENT_TASK_IDX) }
+}
+
+/// Longest timeout accepted by `task_delay` / `block_current_task`, in ticks.
+pub const MAX_DELAY_TICKS: u32 = 0x7FFF_FFFF;
+
+/// Mark the current task blocked until `GLOBAL_TICK_COUNT` reaches `now + ticks` and switch away.
+/// The task may also be woken earlier by `unblock_task` (e.g. when a semaphore is given),
+/// so callers must re-check the condition they were waiting for.
+/// The idle task never blocks; for it this is a no-op.
+/// May be called inside a critical section: the switch then happens as soon as it is left.
+pub fn block_current_task(ticks: u32) {
+    critical::free(|| unsafe {
+        let cur = CURRENT_TASK_IDX;
+        if cur == 0 {
+            return;
+        }
+        let ticks = ticks.min(MAX_DELAY_TICKS);
+        TASKS[cur].block_count = GLOBAL_TICK_COUNT.wrapping_add(ticks);
+        TASKS[cur].current_state = TASK_BLOCKED_STATE;
+        trace::record(TraceKind::Block, ticks.min(u16::MAX as u32) as u16);
+    });
+    schedule();
+}
+
+/// Make task `idx` ready to run again if it is blocked. Safe to call from an ISR.
+pub fn unblock_task(idx: usize) {
+    if idx == 0 || idx >= MAX_TASK {
+        return;
+    }
+    critical::free(|| unsafe {
+        if TASKS[idx].current_state == TASK_BLOCKED_STATE {
+            TASKS[idx].current_state = TASK_READY_STATE;
+            trace::record(TraceKind::Wake, idx as u16);
+        }
+    });
+}
+
+/// Stop task `idx` for good: the scheduler never selects it again. The idle task cannot be killed.
+/// The switch away from a running task happens at the next PendSV.
+pub fn kill_task(idx: usize) {
+    if idx == 0 || idx >= MAX_TASK {
+        return;
+    }
+    critical::free(|| unsafe {
+        TASKS[idx].current_state = TASK_DEAD_STATE;
+        trace::record(TraceKind::Kill, idx as u16);
+    });
+    schedule();
+}
+
+/// Snapshot of a task's scheduling state, for diagnostics.
+#[derive(Clone, Copy)]
+pub struct TaskInfo {
+    pub name: &'static str,
+    pub priority: usize,
+    pub state: u8,
+    pub running: bool,
+    /// Ticks the task has been running since start (wraps around).
+    pub run_ticks: u32,
+    pub stack_size: u32,
+    /// Maximum stack depth reached so far, in bytes.
+    pub stack_used: u32,
+    /// Process stack pointer saved at the last switch away from the task.
+    pub psp: u32,
+}
+
+/// Deepest stack usage of task `idx`, found by scanning for the first overwritten fill word.
+fn task_stack_high_water(idx: usize) -> u32 {
+    let top = task_stack_start(idx);
+    let bottom = top - SIZE_TASK_STACK;
+    let mut addr = bottom;
+    while addr < top {
+        if unsafe { read_volatile(addr as *const u32) } != STACK_FILL_PATTERN {
+            break;
+        }
+        addr += 4;
+    }
+    top - addr
+}
+
+/// Returns scheduling and stack information about task `idx`, or `None` if out of range.
+pub fn task_info(idx: usize) -> Option<TaskInfo> {
+    if idx >= MAX_TASK {
+        return None;
+    }
+    let (name, priority, state, run_ticks, psp) = critical::free(|| unsafe {
+        (TASKS[idx].name, TASKS[idx].priority, TASKS[idx].current_state, TASK_RUN_TICKS[idx], TASKS[idx].psp_value)
+    });
+    Some(TaskInfo {
+        name,
+        priority,
+        state,
+        running: idx == current_task(),
+        run_ticks,
+        stack_size: SIZE_TASK_STACK,
+        stack_used: task_stack_high_water(idx),
+        psp,
+    })
+}
+
+/// Register a function called from the SysTick handler on every tick, with the new tick count.
+/// It runs in interrupt context before the scheduler is invoked, so it must be short and may only
+/// use `*_from_isr` primitives. `None` removes it.
+pub fn set_tick_hook(hook: Option<fn(u32)>) {
+    critical::free(|| unsafe {
+        TICK_HOOK = hook;
+    });
+}
+
+/// Block the calling task for `ticks` kernel ticks.
+pub fn task_delay(ticks: u32) {
+    if current_task() == 0 {
+        // The idle task must stay runnable: spin instead.
+        let start = get_tick_count();
+        while get_tick_count().wrapping_sub(start) < ticks {}
+        return;
+    }
+
+    let wake = get_tick_count().wrapping_add(ticks);
+    // Loop in case something else unblocked us early.
+    while (get_tick_count().wrapping_sub(wake) as i32) < 0 {
+        block_current_task(wake.wrapping_sub(get_tick_count()));
+    }
+}
+
+/// Make ready every blocked task whose wake tick has been reached.
+/// Called from the SysTick handler or inside a critical section.
+unsafe fn wake_expired_tasks() {
     unsafe {
-        core::ptr::write_volatile(SCB_ICSR, 1 << 28);
+        #[allow(clippy::needless_range_loop)]
+        for i in 1..MAX_TASK {
+            if TASKS[i].current_state == TASK_BLOCKED_STATE {
+                // Wake when now >= wake_tick (stored in block_count)
+                if (GLOBAL_TICK_COUNT.wrapping_sub(TASKS[i].block_count) as i32) >= 0 {
+                    TASKS[i].current_state = TASK_READY_STATE;
+                }
+            }
+        }
     }
 }
 
+/// Ticks until the earliest blocked task times out (at most `MAX_DELAY_TICKS`), or `None` if a
+/// task other than idle is ready to run. Used by `power` to bound a Stop.
+pub(crate) fn ticks_until_next_wake() -> Option<u32> {
+    critical::free(|| unsafe {
+        let mut next = MAX_DELAY_TICKS;
+        #[allow(clippy::needless_range_loop)]
+        for i in 1..MAX_TASK {
+            match TASKS[i].current_state {
+                TASK_READY_STATE => return None,
+                TASK_BLOCKED_STATE => {
+                    let remaining = TASKS[i].block_count.wrapping_sub(GLOBAL_TICK_COUNT) as i32;
+                    next = next.min(remaining.max(0) as u32);
+                }
+                _ => {}
+            }
+        }
+        Some(next)
+    })
+}
+
+/// Account for `ticks` that elapsed while SysTick was stopped (Stop mode, see `power`): the time
+/// is charged to the idle task and the tasks whose timeout passed are woken. The tick hook is not
+/// called for these ticks.
+pub(crate) fn advance_ticks(ticks: u32) {
+    if ticks == 0 {
+        return;
+    }
+    critical::free(|| unsafe {
+        GLOBAL_TICK_COUNT = GLOBAL_TICK_COUNT.wrapping_add(ticks);
+        TASK_RUN_TICKS[0] = TASK_RUN_TICKS[0].wrapping_add(ticks);
+        wake_expired_tasks();
+    });
+    schedule();
+}
+
 
 #[exception]
 fn SysTick() {
     unsafe {
         GLOBAL_TICK_COUNT = GLOBAL_TICK_COUNT.wrapping_add(1);
+        TASK_RUN_TICKS[CURRENT_TASK_IDX] = TASK_RUN_TICKS[CURRENT_TASK_IDX].wrapping_add(1);
+        wake_expired_tasks();
 
-        // for i in 0..MAX_TASK {
-        //     if TASKS[i].current_state == TASK_BLOCKED_STATE {
-        //         // Wake when now >= wake_tick (stored in block_count)
-        //         if (GLOBAL_TICK_COUNT.wrapping_sub(TASKS[i].block_count) as i32) >= 0 {
-        //             TASKS[i].current_state = TASK_READY_STATE;
-        //         }
-        //     }
-        // }
+        if let Some(hook) = TICK_HOOK {
+            hook(GLOBAL_TICK_COUNT);
+        }
+        watchdog::monitor(GLOBAL_TICK_COUNT);
     }
     schedule();
 }
@@ -120,6 +322,13 @@ unsafe fn init_task_stack() {
     #[allow(clippy::needless_range_loop)] 
     for i in 0..MAX_TASK {
         unsafe {
+            // Pre-fill the stack so its high-water mark can be measured later
+            let mut fill = (task_stack_start(i) - SIZE_TASK_STACK) as *mut u32;
+            while (fill as u32) < task_stack_start(i) - STACK_FILL_SKIP_TOP {
+                fill.write_volatile(STACK_FILL_PATTERN);
+                fill = fill.offset(1);
+            }
+
             // Get starting PSP for this task
             let mut p = task_stack_start(i) as *mut u32;
 
@@ -157,20 +366,24 @@ pub fn scheduler_init() {
     unsafe {
         init_scheduler_stack(scheduler_stack_start());
 
-        // SHPR3 base
-        let shpr3 = 0xE000_ED20 as *mut u32;
-
-        // Clear both fields (PendSV [23:16], SysTick [31:24]) then set:
-        //   PendSV  = 0xFF (lowest)
-        //   SysTick = 0xF0 (low, but above PendSV)
-        let mut v = core::ptr::read_volatile(shpr3);
-        v &= !((0xFFu32 << 16) | (0xFFu32 << 24)); // clear PendSV & SysTick fields
-        v |= (0xFFu32 << 16) | (0xF0u32 << 24);    // set PendSV, SysTick
-        core::ptr::write_volatile(shpr3, v);
+        // PendSV lowest, so the context switch never preempts an ISR;
+        // SysTick just above it (only 4 priority bits exist: 0xF0 would equal PendSV)
+        set_system_handler_priority(SystemHandler::PendSV, PENDSV_PRIORITY);
+        set_system_handler_priority(SystemHandler::SysTick, SYSTICK_PRIORITY);
+
+        // IRQs still at their reset priority (0) would be above MAX_SYSCALL_PRIORITY, where
+        // kernel calls are forbidden: move them to the most urgent level allowed to use the kernel.
+        // Interrupts that must never be delayed by the kernel get a priority in
+        // 1..MAX_SYSCALL_PRIORITY before this point.
+        for irq in 0..NUM_IRQS {
+            if get_interrupt_priority(irq) == 0 {
+                set_interrupt_priority(irq, MAX_SYSCALL_PRIORITY);
+            }
+        }
 
-        // (Optional) byte-wise form—often clearer:
-        // *(0xE000_ED22 as *mut u8) = 0xFF; // PendSV
-        // *(0xE000_ED23 as *mut u8) = 0xF0; // SysTick
+        // MemManage, BusFault and UsageFault get their own handlers instead of escalating
+        // to HardFault (see `fault`)
+        enable_fault_handlers();
 
         // If you keep FP enabled, this disables lazy stacking (ASPEN=1, LSPEN=0).
         let fpccr = 0xE000_EF34 as *mut u32;
@@ -179,10 +392,11 @@ pub fn scheduler_init() {
          init_task_stack();
         let mut systick = SysTick::take().expect("Failed to take SysTick instance!");
        
-        systick.init_systic_interrupt_ms(KERNEL_TICK_PERIOD_MS, CORE_CLOCK_MHZ);
+        systick.init_systic_interrupt_ms(KERNEL_TICK_PERIOD_MS);
 
         //update_to_next_task();
         switch_sp_to_psp();
+        SCHEDULER_STARTED.store(true, Ordering::Release);
         let entry = TASKS[CURRENT_TASK_IDX].task_handler;
         (entry)();
     }
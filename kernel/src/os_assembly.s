@@ -59,3 +59,39 @@ init_scheduler_stack:
     msr     msp, r0         // Load R0 value (top_of_stack variable) into MSP
     bx      lr              // Return from function
 
+
+//------------------------------------------------------
+// Fault trampolines: find the stack the exception frame was pushed on
+// (EXC_RETURN bit 2: 0 = MSP, 1 = PSP) and hand it to the Rust handler
+//   kernel_fault_entry(frame: r0, exc_return: r1, kind: r2)
+.global HardFault
+.type HardFault, %function
+HardFault:
+    movs    r2, #0
+    b       fault_common
+
+.global MemoryManagement
+.type MemoryManagement, %function
+MemoryManagement:
+    movs    r2, #1
+    b       fault_common
+
+.global BusFault
+.type BusFault, %function
+BusFault:
+    movs    r2, #2
+    b       fault_common
+
+.global UsageFault
+.type UsageFault, %function
+UsageFault:
+    movs    r2, #3
+    b       fault_common
+
+fault_common:
+    mov     r1, lr
+    tst     r1, #4
+    ite     eq
+    mrseq   r0, msp
+    mrsne   r0, psp
+    b       kernel_fault_entry
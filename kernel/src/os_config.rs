@@ -24,6 +24,12 @@
 // //! ---
 
 
+// Highest interrupt priority (0..=15, lower value = more urgent) that may use the kernel.
+// Kernel critical sections raise BASEPRI to this level: interrupts with a priority value below it
+// are never delayed by the kernel, but must not call any kernel API (not even `*_from_isr`).
+// PendSV and SysTick run at the lowest priorities, below this level.
+pub const MAX_SYSCALL_PRIORITY: u8 = 5;
+
 // Kernel tick period in milliseconds.
 // Lower => more frequent switching. Higher => less frequent.
 pub const KERNEL_TICK_PERIOD_MS: u32 = 1;
@@ -43,6 +49,13 @@ pub const SRAM_START: u32 = 0x2000_0000;
 pub const SRAM_SIZE: u32 = 128 * 1024; // 128 KB
 pub const SRAM_END: u32 = SRAM_START + SRAM_SIZE;
 
+// Flash sector holding the crash log (see `crashlog`). It must lie outside the bootloader and
+// the firmware slots (flash map in `boot-format`).
+pub const CRASHLOG_SECTOR: u32 = 11;
+
+// Flash sectors holding the persistent configuration (see `config`), also outside the slots.
+pub const CONFIG_SECTORS: [u32; 2] = [9, 10];
+
 
 
 
@@ -62,6 +75,15 @@ pub const fn scheduler_stack_start() -> u32 {
 /// Task states
 pub const TASK_READY_STATE: u8 = 0x00;
 pub const TASK_BLOCKED_STATE: u8 = 0xFF;
+/// Task stopped for good after a fault (see `fault::FaultPolicy::KillTask`)
+pub const TASK_DEAD_STATE: u8 = 0xFE;
+
+/// Word written over unused task stack memory at init, used to measure the stack high-water mark
+pub const STACK_FILL_PATTERN: u32 = 0xA5A5_A5A5;
+
+/// Bytes at the top of each task stack that are not pre-filled with `STACK_FILL_PATTERN`
+/// (the idle task's stack overlaps the reset-time MSP stack still in use during init).
+pub const STACK_FILL_SKIP_TOP: u32 = 256;
 
 /// Default xPSR value for initial stack frame (Thumb bit set)
 pub const DUMMY_XPSR: u32 = 0x0100_0000;
@@ -76,9 +98,10 @@ pub type TaskHandler = unsafe extern "C" fn();
 pub struct Tcb {
     pub psp_value: u32,     // Process Stack Pointer for the task
     pub priority: usize,       // Smaller number => higher priority
-    pub current_state: u8,  // TASK_READY_STATE or TASK_BLOCKED_STATE
+    pub current_state: u8,  // TASK_READY_STATE, TASK_BLOCKED_STATE or TASK_DEAD_STATE
     pub block_count: u32,   // blocking counter (if used)
     pub task_handler: TaskHandler,
+    pub name: &'static str, // shown by the shell `ps`/`top`/`mem` commands
 }
 
 // ---------- Extern declarations for task handlers ----------
@@ -97,8 +120,8 @@ unsafe extern "C" {
 /// Static array of all TCBS for tasks.
 /// Initialize stacks and other fields at runtime during scheduler init.
 pub static mut TASKS: [Tcb; MAX_TASK] = [
-    Tcb { psp_value: 0, priority: 0, current_state: TASK_READY_STATE,   block_count: 0, task_handler: Idle_task_handler },
-    Tcb { psp_value: 0, priority: 1, current_state: TASK_BLOCKED_STATE,   block_count: 0, task_handler: task1_handler },
-    Tcb { psp_value: 0, priority: 2, current_state: TASK_BLOCKED_STATE,   block_count: 0, task_handler: task2_handler },
-    Tcb { psp_value: 0, priority: 2, current_state: TASK_BLOCKED_STATE,   block_count: 0, task_handler: task3_handler },
+    Tcb { psp_value: 0, priority: 0, current_state: TASK_READY_STATE,   block_count: 0, task_handler: Idle_task_handler, name: "idle" },
+    Tcb { psp_value: 0, priority: 1, current_state: TASK_BLOCKED_STATE,   block_count: 0, task_handler: task1_handler, name: "task1" },
+    Tcb { psp_value: 0, priority: 2, current_state: TASK_BLOCKED_STATE,   block_count: 0, task_handler: task2_handler, name: "task2" },
+    Tcb { psp_value: 0, priority: 2, current_state: TASK_BLOCKED_STATE,   block_count: 0, task_handler: task3_handler, name: "task3" },
    ];
\ No newline at end of file
@@ -0,0 +1,284 @@
+//! Power management: the idle task sleeps in the deepest low-power mode allowed.
+//!
+//! The application's idle task calls `idle` in its loop. Each call enters one of:
+//! - `Sleep`: the core stops until the next interrupt (at the latest the next SysTick),
+//!   peripherals keep running;
+//! - `Stop`: every clock stops until the next task timeout, an RTC wakeup timer being programmed
+//!   for it, or until an EXTI interrupt (GPIO, RTC alarm, ...). The kernel tick count is then
+//!   advanced by the time spent, measured on the RTC, and the clock tree restored.
+//!
+//! Stop needs the RTC running (`drivers::rtc::rtc_init`), whose wakeup timer it takes over while
+//! in Stop, and is only worth it for at least `MIN_STOP_MS`. With the watchdog started, a Stop
+//! lasts at most half its timeout, as nothing feeds it meanwhile.
+//!
+//! Drivers and tasks that need more than a mode provides hold a `PowerLock` naming the mode it
+//! blocks: while any lock blocking Stop is held, `idle` goes no deeper than Sleep. A lock is a
+//! counter (`acquire` / `release`, or the `hold` guard), usable from interrupts; a lock built with
+//! `PowerLock::busy_while` is held as long as its check returns `true`. The serial ports block Stop
+//! while a task waits for data (a USART cannot receive in Stop) and while they transmit, and the
+//! input service while it debounces a button (the tick stops in Stop).
+//! `set_max_mode` caps the mode for the whole system, e.g. while debugging.
+//!
+//! Standby powers the MCU down until a wakeup source restarts it from reset, so `idle` never
+//! picks it: call `standby` explicitly.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use drivers::cortex_m4::{disable_global_interrupt, enable_global_interrupt};
+use drivers::exti::is_exti_pending;
+use drivers::pwr::{pwr_sleep, pwr_standby, pwr_stop, StopRegulator};
+use drivers::rtc::{
+    rtc_clock, rtc_datetime_ms, rtc_disable_wakeup, rtc_set_wakeup, rtc_wait_sync, EXTI_LINE_RTC_WAKEUP,
+};
+use crate::critical;
+use crate::os::{advance_ticks, ticks_until_next_wake};
+use crate::os_config::KERNEL_TICK_PERIOD_MS;
+use crate::watchdog;
+
+/// Maximum number of registered power locks.
+pub const MAX_POWER_LOCKS: usize = 16;
+
+/// Shortest idle period spent in Stop; shorter ones use Sleep, as waking from Stop restarts the
+/// HSE and the PLL.
+pub const MIN_STOP_MS: u32 = 5;
+
+/// Low-power modes `idle` chooses from, from the lightest to the deepest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PowerMode {
+    /// No low-power mode: `idle` returns at once.
+    Run,
+    Sleep,
+    Stop,
+}
+
+impl PowerMode {
+    /// The next lighter mode.
+    const fn lighter(self) -> PowerMode {
+        match self {
+            PowerMode::Run | PowerMode::Sleep => PowerMode::Run,
+            PowerMode::Stop => PowerMode::Sleep,
+        }
+    }
+
+    fn from_u32(value: u32) -> PowerMode {
+        match value {
+            0 => PowerMode::Run,
+            1 => PowerMode::Sleep,
+            _ => PowerMode::Stop,
+        }
+    }
+}
+
+impl fmt::Display for PowerMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PowerMode::Run => "run",
+            PowerMode::Sleep => "sleep",
+            PowerMode::Stop => "stop",
+        })
+    }
+}
+
+/// A reason to keep the system out of a low-power mode, declared as a `static`.
+pub struct PowerLock {
+    name: &'static str,
+    blocks: PowerMode,
+    count: AtomicU32,
+    busy: Option<fn() -> bool>,
+    registered: AtomicBool,
+}
+
+impl PowerLock {
+    /// A lock named `name` that, while held, forbids `blocks` and deeper modes.
+    pub const fn new(name: &'static str, blocks: PowerMode) -> Self {
+        assert!(!matches!(blocks, PowerMode::Run), "A power lock cannot block Run");
+        PowerLock { name, blocks, count: AtomicU32::new(0), busy: None, registered: AtomicBool::new(false) }
+    }
+
+    /// A lock that is also held whenever `busy` returns `true` (checked from `idle`, with
+    /// interrupts masked). Must be registered with `register`.
+    pub const fn busy_while(name: &'static str, blocks: PowerMode, busy: fn() -> bool) -> Self {
+        let lock = PowerLock::new(name, blocks);
+        PowerLock { busy: Some(busy), ..lock }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Shallowest mode the lock forbids.
+    pub fn blocks(&self) -> PowerMode {
+        self.blocks
+    }
+
+    /// Make the lock known to `idle`. Done by the first `acquire`; registering twice is harmless.
+    /// Panics if `MAX_POWER_LOCKS` locks are already registered.
+    pub fn register(&'static self) {
+        if self.registered.load(Ordering::Acquire) {
+            return;
+        }
+        critical::free(|| unsafe {
+            if self.registered.load(Ordering::Relaxed) {
+                return;
+            }
+            let slot = (0..MAX_POWER_LOCKS).find(|&i| LOCKS[i].is_none()).expect("Power lock table full");
+            LOCKS[slot] = Some(self);
+            self.registered.store(true, Ordering::Release);
+        });
+    }
+
+    /// Take the lock (nestable). Safe to call from an ISR once registered.
+    pub fn acquire(&'static self) {
+        self.register();
+        self.count.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Give back one `acquire`.
+    pub fn release(&self) {
+        let previous = self.count.fetch_sub(1, Ordering::AcqRel);
+        assert!(previous > 0, "Power lock {} released more often than acquired", self.name);
+    }
+
+    /// Take the lock until the guard is dropped.
+    pub fn hold(&'static self) -> PowerLockGuard {
+        self.acquire();
+        PowerLockGuard { lock: self }
+    }
+
+    /// Whether the lock currently forbids its mode.
+    pub fn is_held(&self) -> bool {
+        self.count.load(Ordering::Acquire) > 0 || self.busy.is_some_and(|busy| busy())
+    }
+}
+
+/// Releases a `PowerLock` when dropped.
+pub struct PowerLockGuard {
+    lock: &'static PowerLock,
+}
+
+impl Drop for PowerLockGuard {
+    fn drop(&mut self) {
+        self.lock.release();
+    }
+}
+
+static mut LOCKS: [Option<&'static PowerLock>; MAX_POWER_LOCKS] = [None; MAX_POWER_LOCKS];
+
+static MAX_MODE: AtomicU32 = AtomicU32::new(PowerMode::Stop as u32);
+
+/// Times `idle` entered Sleep and Stop, and ticks spent in Stop.
+static SLEEP_COUNT: AtomicU32 = AtomicU32::new(0);
+static STOP_COUNT: AtomicU32 = AtomicU32::new(0);
+static STOP_TICKS: AtomicU32 = AtomicU32::new(0);
+
+/// Deepest mode `idle` may use regardless of the locks (default `Stop`).
+pub fn set_max_mode(mode: PowerMode) {
+    MAX_MODE.store(mode as u32, Ordering::Relaxed);
+}
+
+pub fn max_mode() -> PowerMode {
+    PowerMode::from_u32(MAX_MODE.load(Ordering::Relaxed))
+}
+
+/// Call `f` with every registered lock.
+pub fn for_each_lock(mut f: impl FnMut(&PowerLock)) {
+    #[allow(clippy::needless_range_loop)]
+    for i in 0..MAX_POWER_LOCKS {
+        if let Some(lock) = critical::free(|| unsafe { LOCKS[i] }) {
+            f(lock);
+        }
+    }
+}
+
+/// Deepest mode allowed now by `set_max_mode` and the locks held.
+pub fn allowed_mode() -> PowerMode {
+    let mut mode = max_mode();
+    for_each_lock(|lock| {
+        if lock.is_held() {
+            mode = mode.min(lock.blocks.lighter());
+        }
+    });
+    mode
+}
+
+/// Usage counters since start-up.
+#[derive(Debug, Clone, Copy)]
+pub struct PowerStats {
+    pub sleeps: u32,
+    pub stops: u32,
+    /// Kernel ticks spent in Stop.
+    pub stop_ticks: u32,
+}
+
+pub fn stats() -> PowerStats {
+    PowerStats {
+        sleeps: SLEEP_COUNT.load(Ordering::Relaxed),
+        stops: STOP_COUNT.load(Ordering::Relaxed),
+        stop_ticks: STOP_TICKS.load(Ordering::Relaxed),
+    }
+}
+
+/// Milliseconds on the RTC (wraps around; only differences are used).
+fn rtc_millis() -> u32 {
+    let (datetime, ms) = rtc_datetime_ms();
+    datetime.to_unix().wrapping_mul(1000).wrapping_add(ms)
+}
+
+/// Enter Stop until the next task timeout or an interrupt. Returns `false`, without stopping,
+/// if that timeout is too close or a task became ready. Called with interrupts masked.
+fn stop() -> bool {
+    let Some(ticks) = ticks_until_next_wake() else {
+        return false;
+    };
+    let mut period_ms = ticks.saturating_mul(KERNEL_TICK_PERIOD_MS);
+    if let Some(timeout) = watchdog::timeout_ms() {
+        period_ms = period_ms.min(timeout / 2);
+    }
+    if period_ms < MIN_STOP_MS {
+        return false;
+    }
+
+    let start = rtc_millis();
+    let period_ms = rtc_set_wakeup(period_ms, None);
+    pwr_stop(StopRegulator::LowPower, false);
+    let timer_expired = is_exti_pending(EXTI_LINE_RTC_WAKEUP);
+    rtc_disable_wakeup();
+    // The calendar shadow registers are stale after Stop
+    rtc_wait_sync();
+    let elapsed_ms = if timer_expired { period_ms } else { rtc_millis().wrapping_sub(start).min(period_ms) };
+
+    let ticks = elapsed_ms / KERNEL_TICK_PERIOD_MS;
+    STOP_COUNT.fetch_add(1, Ordering::Relaxed);
+    STOP_TICKS.fetch_add(ticks, Ordering::Relaxed);
+    advance_ticks(ticks);
+    true
+}
+
+/// Put the system in the deepest mode allowed until something needs the CPU. Called in a loop
+/// by the idle task, and only by it.
+pub fn idle() {
+    let mode = allowed_mode();
+    if mode == PowerMode::Stop && rtc_clock().is_some() {
+        disable_global_interrupt();
+        // Checked again with interrupts masked: an ISR may have taken a lock since
+        let stopped = allowed_mode() == PowerMode::Stop && stop();
+        enable_global_interrupt();
+        if stopped {
+            return;
+        }
+    }
+    if mode >= PowerMode::Sleep {
+        SLEEP_COUNT.fetch_add(1, Ordering::Relaxed);
+        pwr_sleep();
+    }
+}
+
+/// Enter Standby after the console has sent its output. The MCU restarts from reset on a rising
+/// edge on the WKUP pin (PA0) if `wakeup_pin`, after `rtc_wakeup_ms` if given (RTC running), or
+/// on NRST / a watchdog reset.
+pub fn standby(wakeup_pin: bool, rtc_wakeup_ms: Option<u32>) -> ! {
+    if let Some(serial) = crate::console::serial() {
+        serial.flush();
+    }
+    pwr_standby(wakeup_pin, rtc_wakeup_ms)
+}
@@ -0,0 +1,111 @@
+//! Fixed-capacity message queue between tasks and ISRs.
+//!
+//! Items are copied in and out of a ring buffer guarded by short critical sections; a counting
+//! semaphore tracks the number of queued items so that `receive` can sleep until one arrives.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use crate::critical;
+use crate::sync::Semaphore;
+
+/// Queue of up to `N` items of type `T`.
+///
+/// `send_from_isr` may be called from interrupt handlers; `receive` blocks the calling task until an
+/// item is available or the timeout expires. Any number of producers and consumers may share it.
+pub struct Queue<T: Copy, const N: usize> {
+    items: Semaphore,
+    ring: UnsafeCell<Ring<T, N>>,
+}
+
+struct Ring<T, const N: usize> {
+    buf: [MaybeUninit<T>; N],
+    head: usize,
+    len: usize,
+}
+
+// SAFETY: `ring` is only accessed inside `critical::free`.
+unsafe impl<T: Copy + Send, const N: usize> Sync for Queue<T, N> {}
+
+impl<T: Copy, const N: usize> Queue<T, N> {
+    pub const fn new() -> Self {
+        Queue {
+            items: Semaphore::new(0, N as u32),
+            ring: UnsafeCell::new(Ring { buf: [MaybeUninit::uninit(); N], head: 0, len: 0 }),
+        }
+    }
+
+    /// Number of queued items.
+    pub fn len(&self) -> usize {
+        critical::free(|| unsafe { (*self.ring.get()).len })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    fn push(&self, item: T) -> Result<(), T> {
+        critical::free(|| unsafe {
+            let ring = &mut *self.ring.get();
+            if ring.len == N {
+                return Err(item);
+            }
+            let tail = (ring.head + ring.len) % N;
+            ring.buf[tail] = MaybeUninit::new(item);
+            ring.len += 1;
+            Ok(())
+        })
+    }
+
+    fn pop(&self) -> T {
+        critical::free(|| unsafe {
+            let ring = &mut *self.ring.get();
+            // The semaphore count guarantees an item is present
+            let item = ring.buf[ring.head].assume_init();
+            ring.head = (ring.head + 1) % N;
+            ring.len -= 1;
+            item
+        })
+    }
+
+    /// Append `item` and wake a waiting receiver. Returns the item back if the queue is full.
+    pub fn send(&self, item: T) -> Result<(), T> {
+        self.push(item)?;
+        self.items.give();
+        Ok(())
+    }
+
+    /// Same as `send`, for use inside interrupt handlers at or below `MAX_SYSCALL_PRIORITY`.
+    pub fn send_from_isr(&self, item: T) -> Result<(), T> {
+        self.push(item)?;
+        self.items.give_from_isr();
+        Ok(())
+    }
+
+    /// Remove the oldest item, without blocking.
+    pub fn try_receive(&self) -> Option<T> {
+        if self.items.try_take() {
+            Some(self.pop())
+        } else {
+            None
+        }
+    }
+
+    /// Wait up to `timeout_ticks` (`WAIT_FOREVER` never times out) for an item and remove it.
+    pub fn receive(&self, timeout_ticks: u32) -> Option<T> {
+        if self.items.take(timeout_ticks) {
+            Some(self.pop())
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: Copy, const N: usize> Default for Queue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
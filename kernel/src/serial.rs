@@ -0,0 +1,146 @@
+//! Blocking serial ports on top of `drivers::usart`.
+//!
+//! The USART driver buffers data in interrupt-driven ring buffers. This module registers its RX/TX
+//! notifications so that a task reading from an empty port (or writing to a full one) sleeps on a
+//! semaphore instead of spinning, and is woken by the USART interrupt.
+//!
+//! A USART stops in Stop mode: the ports hold a `power` lock blocking it while a task waits for
+//! data and while bytes are still being sent.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU32, Ordering};
+use drivers::usart::{
+    usart_init, usart_is_tx_idle, usart_read, usart_rx_available, usart_set_notify, usart_tx_free,
+    usart_write, UsartConfig, UsartError,
+};
+use crate::os::{get_tick_count, task_delay};
+use crate::power::{PowerLock, PowerMode};
+use crate::sync::{Semaphore, WAIT_FOREVER};
+
+const NUM_PORTS: usize = 6;
+
+static RX_READY: [Semaphore; NUM_PORTS] = [const { Semaphore::new_binary() }; NUM_PORTS];
+static TX_SPACE: [Semaphore; NUM_PORTS] = [const { Semaphore::new_binary() }; NUM_PORTS];
+
+/// Bit `n` set once USART `n` has been opened.
+static OPEN_PORTS: AtomicU32 = AtomicU32::new(0);
+
+static RX_LOCK: PowerLock = PowerLock::new("serial rx", PowerMode::Stop);
+static TX_LOCK: PowerLock = PowerLock::busy_while("serial tx", PowerMode::Stop, transmitting);
+
+/// Whether an open port still has bytes to send.
+fn transmitting() -> bool {
+    let open = OPEN_PORTS.load(Ordering::Relaxed);
+    (1..=NUM_PORTS as u32).any(|usart| open & (1 << usart) != 0 && !usart_is_tx_idle(usart))
+}
+
+fn rx_notify(usart: u32) {
+    RX_READY[(usart - 1) as usize].give_from_isr();
+}
+
+fn tx_notify(usart: u32) {
+    TX_SPACE[(usart - 1) as usize].give_from_isr();
+}
+
+/// Handle to an initialised USART/UART instance (1–6, see `drivers::usart`).
+#[derive(Clone, Copy)]
+pub struct Serial {
+    usart: u32,
+}
+
+impl Serial {
+    /// Initialise `usart` with `config` and hook its interrupts to the kernel.
+    /// The clock tree must already be frozen (see `drivers::rcc`).
+    pub fn open(usart: u32, config: &UsartConfig) -> Self {
+        usart_init(usart, config);
+        usart_set_notify(usart, Some(rx_notify), Some(tx_notify));
+        OPEN_PORTS.fetch_or(1 << usart, Ordering::Relaxed);
+        TX_LOCK.register();
+        Serial { usart }
+    }
+
+    /// Instance number of this port.
+    pub fn usart(&self) -> u32 {
+        self.usart
+    }
+
+    fn index(&self) -> usize {
+        (self.usart - 1) as usize
+    }
+
+    /// Number of received bytes that can be read without blocking.
+    pub fn available(&self) -> usize {
+        usart_rx_available(self.usart)
+    }
+
+    /// Read at least one byte into `buf`, sleeping until data arrives or `timeout_ticks` elapse
+    /// (`WAIT_FOREVER` to wait indefinitely). Returns `Ok(0)` on timeout.
+    pub fn read(&self, buf: &mut [u8], timeout_ticks: u32) -> Result<usize, UsartError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let start = get_tick_count();
+        let _receiving = RX_LOCK.hold();
+        loop {
+            let count = usart_read(self.usart, buf)?;
+            if count > 0 {
+                return Ok(count);
+            }
+
+            let wait = if timeout_ticks == WAIT_FOREVER {
+                WAIT_FOREVER
+            } else {
+                let elapsed = get_tick_count().wrapping_sub(start);
+                if elapsed >= timeout_ticks {
+                    return Ok(0);
+                }
+                timeout_ticks - elapsed
+            };
+            RX_READY[self.index()].take(wait);
+        }
+    }
+
+    /// Read exactly one byte, sleeping until it arrives or `timeout_ticks` elapse.
+    /// Returns `Ok(None)` on timeout.
+    pub fn read_byte(&self, timeout_ticks: u32) -> Result<Option<u8>, UsartError> {
+        let mut byte = [0u8; 1];
+        match self.read(&mut byte, timeout_ticks)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /// Queue all of `data` for transmission, sleeping whenever the TX buffer is full.
+    pub fn write(&self, data: &[u8]) {
+        let mut sent = 0;
+        while sent < data.len() {
+            sent += usart_write(self.usart, &data[sent..]);
+            if sent < data.len() && usart_tx_free(self.usart) == 0 {
+                TX_SPACE[self.index()].take(WAIT_FOREVER);
+            }
+        }
+    }
+
+    /// Sleep until every queued byte has left the shift register.
+    pub fn flush(&self) {
+        while !usart_is_tx_idle(self.usart) {
+            task_delay(1);
+        }
+    }
+}
+
+/// `core::fmt::Write` adapter for a `Serial` that expands `\n` to `\r\n` for terminals.
+pub struct SerialWriter<'a>(pub &'a Serial);
+
+impl Write for SerialWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.0.write(b"\r\n");
+            }
+            self.0.write(line.as_bytes());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,428 @@
+//! Interactive command shell on a serial port.
+//!
+//! The line editor, history and command table are the hardware-free `command_shell` crate,
+//! re-exported here; this module adds the built-in commands and runs a global shell on a serial
+//! port (`run`).
+//!
+//! Built-in commands: `help`, `ps`, `top`, `mem`, `gpio read|write|toggle <port> <pin> [0|1]`,
+//! `crash [list|show <n>|dump [n]|erase]`, `config [list|get|set|delete|erase]`,
+//! `boot [info|confirm|test <a|b>]`, `update`, `power [info|max|standby]` and `reboot`. Applications add their own with `register_command` (global shell used by `run`)
+//! or `Shell::register`. A command of the global shell can also take over its serial port for
+//! another protocol with `hand_over_port`, as `update` does.
+
+use core::fmt::Write;
+use boot_format::Slot;
+use drivers::cortex_m4::system_reset;
+use drivers::gpio::{gpio_read, gpio_write, toggle_gpio};
+use crate::boot::{self, BootError};
+use crate::config::{self, ConfigError};
+use crate::crashlog;
+use crate::os::{get_tick_count, task_delay, task_info};
+use crate::power::{self, PowerMode};
+use crate::os_config::{KERNEL_TICK_PERIOD_MS, MAX_TASK, TASK_DEAD_STATE, TASK_READY_STATE};
+use crate::serial::{Serial, SerialWriter};
+use crate::sync::{Mutex, WAIT_FOREVER};
+use crate::update;
+use crate::watchdog;
+
+pub use command_shell::{
+    parse_args, run_command, Command, CommandHandler, CommandLine, CommandResult, Shell, HISTORY_DEPTH, MAX_ARGS,
+    MAX_COMMANDS, MAX_LINE, PROMPT,
+};
+
+const BUILTIN_COMMANDS: [Command; 10] = [
+    Command { name: "ps", help: "list tasks with state, priority and watchdog deadline", handler: cmd_ps },
+    Command { name: "top", help: "CPU usage per task over one second", handler: cmd_top },
+    Command { name: "mem", help: "task stack high-water marks", handler: cmd_mem },
+    Command { name: "gpio", help: "gpio read|write|toggle <port> <pin> [0|1]", handler: cmd_gpio },
+    Command { name: "crash", help: "crash [list|show <n>|dump [n]|erase]: stored crash reports", handler: cmd_crash },
+    Command { name: "config", help: "config [list|get <key>|set <key> <value>|delete <key>|erase]: persistent settings", handler: cmd_config },
+    Command { name: "boot", help: "boot [info|confirm|test <a|b>]: firmware slots and test boot", handler: cmd_boot },
+    Command { name: "update", help: "receive a firmware image from tools/fwupdate", handler: cmd_update },
+    Command { name: "power", help: "power [info|max <run|sleep|stop>|standby [seconds]]: low-power modes", handler: cmd_power },
+    Command { name: "reboot", help: "reset the system", handler: cmd_reboot },
+];
+
+// ---------- Global shell task ----------
+
+static SHELL: Mutex<Shell> = Mutex::new(Shell::new(&BUILTIN_COMMANDS));
+static PORT_HANDLER: Mutex<Option<fn(&Serial)>> = Mutex::new(None);
+
+/// Add a command to the shell run by `run`. May be called from any task, before or after `run`.
+pub fn register_command(command: Command) -> bool {
+    SHELL.lock().register(command)
+}
+
+/// Give the serial port of the shell run by `run` to `handler` once the current command returns.
+/// The shell resumes, with a new prompt, when `handler` returns.
+pub fn hand_over_port(handler: fn(&Serial)) {
+    *PORT_HANDLER.lock() = Some(handler);
+}
+
+/// Shell task body: read bytes from `serial` forever and answer on the same port. Commands run
+/// with the shell unlocked, so other tasks can `register_command` meanwhile.
+pub fn run(serial: Serial) -> ! {
+    let mut out = SerialWriter(&serial);
+    let _ = out.write_str("\n");
+    SHELL.lock().prompt(&mut out);
+
+    loop {
+        match serial.read_byte(WAIT_FOREVER) {
+            Ok(Some(byte)) => {
+                let Some(line) = SHELL.lock().feed(byte, &mut out) else {
+                    continue;
+                };
+                let command = SHELL.lock().command(line.as_str(), &mut out);
+                if let Some(command) = command {
+                    run_command(command, line.as_str(), &mut out);
+                }
+                let handler = PORT_HANDLER.lock().take();
+                if let Some(handler) = handler {
+                    handler(&serial);
+                    let _ = out.write_str("\n");
+                }
+                SHELL.lock().prompt(&mut out);
+            }
+            Ok(None) => {}
+            Err(err) => log::warn!("serial error {:?}", err),
+        }
+    }
+}
+
+// ---------- Built-in commands ----------
+
+fn state_name(state: u8, running: bool) -> &'static str {
+    if running {
+        "RUNNING"
+    } else if state == TASK_READY_STATE {
+        "READY"
+    } else if state == TASK_DEAD_STATE {
+        "DEAD"
+    } else {
+        "BLOCKED"
+    }
+}
+
+fn cmd_ps(_args: &[&str], out: &mut dyn Write) -> CommandResult {
+    let _ = writeln!(out, "{:<3} {:<10} {:<8} {:>4} {:>9}", "ID", "NAME", "STATE", "PRIO", "WATCHDOG");
+    for idx in 0..MAX_TASK {
+        if let Some(info) = task_info(idx) {
+            let _ = write!(
+                out,
+                "{:<3} {:<10} {:<8} {:>4}",
+                idx,
+                info.name,
+                state_name(info.state, info.running),
+                info.priority
+            );
+            let _ = match watchdog::deadline_ms(idx) {
+                Some(deadline) => writeln!(out, " {:>6} ms", deadline),
+                None => writeln!(out, " {:>9}", "-"),
+            };
+        }
+    }
+    Ok(())
+}
+
+fn cmd_top(_args: &[&str], out: &mut dyn Write) -> CommandResult {
+    let mut before = [0u32; MAX_TASK];
+    for (idx, ticks) in before.iter_mut().enumerate() {
+        *ticks = task_info(idx).map_or(0, |info| info.run_ticks);
+    }
+    let start = get_tick_count();
+
+    task_delay(1000 / KERNEL_TICK_PERIOD_MS);
+
+    let window = get_tick_count().wrapping_sub(start).max(1);
+    let _ = writeln!(out, "{:<3} {:<10} {:>5}", "ID", "NAME", "CPU%");
+    for (idx, ticks) in before.iter().enumerate() {
+        if let Some(info) = task_info(idx) {
+            let used = info.run_ticks.wrapping_sub(*ticks);
+            let _ = writeln!(out, "{:<3} {:<10} {:>4}%", idx, info.name, used * 100 / window);
+        }
+    }
+    Ok(())
+}
+
+fn cmd_mem(_args: &[&str], out: &mut dyn Write) -> CommandResult {
+    let _ = writeln!(out, "{:<3} {:<10} {:>6} {:>6} {:>6}", "ID", "NAME", "SIZE", "USED", "FREE");
+    for idx in 0..MAX_TASK {
+        if let Some(info) = task_info(idx) {
+            let _ = writeln!(
+                out,
+                "{:<3} {:<10} {:>6} {:>6} {:>6}",
+                idx,
+                info.name,
+                info.stack_size,
+                info.stack_used,
+                info.stack_size - info.stack_used
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Accepts `a`..`i` (case-insensitive) or `0`..`8`.
+fn parse_port(arg: &str) -> Result<u32, &'static str> {
+    let bytes = arg.as_bytes();
+    if bytes.len() == 1 {
+        match bytes[0].to_ascii_lowercase() {
+            c @ b'a'..=b'i' => return Ok((c - b'a') as u32),
+            c @ b'0'..=b'8' => return Ok((c - b'0') as u32),
+            _ => {}
+        }
+    }
+    Err("port must be a..i")
+}
+
+fn parse_pin(arg: &str) -> Result<u32, &'static str> {
+    match arg.parse::<u32>() {
+        Ok(pin) if pin < 16 => Ok(pin),
+        _ => Err("pin must be 0..15"),
+    }
+}
+
+fn cmd_gpio(args: &[&str], out: &mut dyn Write) -> CommandResult {
+    const USAGE: &str = "usage: gpio read|write|toggle <port> <pin> [0|1]";
+    if args.len() < 4 {
+        return Err(USAGE);
+    }
+    let port = parse_port(args[2])?;
+    let pin = parse_pin(args[3])?;
+
+    match (args[1], args.get(4)) {
+        ("read", None) => {
+            let _ = writeln!(out, "{}", gpio_read(port, pin) as u8);
+        }
+        ("write", Some(&"0")) => gpio_write(port, pin, false),
+        ("write", Some(&"1")) => gpio_write(port, pin, true),
+        ("toggle", None) => toggle_gpio(port, pin),
+        _ => return Err(USAGE),
+    }
+    Ok(())
+}
+
+fn parse_record(arg: Option<&&str>) -> Result<usize, &'static str> {
+    match arg.and_then(|arg| arg.parse::<usize>().ok()) {
+        Some(index) if index < crashlog::len() => Ok(index),
+        _ => Err("no such crash record"),
+    }
+}
+
+/// `crash dump` output: `record <n>` then the words of each record, 8 per line prefixed with
+/// their byte offset, then `end`. Parsed by `tools/crashdump`.
+fn dump_record(index: usize, out: &mut dyn Write) {
+    let _ = writeln!(out, "record {}", index);
+    for (line, words) in crashlog::raw(index).chunks(8).enumerate() {
+        let _ = write!(out, "{:04x}:", line * 32);
+        for word in words {
+            let _ = write!(out, " {:08x}", word);
+        }
+        let _ = writeln!(out);
+    }
+}
+
+fn cmd_crash(args: &[&str], out: &mut dyn Write) -> CommandResult {
+    const USAGE: &str = "usage: crash [list|show <n>|dump [n]|erase]";
+    match args.get(1).copied().unwrap_or("list") {
+        "list" => {
+            let used = crashlog::len();
+            let _ = writeln!(out, "{} of {} records used", used, crashlog::capacity());
+            for index in 0..used {
+                match crashlog::record(index) {
+                    Ok(record) => {
+                        let _ = writeln!(out, "{:>3}: {}", index, record.summary());
+                    }
+                    Err(err) => {
+                        let _ = writeln!(out, "{:>3}: {}", index, err);
+                    }
+                }
+            }
+        }
+        "show" => {
+            let index = parse_record(args.get(2))?;
+            let record = crashlog::record(index).map_err(|_| "record is corrupted (use `crash dump`)")?;
+            let _ = write!(out, "{}", record);
+        }
+        "dump" => {
+            let range = match args.get(2) {
+                Some(_) => {
+                    let index = parse_record(args.get(2))?;
+                    index..index + 1
+                }
+                None => 0..crashlog::len(),
+            };
+            for index in range {
+                dump_record(index, out);
+            }
+            let _ = writeln!(out, "end");
+        }
+        "erase" => {
+            let _ = writeln!(out, "erasing...");
+            crashlog::erase().map_err(|_| "flash erase failed")?;
+            let _ = writeln!(out, "ok");
+        }
+        _ => return Err(USAGE),
+    }
+    Ok(())
+}
+
+fn config_error(err: ConfigError) -> &'static str {
+    match err {
+        ConfigError::NotMounted => "configuration store not mounted",
+        ConfigError::Store(kvstore::Error::KeyLength) => "invalid key length",
+        ConfigError::Store(kvstore::Error::ValueLength) => "value too long",
+        ConfigError::Store(kvstore::Error::Full) => "configuration store full",
+        ConfigError::Store(kvstore::Error::BufferTooSmall) => "value too long to show",
+        ConfigError::Store(_) => "flash error",
+    }
+}
+
+/// Values are shown as text when printable, in hex otherwise.
+fn print_value(value: &[u8], out: &mut dyn Write) {
+    match core::str::from_utf8(value) {
+        Ok(text) if !text.chars().any(char::is_control) => {
+            let _ = writeln!(out, "\"{}\"", text);
+        }
+        _ => {
+            for byte in value {
+                let _ = write!(out, "{:02x}", byte);
+            }
+            let _ = writeln!(out);
+        }
+    }
+}
+
+fn cmd_config(args: &[&str], out: &mut dyn Write) -> CommandResult {
+    const USAGE: &str = "usage: config [list|get <key>|set <key> <value>|delete <key>|erase]";
+    match (args.get(1).copied().unwrap_or("list"), args.get(2), args.get(3)) {
+        ("list", None, None) => {
+            let (used, capacity) = config::usage().map_err(config_error)?;
+            config::keys(|key, len| {
+                let _ = writeln!(out, "{:<32} {} bytes", key, len);
+            })
+            .map_err(config_error)?;
+            let _ = writeln!(out, "{} of {} bytes used", used, capacity);
+        }
+        ("get", Some(key), None) => {
+            // Task stacks are small: longer values are only listed
+            let mut value = [0u8; 64];
+            let len = config::get(key, &mut value).map_err(config_error)?.ok_or("key not set")?;
+            print_value(&value[..len], out);
+        }
+        ("set", Some(key), Some(value)) => config::set(key, value.as_bytes()).map_err(config_error)?,
+        ("delete", Some(key), None) => {
+            if !config::delete(key).map_err(config_error)? {
+                return Err("key not set");
+            }
+        }
+        ("erase", None, None) => {
+            let _ = writeln!(out, "erasing...");
+            config::erase().map_err(config_error)?;
+            let _ = writeln!(out, "ok");
+        }
+        _ => return Err(USAGE),
+    }
+    Ok(())
+}
+
+fn boot_error(err: BootError) -> &'static str {
+    match err {
+        BootError::NotInSlot => "not started by the bootloader",
+        BootError::RunningSlot => "slot is running",
+        BootError::Image(_) => "no valid image in slot",
+        BootError::Flash(_) => "flash error",
+    }
+}
+
+fn cmd_boot(args: &[&str], out: &mut dyn Write) -> CommandResult {
+    const USAGE: &str = "usage: boot [info|confirm|test <a|b>]";
+    match (args.get(1).copied().unwrap_or("info"), args.get(2)) {
+        ("info", None) => {
+            let running = boot::running_slot();
+            match boot::state() {
+                Some(state) => {
+                    let _ = writeln!(out, "state: {}", state);
+                }
+                None => {
+                    let _ = writeln!(out, "state: none recorded");
+                }
+            }
+            for slot in Slot::ALL {
+                let marker = if Some(slot) == running { " (running)" } else { "" };
+                match boot::image(slot) {
+                    Ok(header) => {
+                        let _ = writeln!(out, "slot {}: v{}, {} bytes{}", slot, header.version, header.length, marker);
+                    }
+                    Err(err) => {
+                        let _ = writeln!(out, "slot {}: {}{}", slot, err, marker);
+                    }
+                }
+            }
+        }
+        ("confirm", None) => boot::confirm().map_err(boot_error)?,
+        ("test", Some(name)) => {
+            let slot = Slot::from_name(name).ok_or(USAGE)?;
+            boot::request_test_boot(slot).map_err(boot_error)?;
+            let _ = writeln!(out, "slot {} boots on the next reset", slot);
+        }
+        _ => return Err(USAGE),
+    }
+    Ok(())
+}
+
+fn cmd_update(_args: &[&str], out: &mut dyn Write) -> CommandResult {
+    let _ = writeln!(out, "waiting for the image (Ctrl-C to cancel)");
+    hand_over_port(update::serve);
+    Ok(())
+}
+
+fn cmd_power(args: &[&str], out: &mut dyn Write) -> CommandResult {
+    const USAGE: &str = "usage: power [info|max <run|sleep|stop>|standby [seconds]]";
+    match (args.get(1).copied().unwrap_or("info"), args.get(2).copied()) {
+        ("info", None) => {
+            let stats = power::stats();
+            let _ = writeln!(out, "idle mode: {} (max {})", power::allowed_mode(), power::max_mode());
+            let _ = writeln!(
+                out,
+                "sleeps: {}, stops: {} ({} ms in stop)",
+                stats.sleeps,
+                stats.stops,
+                stats.stop_ticks * KERNEL_TICK_PERIOD_MS
+            );
+            let _ = writeln!(out, "{:<16} {:<7} {:>4}", "LOCK", "BLOCKS", "HELD");
+            power::for_each_lock(|lock| {
+                let held = if lock.is_held() { "yes" } else { "no" };
+                let _ = writeln!(out, "{:<16} {:<7} {:>4}", lock.name(), lock.blocks(), held);
+            });
+        }
+        ("max", Some(mode)) => {
+            let mode = match mode {
+                "run" => PowerMode::Run,
+                "sleep" => PowerMode::Sleep,
+                "stop" => PowerMode::Stop,
+                _ => return Err(USAGE),
+            };
+            power::set_max_mode(mode);
+        }
+        ("standby", seconds) => {
+            let wakeup_ms = match seconds.map(str::parse::<u32>) {
+                None => None,
+                Some(Ok(seconds @ 1..=131_072)) => Some(seconds * 1000),
+                Some(_) => return Err(USAGE),
+            };
+            let rtc = if wakeup_ms.is_some() { " or the RTC" } else { "" };
+            let _ = writeln!(out, "entering standby, wake up with the user button (PA0){}", rtc);
+            power::standby(true, wakeup_ms);
+        }
+        _ => return Err(USAGE),
+    }
+    Ok(())
+}
+
+fn cmd_reboot(_args: &[&str], out: &mut dyn Write) -> CommandResult {
+    let _ = writeln!(out, "rebooting...");
+    // Give the UART time to send the message.
+    task_delay(10);
+    system_reset();
+}
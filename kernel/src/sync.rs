@@ -0,0 +1,198 @@
+//! Task synchronisation primitives.
+//!
+//! A task that cannot proceed marks itself blocked with `block_current_task` and is made ready again
+//! by whoever releases the resource (another task or an ISR). Wake-ups may be spurious, so every
+//! wait re-checks its condition in a loop.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::critical;
+use crate::os::{block_current_task, current_task, get_tick_count, schedule, unblock_task, MAX_DELAY_TICKS};
+use crate::os_config::MAX_TASK;
+
+/// Timeout value meaning "wait forever".
+pub const WAIT_FOREVER: u32 = u32::MAX;
+
+/// Counting semaphore.
+///
+/// `give_from_isr` may be called from interrupt handlers; `take` blocks the calling task until the
+/// count is non-zero or the timeout expires.
+pub struct Semaphore {
+    count: AtomicU32,
+    max: u32,
+    waiters: AtomicU32, // bit i set => task i is waiting
+}
+
+impl Semaphore {
+    /// Counting semaphore starting at `initial`, saturating at `max`.
+    pub const fn new(initial: u32, max: u32) -> Self {
+        Semaphore {
+            count: AtomicU32::new(initial),
+            max,
+            waiters: AtomicU32::new(0),
+        }
+    }
+
+    /// Binary semaphore (max count 1), initially empty.
+    pub const fn new_binary() -> Self {
+        Self::new(0, 1)
+    }
+
+    /// Current count.
+    pub fn count(&self) -> u32 {
+        self.count.load(Ordering::Acquire)
+    }
+
+    /// Decrement the count if it is non-zero, without blocking.
+    pub fn try_take(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |c| c.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Wait until the count can be decremented or `timeout_ticks` elapse
+    /// (`WAIT_FOREVER` never times out). Returns `true` if the semaphore was taken.
+    pub fn take(&self, timeout_ticks: u32) -> bool {
+        let start = get_tick_count();
+        let bit = 1u32 << current_task();
+
+        loop {
+            if self.try_take() {
+                return true;
+            }
+
+            let remaining = if timeout_ticks == WAIT_FOREVER {
+                MAX_DELAY_TICKS
+            } else {
+                let elapsed = get_tick_count().wrapping_sub(start);
+                if elapsed >= timeout_ticks {
+                    return false;
+                }
+                timeout_ticks - elapsed
+            };
+
+            // Register as waiter, re-check and block inside a critical section so a `give` from an
+            // ISR cannot slip in between. The context switch happens once the section is left.
+            let taken = critical::free(|| {
+                if self.try_take() {
+                    return true;
+                }
+                self.waiters.fetch_or(bit, Ordering::AcqRel);
+                block_current_task(remaining);
+                false
+            });
+            if taken {
+                return true;
+            }
+
+            self.waiters.fetch_and(!bit, Ordering::AcqRel);
+        }
+    }
+
+    /// Increment the count and wake the waiting tasks. Returns `false` if already at `max`.
+    /// A context switch is only requested when a waiting task was woken.
+    pub fn give(&self) -> bool {
+        self.give_from_isr()
+    }
+
+    /// Same as `give`, for use inside interrupt handlers at or below `MAX_SYSCALL_PRIORITY`. The
+    /// context switch (if any) is requested through PendSV and happens when the handler returns.
+    pub fn give_from_isr(&self) -> bool {
+        critical::assert_syscall_allowed();
+        let max = self.max;
+        let given = self
+            .count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |c| if c < max { Some(c + 1) } else { None })
+            .is_ok();
+
+        let waiters = self.waiters.swap(0, Ordering::AcqRel);
+        if waiters != 0 {
+            for idx in 0..MAX_TASK {
+                if waiters & (1 << idx) != 0 {
+                    unblock_task(idx);
+                }
+            }
+            schedule();
+        }
+        given
+    }
+}
+
+/// Mutual exclusion between tasks, built on a binary semaphore.
+///
+/// Must not be locked from an ISR (an ISR cannot sleep); use `try_lock` there if unavoidable.
+pub struct Mutex<T> {
+    lock: Semaphore,
+    data: UnsafeCell<T>,
+}
+
+// SAFETY: access to `data` is serialised by `lock`.
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Mutex {
+            lock: Semaphore::new(1, 1),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Sleep until the mutex is free, then lock it.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.lock.take(WAIT_FOREVER);
+        MutexGuard { mutex: self }
+    }
+
+    /// Lock the mutex, giving up after `timeout_ticks`.
+    pub fn lock_timeout(&self, timeout_ticks: u32) -> Option<MutexGuard<'_, T>> {
+        if self.lock.take(timeout_ticks) {
+            Some(MutexGuard { mutex: self })
+        } else {
+            None
+        }
+    }
+
+    /// Lock the mutex only if it is free right now.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.lock.try_take() {
+            Some(MutexGuard { mutex: self })
+        } else {
+            None
+        }
+    }
+
+    /// Access the data without locking.
+    ///
+    /// # Safety
+    /// The caller must guarantee no other context holds or takes the lock meanwhile
+    /// (e.g. in a panic or fault handler after the system has stopped scheduling).
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn force_get(&self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+/// Releases the mutex when dropped.
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.lock.give();
+    }
+}
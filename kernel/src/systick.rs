@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 
 use core::ptr::{read_volatile, write_volatile};
+use drivers::rcc::clocks;
 
 pub const SYSTICK_BASE : u32 = 0xE000_E010;
 
@@ -63,15 +64,17 @@ impl SysTick {
         }
     }
 
-    /// Delay in microseconds
-    pub fn delay_us(&mut self, us: u32, core_clk_mhz: u32) {
-        let ticks = core_clk_mhz * us;
+    /// Delay in microseconds (at most 0xFF_FFFF core ticks, ~99 ms at 168 MHz)
+    pub fn delay_us(&mut self, us: u32) {
+        let ticks = clocks().hclk_mhz() * us;
         self.delay_ticks(ticks);
     }
 
     /// Delay in milliseconds
-    pub fn delay_ms(&mut self, ms: u32, core_clk_mhz: u32) {
-        self.delay_us(ms * 1_000, core_clk_mhz);
+    pub fn delay_ms(&mut self, ms: u32) {
+        for _ in 0..ms {
+            self.delay_us(1_000);
+        }
     }
 
     /// Delay in core ticks
@@ -104,14 +107,14 @@ impl SysTick {
     }
 
     /// Initialize interrupt to fire every `us` microseconds
-    pub fn init_systic_interrupt_us(&mut self, interval_us: u32, core_clk_mhz: u32) {
-        let ticks = core_clk_mhz * interval_us;
+    pub fn init_systic_interrupt_us(&mut self, interval_us: u32) {
+        let ticks = clocks().hclk_mhz() * interval_us;
         self.configure_interrupt_ticks(ticks);
     }
 
     /// Initialize interrupt to fire every `ms` milliseconds
-    pub fn init_systic_interrupt_ms(&mut self, interval_ms: u32, core_clk_mhz: u32) {
-        let ticks = core_clk_mhz * 1_000 * interval_ms;
+    pub fn init_systic_interrupt_ms(&mut self, interval_ms: u32) {
+        let ticks = clocks().hclk_mhz() * 1_000 * interval_ms;
         self.configure_interrupt_ticks(ticks);
     }
 
@@ -0,0 +1,43 @@
+//! Kernel event trace.
+//!
+//! A small ring of the most recent scheduler events (context switches, blocking, wake-ups, task
+//! kills) plus application events added with `user`. It costs a few instructions per event and is
+//! meant for post-mortem analysis: the crash log stores its last entries with every crash report.
+
+use crashlog_format::{TraceEvent, TraceKind};
+use crate::critical;
+use crate::os::{current_task, get_tick_count};
+
+/// Number of events kept.
+pub const TRACE_LEN: usize = 64;
+
+static mut EVENTS: [TraceEvent; TRACE_LEN] = [TraceEvent::EMPTY; TRACE_LEN];
+static mut NEXT: usize = 0;
+static mut COUNT: usize = 0;
+
+/// Record a kernel event with the current tick and task.
+pub fn record(kind: TraceKind, arg: u16) {
+    let event = TraceEvent { tick: get_tick_count(), kind: kind as u8, task: current_task() as u8, arg };
+    critical::free(|| unsafe {
+        EVENTS[NEXT] = event;
+        NEXT = (NEXT + 1) % TRACE_LEN;
+        COUNT = (COUNT + 1).min(TRACE_LEN);
+    });
+}
+
+/// Record an application event.
+pub fn user(arg: u16) {
+    record(TraceKind::User, arg);
+}
+
+/// Copy the most recent events into `out`, oldest first. Returns the number copied.
+pub fn snapshot(out: &mut [TraceEvent]) -> usize {
+    critical::free(|| unsafe {
+        let len = COUNT.min(out.len());
+        let first = (NEXT + TRACE_LEN - len) % TRACE_LEN;
+        for (i, slot) in out.iter_mut().take(len).enumerate() {
+            *slot = EVENTS[(first + i) % TRACE_LEN];
+        }
+        len
+    })
+}
@@ -0,0 +1,97 @@
+//! Firmware update over the shell serial port.
+//!
+//! The shell `update` command hands its port to `serve`, which runs the `update_protocol` receiver
+//! on it until the host ends the session (or stays silent for `IDLE_TIMEOUT_MS`, or Ctrl-C is
+//! received between frames). The host tool (`tools/fwupdate`) writes an image into the slot that
+//! is not running; the image is test-booted at the next reset and must confirm itself (see
+//! `boot`). Console output is deferred while the port speaks the protocol.
+
+use boot_format::Slot;
+use drivers::cortex_m4::system_reset;
+use update_protocol::{Decoder, DeviceInfo, Event, NakReason, Receiver, Target, FRAME_GAP_MS, MAX_REPLY};
+use crate::boot::{self, BootError};
+use crate::console;
+use crate::os_config::KERNEL_TICK_PERIOD_MS;
+use crate::serial::Serial;
+
+/// A session ends after this long without receiving anything.
+pub const IDLE_TIMEOUT_MS: u32 = 30_000;
+
+const CTRL_C: u8 = 0x03;
+
+/// The firmware slots of internal flash.
+struct FlashTarget;
+
+fn nak_reason(err: BootError) -> NakReason {
+    match err {
+        BootError::NotInSlot => NakReason::State,
+        BootError::RunningSlot => NakReason::RunningSlot,
+        BootError::Image(_) => NakReason::BadImage,
+        BootError::Flash(_) => NakReason::Flash,
+    }
+}
+
+impl Target for FlashTarget {
+    fn info(&self) -> DeviceInfo {
+        let running = boot::running_slot();
+        DeviceInfo { running, version: running.and_then(boot::header).map(|header| header.version) }
+    }
+
+    fn begin(&mut self, slot: Slot, _length: u32) -> Result<(), NakReason> {
+        boot::erase_slot(slot).map_err(nak_reason)
+    }
+
+    fn write(&mut self, slot: Slot, offset: u32, data: &[u8]) -> Result<(), NakReason> {
+        boot::write_slot(slot, offset, data).map_err(nak_reason)
+    }
+
+    fn finish(&mut self, slot: Slot) -> Result<(), NakReason> {
+        boot::request_test_boot(slot).map_err(nak_reason)
+    }
+}
+
+/// Frame being received, kept off the (small) task stack.
+static mut DECODER: Decoder = Decoder::new();
+
+/// Run an update session on `serial`. Resets the system if the host asks for it.
+pub fn serve(serial: &Serial) {
+    let decoder = &raw mut DECODER;
+    let decoder = unsafe { &mut *decoder };
+    decoder.reset();
+    let mut receiver = Receiver::new();
+    let mut reply = [0u8; MAX_REPLY];
+
+    console::set_deferred(true);
+    loop {
+        let timeout_ms = if decoder.is_idle() { IDLE_TIMEOUT_MS } else { FRAME_GAP_MS };
+        let byte = match serial.read_byte(timeout_ms / KERNEL_TICK_PERIOD_MS) {
+            Ok(Some(byte)) => byte,
+            // The rest of the frame was lost: the host sends it again
+            Ok(None) if !decoder.is_idle() => {
+                decoder.reset();
+                continue;
+            }
+            Ok(None) => break,
+            Err(_) => continue,
+        };
+        if byte == CTRL_C && decoder.is_idle() {
+            break;
+        }
+        let Some(frame) = decoder.feed(byte) else {
+            continue;
+        };
+        let (len, event) = receiver.handle(&frame, &mut FlashTarget, &mut reply);
+        serial.write(&reply[..len]);
+        match event {
+            Event::Installed(slot) => log::info!("update: slot {} installed, test boot at the next reset", slot),
+            Event::Aborted => break,
+            Event::Reboot => {
+                serial.flush();
+                system_reset();
+            }
+            Event::Started | Event::None => {}
+        }
+    }
+    console::set_deferred(false);
+    console::flush_deferred();
+}
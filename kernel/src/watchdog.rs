@@ -0,0 +1,212 @@
+//! Task liveness supervision on top of the independent watchdog.
+//!
+//! A task opts in with `register(deadline_ms)` and must then call `check_in` at least once per
+//! deadline. A monitor run from the SysTick handler feeds the hardware watchdog (`iwdg`) only
+//! while every registered task is on time: when one misses its deadline, the monitor records a
+//! `StallReport` in a `.noinit` RAM slot and stops feeding, and the hardware resets the MCU once
+//! its timeout expires. A scheduler that stops ticking altogether (interrupts masked for good,
+//! an ISR stuck) also starves the watchdog, without a report.
+//!
+//! After the reset, `last_stall` returns the report (e.g. to log it at start-up) until
+//! `clear_last_stall` is called; `drivers::rcc::reset_cause` tells a watchdog reset from others.
+//!
+//! Deadlines are measured in kernel ticks, which do not advance while the CPU is stalled on a
+//! flash erase; the hardware timeout, which does, must exceed the longest erase (~2 s for a
+//! 128 KB sector).
+
+use core::fmt;
+use drivers::iwdg::{iwdg_feed, iwdg_freeze_on_debug, iwdg_start};
+use crate::critical;
+use crate::os::{current_task, get_tick_count, task_info};
+use crate::os_config::{KERNEL_TICK_PERIOD_MS, MAX_TASK};
+
+/// A registered task: check-in deadline and tick of its last check-in.
+#[derive(Clone, Copy)]
+struct Watch {
+    deadline_ticks: u32,
+    last_check_in: u32,
+}
+
+static mut WATCHES: [Option<Watch>; MAX_TASK] = [None; MAX_TASK];
+
+/// The hardware watchdog runs and the monitor feeds it.
+static mut RUNNING: bool = false;
+
+/// Hardware timeout applied by `start`, in milliseconds.
+static mut TIMEOUT_MS: u32 = 0;
+
+/// A task missed its deadline: the watchdog is no longer fed.
+static mut STALLED: bool = false;
+
+/// The task that missed its deadline and by how much.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct StallReport {
+    /// Index of the stalled task.
+    pub task: u32,
+    /// Check-in deadline of the task, in milliseconds.
+    pub deadline_ms: u32,
+    /// Time since its last check-in when the monitor gave up, in milliseconds.
+    pub silent_ms: u32,
+    /// Kernel tick at detection.
+    pub tick: u32,
+}
+
+impl StallReport {
+    /// Name of the stalled task, if the index is valid.
+    pub fn task_name(&self) -> Option<&'static str> {
+        task_info(self.task as usize).map(|info| info.name)
+    }
+}
+
+impl fmt::Display for StallReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "task {}", self.task)?;
+        if let Some(name) = self.task_name() {
+            write!(f, " ({})", name)?;
+        }
+        write!(
+            f,
+            " missed its {} ms deadline: no check-in for {} ms at tick {}",
+            self.deadline_ms, self.silent_ms, self.tick
+        )
+    }
+}
+
+// ---------- Report preserved across reset ----------
+
+const REPORT_MAGIC: u32 = 0x57D0_57A1;
+
+#[repr(C)]
+struct NoInitSlot {
+    magic: u32,
+    report: StallReport,
+    /// Bitwise complement of `magic`, written last: a torn write is detected.
+    check: u32,
+}
+
+/// Not zeroed at start-up (see the `.noinit` output section in memory.x).
+#[unsafe(link_section = ".noinit.watchdog_stall")]
+static mut LAST_STALL: core::mem::MaybeUninit<NoInitSlot> = core::mem::MaybeUninit::uninit();
+
+fn slot() -> *mut NoInitSlot {
+    (&raw mut LAST_STALL).cast()
+}
+
+/// Report of the stall that caused the last watchdog reset, if any.
+pub fn last_stall() -> Option<StallReport> {
+    let slot = slot();
+    unsafe {
+        let magic = core::ptr::read_volatile(&raw const (*slot).magic);
+        let check = core::ptr::read_volatile(&raw const (*slot).check);
+        if magic != REPORT_MAGIC || check != !REPORT_MAGIC {
+            return None;
+        }
+        Some(core::ptr::read_volatile(&raw const (*slot).report))
+    }
+}
+
+/// Forget the preserved report.
+pub fn clear_last_stall() {
+    let slot = slot();
+    unsafe {
+        core::ptr::write_volatile(&raw mut (*slot).magic, 0);
+        core::ptr::write_volatile(&raw mut (*slot).check, 0);
+    }
+}
+
+fn store_report(report: &StallReport) {
+    let slot = slot();
+    unsafe {
+        core::ptr::write_volatile(&raw mut (*slot).check, 0);
+        core::ptr::write_volatile(&raw mut (*slot).magic, REPORT_MAGIC);
+        core::ptr::write_volatile(&raw mut (*slot).report, *report);
+        core::ptr::write_volatile(&raw mut (*slot).check, !REPORT_MAGIC);
+    }
+}
+
+// ---------- Supervision ----------
+
+/// Start the independent watchdog with a timeout of `timeout_ms` and its monitor. The watchdog
+/// cannot be stopped again; it is frozen while a debugger halts the core. Returns the timeout
+/// applied (at the nominal LSI frequency, see `iwdg`).
+pub fn start(timeout_ms: u32) -> u32 {
+    iwdg_freeze_on_debug(true);
+    let config = iwdg_start(timeout_ms);
+    critical::free(|| unsafe {
+        RUNNING = true;
+        TIMEOUT_MS = config.timeout_ms();
+    });
+    config.timeout_ms()
+}
+
+/// Hardware timeout if the watchdog runs. It keeps counting in Stop mode, where nothing feeds it.
+pub(crate) fn timeout_ms() -> Option<u32> {
+    critical::free(|| unsafe { RUNNING.then_some(TIMEOUT_MS) })
+}
+
+/// Put the calling task under supervision: it must call `check_in` at least every
+/// `deadline_ms`, starting now. Registering again changes the deadline.
+pub fn register(deadline_ms: u32) {
+    assert!(deadline_ms > 0, "Watchdog deadline must not be zero");
+    let task = current_task();
+    let deadline_ticks = deadline_ms.div_ceil(KERNEL_TICK_PERIOD_MS);
+    critical::free(|| unsafe {
+        WATCHES[task] = Some(Watch { deadline_ticks, last_check_in: get_tick_count() });
+    });
+}
+
+/// Stop supervising the calling task, e.g. before it waits for something indefinitely.
+pub fn unregister() {
+    let task = current_task();
+    critical::free(|| unsafe {
+        WATCHES[task] = None;
+    });
+}
+
+/// Report the calling task alive. Does nothing for a task that is not registered.
+pub fn check_in() {
+    let task = current_task();
+    critical::free(|| unsafe {
+        if let Some(watch) = WATCHES[task] {
+            WATCHES[task] = Some(Watch { last_check_in: get_tick_count(), ..watch });
+        }
+    });
+}
+
+/// Whether task `idx` is registered, with its deadline in milliseconds.
+pub fn deadline_ms(idx: usize) -> Option<u32> {
+    if idx >= MAX_TASK {
+        return None;
+    }
+    critical::free(|| unsafe { WATCHES[idx] }).map(|watch| watch.deadline_ticks * KERNEL_TICK_PERIOD_MS)
+}
+
+/// Called from the SysTick handler on every tick: feed the watchdog unless a task is late.
+pub(crate) fn monitor(now: u32) {
+    unsafe {
+        if !RUNNING || STALLED {
+            return;
+        }
+        #[allow(clippy::needless_range_loop)]
+        for task in 0..MAX_TASK {
+            let Some(watch) = WATCHES[task] else {
+                continue;
+            };
+            let silent = now.wrapping_sub(watch.last_check_in);
+            if silent > watch.deadline_ticks {
+                STALLED = true;
+                let report = StallReport {
+                    task: task as u32,
+                    deadline_ms: watch.deadline_ticks * KERNEL_TICK_PERIOD_MS,
+                    silent_ms: silent * KERNEL_TICK_PERIOD_MS,
+                    tick: now,
+                };
+                store_report(&report);
+                crate::console::emergency_print(format_args!("\n*** WATCHDOG: {}, resetting\n", report));
+                return;
+            }
+        }
+    }
+    iwdg_feed();
+}
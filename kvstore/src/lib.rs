@@ -0,0 +1,916 @@
+//! Wear-levelled key-value store on a pair of flash sectors.
+//!
+//! The store is a log: `set` and `delete` append a record to the active sector and never modify
+//! data in place, so a key can be rewritten many times before the sector needs an erase. When the
+//! active sector is full, the live records are copied to the other sector, which then becomes the
+//! active one ("compaction"); the two sectors are therefore erased alternately and at the rate of
+//! one erase per sector-full of writes.
+//!
+//! Sector layout: an 8-byte header (`SECTOR_MAGIC`, generation) followed by records. A record is
+//!
+//! | Bytes | Content                                                                   |
+//! |-------|---------------------------------------------------------------------------|
+//! | 0–3   | key length (8 bits), value length (16 bits, `0xFFFF` = deleted), check    |
+//! | 4–7   | CRC-32 of bytes 0–3, the key and the value                                |
+//! | 8–    | key, value, `0xFF` padding to a multiple of 4 bytes                       |
+//!
+//! Power-failure safety relies on the write order. A record is written header first and CRC
+//! last: a record cut short occupies its space but fails its CRC and is ignored, so the previous
+//! value of the key stays current. During compaction the header of the new sector is written only
+//! once every live record has been copied; until then the old sector remains the active one, and
+//! when both headers are valid the higher generation wins. The old sector is not erased after a
+//! compaction but before it is reused, which also makes an interrupted erase harmless.
+//!
+//! The store only needs the small `Flash` trait. The firmware implements it over internal flash
+//! (kernel `config` module); `RamFlash` simulates it in RAM, including power cuts, so the store can
+//! be exercised on a host.
+
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt;
+use boot_format::Crc32;
+
+/// First word of the header of a formatted sector.
+pub const SECTOR_MAGIC: u32 = 0x4B56_5331;
+
+/// Longest key, in bytes.
+pub const MAX_KEY_LEN: usize = 32;
+
+/// Longest value, in bytes.
+pub const MAX_VALUE_LEN: usize = 1024;
+
+const ERASED: u32 = 0xFFFF_FFFF;
+const SECTOR_HEADER_SIZE: u32 = 8;
+const RECORD_HEADER_SIZE: u32 = 8;
+const TOMBSTONE: u16 = 0xFFFF;
+const HEADER_CHECK: u8 = 0x5A;
+
+/// Bytes moved per flash access when copying or comparing records.
+const CHUNK: usize = 32;
+
+/// The two flash sectors used by a `Store`.
+///
+/// Both sectors have the same size. Offsets and lengths passed to `write` are multiples of 4, and
+/// writing may only clear bits of erased (`0xFF`) bytes, as on real flash.
+pub trait Flash {
+    type Error;
+
+    /// Size of each sector in bytes.
+    fn sector_size(&self) -> u32;
+
+    /// Read `buf.len()` bytes of `sector` starting at `offset`.
+    fn read(&self, sector: usize, offset: u32, buf: &mut [u8]);
+
+    /// Program `data` into `sector` at `offset`.
+    fn write(&mut self, sector: usize, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Erase `sector` to `0xFF`.
+    fn erase(&mut self, sector: usize) -> Result<(), Self::Error>;
+}
+
+/// Errors returned by the store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    /// The flash reported an error.
+    Flash(E),
+    /// Key empty or longer than `MAX_KEY_LEN`.
+    KeyLength,
+    /// Value longer than `MAX_VALUE_LEN`.
+    ValueLength,
+    /// The buffer passed to `get` cannot hold the value (see `Store::value_len`).
+    BufferTooSmall,
+    /// The live records do not leave room for the new one, even after compaction.
+    Full,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Self {
+        Error::Flash(err)
+    }
+}
+
+impl<E: fmt::Debug> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Flash(err) => write!(f, "flash error ({:?})", err),
+            Error::KeyLength => write!(f, "key must be 1 to {} bytes long", MAX_KEY_LEN),
+            Error::ValueLength => write!(f, "value longer than {} bytes", MAX_VALUE_LEN),
+            Error::BufferTooSmall => write!(f, "buffer too small for the value"),
+            Error::Full => write!(f, "store full"),
+        }
+    }
+}
+
+fn align4(size: u32) -> u32 {
+    (size + 3) & !3
+}
+
+fn header_check(key_len: u8, value_len: u16) -> u8 {
+    let [lo, hi] = value_len.to_le_bytes();
+    HEADER_CHECK ^ key_len ^ lo ^ hi
+}
+
+fn encode_header(key_len: usize, value_len: u16) -> u32 {
+    let check = header_check(key_len as u8, value_len);
+    key_len as u32 | (value_len as u32) << 8 | (check as u32) << 24
+}
+
+/// A record found in a sector.
+#[derive(Clone, Copy)]
+struct Entry {
+    offset: u32,
+    header: u32,
+    key_len: usize,
+    /// `None` for a deletion.
+    value_len: Option<usize>,
+}
+
+impl Entry {
+    fn data_len(&self) -> usize {
+        self.key_len + self.value_len.unwrap_or(0)
+    }
+
+    /// Space taken in the sector.
+    fn size(&self) -> u32 {
+        align4(RECORD_HEADER_SIZE + self.data_len() as u32)
+    }
+
+    fn key_offset(&self) -> u32 {
+        self.offset + RECORD_HEADER_SIZE
+    }
+
+    fn value_offset(&self) -> u32 {
+        self.key_offset() + self.key_len as u32
+    }
+}
+
+/// What lies at an offset of a sector.
+enum Slot {
+    /// Erased flash: the end of the log.
+    End,
+    /// A header that cannot be trusted (interrupted write): nothing after it can be located.
+    Corrupt,
+    Record(Entry),
+}
+
+/// Key-value store over the two sectors of `F`.
+pub struct Store<F: Flash> {
+    flash: F,
+    active: usize,
+    generation: u32,
+    /// Where the next record goes. The sector size when the tail of the log cannot be written to.
+    write_offset: u32,
+}
+
+impl<F: Flash> Store<F> {
+    /// Open the store, formatting the sectors if neither holds one.
+    pub fn mount(flash: F) -> Result<Self, Error<F::Error>> {
+        let mut store = Store { flash, active: 0, generation: 0, write_offset: SECTOR_HEADER_SIZE };
+        match [store.sector_generation(0), store.sector_generation(1)] {
+            [None, None] => store.format()?,
+            [Some(generation), None] => store.activate(0, generation),
+            [None, Some(generation)] => store.activate(1, generation),
+            [Some(first), Some(second)] => {
+                // The old sector of a compaction keeps its header until it is reused: the newer one wins
+                if (second.wrapping_sub(first) as i32) > 0 {
+                    store.activate(1, second);
+                } else {
+                    store.activate(0, first);
+                }
+            }
+        }
+        Ok(store)
+    }
+
+    /// Erase both sectors and start an empty store.
+    pub fn format(&mut self) -> Result<(), Error<F::Error>> {
+        self.flash.erase(1)?;
+        self.flash.erase(0)?;
+        self.write_sector_header(0, 0)?;
+        self.activate(0, 0);
+        Ok(())
+    }
+
+    /// The underlying flash.
+    pub fn flash(&self) -> &F {
+        &self.flash
+    }
+
+    /// The underlying flash, e.g. to remount it after a simulated power cut.
+    pub fn into_flash(self) -> F {
+        self.flash
+    }
+
+    /// Number of compactions since the store was formatted.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Bytes of the active sector used so far, including superseded records.
+    pub fn used(&self) -> u32 {
+        self.write_offset
+    }
+
+    /// Size of a sector.
+    pub fn capacity(&self) -> u32 {
+        self.flash.sector_size()
+    }
+
+    /// Copy the value of `key` into `buf`. Returns its length, or `None` if the key is not set.
+    pub fn get(&self, key: &str, buf: &mut [u8]) -> Result<Option<usize>, Error<F::Error>> {
+        check_key(key)?;
+        let Some(entry) = self.find(key.as_bytes()) else {
+            return Ok(None);
+        };
+        let Some(len) = entry.value_len else {
+            return Ok(None);
+        };
+        if len > buf.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        self.flash.read(self.active, entry.value_offset(), &mut buf[..len]);
+        Ok(Some(len))
+    }
+
+    /// Length of the value of `key`, or `None` if the key is not set.
+    pub fn value_len(&self, key: &str) -> Result<Option<usize>, Error<F::Error>> {
+        check_key(key)?;
+        Ok(self.find(key.as_bytes()).and_then(|entry| entry.value_len))
+    }
+
+    /// Set `key` to `value`. Writing the value the key already has does not use any flash.
+    pub fn set(&mut self, key: &str, value: &[u8]) -> Result<(), Error<F::Error>> {
+        check_key(key)?;
+        if value.len() > MAX_VALUE_LEN {
+            return Err(Error::ValueLength);
+        }
+        if let Some(entry) = self.find(key.as_bytes())
+            && entry.value_len == Some(value.len())
+            && self.value_eq(&entry, value)
+        {
+            return Ok(());
+        }
+        self.append(key.as_bytes(), Some(value))
+    }
+
+    /// Remove `key`. Returns whether it was set.
+    pub fn delete(&mut self, key: &str) -> Result<bool, Error<F::Error>> {
+        check_key(key)?;
+        match self.find(key.as_bytes()) {
+            Some(Entry { value_len: Some(_), .. }) => {
+                self.append(key.as_bytes(), None)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Call `f` with every key that is set and the length of its value, in the order the keys
+    /// were last written.
+    pub fn keys(&self, mut f: impl FnMut(&str, usize)) {
+        let mut key = [0u8; MAX_KEY_LEN];
+        for entry in self.records(self.active) {
+            let Some(len) = entry.value_len else {
+                continue;
+            };
+            if self.superseded(self.active, &entry) {
+                continue;
+            }
+            let key = &mut key[..entry.key_len];
+            self.flash.read(self.active, entry.key_offset(), key);
+            if let Ok(key) = core::str::from_utf8(key) {
+                f(key, len);
+            }
+        }
+    }
+
+    /// Copy the live records to the other sector and make it the active one.
+    pub fn compact(&mut self) -> Result<(), Error<F::Error>> {
+        let from = self.active;
+        let to = 1 - from;
+        self.flash.erase(to)?;
+
+        let mut offset = SECTOR_HEADER_SIZE;
+        let mut next = SECTOR_HEADER_SIZE;
+        let mut buf = [0u8; CHUNK];
+        while let Some(entry) = (Records { store: self, sector: from, offset: next }).next() {
+            next = entry.offset + entry.size();
+            if entry.value_len.is_none() || self.superseded(from, &entry) {
+                continue;
+            }
+            let size = entry.size();
+            let mut done = 0;
+            while done < size {
+                let n = (size - done).min(CHUNK as u32);
+                let chunk = &mut buf[..n as usize];
+                self.flash.read(from, entry.offset + done, chunk);
+                self.flash.write(to, offset + done, chunk)?;
+                done += n;
+            }
+            offset += size;
+        }
+
+        let generation = self.generation.wrapping_add(1);
+        self.write_sector_header(to, generation)?;
+        self.active = to;
+        self.generation = generation;
+        self.write_offset = offset;
+        Ok(())
+    }
+
+    fn read_word(&self, sector: usize, offset: u32) -> u32 {
+        let mut bytes = [0u8; 4];
+        self.flash.read(sector, offset, &mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// Generation of a formatted sector.
+    fn sector_generation(&self, sector: usize) -> Option<u32> {
+        (self.read_word(sector, 0) == SECTOR_MAGIC).then(|| self.read_word(sector, 4))
+    }
+
+    /// Mark `sector` as formatted. The magic goes last: it is what makes the sector valid.
+    fn write_sector_header(&mut self, sector: usize, generation: u32) -> Result<(), Error<F::Error>> {
+        self.flash.write(sector, 4, &generation.to_le_bytes())?;
+        self.flash.write(sector, 0, &SECTOR_MAGIC.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn activate(&mut self, sector: usize, generation: u32) {
+        self.active = sector;
+        self.generation = generation;
+        self.write_offset = self.log_end(sector);
+    }
+
+    /// Offset after the last record of `sector`, or the sector size if the log ends with a
+    /// damaged header (the next write then compacts the store).
+    fn log_end(&self, sector: usize) -> u32 {
+        let mut offset = SECTOR_HEADER_SIZE;
+        loop {
+            match self.slot(sector, offset) {
+                Slot::End => return offset,
+                Slot::Corrupt => return self.flash.sector_size(),
+                Slot::Record(entry) => offset += entry.size(),
+            }
+        }
+    }
+
+    fn slot(&self, sector: usize, offset: u32) -> Slot {
+        if offset + RECORD_HEADER_SIZE > self.flash.sector_size() {
+            return Slot::End;
+        }
+        let header = self.read_word(sector, offset);
+        if header == ERASED {
+            return Slot::End;
+        }
+        let key_len = (header & 0xFF) as u8;
+        let value_len = (header >> 8) as u16;
+        let check = (header >> 24) as u8;
+        let value_len = if value_len == TOMBSTONE { None } else { Some(value_len as usize) };
+        let entry = Entry { offset, header, key_len: key_len as usize, value_len };
+        let valid = check == header_check(key_len, (header >> 8) as u16)
+            && (1..=MAX_KEY_LEN).contains(&entry.key_len)
+            && entry.value_len.unwrap_or(0) <= MAX_VALUE_LEN
+            && offset + entry.size() <= self.flash.sector_size();
+        if valid { Slot::Record(entry) } else { Slot::Corrupt }
+    }
+
+    /// Whether the stored CRC of `entry` matches its content.
+    fn crc_ok(&self, sector: usize, entry: &Entry) -> bool {
+        let mut crc = Crc32::new();
+        crc.update(&entry.header.to_le_bytes());
+        let mut buf = [0u8; CHUNK];
+        let mut done = 0;
+        while done < entry.data_len() {
+            let n = (entry.data_len() - done).min(CHUNK);
+            self.flash.read(sector, entry.key_offset() + done as u32, &mut buf[..n]);
+            crc.update(&buf[..n]);
+            done += n;
+        }
+        crc.finish() == self.read_word(sector, entry.offset + 4)
+    }
+
+    /// Complete records of `sector`, oldest first.
+    fn records(&self, sector: usize) -> Records<'_, F> {
+        Records { store: self, sector, offset: SECTOR_HEADER_SIZE }
+    }
+
+    fn key_eq(&self, sector: usize, entry: &Entry, key: &[u8]) -> bool {
+        if entry.key_len != key.len() {
+            return false;
+        }
+        let mut stored = [0u8; MAX_KEY_LEN];
+        self.flash.read(sector, entry.key_offset(), &mut stored[..key.len()]);
+        stored[..key.len()] == *key
+    }
+
+    fn value_eq(&self, entry: &Entry, value: &[u8]) -> bool {
+        let mut buf = [0u8; CHUNK];
+        value.chunks(CHUNK).enumerate().all(|(i, chunk)| {
+            let stored = &mut buf[..chunk.len()];
+            self.flash.read(self.active, entry.value_offset() + (i * CHUNK) as u32, stored);
+            stored == chunk
+        })
+    }
+
+    /// Most recent record of `key` in the active sector.
+    fn find(&self, key: &[u8]) -> Option<Entry> {
+        self.records(self.active).filter(|entry| self.key_eq(self.active, entry, key)).last()
+    }
+
+    /// Whether a later record of `sector` has the same key as `entry`.
+    fn superseded(&self, sector: usize, entry: &Entry) -> bool {
+        let mut key = [0u8; MAX_KEY_LEN];
+        let key = &mut key[..entry.key_len];
+        self.flash.read(sector, entry.key_offset(), key);
+        let later = Records { store: self, sector, offset: entry.offset + entry.size() };
+        later.into_iter().any(|other| self.key_eq(sector, &other, key))
+    }
+
+    /// Append a record setting `key` to `value`, or deleting it if `value` is `None`.
+    fn append(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<(), Error<F::Error>> {
+        let value_len = value.map_or(TOMBSTONE, |value| value.len() as u16);
+        let header = encode_header(key.len(), value_len);
+        let value = value.unwrap_or(&[]);
+        let data_len = key.len() + value.len();
+        let size = align4(RECORD_HEADER_SIZE + data_len as u32);
+
+        if self.write_offset + size > self.flash.sector_size() {
+            self.compact()?;
+            if self.write_offset + size > self.flash.sector_size() {
+                return Err(Error::Full);
+            }
+        }
+
+        let mut crc = Crc32::new();
+        crc.update(&header.to_le_bytes());
+        crc.update(key);
+        crc.update(value);
+
+        // Once the header is written the record takes its space whatever happens next; until then
+        // a failed write leaves the tail in an unknown state and nothing must be appended to it.
+        let offset = self.write_offset;
+        self.write_offset = self.flash.sector_size();
+        self.flash.write(self.active, offset, &header.to_le_bytes())?;
+        self.write_offset = offset + size;
+
+        let byte = |i: usize| match i {
+            i if i < key.len() => key[i],
+            i if i < data_len => value[i - key.len()],
+            _ => 0xFF,
+        };
+        let padded = (size - RECORD_HEADER_SIZE) as usize;
+        let mut buf = [0u8; CHUNK];
+        let mut done = 0;
+        while done < padded {
+            let n = (padded - done).min(CHUNK);
+            for (i, b) in buf[..n].iter_mut().enumerate() {
+                *b = byte(done + i);
+            }
+            self.flash.write(self.active, offset + RECORD_HEADER_SIZE + done as u32, &buf[..n])?;
+            done += n;
+        }
+
+        // The CRC completes the record
+        self.flash.write(self.active, offset + 4, &crc.finish().to_le_bytes())?;
+        Ok(())
+    }
+}
+
+fn check_key<E>(key: &str) -> Result<(), Error<E>> {
+    if (1..=MAX_KEY_LEN).contains(&key.len()) { Ok(()) } else { Err(Error::KeyLength) }
+}
+
+/// Iterator over the records of a sector that pass their CRC check.
+struct Records<'a, F: Flash> {
+    store: &'a Store<F>,
+    sector: usize,
+    offset: u32,
+}
+
+impl<F: Flash> Iterator for Records<'_, F> {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        loop {
+            let Slot::Record(entry) = self.store.slot(self.sector, self.offset) else {
+                return None;
+            };
+            self.offset += entry.size();
+            if self.store.crc_ok(self.sector, &entry) {
+                return Some(entry);
+            }
+        }
+    }
+}
+
+/// Errors of `RamFlash`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimError {
+    /// The simulated power cut happened.
+    PowerLoss,
+    /// Programming would have to set bits that are not erased.
+    NotErased,
+    /// Offset or length not a multiple of 4, or beyond the sector.
+    Alignment,
+}
+
+/// Two flash sectors of `SIZE` bytes simulated in RAM, for running the store on a host.
+///
+/// Programming follows flash rules (it can only clear erased bits) and a power cut can be
+/// scheduled with `cut_power_after`: the word writes or erases past the budget fail with
+/// `SimError::PowerLoss` without touching the array, except an erase caught by the cut, which
+/// leaves the first half of its sector erased and the rest as it was.
+#[derive(Clone)]
+pub struct RamFlash<const SIZE: usize> {
+    sectors: [[u8; SIZE]; 2],
+    erase_counts: [u32; 2],
+    budget: Option<u32>,
+    lost: bool,
+}
+
+impl<const SIZE: usize> Default for RamFlash<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize> RamFlash<SIZE> {
+    /// Erased sectors.
+    pub const fn new() -> Self {
+        RamFlash { sectors: [[0xFF; SIZE]; 2], erase_counts: [0; 2], budget: None, lost: false }
+    }
+
+    /// Let `operations` more word writes or erases succeed, then fail everything.
+    pub fn cut_power_after(&mut self, operations: u32) {
+        self.budget = Some(operations);
+    }
+
+    /// Cancel the power cut.
+    pub fn restore_power(&mut self) {
+        self.budget = None;
+        self.lost = false;
+    }
+
+    /// Whether the power cut has happened.
+    pub fn power_lost(&self) -> bool {
+        self.lost
+    }
+
+    /// Content of `sector`.
+    pub fn sector(&self, sector: usize) -> &[u8; SIZE] {
+        &self.sectors[sector]
+    }
+
+    /// Number of times `sector` was erased.
+    pub fn erase_count(&self, sector: usize) -> u32 {
+        self.erase_counts[sector]
+    }
+
+    /// Use up one operation of the power budget.
+    fn spend(&mut self) -> Result<(), SimError> {
+        match &mut self.budget {
+            Some(0) => {
+                self.lost = true;
+                Err(SimError::PowerLoss)
+            }
+            Some(left) => {
+                *left -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl<const SIZE: usize> Flash for RamFlash<SIZE> {
+    type Error = SimError;
+
+    fn sector_size(&self) -> u32 {
+        SIZE as u32
+    }
+
+    fn read(&self, sector: usize, offset: u32, buf: &mut [u8]) {
+        let offset = offset as usize;
+        buf.copy_from_slice(&self.sectors[sector][offset..offset + buf.len()]);
+    }
+
+    fn write(&mut self, sector: usize, offset: u32, data: &[u8]) -> Result<(), SimError> {
+        let offset = offset as usize;
+        if !offset.is_multiple_of(4) || !data.len().is_multiple_of(4) || offset + data.len() > SIZE {
+            return Err(SimError::Alignment);
+        }
+        for (i, word) in data.chunks_exact(4).enumerate() {
+            self.spend()?;
+            let target = &mut self.sectors[sector][offset + 4 * i..offset + 4 * i + 4];
+            if target.iter().zip(word).any(|(old, new)| old & new != *new) {
+                return Err(SimError::NotErased);
+            }
+            target.copy_from_slice(word);
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self, sector: usize) -> Result<(), SimError> {
+        let cut_now = !self.lost;
+        if let Err(err) = self.spend() {
+            if cut_now {
+                self.sectors[sector][..SIZE / 2].fill(0xFF);
+            }
+            return Err(err);
+        }
+        self.sectors[sector].fill(0xFF);
+        self.erase_counts[sector] += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    const SIZE: usize = 256;
+    type Sim = RamFlash<SIZE>;
+    type Model = BTreeMap<String, Vec<u8>>;
+    type Op = fn(&mut Store<Sim>) -> Result<(), Error<SimError>>;
+
+    fn get(store: &Store<Sim>, key: &str) -> Option<Vec<u8>> {
+        let mut buf = [0u8; MAX_VALUE_LEN];
+        store.get(key, &mut buf).unwrap().map(|len| buf[..len].to_vec())
+    }
+
+    /// Every key and value of the store.
+    fn contents(store: &Store<Sim>) -> Model {
+        let mut keys = Vec::new();
+        store.keys(|key, _| keys.push(key.to_string()));
+        keys.into_iter().map(|key| (key.clone(), get(store, &key).unwrap())).collect()
+    }
+
+    fn mount(flash: &Sim) -> Store<Sim> {
+        Store::mount(flash.clone()).unwrap()
+    }
+
+    /// Flash of a store holding a few keys, with `a` rewritten until the next write of `a`
+    /// (`set_a`) compacts the store if `compacting`, or just before that otherwise.
+    fn populated(compacting: bool) -> Sim {
+        let mut store = Store::mount(Sim::new()).unwrap();
+        store.set("b", b"bravo").unwrap();
+        store.set("c", b"charlie").unwrap();
+        store.set("gone", b"deleted").unwrap();
+        store.delete("gone").unwrap();
+        let mut previous = store.flash().clone();
+        for i in 0.. {
+            let before = store.flash().clone();
+            store.set("a", format!("alpha{:03}", i).as_bytes()).unwrap();
+            if store.generation() != 0 {
+                return if compacting { before } else { previous };
+            }
+            previous = before;
+        }
+        unreachable!()
+    }
+
+    fn set_a(store: &mut Store<Sim>) -> Result<(), Error<SimError>> {
+        store.set("a", b"new value")
+    }
+
+    fn delete_b(store: &mut Store<Sim>) -> Result<(), Error<SimError>> {
+        store.delete("b").map(|_| ())
+    }
+
+    fn compact(store: &mut Store<Sim>) -> Result<(), Error<SimError>> {
+        store.compact()
+    }
+
+    /// Run `op` on the store in `flash` with the power cut after each possible number of flash
+    /// writes and erases. After each cut, remount and check that every key holds its value from
+    /// before or after `op`, then (`depth` > 1) cut the power again while `op` is retried, and
+    /// finally retry it to completion. Returns the number of cut points tried.
+    fn check_power_cuts(flash: &Sim, op: Op, depth: u32) -> usize {
+        let before = contents(&mount(flash));
+        let after = {
+            let mut store = mount(flash);
+            op(&mut store).unwrap();
+            contents(&store)
+        };
+
+        let mut cuts = 0;
+        loop {
+            let mut cut_flash = flash.clone();
+            cut_flash.cut_power_after(cuts as u32);
+            let mut store = Store::mount(cut_flash).unwrap();
+            let result = op(&mut store);
+            let mut cut_flash = store.into_flash();
+            if !cut_flash.power_lost() {
+                assert!(result.is_ok());
+                return cuts;
+            }
+            assert_eq!(result, Err(Error::Flash(SimError::PowerLoss)));
+            cut_flash.restore_power();
+
+            let store = mount(&cut_flash);
+            let keys = before.keys().chain(after.keys());
+            for key in keys {
+                let value = get(&store, key);
+                assert!(
+                    value.as_ref() == before.get(key) || value.as_ref() == after.get(key),
+                    "key {} holds {:?} after a power cut at operation {}",
+                    key,
+                    value,
+                    cuts
+                );
+            }
+
+            if depth > 1 {
+                check_power_cuts(&cut_flash, op, depth - 1);
+            }
+            let mut store = mount(&cut_flash);
+            op(&mut store).unwrap();
+            assert_eq!(contents(&store), after);
+            assert_eq!(contents(&mount(store.flash())), after);
+            cuts += 1;
+        }
+    }
+
+    #[test]
+    fn set_get_delete() {
+        let mut store = Store::mount(Sim::new()).unwrap();
+        assert_eq!(get(&store, "key"), None);
+        store.set("key", b"one").unwrap();
+        store.set("other", b"").unwrap();
+        assert_eq!(get(&store, "key").as_deref(), Some(&b"one"[..]));
+        assert_eq!(get(&store, "other").as_deref(), Some(&b""[..]));
+        assert_eq!(store.value_len("key"), Ok(Some(3)));
+
+        store.set("key", b"two").unwrap();
+        assert_eq!(get(&store, "key").as_deref(), Some(&b"two"[..]));
+        assert_eq!(store.delete("key"), Ok(true));
+        assert_eq!(store.delete("key"), Ok(false));
+        assert_eq!(get(&store, "key"), None);
+        assert_eq!(store.value_len("key"), Ok(None));
+
+        let mut keys = Vec::new();
+        store.set("key", b"three").unwrap();
+        store.keys(|key, len| keys.push((key.to_string(), len)));
+        assert_eq!(keys, [("other".to_string(), 0), ("key".to_string(), 5)]);
+
+        // Everything survives a remount
+        let store = mount(store.flash());
+        assert_eq!(get(&store, "key").as_deref(), Some(&b"three"[..]));
+        assert_eq!(get(&store, "other").as_deref(), Some(&b""[..]));
+    }
+
+    #[test]
+    fn rejects_invalid_keys_and_values() {
+        let mut store = Store::mount(Sim::new()).unwrap();
+        let long_key = "k".repeat(MAX_KEY_LEN + 1);
+        assert_eq!(store.set("", b"x"), Err(Error::KeyLength));
+        assert_eq!(store.set(&long_key, b"x"), Err(Error::KeyLength));
+        assert_eq!(store.set("key", &[0; MAX_VALUE_LEN + 1]), Err(Error::ValueLength));
+        assert!(store.set(&"k".repeat(MAX_KEY_LEN), b"x").is_ok());
+
+        store.set("key", b"four").unwrap();
+        assert_eq!(store.get("key", &mut [0; 3]), Err(Error::BufferTooSmall));
+
+        // A value that cannot fit even in an empty sector
+        let mut store = Store::mount(RamFlash::<64>::new()).unwrap();
+        assert_eq!(store.set("key", &[0; 64]), Err(Error::Full));
+    }
+
+    #[test]
+    fn rewriting_the_same_value_uses_no_flash() {
+        let mut store = Store::mount(Sim::new()).unwrap();
+        store.set("key", b"value").unwrap();
+        let used = store.used();
+        let sectors = store.flash().clone().sectors;
+        store.set("key", b"value").unwrap();
+        assert_eq!(store.used(), used);
+        assert_eq!(store.flash().sectors, sectors);
+    }
+
+    #[test]
+    fn compaction_keeps_live_records_and_alternates_sectors() {
+        let mut store = Store::mount(Sim::new()).unwrap();
+        let mut model = Model::new();
+        for i in 0..200 {
+            let key = format!("k{}", i % 5);
+            let value = format!("v{}", i).into_bytes();
+            store.set(&key, &value).unwrap();
+            model.insert(key, value);
+            if i % 7 == 0 {
+                store.delete("k3").unwrap();
+                model.remove("k3");
+            }
+            assert_eq!(contents(&store), model);
+        }
+        let generation = store.generation();
+        assert!(generation > 10);
+        // Both sectors wear at the same rate
+        let erases = [store.flash().erase_count(0), store.flash().erase_count(1)];
+        assert!(erases[0].abs_diff(erases[1]) <= 1, "{:?}", erases);
+
+        let store = mount(store.flash());
+        assert_eq!(store.generation(), generation);
+        assert_eq!(contents(&store), model);
+    }
+
+    #[test]
+    fn store_full() {
+        let mut store = Store::mount(Sim::new()).unwrap();
+        let value = [0x55; 100];
+        store.set("one", &value).unwrap();
+        store.set("two", &value).unwrap();
+        assert_eq!(store.set("three", &value), Err(Error::Full));
+        // The old value of a key stays live until the new one is written
+        assert_eq!(store.set("two", &[0xAA; 100]), Err(Error::Full));
+        assert_eq!(get(&store, "one").as_deref(), Some(&value[..]));
+        assert_eq!(get(&store, "two").as_deref(), Some(&value[..]));
+
+        // A deletion makes room at the next compaction
+        store.delete("one").unwrap();
+        store.set("three", &[0xAA; 100]).unwrap();
+        assert_eq!(get(&store, "three").as_deref(), Some(&[0xAA; 100][..]));
+        assert_eq!(get(&store, "two").as_deref(), Some(&value[..]));
+    }
+
+    #[test]
+    fn ignores_records_failing_their_crc() {
+        let mut store = Store::mount(Sim::new()).unwrap();
+        store.set("key", b"old value").unwrap();
+        let offset = store.used();
+        store.set("key", b"new value").unwrap();
+        store.set("other", b"kept").unwrap();
+
+        // Clear one bit of the new value, as a write cut short or a worn cell would
+        let mut flash = store.into_flash();
+        let word_offset = offset + RECORD_HEADER_SIZE + 4;
+        let mut word = [0u8; 4];
+        flash.read(0, word_offset, &mut word);
+        let word = u32::from_le_bytes(word);
+        flash.write(0, word_offset, &(word & (word - 1)).to_le_bytes()).unwrap();
+
+        let store = Store::mount(flash).unwrap();
+        assert_eq!(get(&store, "key").as_deref(), Some(&b"old value"[..]));
+        assert_eq!(get(&store, "other").as_deref(), Some(&b"kept"[..]));
+    }
+
+    #[test]
+    fn recovers_from_a_damaged_header() {
+        let mut store = Store::mount(Sim::new()).unwrap();
+        store.set("key", b"old").unwrap();
+        let offset = store.used();
+        store.set("key", b"new").unwrap();
+
+        // A header failing its check hides the rest of the log: the next write compacts
+        let mut flash = store.into_flash();
+        let mut header = [0u8; 4];
+        flash.read(0, offset, &mut header);
+        header[3] &= header[3] - 1;
+        flash.write(0, offset, &header).unwrap();
+
+        let mut store = Store::mount(flash).unwrap();
+        assert_eq!(get(&store, "key").as_deref(), Some(&b"old"[..]));
+        assert_eq!(store.used(), SIZE as u32);
+        store.set("more", b"data").unwrap();
+        assert_eq!(store.generation(), 1);
+        assert_eq!(get(&store, "key").as_deref(), Some(&b"old"[..]));
+        assert_eq!(get(&store, "more").as_deref(), Some(&b"data"[..]));
+    }
+
+    #[test]
+    fn power_cut_during_set() {
+        let flash = populated(false);
+        assert_eq!(mount(&flash).generation(), 0);
+        let cuts = check_power_cuts(&flash, set_a, 2);
+        // Header, data, CRC
+        assert_eq!(cuts, 5);
+    }
+
+    #[test]
+    fn power_cut_during_delete() {
+        assert!(check_power_cuts(&populated(false), delete_b, 2) > 0);
+    }
+
+    #[test]
+    fn power_cut_during_set_with_compaction() {
+        let flash = populated(true);
+        let cuts = check_power_cuts(&flash, set_a, 2);
+        // Erase, copy of the live records, sector header, then the record itself
+        assert!(cuts > 10, "{}", cuts);
+    }
+
+    #[test]
+    fn power_cut_during_compaction() {
+        for compacting in [false, true] {
+            let flash = populated(compacting);
+            assert!(check_power_cuts(&flash, compact, 2) > 10);
+        }
+        // Again from the other sector
+        let mut store = mount(&populated(true));
+        store.compact().unwrap();
+        store.set("d", b"delta").unwrap();
+        assert_eq!(store.generation(), 1);
+        assert!(check_power_cuts(store.flash(), compact, 2) > 10);
+    }
+}
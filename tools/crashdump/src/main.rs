@@ -0,0 +1,185 @@
+//! Host tool for the crash records stored in flash by the firmware crash log.
+//!
+//! Talks to the firmware shell over a serial port (`crash list`, `crash dump`, `crash erase`), or
+//! decodes a raw image of the crash log sector read with a debug probe, e.g.
+//! `st-flash read crash.bin 0x080E0000 0x20000`.
+//!
+//! ```text
+//! crashdump list   <port> [baud]
+//! crashdump show   <port> <n> [baud]
+//! crashdump fetch  <port> <file> [baud]
+//! crashdump erase  <port> [baud]
+//! crashdump decode <file>
+//! ```
+
+use std::io::{Read, Write};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use crashlog_format::{DecodeError, Record, RECORD_WORDS};
+
+const DEFAULT_BAUD: u32 = 115_200;
+const PROMPT: &str = "\n> ";
+
+/// Longest a command may take (`crash erase` stalls the device for up to ~2 s).
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+const USAGE: &str = "usage:
+  crashdump list   <port> [baud]          list the stored crash records
+  crashdump show   <port> <n> [baud]      fetch record n and print it in full
+  crashdump fetch  <port> <file> [baud]   save every record to <file> (raw little-endian words)
+  crashdump erase  <port> [baud]          erase the crash log
+  crashdump decode <file>                 print the records of a raw image (fetch output or
+                                          a dump of the crash log flash sector)";
+
+type Result<T> = std::result::Result<T, String>;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("crashdump: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let arg = |i: usize| args.get(i).map(String::as_str);
+    let baud = |i: usize| -> Result<u32> {
+        arg(i).map_or(Ok(DEFAULT_BAUD), |b| b.parse().map_err(|_| format!("invalid baud rate: {}", b)))
+    };
+
+    match (arg(0), arg(1)) {
+        (Some("list"), Some(port)) => {
+            let mut shell = Shell::open(port, baud(2)?)?;
+            print!("{}", shell.command("crash list")?);
+            Ok(())
+        }
+        (Some("show"), Some(port)) => {
+            let index = arg(2).ok_or(USAGE)?;
+            let mut shell = Shell::open(port, baud(3)?)?;
+            let words = parse_dump(&shell.command(&format!("crash dump {}", index))?)?;
+            print_records(&words);
+            Ok(())
+        }
+        (Some("fetch"), Some(port)) => {
+            let path = arg(2).ok_or(USAGE)?;
+            let mut shell = Shell::open(port, baud(3)?)?;
+            let words = parse_dump(&shell.command("crash dump")?)?;
+            let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+            std::fs::write(path, bytes).map_err(|e| format!("{}: {}", path, e))?;
+            println!("{} record(s) saved to {}", words.len() / RECORD_WORDS, path);
+            Ok(())
+        }
+        (Some("erase"), Some(port)) => {
+            let mut shell = Shell::open(port, baud(2)?)?;
+            print!("{}", shell.command("crash erase")?);
+            Ok(())
+        }
+        (Some("decode"), Some(path)) => {
+            let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+            let words: Vec<u32> =
+                bytes.chunks_exact(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+            print_records(&words);
+            Ok(())
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+/// Print every record in `words`, stopping at the first erased slot.
+fn print_records(words: &[u32]) {
+    let mut found = 0;
+    for (index, slot) in words.chunks(RECORD_WORDS).enumerate() {
+        match Record::parse(slot) {
+            Ok(record) => {
+                println!("slot {}: {}", index, record);
+                found += 1;
+            }
+            Err(DecodeError::Empty) => break,
+            Err(err) => println!("slot {}: {}\n", index, err),
+        }
+    }
+    if found == 0 {
+        println!("no crash records");
+    }
+}
+
+/// Words of the `record <n>` blocks printed by `crash dump`, in order.
+fn parse_dump(text: &str) -> Result<Vec<u32>> {
+    let mut words = Vec::new();
+    let mut complete = false;
+    for line in text.lines().map(str::trim) {
+        if line == "end" {
+            complete = true;
+        } else if let Some(err) = line.strip_prefix("error: ") {
+            return Err(format!("device: {}", err));
+        } else if let Some((offset, data)) = line.split_once(':') {
+            if offset.len() != 4 || !offset.bytes().all(|b| b.is_ascii_hexdigit()) {
+                continue;
+            }
+            for word in data.split_whitespace() {
+                words.push(u32::from_str_radix(word, 16).map_err(|_| format!("bad dump line: {}", line))?);
+            }
+        }
+    }
+    if !complete || words.len() % RECORD_WORDS != 0 {
+        return Err("incomplete dump".to_string());
+    }
+    Ok(words)
+}
+
+/// The firmware shell on a serial port.
+struct Shell {
+    port: Box<dyn serialport::SerialPort>,
+}
+
+impl Shell {
+    fn open(path: &str, baud: u32) -> Result<Self> {
+        let port = serialport::new(path, baud)
+            .timeout(Duration::from_millis(100))
+            .open()
+            .map_err(|e| format!("{}: {}", path, e))?;
+        let mut shell = Shell { port };
+        // Abort any partial line and wait for a fresh prompt
+        shell.write("\x03")?;
+        shell.read_until_prompt()?;
+        Ok(shell)
+    }
+
+    fn write(&mut self, text: &str) -> Result<()> {
+        self.port.write_all(text.as_bytes()).map_err(|e| format!("write: {}", e))
+    }
+
+    /// Everything received until the shell prints its prompt again, without the prompt.
+    fn read_until_prompt(&mut self) -> Result<String> {
+        let start = Instant::now();
+        let mut received = Vec::new();
+        let mut chunk = [0u8; 256];
+        loop {
+            match self.port.read(&mut chunk) {
+                Ok(n) => received.extend(chunk[..n].iter().filter(|&&b| b != b'\r')),
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(format!("read: {}", e)),
+            }
+            let text = String::from_utf8_lossy(&received);
+            if text.ends_with(PROMPT) {
+                return Ok(text[..text.len() - 2].to_string());
+            }
+            if start.elapsed() > COMMAND_TIMEOUT {
+                return Err("no answer from the device shell".to_string());
+            }
+        }
+    }
+
+    /// Run `command` and return its output (without the echoed command line).
+    fn command(&mut self, command: &str) -> Result<String> {
+        self.write(command)?;
+        self.write("\r")?;
+        let output = self.read_until_prompt()?;
+        let output = output.split_once('\n').map_or("", |(_echo, rest)| rest);
+        Ok(output.to_string())
+    }
+}
@@ -0,0 +1,271 @@
+//! Firmware update over the device serial shell.
+//!
+//! ```text
+//! fwupdate send <port> <firmware> [--version x.y.z] [--baud n] [--reboot]
+//! fwupdate simulate <dir> [--running a|b] [--drop-every n]
+//! ```
+//!
+//! `send` starts the shell `update` command and sends a slot image with the `update_protocol`
+//! frames. `<firmware>` is an image made by `mkimage`, or an ELF file / flat binary packaged on the
+//! fly with `--version`. It must be linked for the slot that is not running (`APP_SLOT`). The image
+//! is test-booted at the next reset, right away with `--reboot`.
+//!
+//! `simulate` plays the device on a pseudo-terminal, with its slots stored as files in `<dir>`, so
+//! that `send` can be tried without hardware:
+//!
+//! ```text
+//! fwupdate simulate /tmp/device &              # prints the port to use, e.g. /dev/pts/3
+//! fwupdate send /dev/pts/3 app.elf --version 1.2.0 --reboot
+//! ```
+
+use std::io::{Read, Write};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use boot_format::{Slot, Version, IMAGE_MAGIC};
+use mkimage::{check_image, load_firmware, make_image, Result};
+use update_protocol::{encode, u32_at, Decoder, DeviceInfo, Kind, NakReason, CHUNK_SIZE, MAX_FRAME};
+
+#[cfg(unix)]
+mod simulate;
+
+const DEFAULT_BAUD: u32 = 115_200;
+
+/// Times a request is sent before giving up.
+const ATTEMPTS: u32 = 5;
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(1000);
+/// `Start` erases the two sectors of the slot, which takes up to ~8 s.
+const START_TIMEOUT: Duration = Duration::from_secs(15);
+/// `End` checks the image and may erase the boot state sector.
+const END_TIMEOUT: Duration = Duration::from_secs(10);
+
+const USAGE: &str = "usage:
+  fwupdate send <port> <firmware> [--version x.y.z] [--baud n] [--reboot]
+      send a slot image (or an ELF / binary build, packaged with --version) to the device,
+      then reset it to test-boot the image with --reboot
+  fwupdate simulate <dir> [--running a|b] [--drop-every n]
+      play the device on a pseudo-terminal, with its slots in <dir>/slot-a.bin and slot-b.bin,
+      ignoring every n-th frame received";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("fwupdate: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Positional arguments and `--name value` / `--flag` options.
+struct Args<'a> {
+    positional: Vec<&'a str>,
+    options: Vec<(&'a str, Option<&'a str>)>,
+}
+
+impl<'a> Args<'a> {
+    fn parse(args: &'a [String], flags: &[&str]) -> Result<Self> {
+        let mut parsed = Args { positional: Vec::new(), options: Vec::new() };
+        let mut args = args.iter().map(String::as_str);
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if flags.contains(&name) => parsed.options.push((name, None)),
+                Some(name) => parsed.options.push((name, Some(args.next().ok_or(USAGE)?))),
+                None => parsed.positional.push(arg),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|&(n, _)| n == name)
+    }
+
+    fn option(&self, name: &str) -> Option<&'a str> {
+        self.options.iter().find(|&&(n, _)| n == name).and_then(|&(_, value)| value)
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let args = Args::parse(args, &["reboot"])?;
+    match args.positional[..] {
+        ["send", port, firmware] => {
+            let baud = args.option("baud").map_or(Ok(DEFAULT_BAUD), |b| b.parse().map_err(|_| format!("invalid baud rate: {}", b)))?;
+            let version = args
+                .option("version")
+                .map(|v| Version::parse(v).ok_or_else(|| format!("invalid version: {}", v)))
+                .transpose()?;
+            let image = load_image(firmware, version)?;
+            send(port, baud, &image, args.flag("reboot"))
+        }
+        #[cfg(unix)]
+        ["simulate", dir] => {
+            let running = match args.option("running") {
+                Some(name) => Slot::from_name(name).ok_or_else(|| format!("invalid slot: {}", name))?,
+                None => Slot::A,
+            };
+            let drop_every = args
+                .option("drop-every")
+                .map(|n| n.parse().ok().filter(|&n| n > 0).ok_or_else(|| format!("invalid frame count: {}", n)))
+                .transpose()?;
+            simulate::run(std::path::Path::new(dir), running, drop_every)
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+/// Slot image to send: `path` as-is if it is one, else the firmware build it holds packaged as
+/// `version`.
+fn load_image(path: &str, version: Option<Version>) -> Result<Vec<u8>> {
+    let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let image = if bytes.starts_with(&IMAGE_MAGIC.to_le_bytes()) {
+        bytes
+    } else {
+        let version = version.ok_or_else(|| format!("{}: not a slot image, give its --version to package it", path))?;
+        make_image(&load_firmware(path)?, version)?
+    };
+    check_image(&image).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(image)
+}
+
+/// Answer of the device to a request.
+enum Reply {
+    Ack(Vec<u8>),
+    Nak(NakReason, Option<u32>),
+}
+
+/// The device in update mode on a serial port.
+struct Link {
+    port: Box<dyn serialport::SerialPort>,
+    decoder: Decoder,
+    seq: u16,
+}
+
+impl Link {
+    /// Open `path` and start the `update` command of the shell.
+    fn open(path: &str, baud: u32) -> Result<Self> {
+        let port = serialport::new(path, baud)
+            .timeout(Duration::from_millis(20))
+            .open()
+            .map_err(|e| format!("{}: {}", path, e))?;
+        let mut link = Link { port, decoder: Decoder::new(), seq: 0 };
+        // Leave a previous update session or abort a partial command line, then start the command
+        link.write(b"\x03")?;
+        std::thread::sleep(Duration::from_millis(100));
+        link.write(b"update\r")?;
+        Ok(link)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.port.write_all(bytes).map_err(|e| format!("write: {}", e))
+    }
+
+    /// Send a request until the device answers it.
+    fn request(&mut self, kind: Kind, payload: &[u8], timeout: Duration) -> Result<Reply> {
+        self.seq = self.seq.wrapping_add(1);
+        let mut frame = [0u8; MAX_FRAME];
+        let len = encode(kind, self.seq, payload, &mut frame);
+        for _ in 0..ATTEMPTS {
+            // A partial answer left by a damaged length field would swallow the next one
+            self.decoder.reset();
+            self.write(&frame[..len])?;
+            if let Some(reply) = self.wait_reply(timeout)? {
+                return Ok(reply);
+            }
+        }
+        Err(format!("no answer from the device to {:?} (is the shell on this port?)", kind))
+    }
+
+    /// Answer to the last request, `None` if none comes within `timeout`.
+    fn wait_reply(&mut self, timeout: Duration) -> Result<Option<Reply>> {
+        let start = Instant::now();
+        let mut chunk = [0u8; 64];
+        while start.elapsed() < timeout {
+            let n = match self.port.read(&mut chunk) {
+                Ok(n) => n,
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(format!("read: {}", e)),
+            };
+            for &byte in &chunk[..n] {
+                let Some(frame) = self.decoder.feed(byte) else {
+                    continue;
+                };
+                if frame.seq != self.seq {
+                    continue; // late answer to a request sent again
+                }
+                match Kind::from_u8(frame.kind) {
+                    Some(Kind::Ack) => return Ok(Some(Reply::Ack(frame.payload.to_vec()))),
+                    Some(Kind::Nak) => {
+                        let reason = frame.payload.first().and_then(|&r| NakReason::from_u8(r)).unwrap_or(NakReason::Malformed);
+                        return Ok(Some(Reply::Nak(reason, u32_at(frame.payload, 1))));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Send a request that must be acknowledged, returning the `Ack` payload.
+    fn expect_ack(&mut self, kind: Kind, payload: &[u8], timeout: Duration) -> Result<Vec<u8>> {
+        match self.request(kind, payload, timeout)? {
+            Reply::Ack(payload) => Ok(payload),
+            Reply::Nak(reason, _) => Err(format!("device refused {:?}: {}", kind, reason)),
+        }
+    }
+}
+
+fn send(port: &str, baud: u32, image: &[u8], reboot: bool) -> Result<()> {
+    let (header, slot) = check_image(image)?;
+    let mut link = Link::open(port, baud)?;
+
+    let info = DeviceInfo::decode(&link.expect_ack(Kind::Info, &[], REQUEST_TIMEOUT)?).ok_or("invalid device info")?;
+    let running = info.running.ok_or("the device firmware does not run from a slot (no bootloader)")?;
+    match info.version {
+        Some(version) => println!("device runs v{} from slot {}", version, running),
+        None => println!("device runs from slot {}", running),
+    }
+    if slot == running {
+        let _ = link.request(Kind::Abort, &[], REQUEST_TIMEOUT);
+        return Err(format!(
+            "the image is linked for slot {}, which is running: rebuild the firmware with APP_SLOT={}",
+            slot,
+            slot.other()
+        ));
+    }
+
+    println!("erasing slot {}", slot);
+    let mut start = [slot as u8, 0, 0, 0, 0];
+    start[1..].copy_from_slice(&(image.len() as u32).to_le_bytes());
+    link.expect_ack(Kind::Start, &start, START_TIMEOUT)?;
+
+    let begun = Instant::now();
+    let mut offset = 0;
+    let mut payload = Vec::with_capacity(4 + CHUNK_SIZE);
+    while offset < image.len() {
+        let end = (offset + CHUNK_SIZE).min(image.len());
+        payload.clear();
+        payload.extend_from_slice(&(offset as u32).to_le_bytes());
+        payload.extend_from_slice(&image[offset..end]);
+        offset = match link.request(Kind::Data, &payload, REQUEST_TIMEOUT)? {
+            Reply::Ack(next) => u32_at(&next, 0).ok_or("invalid Data answer")? as usize,
+            // The device lost track of a frame: resume where it stands
+            Reply::Nak(NakReason::Offset, Some(expected)) => expected as usize,
+            Reply::Nak(reason, _) => return Err(format!("device refused data at {:#x}: {}", offset, reason)),
+        };
+        print!("\rsending v{} to slot {}: {}/{} bytes", header.version, slot, offset, image.len());
+        let _ = std::io::stdout().flush();
+    }
+    println!(" ({:.1} s)", begun.elapsed().as_secs_f32());
+
+    link.expect_ack(Kind::End, &[], END_TIMEOUT)?;
+    if reboot {
+        link.expect_ack(Kind::Reboot, &[], REQUEST_TIMEOUT)?;
+        println!("installed, the device resets to test-boot v{}", header.version);
+    } else {
+        link.expect_ack(Kind::Abort, &[], REQUEST_TIMEOUT)?;
+        println!("installed, v{} is test-booted at the next reset", header.version);
+    }
+    Ok(())
+}
@@ -0,0 +1,197 @@
+//! The device side of an update on a pseudo-terminal: a minimal shell whose `update` command runs
+//! the same `Receiver` as the firmware, over slots kept in files.
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use boot_format::{check_slot, ImageHeader, Slot, SLOT_SIZE};
+use mkimage::Result;
+use serialport::{SerialPort, TTYPort};
+use update_protocol::{Decoder, DeviceInfo, Event, NakReason, Receiver, Target, FRAME_GAP_MS, MAX_REPLY};
+
+const PROMPT: &str = "\r\n> ";
+const CTRL_C: u8 = 0x03;
+/// Same as the firmware (`kernel::update::IDLE_TIMEOUT_MS`).
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Firmware slots stored in `<dir>/slot-<a|b>.bin`, programmed like flash: a byte can only be
+/// written once after an erase.
+struct SimTarget {
+    dir: PathBuf,
+    running: Slot,
+    slots: [Vec<u8>; 2],
+}
+
+impl SimTarget {
+    fn open(dir: &Path, running: Slot) -> Result<Self> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let mut target = SimTarget { dir: dir.to_path_buf(), running, slots: [Vec::new(), Vec::new()] };
+        for slot in Slot::ALL {
+            let mut contents = std::fs::read(target.path(slot)).unwrap_or_default();
+            contents.resize(SLOT_SIZE as usize, 0xFF);
+            target.slots[slot as usize] = contents;
+        }
+        Ok(target)
+    }
+
+    fn path(&self, slot: Slot) -> PathBuf {
+        self.dir.join(format!("slot-{}.bin", slot.to_string().to_lowercase()))
+    }
+
+    fn save(&self, slot: Slot) -> std::result::Result<(), NakReason> {
+        std::fs::write(self.path(slot), &self.slots[slot as usize]).map_err(|e| {
+            eprintln!("{}: {}", self.path(slot).display(), e);
+            NakReason::Flash
+        })
+    }
+}
+
+impl Target for SimTarget {
+    fn info(&self) -> DeviceInfo {
+        let header = ImageHeader::parse(&self.slots[self.running as usize]).ok();
+        DeviceInfo { running: Some(self.running), version: header.map(|header| header.version) }
+    }
+
+    fn begin(&mut self, slot: Slot, length: u32) -> std::result::Result<(), NakReason> {
+        println!("erase slot {} for {} bytes", slot, length);
+        self.slots[slot as usize].fill(0xFF);
+        self.save(slot)
+    }
+
+    fn write(&mut self, slot: Slot, offset: u32, data: &[u8]) -> std::result::Result<(), NakReason> {
+        let target = &mut self.slots[slot as usize][offset as usize..offset as usize + data.len()];
+        if target.iter().any(|&b| b != 0xFF) {
+            return Err(NakReason::Flash);
+        }
+        target.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn finish(&mut self, slot: Slot) -> std::result::Result<(), NakReason> {
+        let header = check_slot(slot, &self.slots[slot as usize]).map_err(|err| {
+            println!("slot {}: {}", slot, err);
+            NakReason::BadImage
+        })?;
+        self.save(slot)?;
+        println!("slot {}: v{} installed, test boot at the next reset", slot, header.version);
+        Ok(())
+    }
+}
+
+/// Serve the simulated shell until an update session asks for a reset.
+pub fn run(dir: &Path, running: Slot, drop_every: Option<u32>) -> Result<()> {
+    let mut target = SimTarget::open(dir, running)?;
+    let (mut port, tty) = TTYPort::pair().map_err(|e| format!("pseudo-terminal: {}", e))?;
+    port.set_timeout(Duration::from_millis(100)).map_err(|e| format!("pseudo-terminal: {}", e))?;
+    println!("device on {} (running slot {})", tty.name().unwrap_or_default(), running);
+    // Close our end of the terminal so that `send` can lock it
+    drop(tty);
+    let _ = std::io::stdout().flush();
+
+    let mut line = String::new();
+    loop {
+        let Some(byte) = read_byte(&mut port)? else {
+            continue;
+        };
+        match byte {
+            CTRL_C => {
+                line.clear();
+                write(&mut port, b"^C")?;
+                write(&mut port, PROMPT.as_bytes())?;
+            }
+            b'\r' | b'\n' => {
+                write(&mut port, b"\r\n")?;
+                match line.trim() {
+                    "" => {}
+                    "update" => {
+                        write(&mut port, b"waiting for the image (Ctrl-C to cancel)\r\n")?;
+                        if serve(&mut port, &mut target, drop_every)? {
+                            // Let the host read the answer before the terminal goes away
+                            let _ = port.flush();
+                            std::thread::sleep(Duration::from_millis(500));
+                            println!("reset");
+                            return Ok(());
+                        }
+                    }
+                    other => write(&mut port, format!("unknown command: {}\r\n", other).as_bytes())?,
+                }
+                line.clear();
+                write(&mut port, PROMPT.as_bytes())?;
+            }
+            byte if byte.is_ascii_graphic() || byte == b' ' => {
+                line.push(byte as char);
+                write(&mut port, &[byte])?;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Run an update session like `kernel::update::serve`. Returns whether the host asked for a reset.
+fn serve(port: &mut TTYPort, target: &mut SimTarget, drop_every: Option<u32>) -> Result<bool> {
+    println!("update session started");
+    let mut decoder = Decoder::new();
+    let mut receiver = Receiver::new();
+    let mut reply = [0u8; MAX_REPLY];
+    let mut frames = 0;
+    let mut last = Instant::now();
+
+    loop {
+        let Some(byte) = read_byte(port)? else {
+            if !decoder.is_idle() && last.elapsed() >= Duration::from_millis(FRAME_GAP_MS.into()) {
+                decoder.reset();
+            }
+            if last.elapsed() > IDLE_TIMEOUT {
+                println!("update session timed out");
+                return Ok(false);
+            }
+            continue;
+        };
+        last = Instant::now();
+        if byte == CTRL_C && decoder.is_idle() {
+            println!("update session cancelled");
+            return Ok(false);
+        }
+        let Some(frame) = decoder.feed(byte) else {
+            continue;
+        };
+        frames += 1;
+        if drop_every.is_some_and(|n| frames % n == 0) {
+            continue; // as if the frame were damaged on the line
+        }
+        let (len, event) = receiver.handle(&frame, target, &mut reply);
+        write(port, &reply[..len])?;
+        match event {
+            Event::Aborted => {
+                println!("update session ended");
+                return Ok(false);
+            }
+            Event::Reboot => return Ok(true),
+            Event::Installed(_) | Event::Started | Event::None => {}
+        }
+    }
+}
+
+fn read_byte(port: &mut TTYPort) -> Result<Option<u8>> {
+    let mut byte = [0u8];
+    match port.read(&mut byte) {
+        Ok(1) => Ok(Some(byte[0])),
+        Ok(_) => Ok(None),
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => Ok(None),
+        // Hang-up: nobody has the terminal open
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {
+            std::thread::sleep(Duration::from_millis(100));
+            Ok(None)
+        }
+        Err(e) => Err(format!("read: {}", e)),
+    }
+}
+
+fn write(port: &mut TTYPort, bytes: &[u8]) -> Result<()> {
+    match port.write_all(bytes) {
+        // Lost, like the output of a UART with nothing connected
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => result.map_err(|e| format!("write: {}", e)),
+    }
+}
@@ -0,0 +1,80 @@
+//! `fwupdate send` against `fwupdate simulate`, over a pseudo-terminal that loses frames.
+
+#![cfg(unix)]
+
+use std::io::{BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+
+use boot_format::{check_slot, Slot, Version};
+use mkimage::make_image;
+
+const FWUPDATE: &str = env!("CARGO_BIN_EXE_fwupdate");
+
+/// Simulator process, killed if the test fails before it exits.
+struct Device(Child);
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Directory of the simulated slots, removed at the end.
+struct TempDir(PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Firmware binary of `len` bytes whose reset vector lies in `slot`.
+fn firmware(slot: Slot, len: usize) -> Vec<u8> {
+    let mut binary: Vec<u8> = (0..len).map(|i| (i * 13 + 5) as u8).collect();
+    binary[0..4].copy_from_slice(&0x2002_0000u32.to_le_bytes());
+    binary[4..8].copy_from_slice(&(slot.image_address() + 0x189).to_le_bytes());
+    binary
+}
+
+#[test]
+fn sends_an_image_despite_dropped_frames() {
+    let dir = TempDir(std::env::temp_dir().join(format!("fwupdate-simulate-{}", std::process::id())));
+    std::fs::create_dir_all(&dir.0).unwrap();
+    let image = make_image(&firmware(Slot::B, 3000), Version::new(1, 4, 0)).unwrap();
+    let image_path = dir.0.join("image.bin");
+    std::fs::write(&image_path, &image).unwrap();
+
+    // Every 7th frame is ignored by the device: the host must resend it
+    let mut device = Device(
+        Command::new(FWUPDATE)
+            .arg("simulate")
+            .arg(&dir.0)
+            .args(["--running", "a", "--drop-every", "7"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    let mut output = BufReader::new(device.0.stdout.take().unwrap());
+    let mut line = String::new();
+    output.read_line(&mut line).unwrap();
+    let tty = line
+        .strip_prefix("device on ")
+        .and_then(|rest| rest.split_whitespace().next())
+        .unwrap_or_else(|| panic!("unexpected simulator output: {:?}", line));
+
+    let send = Command::new(FWUPDATE).arg("send").arg(tty).arg(&image_path).arg("--reboot").output().unwrap();
+    assert!(send.status.success(), "send failed: {}", String::from_utf8_lossy(&send.stderr));
+
+    // The device resets once the image is installed
+    let mut log = String::new();
+    output.read_to_string(&mut log).unwrap();
+    assert!(device.0.wait().unwrap().success(), "simulator failed:\n{}", log);
+    assert!(log.contains("slot B: v1.4.0 installed"), "simulator output:\n{}", log);
+
+    let slot = std::fs::read(dir.0.join("slot-b.bin")).unwrap();
+    let header = check_slot(Slot::B, &slot).unwrap();
+    assert_eq!(header.version, Version::new(1, 4, 0));
+    assert_eq!(slot[..image.len()], image[..]);
+}
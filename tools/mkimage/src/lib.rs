@@ -0,0 +1,151 @@
+//! Firmware images for the bootloader slots.
+//!
+//! A slot image is the `boot_format` header padded to `HEADER_SIZE` bytes with `0xFF`, followed
+//! by the flat binary of the firmware starting at its vector table. It is written as-is at the
+//! address of the slot the firmware was linked for.
+
+use boot_format::{check_slot, ImageHeader, Slot, Version, HEADER_SIZE, SLOT_SIZE};
+
+pub type Result<T> = std::result::Result<T, String>;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const PT_LOAD: u32 = 1;
+
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16> {
+    let b = bytes.get(offset..offset + 2).ok_or("truncated ELF file")?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32> {
+    let b = bytes.get(offset..offset + 4).ok_or("truncated ELF file")?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Flat binary of the loadable segments of a 32-bit little-endian ELF file, placed at their
+/// load (physical) addresses: initialised data follows the code as in flash. Gaps are `0xFF`.
+pub fn elf_to_binary(elf: &[u8]) -> Result<Vec<u8>> {
+    if !elf.starts_with(ELF_MAGIC) || elf.get(4) != Some(&1) || elf.get(5) != Some(&1) {
+        return Err("not a 32-bit little-endian ELF file".to_string());
+    }
+    let phoff = u32_at(elf, 0x1C)? as usize;
+    let phentsize = u16_at(elf, 0x2A)? as usize;
+    let phnum = u16_at(elf, 0x2C)? as usize;
+
+    // (load address, file contents) of every segment with data
+    let mut segments = Vec::new();
+    for i in 0..phnum {
+        let ph = phoff + i * phentsize;
+        let offset = u32_at(elf, ph + 4)? as usize;
+        let paddr = u32_at(elf, ph + 12)?;
+        let filesz = u32_at(elf, ph + 16)? as usize;
+        if u32_at(elf, ph)? == PT_LOAD && filesz > 0 {
+            segments.push((paddr, elf.get(offset..offset + filesz).ok_or("truncated ELF file")?));
+        }
+    }
+    segments.sort_by_key(|&(address, _)| address);
+    let base = segments.first().ok_or("no loadable segment in ELF file")?.0;
+    let end = segments.iter().map(|&(address, data)| address as usize + data.len()).max().unwrap_or(0);
+    if end - base as usize > SLOT_SIZE as usize {
+        return Err(format!("ELF segments span {} bytes from {:#010x}: not a slot image", end - base as usize, base));
+    }
+
+    let mut binary = vec![0xFF; end - base as usize];
+    for (address, data) in segments {
+        let at = (address - base) as usize;
+        binary[at..at + data.len()].copy_from_slice(data);
+    }
+    Ok(binary)
+}
+
+/// Read a firmware build: an ELF file, or a flat binary (`objcopy -O binary`).
+pub fn load_firmware(path: &str) -> Result<Vec<u8>> {
+    let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    if bytes.starts_with(ELF_MAGIC) { elf_to_binary(&bytes).map_err(|e| format!("{}: {}", path, e)) } else { Ok(bytes) }
+}
+
+/// Slot a firmware binary is linked for, found from its reset vector.
+pub fn firmware_slot(binary: &[u8]) -> Result<Slot> {
+    if binary.len() < 8 {
+        return Err("firmware too short for a vector table".to_string());
+    }
+    let reset = u32::from_le_bytes([binary[4], binary[5], binary[6], binary[7]]);
+    Slot::containing(reset).ok_or_else(|| {
+        format!("reset vector {:#010x} is not in a firmware slot (was the firmware built for the bootloader?)", reset)
+    })
+}
+
+/// Slot image (header and binary) of a firmware binary.
+pub fn make_image(binary: &[u8], version: Version) -> Result<Vec<u8>> {
+    let slot = firmware_slot(binary)?;
+    if binary.len() > (SLOT_SIZE - HEADER_SIZE) as usize {
+        return Err(format!("firmware is {} bytes, a slot holds {}", binary.len(), SLOT_SIZE - HEADER_SIZE));
+    }
+    let header = ImageHeader::new(version, slot.image_address(), binary);
+    let mut image = vec![0xFF; HEADER_SIZE as usize];
+    image[..header.encode().len()].copy_from_slice(&header.encode());
+    image.extend_from_slice(binary);
+    Ok(image)
+}
+
+/// Header and slot of a slot image, after checking it as the bootloader does.
+pub fn check_image(image: &[u8]) -> Result<(ImageHeader, Slot)> {
+    let header = ImageHeader::parse(image).map_err(|e| e.to_string())?;
+    let slot = header.slot().ok_or_else(|| format!("load address {:#010x} is not a slot", header.load_address))?;
+    check_slot(slot, image).map_err(|e| e.to_string())?;
+    Ok((header, slot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boot_format::{ImageError, HEADER_LEN};
+
+    /// Firmware binary of `len` bytes whose reset vector lies in `slot`.
+    fn firmware(slot: Slot, len: usize) -> Vec<u8> {
+        let mut binary: Vec<u8> = (0..len).map(|i| (i * 31 + 7) as u8).collect();
+        binary[0..4].copy_from_slice(&0x2002_0000u32.to_le_bytes());
+        binary[4..8].copy_from_slice(&(slot.image_address() + 0x1C5).to_le_bytes());
+        binary
+    }
+
+    #[test]
+    fn image_round_trip() {
+        for slot in Slot::ALL {
+            let binary = firmware(slot, 5000);
+            let image = make_image(&binary, Version::new(1, 2, 3)).unwrap();
+            assert_eq!(image.len(), HEADER_SIZE as usize + binary.len());
+            assert!(image[HEADER_LEN..HEADER_SIZE as usize].iter().all(|&b| b == 0xFF));
+            assert_eq!(image[HEADER_SIZE as usize..], binary[..]);
+
+            let header = ImageHeader::parse(&image).unwrap();
+            assert_eq!(header.version, Version::new(1, 2, 3));
+            assert_eq!(header.slot(), Some(slot));
+            assert_eq!(header.length, binary.len() as u32);
+            assert_eq!(check_slot(slot, &image), Ok(header));
+            assert_eq!(check_slot(slot.other(), &image), Err(ImageError::WrongSlot));
+            assert_eq!(check_image(&image), Ok((header, slot)));
+        }
+    }
+
+    #[test]
+    fn rejects_firmware_not_built_for_a_slot() {
+        assert!(make_image(&[0; 7], Version::new(1, 0, 0)).is_err());
+        let mut binary = firmware(Slot::A, 100);
+        binary[4..8].copy_from_slice(&0x0800_0101u32.to_le_bytes());
+        assert!(make_image(&binary, Version::new(1, 0, 0)).is_err());
+        let binary = firmware(Slot::B, (SLOT_SIZE - HEADER_SIZE) as usize + 1);
+        assert!(make_image(&binary, Version::new(1, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn check_image_rejects_damage() {
+        let image = make_image(&firmware(Slot::A, 300), Version::new(1, 0, 0)).unwrap();
+        assert!(check_image(&image[..image.len() - 1]).is_err());
+        let mut damaged = image.clone();
+        damaged[HEADER_SIZE as usize + 100] ^= 0x04;
+        assert_eq!(check_image(&damaged), Err(ImageError::BadCrc.to_string()));
+        let mut damaged = image;
+        damaged[8] ^= 0x01;
+        assert_eq!(check_image(&damaged), Err(ImageError::BadHeaderCrc.to_string()));
+    }
+}
@@ -0,0 +1,54 @@
+//! Package a firmware build for the bootloader.
+//!
+//! ```text
+//! mkimage <firmware> <image> <version>
+//! mkimage info <image>
+//! ```
+//!
+//! `<firmware>` is the ELF file produced by `cargo build` or a flat binary. The image is written to
+//! the slot the firmware was built for (`APP_SLOT`), e.g. `st-flash write app.img 0x08020000`.
+
+use std::process::ExitCode;
+
+use boot_format::Version;
+use mkimage::{check_image, load_firmware, make_image, Result};
+
+const USAGE: &str = "usage:
+  mkimage <firmware> <image> <version>   package an ELF or binary build as a slot image
+  mkimage info <image>                   check a slot image and print its header";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("mkimage: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args[..] {
+        ["info", path] => {
+            let image = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+            let (header, slot) = check_image(&image)?;
+            println!("slot:     {} ({:#010x})", slot, slot.address());
+            println!("version:  {}", header.version);
+            println!("length:   {} bytes", header.length);
+            println!("crc32:    {:08x}", header.crc32);
+            println!("sha256:   {}", header.sha256.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+            Ok(())
+        }
+        [firmware, output, version] => {
+            let version = Version::parse(version).ok_or_else(|| format!("invalid version: {}", version))?;
+            let image = make_image(&load_firmware(firmware)?, version)?;
+            std::fs::write(output, &image).map_err(|e| format!("{}: {}", output, e))?;
+            let (_, slot) = check_image(&image)?;
+            println!("{}: v{} for slot {}, write it at {:#010x}", output, version, slot, slot.address());
+            Ok(())
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
@@ -0,0 +1,783 @@
+//! Firmware update protocol over a serial link, shared by the firmware and the host tool.
+//!
+//! The host sends a request frame and waits for the answer (`Ack` or `Nak` carrying the same
+//! sequence number) before sending the next one, resending the request when no answer comes.
+//! Bytes outside frames (shell echo, stray log output) and frames failing their CRC are ignored,
+//! so a lost or damaged frame simply times out and is sent again. A frame is written in one go:
+//! the receiving side drops a partial frame after `FRAME_GAP_MS` of silence, so a frame whose
+//! length field was damaged does not swallow the frames sent again after it.
+//!
+//! Frame layout:
+//!
+//! | Bytes    | Content                                              |
+//! |----------|------------------------------------------------------|
+//! | 0        | `SOF`                                                |
+//! | 1        | `Kind`                                               |
+//! | 2–3      | sequence number (little-endian)                      |
+//! | 4–5      | payload length, at most `MAX_PAYLOAD`                |
+//! | 6–       | payload                                              |
+//! | last 4   | CRC-32 of everything after `SOF`                     |
+//!
+//! Requests, with the payload of their `Ack`:
+//!
+//! | Request  | Payload                          | `Ack` payload                         |
+//! |----------|----------------------------------|---------------------------------------|
+//! | `Info`   | –                                | `DeviceInfo`                          |
+//! | `Start`  | slot, image length (u32)         | –                                     |
+//! | `Data`   | offset (u32), up to `CHUNK_SIZE` | offset expected next (u32)            |
+//! | `End`    | –                                | –                                     |
+//! | `Abort`  | –                                | –                                     |
+//! | `Reboot` | –                                | – (the device resets after answering) |
+//!
+//! A `Nak` payload is a `NakReason`, followed for `NakReason::Offset` by the offset expected.
+//!
+//! The image sent is a slot image (`boot_format` header and binary) linked for the slot that is
+//! not running. `Start` erases the slot, `Data` frames must come in order (a repeated frame is
+//! acknowledged again without being written), `End` validates the image and arms its test boot.
+//! `Receiver` implements the device side over any `Target`.
+
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt;
+use boot_format::{crc32, Slot, Version, HEADER_SIZE, SLOT_SIZE};
+
+/// First byte of every frame.
+pub const SOF: u8 = 0xA5;
+
+/// Image bytes per `Data` frame.
+pub const CHUNK_SIZE: usize = 256;
+
+/// Longest payload.
+pub const MAX_PAYLOAD: usize = 4 + CHUNK_SIZE;
+
+const HEADER_LEN: usize = 6;
+const CRC_LEN: usize = 4;
+
+/// Longest frame.
+pub const MAX_FRAME: usize = HEADER_LEN + MAX_PAYLOAD + CRC_LEN;
+
+/// Silence in the middle of a frame after which the receiver drops it (`Decoder::reset`).
+pub const FRAME_GAP_MS: u32 = 100;
+
+/// Longest answer frame.
+pub const MAX_REPLY: usize = HEADER_LEN + 8 + CRC_LEN;
+
+/// Frame kinds.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Info = 1,
+    Start = 2,
+    Data = 3,
+    End = 4,
+    Abort = 5,
+    Reboot = 6,
+    Ack = 0x80,
+    Nak = 0x81,
+}
+
+impl Kind {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Kind::Info),
+            2 => Some(Kind::Start),
+            3 => Some(Kind::Data),
+            4 => Some(Kind::End),
+            5 => Some(Kind::Abort),
+            6 => Some(Kind::Reboot),
+            0x80 => Some(Kind::Ack),
+            0x81 => Some(Kind::Nak),
+            _ => None,
+        }
+    }
+}
+
+/// Why a request was refused.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NakReason {
+    /// Unknown request or payload of the wrong size.
+    Malformed = 1,
+    /// Request not valid now (`Data` or `End` without `Start`, `End` before the last byte).
+    State = 2,
+    /// `Data` not at the expected offset.
+    Offset = 3,
+    /// The image is for the running slot.
+    RunningSlot = 4,
+    /// The image does not fit in a slot.
+    TooLarge = 5,
+    /// Erasing or programming the flash failed.
+    Flash = 6,
+    /// The complete image failed validation.
+    BadImage = 7,
+}
+
+impl NakReason {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(NakReason::Malformed),
+            2 => Some(NakReason::State),
+            3 => Some(NakReason::Offset),
+            4 => Some(NakReason::RunningSlot),
+            5 => Some(NakReason::TooLarge),
+            6 => Some(NakReason::Flash),
+            7 => Some(NakReason::BadImage),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for NakReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NakReason::Malformed => "malformed request",
+            NakReason::State => "unexpected request",
+            NakReason::Offset => "unexpected offset",
+            NakReason::RunningSlot => "image is for the running slot",
+            NakReason::TooLarge => "image too large",
+            NakReason::Flash => "flash error",
+            NakReason::BadImage => "image failed validation",
+        })
+    }
+}
+
+/// A received frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    /// `Kind` value.
+    pub kind: u8,
+    pub seq: u16,
+    pub payload: &'a [u8],
+}
+
+/// Encode a frame into `out`. Returns its length.
+pub fn encode(kind: Kind, seq: u16, payload: &[u8], out: &mut [u8]) -> usize {
+    assert!(payload.len() <= MAX_PAYLOAD, "payload too long");
+    let len = HEADER_LEN + payload.len() + CRC_LEN;
+    out[0] = SOF;
+    out[1] = kind as u8;
+    out[2..4].copy_from_slice(&seq.to_le_bytes());
+    out[4..6].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+    out[HEADER_LEN..len - CRC_LEN].copy_from_slice(payload);
+    let crc = crc32(&out[1..len - CRC_LEN]);
+    out[len - CRC_LEN..len].copy_from_slice(&crc.to_le_bytes());
+    len
+}
+
+/// Byte-driven frame decoder.
+pub struct Decoder {
+    buf: [u8; MAX_FRAME],
+    len: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Decoder { buf: [0; MAX_FRAME], len: 0 }
+    }
+
+    /// Drop any partial frame.
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    /// Whether no frame is partially received.
+    pub fn is_idle(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Feed one received byte. Returns the frame it completes, if valid.
+    pub fn feed(&mut self, byte: u8) -> Option<Frame<'_>> {
+        if self.len == 0 && byte != SOF {
+            return None;
+        }
+        self.buf[self.len] = byte;
+        self.len += 1;
+        if self.len < HEADER_LEN {
+            return None;
+        }
+        let payload_len = u16::from_le_bytes([self.buf[4], self.buf[5]]) as usize;
+        if payload_len > MAX_PAYLOAD {
+            self.len = 0;
+            return None;
+        }
+        let total = HEADER_LEN + payload_len + CRC_LEN;
+        if self.len < total {
+            return None;
+        }
+        self.len = 0;
+
+        let body = &self.buf[1..total - CRC_LEN];
+        let crc = u32_at(&self.buf, total - CRC_LEN)?;
+        if crc != crc32(body) {
+            return None;
+        }
+        Some(Frame {
+            kind: self.buf[1],
+            seq: u16::from_le_bytes([self.buf[2], self.buf[3]]),
+            payload: &self.buf[HEADER_LEN..total - CRC_LEN],
+        })
+    }
+}
+
+/// Little-endian word at `offset` of `bytes`.
+pub fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    let b = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// `Info` answer: what the device runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// `None` when the firmware was not started by the bootloader.
+    pub running: Option<Slot>,
+    /// Version from the header of the running image.
+    pub version: Option<Version>,
+}
+
+impl DeviceInfo {
+    pub const LEN: usize = 6;
+
+    pub fn encode(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[0] = self.running.map_or(0xFF, |slot| slot as u8);
+        bytes[1] = self.version.is_some() as u8;
+        bytes[2..].copy_from_slice(&self.version.unwrap_or_default().to_u32().to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::LEN {
+            return None;
+        }
+        let version = Version::from_u32(u32_at(bytes, 2)?);
+        Some(DeviceInfo { running: Slot::from_u8(bytes[0]), version: (bytes[1] != 0).then_some(version) })
+    }
+}
+
+/// Where the receiver stores an image: the flash slots of the device, or a simulation of them.
+pub trait Target {
+    /// What the device runs.
+    fn info(&self) -> DeviceInfo;
+
+    /// Erase `slot` to receive an image of `length` bytes. Never called for the running slot.
+    fn begin(&mut self, slot: Slot, length: u32) -> Result<(), NakReason>;
+
+    /// Program `data` at `offset` of `slot`. Offsets follow each other from 0.
+    fn write(&mut self, slot: Slot, offset: u32, data: &[u8]) -> Result<(), NakReason>;
+
+    /// Validate the complete image in `slot` and arm its test boot.
+    fn finish(&mut self, slot: Slot) -> Result<(), NakReason>;
+}
+
+/// What a request changed, for the code driving the receiver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    None,
+    /// `Start` accepted: a transfer is in progress.
+    Started,
+    /// `End` accepted: the image in this slot boots (on test) at the next reset.
+    Installed(Slot),
+    /// The host abandoned the transfer.
+    Aborted,
+    /// The host asks for a reset, to be done once the answer is sent.
+    Reboot,
+}
+
+#[derive(Clone, Copy)]
+struct Transfer {
+    slot: Slot,
+    length: u32,
+    received: u32,
+}
+
+/// Device side of the protocol.
+pub struct Receiver {
+    transfer: Option<Transfer>,
+    /// Slot of the last image installed, to acknowledge a repeated `End`.
+    installed: Option<Slot>,
+}
+
+impl Default for Receiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Receiver {
+    pub const fn new() -> Self {
+        Receiver { transfer: None, installed: None }
+    }
+
+    /// Bytes received and expected in the current transfer.
+    pub fn progress(&self) -> Option<(u32, u32)> {
+        self.transfer.map(|t| (t.received, t.length))
+    }
+
+    /// Handle a request: writes the answer into `reply` and returns its length with what changed.
+    pub fn handle<T: Target>(&mut self, frame: &Frame, target: &mut T, reply: &mut [u8; MAX_REPLY]) -> (usize, Event) {
+        let mut answer = [0u8; 8];
+        let result = match Kind::from_u8(frame.kind) {
+            Some(Kind::Info) => {
+                answer[..DeviceInfo::LEN].copy_from_slice(&target.info().encode());
+                Ok((DeviceInfo::LEN, Event::None))
+            }
+            Some(Kind::Start) => self.start(frame.payload, target),
+            Some(Kind::Data) => self.data(frame.payload, target, &mut answer),
+            Some(Kind::End) => self.end(target),
+            Some(Kind::Abort) => {
+                self.transfer = None;
+                Ok((0, Event::Aborted))
+            }
+            Some(Kind::Reboot) => Ok((0, Event::Reboot)),
+            _ => Err(NakReason::Malformed),
+        };
+
+        match result {
+            Ok((len, event)) => (encode(Kind::Ack, frame.seq, &answer[..len], reply), event),
+            Err(reason) => {
+                let mut nak = [0u8; 5];
+                nak[0] = reason as u8;
+                let len = match (reason, self.transfer) {
+                    (NakReason::Offset, Some(transfer)) => {
+                        nak[1..].copy_from_slice(&transfer.received.to_le_bytes());
+                        5
+                    }
+                    _ => 1,
+                };
+                (encode(Kind::Nak, frame.seq, &nak[..len], reply), Event::None)
+            }
+        }
+    }
+
+    fn start<T: Target>(&mut self, payload: &[u8], target: &mut T) -> Result<(usize, Event), NakReason> {
+        if payload.len() != 5 {
+            return Err(NakReason::Malformed);
+        }
+        let slot = Slot::from_u8(payload[0]).ok_or(NakReason::Malformed)?;
+        let length = u32_at(payload, 1).ok_or(NakReason::Malformed)?;
+        if target.info().running == Some(slot) {
+            return Err(NakReason::RunningSlot);
+        }
+        if !(HEADER_SIZE..=SLOT_SIZE).contains(&length) {
+            return Err(NakReason::TooLarge);
+        }
+        self.transfer = None;
+        self.installed = None;
+        target.begin(slot, length)?;
+        self.transfer = Some(Transfer { slot, length, received: 0 });
+        Ok((0, Event::Started))
+    }
+
+    fn data<T: Target>(&mut self, payload: &[u8], target: &mut T, answer: &mut [u8]) -> Result<(usize, Event), NakReason> {
+        let transfer = self.transfer.as_mut().ok_or(NakReason::State)?;
+        let offset = u32_at(payload, 0).ok_or(NakReason::Malformed)?;
+        let data = &payload[4..];
+        let end = offset + data.len() as u32;
+        if end > transfer.length {
+            return Err(NakReason::TooLarge);
+        }
+        if offset == transfer.received {
+            target.write(transfer.slot, offset, data)?;
+            transfer.received = end;
+        } else if end > transfer.received {
+            // A repeated frame (its answer was lost) is below `received`; anything else is a gap
+            return Err(NakReason::Offset);
+        }
+        answer[..4].copy_from_slice(&transfer.received.to_le_bytes());
+        Ok((4, Event::None))
+    }
+
+    fn end<T: Target>(&mut self, target: &mut T) -> Result<(usize, Event), NakReason> {
+        let Some(transfer) = self.transfer else {
+            // The answer to the `End` that installed the image may have been lost
+            return match self.installed {
+                Some(slot) => Ok((0, Event::Installed(slot))),
+                None => Err(NakReason::State),
+            };
+        };
+        if transfer.received != transfer.length {
+            return Err(NakReason::State);
+        }
+        self.transfer = None;
+        target.finish(transfer.slot)?;
+        self.installed = Some(transfer.slot);
+        Ok((0, Event::Installed(transfer.slot)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boot_format::{check_slot, ImageHeader};
+
+    const RUNNING_VERSION: Version = Version::new(1, 2, 3);
+
+    /// Encoded frame.
+    fn frame(kind: Kind, seq: u16, payload: &[u8]) -> Vec<u8> {
+        let mut out = [0u8; MAX_FRAME];
+        let len = encode(kind, seq, payload, &mut out);
+        out[..len].to_vec()
+    }
+
+    /// Feed `bytes` to `decoder`, returning the `(kind, seq, payload)` of the frames decoded.
+    fn decode(decoder: &mut Decoder, bytes: &[u8]) -> Vec<(u8, u16, Vec<u8>)> {
+        bytes
+            .iter()
+            .filter_map(|&byte| decoder.feed(byte).map(|f| (f.kind, f.seq, f.payload.to_vec())))
+            .collect()
+    }
+
+    #[test]
+    fn encodes_frames() {
+        let bytes = frame(Kind::Data, 0x1234, &[1, 2, 3]);
+        assert_eq!(bytes[..9], [SOF, Kind::Data as u8, 0x34, 0x12, 3, 0, 1, 2, 3]);
+        assert_eq!(u32_at(&bytes, 9), Some(crc32(&bytes[1..9])));
+        assert_eq!(bytes.len(), 13);
+
+        assert_eq!(frame(Kind::Ack, 0, &[]).len(), HEADER_LEN + CRC_LEN);
+        assert_eq!(frame(Kind::Data, 0, &[0; MAX_PAYLOAD]).len(), MAX_FRAME);
+    }
+
+    #[test]
+    #[should_panic(expected = "payload too long")]
+    fn rejects_long_payloads() {
+        frame(Kind::Data, 0, &[0; MAX_PAYLOAD + 1]);
+    }
+
+    #[test]
+    fn decodes_frames() {
+        let mut decoder = Decoder::new();
+        let payload: Vec<u8> = (0..MAX_PAYLOAD as u32).map(|i| i as u8).collect();
+        let mut stream = frame(Kind::Info, 1, &[]);
+        stream.extend(frame(Kind::Data, 2, &payload));
+        stream.extend(frame(Kind::Nak, 0xFFFF, &[3, 4]));
+        assert_eq!(
+            decode(&mut decoder, &stream),
+            [(Kind::Info as u8, 1, vec![]), (Kind::Data as u8, 2, payload), (Kind::Nak as u8, 0xFFFF, vec![3, 4])]
+        );
+        assert!(decoder.is_idle());
+    }
+
+    #[test]
+    fn decoder_skips_bytes_outside_frames() {
+        let mut decoder = Decoder::new();
+        let mut stream = b"> update\r\nwaiting for the image\r\n".to_vec();
+        stream.extend(frame(Kind::Start, 7, &[1, 0, 2, 0, 0]));
+        stream.extend(b"\x00\xFF log line\r\n");
+        stream.extend(frame(Kind::End, 8, &[]));
+        let frames = decode(&mut decoder, &stream);
+        assert_eq!(frames, [(Kind::Start as u8, 7, vec![1, 0, 2, 0, 0]), (Kind::End as u8, 8, vec![])]);
+    }
+
+    #[test]
+    fn decoder_drops_corrupted_frames_and_resyncs() {
+        let good = frame(Kind::Data, 3, &[0x10, 0x20, 0x30, 0x40, 0x50]);
+        for i in 1..good.len() {
+            for bit in 0..8 {
+                let mut decoder = Decoder::new();
+                let mut bad = good.clone();
+                bad[i] ^= 1 << bit;
+                assert_eq!(decode(&mut decoder, &bad), [], "byte {} bit {}", i, bit);
+                // Only a damaged length leaves the decoder waiting for more bytes, until the line
+                // stays silent for `FRAME_GAP_MS` before the host sends the frame again
+                assert!(decoder.is_idle() || (4..6).contains(&i), "byte {} bit {}", i, bit);
+                decoder.reset();
+                assert_eq!(decode(&mut decoder, &good), [(Kind::Data as u8, 3, good[6..11].to_vec())]);
+            }
+        }
+    }
+
+    #[test]
+    fn decoder_drops_truncated_frames_and_bad_lengths() {
+        let good = frame(Kind::End, 9, &[]);
+
+        // Bytes lost on the line: the frame eats the start of its next copy, the one after gets through
+        let mut decoder = Decoder::new();
+        let mut stream = good[..good.len() - 3].to_vec();
+        stream.extend(&good);
+        stream.extend(&good);
+        assert_eq!(decode(&mut decoder, &stream), [(Kind::End as u8, 9, vec![])]);
+
+        // A length above `MAX_PAYLOAD` is dropped as soon as it is read
+        let mut decoder = Decoder::new();
+        let too_long = (MAX_PAYLOAD as u16 + 1).to_le_bytes();
+        assert_eq!(decode(&mut decoder, &[SOF, Kind::Data as u8, 0, 0, too_long[0], too_long[1]]), []);
+        assert!(decoder.is_idle());
+        assert_eq!(decode(&mut decoder, &good), [(Kind::End as u8, 9, vec![])]);
+
+        // `reset` drops a partial frame
+        let mut decoder = Decoder::new();
+        decode(&mut decoder, &good[..5]);
+        assert!(!decoder.is_idle());
+        decoder.reset();
+        assert_eq!(decode(&mut decoder, &good), [(Kind::End as u8, 9, vec![])]);
+    }
+
+    #[test]
+    fn device_info_round_trip() {
+        for info in [
+            DeviceInfo { running: Some(Slot::B), version: Some(RUNNING_VERSION) },
+            DeviceInfo { running: Some(Slot::A), version: None },
+            DeviceInfo { running: None, version: None },
+        ] {
+            assert_eq!(DeviceInfo::decode(&info.encode()), Some(info));
+        }
+        assert_eq!(DeviceInfo::decode(&[0; DeviceInfo::LEN - 1]), None);
+    }
+
+    /// Slots in RAM, programmed like flash, validated like the firmware does.
+    struct MockTarget {
+        running: Slot,
+        slots: [Vec<u8>; 2],
+        writes: usize,
+    }
+
+    impl MockTarget {
+        fn new(running: Slot) -> Self {
+            MockTarget { running, slots: [vec![0; SLOT_SIZE as usize], vec![0; SLOT_SIZE as usize]], writes: 0 }
+        }
+    }
+
+    impl Target for MockTarget {
+        fn info(&self) -> DeviceInfo {
+            DeviceInfo { running: Some(self.running), version: Some(RUNNING_VERSION) }
+        }
+
+        fn begin(&mut self, slot: Slot, _length: u32) -> Result<(), NakReason> {
+            self.slots[slot as usize].fill(0xFF);
+            Ok(())
+        }
+
+        fn write(&mut self, slot: Slot, offset: u32, data: &[u8]) -> Result<(), NakReason> {
+            self.writes += 1;
+            let target = &mut self.slots[slot as usize][offset as usize..offset as usize + data.len()];
+            if target.iter().any(|&b| b != 0xFF) {
+                return Err(NakReason::Flash);
+            }
+            target.copy_from_slice(data);
+            Ok(())
+        }
+
+        fn finish(&mut self, slot: Slot) -> Result<(), NakReason> {
+            check_slot(slot, &self.slots[slot as usize]).map(|_| ()).map_err(|_| NakReason::BadImage)
+        }
+    }
+
+    /// Slot image of `binary_len` bytes of code linked for `slot`.
+    fn image(slot: Slot, binary_len: usize) -> Vec<u8> {
+        let binary: Vec<u8> = (0..binary_len).map(|i| (i * 7) as u8).collect();
+        let header = ImageHeader::new(Version::new(2, 0, 0), slot.image_address(), &binary);
+        let mut image = vec![0xFF; HEADER_SIZE as usize];
+        image[..header.encode().len()].copy_from_slice(&header.encode());
+        image.extend(binary);
+        image
+    }
+
+    /// A receiver in front of a target, with the sequence numbers of the host.
+    struct Session {
+        receiver: Receiver,
+        target: MockTarget,
+        seq: u16,
+    }
+
+    /// Decoded answer: `Ok(ack payload)` or `Err((reason, expected offset))`, with the event.
+    type Answer = (Result<Vec<u8>, (NakReason, Option<u32>)>, Event);
+
+    impl Session {
+        fn new() -> Self {
+            Session { receiver: Receiver::new(), target: MockTarget::new(Slot::A), seq: 0 }
+        }
+
+        fn request(&mut self, kind: Kind, payload: &[u8]) -> Answer {
+            self.seq = self.seq.wrapping_add(1);
+            self.send(kind, self.seq, payload)
+        }
+
+        fn send(&mut self, kind: Kind, seq: u16, payload: &[u8]) -> Answer {
+            let request = Frame { kind: kind as u8, seq, payload };
+            let mut reply = [0u8; MAX_REPLY];
+            let (len, event) = self.receiver.handle(&request, &mut self.target, &mut reply);
+            let mut decoder = Decoder::new();
+            let frames = decode(&mut decoder, &reply[..len]);
+            let [(kind, answer_seq, payload)] = &frames[..] else {
+                panic!("not a single answer frame: {:?}", &reply[..len]);
+            };
+            assert_eq!(*answer_seq, seq);
+            match Kind::from_u8(*kind) {
+                Some(Kind::Ack) => (Ok(payload.clone()), event),
+                Some(Kind::Nak) => {
+                    assert_eq!(event, Event::None);
+                    (Err((NakReason::from_u8(payload[0]).unwrap(), u32_at(payload, 1))), event)
+                }
+                _ => panic!("unexpected answer kind {}", kind),
+            }
+        }
+
+        fn start(&mut self, slot: Slot, length: usize) -> Answer {
+            let mut payload = [slot as u8, 0, 0, 0, 0];
+            payload[1..].copy_from_slice(&(length as u32).to_le_bytes());
+            self.request(Kind::Start, &payload)
+        }
+
+        /// `Start` a transfer of `length` bytes to `slot`, which must be accepted.
+        fn begin(&mut self, slot: Slot, length: usize) {
+            assert_eq!(self.start(slot, length), (Ok(vec![]), Event::Started));
+        }
+
+        /// Transfer the whole of `image` to `slot`, every frame being acknowledged.
+        fn upload(&mut self, slot: Slot, image: &[u8]) {
+            self.begin(slot, image.len());
+            for offset in (0..image.len()).step_by(CHUNK_SIZE) {
+                assert_eq!(self.data(image, offset), acked((offset + CHUNK_SIZE).min(image.len())));
+            }
+        }
+
+        fn data(&mut self, image: &[u8], offset: usize) -> Answer {
+            let end = (offset + CHUNK_SIZE).min(image.len());
+            let mut payload = (offset as u32).to_le_bytes().to_vec();
+            payload.extend_from_slice(&image[offset..end]);
+            self.request(Kind::Data, &payload)
+        }
+    }
+
+    fn acked(offset: usize) -> Answer {
+        (Ok((offset as u32).to_le_bytes().to_vec()), Event::None)
+    }
+
+    fn nak(reason: NakReason) -> Answer {
+        (Err((reason, None)), Event::None)
+    }
+
+    #[test]
+    fn receives_an_image() {
+        let image = image(Slot::B, 3000);
+        let mut session = Session::new();
+
+        let (info, _) = session.request(Kind::Info, &[]);
+        assert_eq!(
+            DeviceInfo::decode(&info.unwrap()),
+            Some(DeviceInfo { running: Some(Slot::A), version: Some(RUNNING_VERSION) })
+        );
+
+        assert_eq!(session.start(Slot::B, image.len()), (Ok(vec![]), Event::Started));
+        for offset in (0..image.len()).step_by(CHUNK_SIZE) {
+            assert_eq!(session.data(&image, offset), acked((offset + CHUNK_SIZE).min(image.len())));
+            assert_eq!(session.receiver.progress(), Some(((offset + CHUNK_SIZE).min(image.len()) as u32, image.len() as u32)));
+        }
+        assert_eq!(session.request(Kind::End, &[]), (Ok(vec![]), Event::Installed(Slot::B)));
+        assert_eq!(session.receiver.progress(), None);
+        assert_eq!(session.target.slots[Slot::B as usize][..image.len()], image[..]);
+
+        // The answer to `End` may have been lost: a repeated `End` is acknowledged again
+        assert_eq!(session.request(Kind::End, &[]), (Ok(vec![]), Event::Installed(Slot::B)));
+        assert_eq!(session.request(Kind::Reboot, &[]), (Ok(vec![]), Event::Reboot));
+    }
+
+    #[test]
+    fn repeated_and_out_of_order_data() {
+        let image = image(Slot::B, 1000);
+        let mut session = Session::new();
+        session.begin(Slot::B, image.len());
+        assert_eq!(session.data(&image, 0), acked(CHUNK_SIZE));
+
+        // A repeated frame (the answer was lost) is acknowledged again without being written
+        let writes = session.target.writes;
+        assert_eq!(session.data(&image, 0), acked(CHUNK_SIZE));
+        assert_eq!(session.target.writes, writes);
+
+        // A lost frame leaves a gap: the answer tells where to resume
+        assert_eq!(session.data(&image, 2 * CHUNK_SIZE), (Err((NakReason::Offset, Some(CHUNK_SIZE as u32))), Event::None));
+        // `End` before the last byte
+        assert_eq!(session.request(Kind::End, &[]), nak(NakReason::State));
+
+        for offset in (CHUNK_SIZE..image.len()).step_by(CHUNK_SIZE) {
+            assert_eq!(session.data(&image, offset), acked((offset + CHUNK_SIZE).min(image.len())));
+        }
+        assert_eq!(session.request(Kind::End, &[]), (Ok(vec![]), Event::Installed(Slot::B)));
+    }
+
+    #[test]
+    fn retried_request_with_the_same_sequence_number() {
+        let image = image(Slot::B, 600);
+        let mut session = Session::new();
+        session.begin(Slot::B, image.len());
+        let mut payload = 0u32.to_le_bytes().to_vec();
+        payload.extend_from_slice(&image[..CHUNK_SIZE]);
+        // The host resends a request whose answer did not arrive with the same number
+        assert_eq!(session.send(Kind::Data, 42, &payload), acked(CHUNK_SIZE));
+        assert_eq!(session.send(Kind::Data, 42, &payload), acked(CHUNK_SIZE));
+    }
+
+    #[test]
+    fn refuses_the_running_slot_and_bad_lengths() {
+        let mut session = Session::new();
+        assert_eq!(session.start(Slot::A, 4096), nak(NakReason::RunningSlot));
+        assert_eq!(session.start(Slot::B, HEADER_SIZE as usize - 1), nak(NakReason::TooLarge));
+        assert_eq!(session.start(Slot::B, SLOT_SIZE as usize + 1), nak(NakReason::TooLarge));
+        assert_eq!(session.request(Kind::Start, &[Slot::B as u8, 0, 0x10]), nak(NakReason::Malformed));
+        assert_eq!(session.request(Kind::Start, &[9, 0, 0x10, 0, 0]), nak(NakReason::Malformed));
+        assert_eq!(session.receiver.progress(), None);
+
+        // Data beyond the announced length
+        let image = image(Slot::B, 100);
+        session.begin(Slot::B, image.len());
+        let mut payload = 0u32.to_le_bytes().to_vec();
+        payload.extend_from_slice(&image);
+        payload.push(0);
+        assert_eq!(session.request(Kind::Data, &payload), nak(NakReason::TooLarge));
+        assert_eq!(session.request(Kind::Data, &[1, 2]), nak(NakReason::Malformed));
+    }
+
+    #[test]
+    fn refuses_requests_out_of_a_transfer() {
+        let mut session = Session::new();
+        let mut payload = 0u32.to_le_bytes().to_vec();
+        payload.extend_from_slice(&[0; 16]);
+        assert_eq!(session.request(Kind::Data, &payload), nak(NakReason::State));
+        assert_eq!(session.request(Kind::End, &[]), nak(NakReason::State));
+        assert_eq!(session.send(Kind::Ack, 1, &[]), nak(NakReason::Malformed));
+
+        // Abort drops the transfer
+        session.begin(Slot::B, 1000);
+        assert_eq!(session.request(Kind::Abort, &[]), (Ok(vec![]), Event::Aborted));
+        assert_eq!(session.request(Kind::Data, &payload), nak(NakReason::State));
+    }
+
+    #[test]
+    fn rejects_an_image_for_the_wrong_slot() {
+        let for_a = image(Slot::A, 700);
+        let mut session = Session::new();
+        session.target.running = Slot::B;
+        // Linked for slot A, sent to slot A while B runs: fine
+        session.upload(Slot::A, &for_a);
+        assert_eq!(session.request(Kind::End, &[]), (Ok(vec![]), Event::Installed(Slot::A)));
+
+        // Linked for slot B, stored in slot A: it cannot run there
+        let for_b = image(Slot::B, 700);
+        session.upload(Slot::A, &for_b);
+        assert_eq!(session.request(Kind::End, &[]), nak(NakReason::BadImage));
+        // Nothing is installed, a repeated `End` is refused too
+        assert_eq!(session.request(Kind::End, &[]), nak(NakReason::State));
+    }
+
+    #[test]
+    fn rejects_an_image_failing_its_crc() {
+        let mut image = image(Slot::B, 2000);
+        let last = image.len() - 1;
+        image[last] ^= 0x01;
+        let mut session = Session::new();
+        session.upload(Slot::B, &image);
+        assert_eq!(session.request(Kind::End, &[]), nak(NakReason::BadImage));
+
+        // The same transfer again, undamaged
+        image[last] ^= 0x01;
+        session.upload(Slot::B, &image);
+        assert_eq!(session.request(Kind::End, &[]), (Ok(vec![]), Event::Installed(Slot::B)));
+    }
+}